@@ -10,7 +10,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build_client(true)
         .build_server(true)
         .file_descriptor_set_path(out_dir.join(NODE_DESCRIPTOR_FILE))
-        .compile(&["proto/node/v1alpha2/stream.proto"], &["proto/node"])?;
+        .compile(
+            &[
+                "proto/node/v1alpha2/stream.proto",
+                "proto/node/v1alpha2/admin.proto",
+            ],
+            &["proto/node"],
+        )?;
 
     tonic_build::configure()
         .build_client(true)
@@ -26,6 +32,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &["proto/starknet"],
         )?;
 
+    // add jsonpb definitions, used by the http gateway
+    let node_description_set = std::fs::read(out_dir.join(NODE_DESCRIPTOR_FILE))?;
+    pbjson_build::Builder::new()
+        .register_descriptors(&node_description_set)?
+        .build(&[".apibara.node"])?;
+
     // add jsonpb definitions, but only for the data types
     let starknet_description_set = std::fs::read(out_dir.join(STARKNET_DESCRIPTOR_FILE))?;
     pbjson_build::Builder::new()
@@ -1,5 +1,6 @@
 pub mod v1alpha2 {
     tonic::include_proto!("apibara.node.v1alpha2");
+    tonic::include_proto!("apibara.node.v1alpha2.serde");
 
     pub(crate) const FILE_DESCRIPTOR_SET: &[u8] =
         tonic::include_file_descriptor_set!("node_v1alpha2_descriptor");
@@ -20,6 +20,22 @@ impl BlockStatus {
     pub fn is_rejected(&self) -> bool {
         *self == BlockStatus::Rejected
     }
+
+    /// Returns the finality status of the transactions in a block with this status.
+    pub fn transaction_finality(&self) -> TransactionFinalityStatus {
+        match self {
+            BlockStatus::Pending => TransactionFinalityStatus::Pending,
+            BlockStatus::AcceptedOnL2 => TransactionFinalityStatus::AcceptedOnL2,
+            BlockStatus::AcceptedOnL1 => TransactionFinalityStatus::AcceptedOnL1,
+            _ => TransactionFinalityStatus::Unspecified,
+        }
+    }
+}
+
+impl TransactionReceipt {
+    pub fn is_reverted(&self) -> bool {
+        self.execution_status() == ExecutionStatus::Reverted
+    }
 }
 
 impl FieldElement {
@@ -32,6 +48,14 @@ impl FieldElement {
         }
     }
 
+    /// Returns the value as `usize`, if it fits.
+    pub fn to_usize(&self) -> Option<usize> {
+        if self.lo_lo != 0 || self.lo_hi != 0 || self.hi_lo != 0 {
+            return None;
+        }
+        usize::try_from(self.hi_hi).ok()
+    }
+
     pub fn from_bytes(bytes: &[u8; 32]) -> Self {
         let lo_lo = u64::from_be_bytes([
             bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
@@ -124,7 +148,9 @@ mod tests {
 
     use starknet::core::types::FieldElement as Felt;
 
-    use crate::starknet::v1alpha2::FieldElement;
+    use crate::starknet::v1alpha2::{
+        BlockStatus, ExecutionStatus, FieldElement, TransactionFinalityStatus, TransactionReceipt,
+    };
 
     #[quickcheck]
     fn test_felt_from_u64(num: u64) {
@@ -146,4 +172,38 @@ mod tests {
         assert_eq!(felt.hi_lo, 0);
         assert_eq!(felt.hi_hi, 0);
     }
+
+    #[test]
+    fn test_transaction_finality() {
+        assert_eq!(
+            BlockStatus::Pending.transaction_finality(),
+            TransactionFinalityStatus::Pending
+        );
+        assert_eq!(
+            BlockStatus::AcceptedOnL2.transaction_finality(),
+            TransactionFinalityStatus::AcceptedOnL2
+        );
+        assert_eq!(
+            BlockStatus::AcceptedOnL1.transaction_finality(),
+            TransactionFinalityStatus::AcceptedOnL1
+        );
+        assert_eq!(
+            BlockStatus::Rejected.transaction_finality(),
+            TransactionFinalityStatus::Unspecified
+        );
+        assert_eq!(
+            BlockStatus::Unspecified.transaction_finality(),
+            TransactionFinalityStatus::Unspecified
+        );
+    }
+
+    #[test]
+    fn test_receipt_is_reverted() {
+        let mut receipt = TransactionReceipt::default();
+        assert!(!receipt.is_reverted());
+        receipt.set_execution_status(ExecutionStatus::Succeeded);
+        assert!(!receipt.is_reverted());
+        receipt.set_execution_status(ExecutionStatus::Reverted);
+        assert!(receipt.is_reverted());
+    }
 }
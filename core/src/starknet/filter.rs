@@ -52,6 +52,77 @@ impl Filter {
         self.messages.push(closure(L2ToL1MessageFilter::default()));
         self
     }
+
+    /// Return transactions once per block, referenced by events and messages
+    pub fn with_normalized(mut self, normalized: bool) -> Self {
+        self.normalized = normalized;
+        self
+    }
+
+    /// Add factory to filter
+    pub fn add_factory<F>(mut self, closure: F) -> Self
+    where
+        F: Fn(FactoryFilter) -> FactoryFilter,
+    {
+        self.factories.push(closure(FactoryFilter::default()));
+        self
+    }
+}
+
+impl FactoryFilter {
+    /// Filter the event emitted by the factory
+    pub fn with_event(mut self, event: EventFilter) -> Self {
+        self.event = Some(event);
+        self
+    }
+    /// Filter contracts deployed with the class hash
+    pub fn with_class_hash(mut self, class_hash: FieldElement) -> Self {
+        self.class_hash = Some(class_hash);
+        self
+    }
+    /// Read the contract address from the event keys
+    pub fn with_key_position(mut self, position: u32) -> Self {
+        self.address = Some(factory_filter::Address::KeyPosition(position));
+        self
+    }
+    /// Read the contract address from the event data
+    pub fn with_data_position(mut self, position: u32) -> Self {
+        self.address = Some(factory_filter::Address::DataPosition(position));
+        self
+    }
+    /// Filter events emitted by the deployed contracts
+    pub fn with_events(mut self, events: EventFilter) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Returns the address of the contract deployed, if the event was emitted by the factory.
+    pub fn deployed_address(&self, event: &Event) -> Option<FieldElement> {
+        if !self.event.as_ref()?.matches(event) {
+            return None;
+        }
+        match self.address.as_ref()? {
+            factory_filter::Address::KeyPosition(position) => {
+                event.keys.get(*position as usize).cloned()
+            }
+            factory_filter::Address::DataPosition(position) => {
+                event.data.get(*position as usize).cloned()
+            }
+        }
+    }
+
+    /// Returns true if the deployed contract was deployed with the factory class hash.
+    pub fn matches_deployed_contract(&self, deployed_contract: &DeployedContract) -> bool {
+        self.class_hash.is_some() && self.class_hash == deployed_contract.class_hash
+    }
+
+    /// Returns the event filter for the contract deployed at the given address.
+    pub fn event_filter_for(&self, address: FieldElement) -> EventFilter {
+        self.events
+            .clone()
+            .unwrap_or_default()
+            .with_contract_address(address)
+    }
 }
 
 impl EventFilter {
@@ -80,6 +151,11 @@ impl InvokeTransactionV0Filter {
         self.calldata = calldata;
         self
     }
+    /// Filter with call data, position by position
+    pub fn with_calldata_match(mut self, calldata: FieldElementsFilter) -> Self {
+        self.calldata_match = Some(calldata);
+        self
+    }
 }
 
 impl InvokeTransactionV1Filter {
@@ -93,6 +169,34 @@ impl InvokeTransactionV1Filter {
         self.calldata = calldata;
         self
     }
+    /// Filter with call data, position by position
+    pub fn with_calldata_match(mut self, calldata: FieldElementsFilter) -> Self {
+        self.calldata_match = Some(calldata);
+        self
+    }
+    /// Filter transactions with a matching call
+    pub fn with_call(mut self, call: CallFilter) -> Self {
+        self.call = Some(call);
+        self
+    }
+}
+
+impl CallFilter {
+    /// Filter call to contract
+    pub fn with_to(mut self, to: FieldElement) -> Self {
+        self.to = Some(to);
+        self
+    }
+    /// Filter call with selector
+    pub fn with_selector(mut self, selector: FieldElement) -> Self {
+        self.selector = Some(selector);
+        self
+    }
+    /// Filter call with call data, position by position
+    pub fn with_calldata(mut self, calldata: FieldElementsFilter) -> Self {
+        self.calldata = Some(calldata);
+        self
+    }
 }
 
 impl DeployTransactionFilter {
@@ -112,6 +216,11 @@ impl DeployTransactionFilter {
         self.constructor_calldata = constructor_calldata;
         self
     }
+    /// Filter with constructor calldata, position by position
+    pub fn with_constructor_calldata_match(mut self, calldata: FieldElementsFilter) -> Self {
+        self.constructor_calldata_match = Some(calldata);
+        self
+    }
 }
 
 impl DeclareTransactionFilter {
@@ -143,6 +252,11 @@ impl L1HandlerTransactionFilter {
         self.calldata = calldata;
         self
     }
+    /// Filter with call data, position by position
+    pub fn with_calldata_match(mut self, calldata: FieldElementsFilter) -> Self {
+        self.calldata_match = Some(calldata);
+        self
+    }
 }
 
 impl DeployAccountTransactionFilter {
@@ -161,6 +275,11 @@ impl DeployAccountTransactionFilter {
         self.constructor_calldata = constructor_calldata;
         self
     }
+    /// Filter with constructor calldata, position by position
+    pub fn with_constructor_calldata_match(mut self, calldata: FieldElementsFilter) -> Self {
+        self.constructor_calldata_match = Some(calldata);
+        self
+    }
 }
 
 impl EventFilter {
@@ -179,6 +298,52 @@ impl EventFilter {
         self.data = data;
         self
     }
+    /// Filter event keys, position by position
+    pub fn with_keys_match(mut self, keys: FieldElementsFilter) -> Self {
+        self.keys_match = Some(keys);
+        self
+    }
+    /// Filter event data, position by position
+    pub fn with_data_match(mut self, data: FieldElementsFilter) -> Self {
+        self.data_match = Some(data);
+        self
+    }
+    /// Include the transaction that emitted the event
+    pub fn with_include_transaction(mut self, include: bool) -> Self {
+        self.include_transaction = Some(include);
+        self
+    }
+    /// Include the receipt of the transaction that emitted the event
+    pub fn with_include_receipt(mut self, include: bool) -> Self {
+        self.include_receipt = Some(include);
+        self
+    }
+    /// Include the transaction signature
+    pub fn with_include_signature(mut self, include: bool) -> Self {
+        self.include_signature = Some(include);
+        self
+    }
+    /// Include the transaction calldata
+    pub fn with_include_calldata(mut self, include: bool) -> Self {
+        self.include_calldata = Some(include);
+        self
+    }
+    /// Include events emitted by reverted transactions
+    pub fn with_include_reverted(mut self, include: bool) -> Self {
+        self.include_reverted = include;
+        self
+    }
+    /// Filter events emitted by contracts with the class hash
+    pub fn from_class_hash(mut self, class_hash: FieldElement) -> Self {
+        self.from_class_hash = Some(class_hash);
+        self
+    }
+    /// Only apply the filter to blocks in the range (inclusive)
+    pub fn with_block_range(mut self, from_block: Option<u64>, to_block: Option<u64>) -> Self {
+        self.from_block = from_block;
+        self.to_block = to_block;
+        self
+    }
 }
 
 impl L2ToL1MessageFilter {
@@ -192,6 +357,65 @@ impl L2ToL1MessageFilter {
         self.payload = payload;
         self
     }
+    /// Filter message payload, position by position
+    pub fn with_payload_match(mut self, payload: FieldElementsFilter) -> Self {
+        self.payload_match = Some(payload);
+        self
+    }
+    /// Include the transaction that sent the message
+    pub fn with_include_transaction(mut self, include: bool) -> Self {
+        self.include_transaction = Some(include);
+        self
+    }
+    /// Include the receipt of the transaction that sent the message
+    pub fn with_include_receipt(mut self, include: bool) -> Self {
+        self.include_receipt = Some(include);
+        self
+    }
+    /// Include the transaction signature
+    pub fn with_include_signature(mut self, include: bool) -> Self {
+        self.include_signature = Some(include);
+        self
+    }
+    /// Include the transaction calldata
+    pub fn with_include_calldata(mut self, include: bool) -> Self {
+        self.include_calldata = Some(include);
+        self
+    }
+}
+
+impl TransactionFilter {
+    /// Include the transaction receipt
+    pub fn with_include_receipt(mut self, include: bool) -> Self {
+        self.include_receipt = Some(include);
+        self
+    }
+    /// Include the transaction signature
+    pub fn with_include_signature(mut self, include: bool) -> Self {
+        self.include_signature = Some(include);
+        self
+    }
+    /// Include the transaction calldata
+    pub fn with_include_calldata(mut self, include: bool) -> Self {
+        self.include_calldata = Some(include);
+        self
+    }
+    /// Include reverted transactions
+    pub fn with_include_reverted(mut self, include: bool) -> Self {
+        self.include_reverted = include;
+        self
+    }
+    /// Include the calls decoded from the transaction calldata
+    pub fn with_include_calls(mut self, include: bool) -> Self {
+        self.include_calls = include;
+        self
+    }
+    /// Only apply the filter to blocks in the range (inclusive)
+    pub fn with_block_range(mut self, from_block: Option<u64>, to_block: Option<u64>) -> Self {
+        self.from_block = from_block;
+        self.to_block = to_block;
+        self
+    }
 }
 
 impl StateUpdateFilter {
@@ -241,6 +465,19 @@ impl StorageDiffFilter {
         self.contract_address = Some(address);
         self
     }
+    /// Filter storage entries with key
+    pub fn with_keys(mut self, keys: Vec<FieldElement>) -> Self {
+        self.keys = keys;
+        self
+    }
+    /// Filter storage entries with key in range `[start, end)`
+    pub fn add_key_range(mut self, start: FieldElement, end: FieldElement) -> Self {
+        self.key_ranges.push(StorageKeyRange {
+            start: Some(start),
+            end: Some(end),
+        });
+        self
+    }
 }
 
 impl DeclaredContractFilter {
@@ -320,6 +557,58 @@ impl FilterMatch for Option<FieldElement> {
     }
 }
 
+fn is_in_block_range(block_number: u64, from_block: Option<u64>, to_block: Option<u64>) -> bool {
+    from_block.map(|from| from <= block_number).unwrap_or(true)
+        && to_block.map(|to| block_number <= to).unwrap_or(true)
+}
+
+/// [Option] extension trait to match lists position by position. `None` matches anything.
+trait PositionMatch {
+    fn positions_match(&self, values: &[FieldElement]) -> bool;
+}
+
+impl PositionMatch for Option<FieldElementsFilter> {
+    fn positions_match(&self, values: &[FieldElement]) -> bool {
+        match self {
+            None => true,
+            Some(filter) => filter.matches(values),
+        }
+    }
+}
+
+impl FieldElementsFilter {
+    /// Accept any of the given values at the next position
+    pub fn add_position(mut self, values: Vec<FieldElement>) -> Self {
+        self.positions.push(FieldElementSet { values });
+        self
+    }
+
+    /// Accept any value at the next position
+    pub fn add_wildcard(mut self) -> Self {
+        self.positions.push(FieldElementSet::default());
+        self
+    }
+
+    /// Returns true if each value is accepted at its position.
+    pub fn matches(&self, values: &[FieldElement]) -> bool {
+        if self.positions.len() > values.len() {
+            return false;
+        }
+
+        self.positions
+            .iter()
+            .zip(values)
+            .all(|(position, value)| position.matches(value))
+    }
+}
+
+impl FieldElementSet {
+    /// Returns true if the set accepts the value.
+    pub fn matches(&self, value: &FieldElement) -> bool {
+        self.values.is_empty() || self.values.contains(value)
+    }
+}
+
 impl TransactionFilter {
     pub fn matches(&self, tx: &Transaction) -> bool {
         match self.filter.as_ref() {
@@ -332,6 +621,16 @@ impl TransactionFilter {
             Some(transaction_filter::Filter::DeployAccount(filter)) => filter.matches(tx),
         }
     }
+
+    /// Returns true if the filter applies to the given block.
+    pub fn is_active_at(&self, block_number: u64) -> bool {
+        is_in_block_range(block_number, self.from_block, self.to_block)
+    }
+
+    /// Returns true if the filter accepts transactions with the given receipt.
+    pub fn matches_receipt(&self, receipt: &TransactionReceipt) -> bool {
+        self.include_reverted || !receipt.is_reverted()
+    }
 }
 
 impl InvokeTransactionV0Filter {
@@ -341,6 +640,7 @@ impl InvokeTransactionV0Filter {
                 self.contract_address.matches(&tx.contract_address)
                     && self.entry_point_selector.matches(&tx.entry_point_selector)
                     && self.calldata.prefix_matches(&tx.calldata)
+                    && self.calldata_match.positions_match(&tx.calldata)
             }
             _ => false,
         }
@@ -353,10 +653,70 @@ impl InvokeTransactionV1Filter {
             Some(transaction::Transaction::InvokeV1(tx)) => {
                 self.sender_address.matches(&tx.sender_address)
                     && self.calldata.prefix_matches(&tx.calldata)
+                    && self.calldata_match.positions_match(&tx.calldata)
+                    && self.call_matches(tx)
             }
             _ => false,
         }
     }
+
+    fn call_matches(&self, tx: &InvokeTransactionV1) -> bool {
+        let filter = match self.call.as_ref() {
+            None => return true,
+            Some(filter) => filter,
+        };
+        tx.decode_calls()
+            .map(|calls| calls.iter().any(|call| filter.matches(call)))
+            .unwrap_or(false)
+    }
+}
+
+impl CallFilter {
+    pub fn matches(&self, call: &Call) -> bool {
+        self.to.matches(&call.to)
+            && self.selector.matches(&call.selector)
+            && self.calldata.positions_match(&call.calldata)
+    }
+}
+
+impl InvokeTransactionV1 {
+    /// Decodes the calls from the calldata of the account `__execute__` entry point.
+    ///
+    /// The calldata is laid out as the array of calls `(to, selector, data_offset, data_len)`,
+    /// followed by the calldata of all calls. Returns `None` if the calldata doesn't
+    /// follow this layout.
+    pub fn decode_calls(&self) -> Option<Vec<Call>> {
+        let mut calldata = self.calldata.iter();
+        let calls_len = calldata.next()?.to_usize()?;
+
+        // calls_len is controlled by the sender, don't trust it to allocate memory.
+        let mut call_array = Vec::with_capacity(calls_len.min(self.calldata.len() / 4));
+        for _ in 0..calls_len {
+            let to = calldata.next()?;
+            let selector = calldata.next()?;
+            let data_offset = calldata.next()?.to_usize()?;
+            let data_len = calldata.next()?.to_usize()?;
+            call_array.push((to, selector, data_offset, data_len));
+        }
+
+        let data_len = calldata.next()?.to_usize()?;
+        let data = calldata.as_slice();
+        if data.len() != data_len {
+            return None;
+        }
+
+        call_array
+            .into_iter()
+            .map(|(to, selector, data_offset, data_len)| {
+                let data_end = data_offset.checked_add(data_len)?;
+                Some(Call {
+                    to: Some(to.clone()),
+                    selector: Some(selector.clone()),
+                    calldata: data.get(data_offset..data_end)?.to_vec(),
+                })
+            })
+            .collect()
+    }
 }
 
 impl DeployTransactionFilter {
@@ -370,6 +730,9 @@ impl DeployTransactionFilter {
                     && self
                         .constructor_calldata
                         .prefix_matches(&tx.constructor_calldata)
+                    && self
+                        .constructor_calldata_match
+                        .positions_match(&tx.constructor_calldata)
             }
             _ => false,
         }
@@ -395,6 +758,7 @@ impl L1HandlerTransactionFilter {
                 self.contract_address.matches(&tx.contract_address)
                     && self.entry_point_selector.matches(&tx.entry_point_selector)
                     && self.calldata.prefix_matches(&tx.calldata)
+                    && self.calldata_match.positions_match(&tx.calldata)
             }
             _ => false,
         }
@@ -412,6 +776,9 @@ impl DeployAccountTransactionFilter {
                     && self
                         .constructor_calldata
                         .prefix_matches(&tx.constructor_calldata)
+                    && self
+                        .constructor_calldata_match
+                        .positions_match(&tx.constructor_calldata)
             }
             _ => false,
         }
@@ -423,6 +790,26 @@ impl EventFilter {
         self.from_address.matches(&event.from_address)
             && self.keys.prefix_matches(&event.keys)
             && self.data.prefix_matches(&event.data)
+            && self.keys_match.positions_match(&event.keys)
+            && self.data_match.positions_match(&event.data)
+    }
+
+    /// Returns true if the filter applies to the given block.
+    pub fn is_active_at(&self, block_number: u64) -> bool {
+        is_in_block_range(block_number, self.from_block, self.to_block)
+    }
+
+    /// Returns true if the filter accepts events emitted by a transaction with the given receipt.
+    pub fn matches_receipt(&self, receipt: &TransactionReceipt) -> bool {
+        self.include_reverted || !receipt.is_reverted()
+    }
+
+    /// Returns true if the filter accepts events emitted by a contract with the given class hash.
+    pub fn matches_class_hash(&self, class_hash: Option<&FieldElement>) -> bool {
+        match self.from_class_hash.as_ref() {
+            None => true,
+            Some(expected) => class_hash == Some(expected),
+        }
     }
 }
 
@@ -430,6 +817,7 @@ impl L2ToL1MessageFilter {
     pub fn matches(&self, message: &L2ToL1Message) -> bool {
         self.to_address.matches(&message.to_address)
             && self.payload.prefix_matches(&message.payload)
+            && self.payload_match.positions_match(&message.payload)
     }
 }
 
@@ -438,6 +826,39 @@ impl StorageDiffFilter {
         self.contract_address
             .matches(&storage_diff.contract_address)
     }
+
+    /// Returns true if the storage entry key is accepted by the filter.
+    pub fn matches_entry(&self, entry: &StorageEntry) -> bool {
+        if self.keys.is_empty() && self.key_ranges.is_empty() {
+            return true;
+        }
+
+        let key = match entry.key.as_ref() {
+            None => return false,
+            Some(key) => key,
+        };
+
+        self.keys.contains(key) || self.key_ranges.iter().any(|range| range.contains(key))
+    }
+}
+
+impl StorageKeyRange {
+    /// Returns true if the key is in the range.
+    pub fn contains(&self, key: &FieldElement) -> bool {
+        // big endian bytes compare in the same order as the field elements.
+        let key = key.to_bytes();
+        let after_start = self
+            .start
+            .as_ref()
+            .map(|start| start.to_bytes() <= key)
+            .unwrap_or(true);
+        let before_end = self
+            .end
+            .as_ref()
+            .map(|end| key < end.to_bytes())
+            .unwrap_or(true);
+        after_start && before_end
+    }
 }
 
 impl DeclaredContractFilter {
@@ -459,3 +880,184 @@ impl NonceUpdateFilter {
         self.contract_address.matches(&nonce.contract_address) && self.nonce.matches(&nonce.nonce)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn felt(value: u64) -> FieldElement {
+        FieldElement::from_u64(value)
+    }
+
+    #[test]
+    fn test_field_elements_filter_matches() {
+        // Transfer OR Approval, any from, to = 3
+        let filter = FieldElementsFilter::default()
+            .add_position(vec![felt(1), felt(2)])
+            .add_wildcard()
+            .add_position(vec![felt(3)]);
+
+        assert!(filter.matches(&[felt(1), felt(9), felt(3)]));
+        assert!(filter.matches(&[felt(2), felt(8), felt(3), felt(7)]));
+        assert!(!filter.matches(&[felt(4), felt(9), felt(3)]));
+        assert!(!filter.matches(&[felt(1), felt(9), felt(4)]));
+        assert!(!filter.matches(&[felt(1), felt(9)]));
+        assert!(FieldElementsFilter::default().matches(&[]));
+    }
+
+    #[test]
+    fn test_invoke_v1_decode_calls() {
+        // two calls: 10.20(1, 2) and 30.40(3)
+        let tx = InvokeTransactionV1 {
+            sender_address: Some(felt(99)),
+            calldata: vec![
+                felt(2),
+                felt(10),
+                felt(20),
+                felt(0),
+                felt(2),
+                felt(30),
+                felt(40),
+                felt(2),
+                felt(1),
+                felt(3),
+                felt(1),
+                felt(2),
+                felt(3),
+            ],
+            calls: Vec::default(),
+        };
+
+        let calls = tx.decode_calls().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].to, Some(felt(10)));
+        assert_eq!(calls[0].calldata, vec![felt(1), felt(2)]);
+        assert_eq!(calls[1].selector, Some(felt(40)));
+        assert_eq!(calls[1].calldata, vec![felt(3)]);
+
+        let filter = InvokeTransactionV1Filter::default().with_call(
+            CallFilter::default()
+                .with_to(felt(30))
+                .with_selector(felt(40)),
+        );
+        let transaction = Transaction {
+            meta: None,
+            transaction: Some(transaction::Transaction::InvokeV1(tx.clone())),
+        };
+        assert!(filter.matches(&transaction));
+
+        let filter = InvokeTransactionV1Filter::default().with_call(
+            CallFilter::default()
+                .with_to(felt(10))
+                .with_selector(felt(40)),
+        );
+        assert!(!filter.matches(&transaction));
+
+        let truncated = InvokeTransactionV1 {
+            calldata: tx.calldata[..12].to_vec(),
+            ..tx
+        };
+        assert!(truncated.decode_calls().is_none());
+    }
+
+    #[test]
+    fn test_invoke_v1_decode_calls_huge_calls_len() {
+        // a malicious sender claims 2^40 calls.
+        let tx = InvokeTransactionV1 {
+            sender_address: Some(felt(99)),
+            calldata: vec![felt(1 << 40), felt(10), felt(20), felt(0), felt(0), felt(0)],
+            calls: Vec::default(),
+        };
+        assert!(tx.decode_calls().is_none());
+
+        let filter =
+            InvokeTransactionV1Filter::default().with_call(CallFilter::default().with_to(felt(10)));
+        let transaction = Transaction {
+            meta: None,
+            transaction: Some(transaction::Transaction::InvokeV1(tx)),
+        };
+        assert!(!filter.matches(&transaction));
+    }
+
+    #[test]
+    fn test_matches_receipt_reverted() {
+        let receipt = |status: ExecutionStatus| {
+            let mut receipt = TransactionReceipt::default();
+            receipt.set_execution_status(status);
+            receipt
+        };
+        let succeeded = receipt(ExecutionStatus::Succeeded);
+        let reverted = receipt(ExecutionStatus::Reverted);
+        let unknown = receipt(ExecutionStatus::Unspecified);
+
+        let filter = TransactionFilter::default();
+        assert!(filter.matches_receipt(&succeeded));
+        assert!(filter.matches_receipt(&unknown));
+        assert!(!filter.matches_receipt(&reverted));
+        let filter = filter.with_include_reverted(true);
+        assert!(filter.matches_receipt(&reverted));
+
+        let filter = EventFilter::default();
+        assert!(filter.matches_receipt(&succeeded));
+        assert!(filter.matches_receipt(&unknown));
+        assert!(!filter.matches_receipt(&reverted));
+        let filter = filter.with_include_reverted(true);
+        assert!(filter.matches_receipt(&reverted));
+    }
+
+    #[test]
+    fn test_storage_diff_filter_matches_entry() {
+        let entry = |key: u64| StorageEntry {
+            key: Some(felt(key)),
+            value: Some(felt(0)),
+        };
+
+        assert!(StorageDiffFilter::default().matches_entry(&entry(7)));
+
+        let filter = StorageDiffFilter::default()
+            .with_keys(vec![felt(1)])
+            .add_key_range(felt(10), felt(20));
+        assert!(filter.matches_entry(&entry(1)));
+        assert!(filter.matches_entry(&entry(10)));
+        assert!(filter.matches_entry(&entry(19)));
+        assert!(!filter.matches_entry(&entry(20)));
+        assert!(!filter.matches_entry(&entry(2)));
+    }
+
+    #[test]
+    fn test_event_filter_keys_match() {
+        let filter = EventFilter::default().with_keys_match(
+            FieldElementsFilter::default()
+                .add_wildcard()
+                .add_position(vec![felt(5)]),
+        );
+        let event = Event {
+            from_address: Some(felt(100)),
+            keys: vec![felt(1), felt(5)],
+            data: Vec::default(),
+        };
+        assert!(filter.matches(&event));
+
+        let event = Event {
+            keys: vec![felt(1), felt(6)],
+            ..event
+        };
+        assert!(!filter.matches(&event));
+    }
+
+    #[test]
+    fn test_event_filter_matches_class_hash() {
+        let filter = EventFilter::default();
+        assert!(filter.matches_class_hash(None));
+        assert!(filter.matches_class_hash(Some(&felt(1))));
+
+        let filter = EventFilter {
+            from_class_hash: Some(felt(1)),
+            ..Default::default()
+        };
+        assert!(filter.matches_class_hash(Some(&felt(1))));
+        assert!(!filter.matches_class_hash(Some(&felt(2))));
+        // contracts with unknown class hash never match.
+        assert!(!filter.matches_class_hash(None));
+    }
+}
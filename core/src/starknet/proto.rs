@@ -25,4 +25,24 @@ mod tests {
         let back = serde_json::from_str::<v1alpha2::FieldElement>(&as_hex).unwrap();
         assert_eq!(fe, back);
     }
+
+    #[test]
+    pub fn test_filter_from_json() {
+        let json = r#"{
+            "header": { "weak": true },
+            "events": [{
+                "fromAddress": "0x0000000000000000000000000000000000000000000000000000000000000abc",
+                "keys": ["0x0000000000000000000000000000000000000000000000000000000000000001"]
+            }]
+        }"#;
+        let filter = serde_json::from_str::<v1alpha2::Filter>(json).unwrap();
+        assert!(filter.header.unwrap().weak);
+        assert_eq!(filter.events.len(), 1);
+        let event = &filter.events[0];
+        assert_eq!(
+            event.from_address,
+            Some(v1alpha2::FieldElement::from_u64(0xabc))
+        );
+        assert_eq!(event.keys, vec![v1alpha2::FieldElement::from_u64(1)]);
+    }
 }
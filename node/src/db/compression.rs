@@ -0,0 +1,769 @@
+//! Compression of table values.
+//!
+//! Compressed values start with a tag byte that identifies the codec. The tag is
+//! chosen so that it's never the first byte of an encoded protobuf message (7 is
+//! not a valid wire type), so values written before compression was enabled are
+//! still read correctly and can be re-encoded in place.
+//!
+//! Tables can have a zstd dictionary, trained on a sample of their values. Values
+//! compressed with a dictionary store the dictionary id after the tag. Dictionaries
+//! are stored in the database and must be loaded with [load_compression_dictionaries]
+//! before reading the values compressed with them. Loaded dictionaries are attached to
+//! their environment, so databases opened by the same process never use each other's
+//! dictionaries.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{Read, Write},
+    sync::{Arc, RwLock},
+};
+
+use lazy_static::lazy_static;
+use libmdbx::{
+    Environment, EnvironmentKind, Error as MdbxError, Transaction, TransactionKind, WriteFlags, RO,
+};
+use prost::Message;
+use tracing::{info, warn};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use super::{
+    mdbx::{MdbxErrorExt, MdbxResult, MdbxTransactionExt},
+    table::Table,
+};
+
+/// Tag of values compressed with zstd.
+const ZSTD_TAG: u8 = 0x07;
+
+/// Tag of values compressed with zstd and a dictionary.
+///
+/// The tag is followed by the 4 bytes big endian dictionary id.
+const ZSTD_DICTIONARY_TAG: u8 = 0x0f;
+
+/// Magic number at the start of zstd dictionaries.
+const ZSTD_DICTIONARY_MAGIC: u32 = 0xEC30A437;
+
+/// Number of values re-encoded in each transaction.
+const RECOMPRESS_BATCH_SIZE: usize = 1_000;
+
+/// Maximum size of a trained dictionary.
+const DICTIONARY_MAX_SIZE: usize = 112_640;
+
+/// Maximum number of values used to train a dictionary.
+const DICTIONARY_MAX_SAMPLES: usize = 10_000;
+
+/// Maximum total size of the values used to train a dictionary.
+const DICTIONARY_MAX_SAMPLES_SIZE: usize = 64 * 1024 * 1024;
+
+/// Minimum number of values needed to train a dictionary.
+const DICTIONARY_MIN_SAMPLES: usize = 100;
+
+lazy_static! {
+    /// Dictionaries loaded from each environment.
+    static ref DICTIONARIES: RwLock<HashMap<EnvironmentKey, Arc<Dictionaries>>> =
+        RwLock::default();
+}
+
+/// Compression used to store the values of a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableCompression {
+    /// Store the encoded protobuf message.
+    #[default]
+    None,
+    /// Compress the encoded protobuf message with zstd, using the given level.
+    ///
+    /// Uses the table dictionary, if any.
+    Zstd(i32),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CompressionError {
+    #[error("unknown compression tag {0}")]
+    UnknownTag(u8),
+    #[error("unknown compression dictionary {0}")]
+    UnknownDictionary(u32),
+    #[error("compressed value is truncated")]
+    Truncated,
+    #[error("zstd error")]
+    Zstd(#[from] std::io::Error),
+}
+
+/// Store the zstd dictionary of each table, by table name.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionDictionaryTable {}
+
+/// A zstd dictionary used to compress the values of a table.
+#[derive(Clone, PartialEq, Message)]
+pub struct CompressionDictionary {
+    /// Dictionary id, as stored in the dictionary itself.
+    #[prost(uint32, tag = "1")]
+    pub id: u32,
+    /// Compression level used with the dictionary.
+    #[prost(int32, tag = "2")]
+    pub level: i32,
+    /// The dictionary content.
+    #[prost(bytes, tag = "3")]
+    pub data: Vec<u8>,
+}
+
+impl Table for CompressionDictionaryTable {
+    type Key = String;
+    type Value = CompressionDictionary;
+
+    fn db_name() -> &'static str {
+        "CompressionDictionary"
+    }
+}
+
+/// Identifies an environment by the address of its mdbx handle.
+///
+/// Addresses are reused after an environment is closed, [load_compression_dictionaries]
+/// replaces the dictionaries left by the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct EnvironmentKey(usize);
+
+/// The compression dictionaries of an environment.
+///
+/// Tables and cursors keep the dictionaries loaded when they were opened.
+#[derive(Default, Clone)]
+pub(crate) struct Dictionaries {
+    /// Dictionaries used to decompress values, by dictionary id.
+    decoders: HashMap<u32, Arc<DecoderDictionary<'static>>>,
+    /// Dictionary used to compress the values of each table, by table name.
+    encoders: HashMap<String, (u32, Arc<EncoderDictionary<'static>>)>,
+}
+
+impl EnvironmentKey {
+    fn of<E: EnvironmentKind>(env: &Environment<E>) -> Self {
+        EnvironmentKey(env.env() as usize)
+    }
+}
+
+impl Dictionaries {
+    /// Returns the dictionaries loaded from the environment.
+    pub(crate) fn of<E: EnvironmentKind>(env: &Environment<E>) -> Arc<Dictionaries> {
+        DICTIONARIES
+            .read()
+            .expect("compression dictionaries lock poisoned")
+            .get(&EnvironmentKey::of(env))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Uses the dictionary to compress the values of `table` and to decompress values
+    /// compressed with it.
+    fn insert(&mut self, table: &str, dictionary: &CompressionDictionary) {
+        let decoder = DecoderDictionary::copy(&dictionary.data);
+        let encoder = EncoderDictionary::copy(&dictionary.data, dictionary.level);
+        self.decoders.insert(dictionary.id, Arc::new(decoder));
+        self.encoders
+            .insert(table.to_string(), (dictionary.id, Arc::new(encoder)));
+    }
+}
+
+/// Encodes the value to the bytes stored in the table.
+pub(crate) fn encode_value<T: Table>(dictionaries: &Dictionaries, value: &T::Value) -> Vec<u8> {
+    let data = value.encode_to_vec();
+    compress(dictionaries, T::compression(), T::db_name(), &data)
+}
+
+/// Decodes the value from the bytes stored in the table.
+pub(crate) fn decode_value<T: Table>(
+    dictionaries: &Dictionaries,
+    data: &[u8],
+) -> MdbxResult<T::Value> {
+    let data = decompress(dictionaries, data)?;
+    T::Value::decode(data.as_ref()).map_err(MdbxError::decode_error)
+}
+
+/// Returns the encoded protobuf message in the bytes stored in a table.
+pub(crate) fn decompress<'a>(
+    dictionaries: &Dictionaries,
+    data: &'a [u8],
+) -> MdbxResult<Cow<'a, [u8]>> {
+    match data.first() {
+        Some(&ZSTD_TAG) => {
+            let data = zstd::decode_all(&data[1..])
+                .map_err(|err| MdbxError::decode_error(CompressionError::Zstd(err)))?;
+            Ok(Cow::Owned(data))
+        }
+        Some(&ZSTD_DICTIONARY_TAG) => {
+            let data = decompress_with_dictionary(dictionaries, &data[1..])
+                .map_err(MdbxError::decode_error)?;
+            Ok(Cow::Owned(data))
+        }
+        // encoded protobuf messages never start with a tag.
+        Some(tag) if tag & 0x07 == 0x07 => {
+            Err(MdbxError::decode_error(CompressionError::UnknownTag(*tag)))
+        }
+        _ => Ok(Cow::Borrowed(data)),
+    }
+}
+
+/// Returns the bytes to store for the encoded protobuf message.
+///
+/// If compression fails, the message is stored uncompressed.
+fn compress(
+    dictionaries: &Dictionaries,
+    compression: TableCompression,
+    table: &str,
+    data: &[u8],
+) -> Vec<u8> {
+    let level = match compression {
+        TableCompression::None => return data.to_vec(),
+        TableCompression::Zstd(level) => level,
+    };
+
+    let compressed = match dictionaries.encoders.get(table) {
+        None => zstd::encode_all(data, level).map(|compressed| (vec![ZSTD_TAG], compressed)),
+        Some((id, dictionary)) => compress_with_dictionary(dictionary, data).map(|compressed| {
+            let mut header = vec![ZSTD_DICTIONARY_TAG];
+            header.extend_from_slice(&id.to_be_bytes());
+            (header, compressed)
+        }),
+    };
+
+    match compressed {
+        Ok((mut out, compressed)) => {
+            out.extend_from_slice(&compressed);
+            out
+        }
+        Err(err) => {
+            // uncompressed messages are always valid values.
+            warn!(err = ?err, table = %table, "failed to compress value");
+            data.to_vec()
+        }
+    }
+}
+
+fn compress_with_dictionary(
+    dictionary: &EncoderDictionary<'static>,
+    data: &[u8],
+) -> std::io::Result<Vec<u8>> {
+    let mut encoder = zstd::stream::Encoder::with_prepared_dictionary(Vec::new(), dictionary)?;
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+fn decompress_with_dictionary(
+    dictionaries: &Dictionaries,
+    data: &[u8],
+) -> Result<Vec<u8>, CompressionError> {
+    if data.len() < 4 {
+        return Err(CompressionError::Truncated);
+    }
+    let mut id = [0; 4];
+    id.copy_from_slice(&data[..4]);
+    let id = u32::from_be_bytes(id);
+
+    let dictionary = dictionaries
+        .decoders
+        .get(&id)
+        .ok_or(CompressionError::UnknownDictionary(id))?;
+
+    let mut decoder = zstd::stream::Decoder::with_prepared_dictionary(&data[4..], dictionary)?;
+    let mut out = Vec::default();
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Returns the id stored in the header of a zstd dictionary.
+fn dictionary_id(data: &[u8]) -> Option<u32> {
+    if data.len() < 8 || data[..4] != ZSTD_DICTIONARY_MAGIC.to_le_bytes() {
+        return None;
+    }
+    let mut id = [0; 4];
+    id.copy_from_slice(&data[4..8]);
+    Some(u32::from_le_bytes(id))
+}
+
+/// Uses the dictionary to compress the values of `table` in the environment, and to
+/// decompress values compressed with it.
+fn register_dictionary<E: EnvironmentKind>(
+    env: &Environment<E>,
+    table: &str,
+    dictionary: &CompressionDictionary,
+) {
+    let mut registry = DICTIONARIES
+        .write()
+        .expect("compression dictionaries lock poisoned");
+    let dictionaries = registry.entry(EnvironmentKey::of(env)).or_default();
+    Arc::make_mut(dictionaries).insert(table, dictionary);
+}
+
+/// Returns the id of the dictionary used to compress the values of the table.
+pub fn compression_dictionary_id<T: Table, E: EnvironmentKind>(
+    env: &Environment<E>,
+) -> Option<u32> {
+    Dictionaries::of(env)
+        .encoders
+        .get(T::db_name())
+        .map(|(id, _)| *id)
+}
+
+/// Loads the compression dictionaries stored in the database, replacing the ones
+/// previously loaded for its environment.
+///
+/// This must be called before reading values compressed with a dictionary.
+pub fn load_compression_dictionaries<K: TransactionKind, E: EnvironmentKind>(
+    txn: &Transaction<K, E>,
+) -> MdbxResult<()> {
+    let mut dictionaries = Dictionaries::default();
+    let mut cursor = txn.open_cursor::<CompressionDictionaryTable>()?;
+    let mut item = cursor.first()?;
+    while let Some((table, dictionary)) = item {
+        dictionaries.insert(&table, &dictionary);
+        item = cursor.next()?;
+    }
+
+    DICTIONARIES
+        .write()
+        .expect("compression dictionaries lock poisoned")
+        .insert(EnvironmentKey::of(txn.env()), Arc::new(dictionaries));
+    Ok(())
+}
+
+/// Trains a zstd dictionary on a sample of the values of the table, and uses it to
+/// compress the values written after it.
+///
+/// If the table already has a dictionary, it's used instead of training a new one.
+/// Returns the dictionary id, or `None` if the table is not compressed or doesn't have
+/// enough values to train a dictionary.
+pub fn train_compression_dictionary<T: Table, E: EnvironmentKind>(
+    db: &Environment<E>,
+) -> MdbxResult<Option<u32>> {
+    train_dictionary::<T, E>(db, DICTIONARY_MAX_SIZE)
+}
+
+fn train_dictionary<T: Table, E: EnvironmentKind>(
+    db: &Environment<E>,
+    max_size: usize,
+) -> MdbxResult<Option<u32>> {
+    let level = match T::compression() {
+        TableCompression::None => return Ok(None),
+        TableCompression::Zstd(level) => level,
+    };
+
+    let table_name = T::db_name().to_string();
+
+    let txn = db.begin_ro_txn()?;
+    let existing = txn
+        .open_table::<CompressionDictionaryTable>()?
+        .get(&table_name)?;
+    if let Some(dictionary) = existing {
+        register_dictionary(db, &table_name, &dictionary);
+        return Ok(Some(dictionary.id));
+    }
+    let samples = sample_values::<T, _>(&Dictionaries::of(db), &txn)?;
+    txn.commit()?;
+
+    if samples.len() < DICTIONARY_MIN_SAMPLES {
+        info!(
+            table = %table_name,
+            samples = %samples.len(),
+            "not enough values to train compression dictionary"
+        );
+        return Ok(None);
+    }
+
+    let data = match zstd::dict::from_samples(&samples, max_size) {
+        Ok(data) => data,
+        Err(err) => {
+            warn!(err = ?err, table = %table_name, "failed to train compression dictionary");
+            return Ok(None);
+        }
+    };
+    let id = match dictionary_id(&data) {
+        Some(id) => id,
+        None => {
+            warn!(table = %table_name, "trained compression dictionary has no id");
+            return Ok(None);
+        }
+    };
+
+    let dictionary = CompressionDictionary { id, level, data };
+    let txn = db.begin_rw_txn()?;
+    let mut cursor = txn.open_cursor::<CompressionDictionaryTable>()?;
+    cursor.put(&table_name, &dictionary)?;
+    txn.commit()?;
+
+    register_dictionary(db, &table_name, &dictionary);
+    info!(
+        table = %table_name,
+        id = %id,
+        samples = %samples.len(),
+        size = %dictionary.data.len(),
+        "trained compression dictionary"
+    );
+    Ok(Some(id))
+}
+
+/// Returns the encoded protobuf messages of values spread over the whole table.
+fn sample_values<T: Table, E: EnvironmentKind>(
+    dictionaries: &Dictionaries,
+    txn: &Transaction<'_, RO, E>,
+) -> MdbxResult<Vec<Vec<u8>>> {
+    let table = txn.open_db(Some(T::db_name()))?;
+    let entries = txn.db_stat(&table)?.entries();
+    let step = (entries / DICTIONARY_MAX_SAMPLES).max(1);
+
+    let mut cursor = txn.cursor(&table)?;
+    let mut samples = Vec::default();
+    let mut samples_size = 0;
+    let mut index = 0;
+    let mut item = cursor.first::<Vec<u8>, Vec<u8>>()?;
+    while let Some((_, data)) = item {
+        if samples.len() == DICTIONARY_MAX_SAMPLES || samples_size >= DICTIONARY_MAX_SAMPLES_SIZE {
+            break;
+        }
+        if index % step == 0 {
+            let data = decompress(dictionaries, &data)?.into_owned();
+            samples_size += data.len();
+            samples.push(data);
+        }
+        index += 1;
+        item = cursor.next()?;
+    }
+    Ok(samples)
+}
+
+/// Re-encodes all values in the table with the table compression.
+///
+/// Values are re-encoded in place, in batches of one transaction each, so that the
+/// database can be migrated without doubling its size. Values that are already
+/// encoded with the table compression and dictionary are skipped, so the migration
+/// can be resumed if interrupted. Returns the number of values re-encoded.
+pub fn recompress_table<T: Table, E: EnvironmentKind>(db: &Environment<E>) -> MdbxResult<usize> {
+    let compression = T::compression();
+    let dictionaries = Dictionaries::of(db);
+    let dictionary_id = compression_dictionary_id::<T, E>(db);
+    let mut recompressed = 0;
+    let mut next_key: Option<Vec<u8>> = None;
+    loop {
+        let txn = db.begin_rw_txn()?;
+        let table = txn.open_db(Some(T::db_name()))?;
+        let mut cursor = txn.cursor(&table)?;
+
+        let mut item = match next_key.as_ref() {
+            None => cursor.first::<Vec<u8>, Vec<u8>>()?,
+            Some(key) => cursor.set_range::<Vec<u8>, Vec<u8>>(key)?,
+        };
+
+        let mut batch_size = 0;
+        while let Some((key, data)) = item {
+            if batch_size == RECOMPRESS_BATCH_SIZE {
+                break;
+            }
+            if !is_encoded_with(compression, dictionary_id, &data) {
+                let data = decompress(&dictionaries, &data)?;
+                let encoded = compress(&dictionaries, compression, T::db_name(), &data);
+                cursor.put(&key, &encoded, WriteFlags::CURRENT)?;
+                recompressed += 1;
+            }
+            batch_size += 1;
+            item = cursor.next()?;
+        }
+
+        next_key = item.map(|t| t.0);
+        txn.commit()?;
+
+        if next_key.is_none() {
+            return Ok(recompressed);
+        }
+    }
+}
+
+/// Returns true if the stored value is already encoded with the given compression
+/// and dictionary.
+fn is_encoded_with(compression: TableCompression, dictionary_id: Option<u32>, data: &[u8]) -> bool {
+    match (compression, dictionary_id) {
+        (TableCompression::None, _) => {
+            data.first() != Some(&ZSTD_TAG) && data.first() != Some(&ZSTD_DICTIONARY_TAG)
+        }
+        (TableCompression::Zstd(_), None) => data.first() == Some(&ZSTD_TAG),
+        (TableCompression::Zstd(_), Some(id)) => {
+            data.first() == Some(&ZSTD_DICTIONARY_TAG)
+                && data.get(1..5) == Some(&id.to_be_bytes()[..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libmdbx::{Environment, NoWriteMap, WriteFlags};
+    use prost::Message;
+    use tempfile::tempdir;
+
+    use crate::db::{
+        MdbxEnvironmentExt, MdbxRWTransactionExt, MdbxTransactionExt, Table, TableCompression,
+    };
+
+    use super::{
+        compress, compression_dictionary_id, decompress, load_compression_dictionaries,
+        recompress_table, train_dictionary, CompressionDictionary, CompressionDictionaryTable,
+        Dictionaries,
+    };
+
+    #[derive(Clone, PartialEq, Message)]
+    struct TestValue {
+        #[prost(uint64, tag = "1")]
+        number: u64,
+        #[prost(string, tag = "2")]
+        data: String,
+    }
+
+    #[derive(Debug, Clone, Copy, Default)]
+    struct TestTable {}
+
+    impl Table for TestTable {
+        type Key = u64;
+        type Value = TestValue;
+
+        fn db_name() -> &'static str {
+            "TestCompressedTable"
+        }
+
+        fn compression() -> TableCompression {
+            TableCompression::Zstd(3)
+        }
+    }
+
+    fn test_value(number: u64) -> TestValue {
+        TestValue {
+            number,
+            data: format!(
+                "transfer from 0x049d36570d4e46f48e99674bd3fcc84644ddd6b96f7c741b1562b82f9e004dc7 to 0x{:064x} amount {}",
+                number * 7919,
+                number % 13
+            ),
+        }
+    }
+
+    fn samples() -> Vec<Vec<u8>> {
+        (0..2_000).map(|i| test_value(i).encode_to_vec()).collect()
+    }
+
+    #[test]
+    fn test_uncompressed_values_are_unchanged() {
+        let data = vec![0x0a, 0x03, 0x01, 0x02, 0x03];
+        let dictionaries = Dictionaries::default();
+        let stored = compress(
+            &dictionaries,
+            TableCompression::None,
+            "TestUncompressed",
+            &data,
+        );
+        assert_eq!(stored, data);
+        assert_eq!(decompress(&dictionaries, &stored).unwrap().as_ref(), &data);
+    }
+
+    #[test]
+    fn test_zstd_round_trip() {
+        let data: Vec<u8> = (0..1_000).map(|i| (i % 7) as u8).collect();
+        let dictionaries = Dictionaries::default();
+        let stored = compress(&dictionaries, TableCompression::Zstd(3), "TestZstd", &data);
+        assert_eq!(stored[0], 0x07);
+        assert!(stored.len() < data.len());
+        assert_eq!(decompress(&dictionaries, &stored).unwrap().as_ref(), &data);
+    }
+
+    #[test]
+    fn test_zstd_dictionary_round_trip() {
+        let samples = samples();
+        let data = zstd::dict::from_samples(&samples, 4_096).unwrap();
+        let id = super::dictionary_id(&data).unwrap();
+        let dictionary = CompressionDictionary { id, level: 3, data };
+        let mut dictionaries = Dictionaries::default();
+        dictionaries.insert("TestDictionary", &dictionary);
+
+        let value = test_value(5_000).encode_to_vec();
+        let compression = TableCompression::Zstd(3);
+        let stored = compress(&dictionaries, compression, "TestDictionary", &value);
+        assert_eq!(stored[0], 0x0f);
+        assert_eq!(&stored[1..5], &id.to_be_bytes());
+        let without_dictionary = compress(&dictionaries, compression, "TestNoDictionary", &value);
+        assert!(stored.len() < without_dictionary.len());
+        assert_eq!(decompress(&dictionaries, &stored).unwrap().as_ref(), &value);
+        // the dictionary is needed to read the value.
+        assert!(decompress(&Dictionaries::default(), &stored).is_err());
+    }
+
+    #[test]
+    fn test_empty_value() {
+        assert!(decompress(&Dictionaries::default(), &[])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_unknown_tag() {
+        let dictionaries = Dictionaries::default();
+        assert!(decompress(&dictionaries, &[0x0f, 0x01]).is_err());
+        assert!(decompress(&dictionaries, &[0x17, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_dictionary() {
+        let data = [0x0f, 0x00, 0x00, 0x00, 0x01, 0x28, 0xb5];
+        assert!(decompress(&Dictionaries::default(), &data).is_err());
+    }
+
+    #[test]
+    fn test_recompress_table() {
+        let path = tempdir().unwrap();
+        let db = Environment::<NoWriteMap>::builder()
+            .with_size_gib(1, 2)
+            .open(path.path())
+            .unwrap();
+
+        // half the values were written before compression was enabled.
+        let txn = db.begin_rw_txn().unwrap();
+        txn.ensure_table::<TestTable>(None).unwrap();
+        txn.ensure_table::<CompressionDictionaryTable>(None)
+            .unwrap();
+        let table = txn.open_db(Some(TestTable::db_name())).unwrap();
+        for number in 0..2_500u64 {
+            if number % 2 == 0 {
+                let data = test_value(number).encode_to_vec();
+                txn.put(&table, &number.to_be_bytes(), &data, WriteFlags::default())
+                    .unwrap();
+            } else {
+                let mut cursor = txn.open_cursor::<TestTable>().unwrap();
+                cursor.put(&number, &test_value(number)).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+
+        assert_eq!(recompress_table::<TestTable, _>(&db).unwrap(), 1_250);
+        // resuming a completed migration does nothing.
+        assert_eq!(recompress_table::<TestTable, _>(&db).unwrap(), 0);
+
+        // values are re-encoded once more with the trained dictionary.
+        let id = train_dictionary::<TestTable, _>(&db, 4_096)
+            .unwrap()
+            .unwrap();
+        assert_eq!(compression_dictionary_id::<TestTable, _>(&db), Some(id));
+        assert_eq!(
+            train_dictionary::<TestTable, _>(&db, 4_096).unwrap(),
+            Some(id)
+        );
+        assert_eq!(recompress_table::<TestTable, _>(&db).unwrap(), 2_500);
+        assert_eq!(recompress_table::<TestTable, _>(&db).unwrap(), 0);
+
+        let txn = db.begin_ro_txn().unwrap();
+        load_compression_dictionaries(&txn).unwrap();
+        let mut cursor = txn.open_cursor::<TestTable>().unwrap();
+        let mut count = 0;
+        let mut item = cursor.first().unwrap();
+        while let Some((number, value)) = item {
+            assert_eq!(value, test_value(number));
+            count += 1;
+            item = cursor.next().unwrap();
+        }
+        assert_eq!(count, 2_500);
+
+        let table = txn.open_db(Some(TestTable::db_name())).unwrap();
+        let stored = txn
+            .get::<Vec<u8>>(&table, &7u64.to_be_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored[0], 0x0f);
+        assert_eq!(&stored[1..5], &id.to_be_bytes());
+    }
+
+    #[test]
+    fn test_dictionaries_are_per_environment() {
+        let open = |path: &std::path::Path| {
+            let db = Environment::<NoWriteMap>::builder()
+                .with_size_gib(1, 2)
+                .open(path)
+                .unwrap();
+            let txn = db.begin_rw_txn().unwrap();
+            txn.ensure_table::<TestTable>(None).unwrap();
+            txn.ensure_table::<CompressionDictionaryTable>(None)
+                .unwrap();
+            load_compression_dictionaries(&txn).unwrap();
+            txn.commit().unwrap();
+            db
+        };
+        let put = |db: &Environment<NoWriteMap>, number: u64| {
+            let txn = db.begin_rw_txn().unwrap();
+            let mut cursor = txn.open_cursor::<TestTable>().unwrap();
+            cursor.put(&number, &test_value(number)).unwrap();
+            txn.commit().unwrap();
+        };
+        let stored_tag = |db: &Environment<NoWriteMap>, number: u64| {
+            let txn = db.begin_ro_txn().unwrap();
+            let table = txn.open_db(Some(TestTable::db_name())).unwrap();
+            let stored = txn
+                .get::<Vec<u8>>(&table, &number.to_be_bytes())
+                .unwrap()
+                .unwrap();
+            stored[0]
+        };
+
+        let first_path = tempdir().unwrap();
+        let first = open(first_path.path());
+        let second_path = tempdir().unwrap();
+        let second = open(second_path.path());
+
+        for number in 0..2_000 {
+            put(&first, number);
+        }
+        let id = train_dictionary::<TestTable, _>(&first, 4_096)
+            .unwrap()
+            .unwrap();
+        assert_eq!(compression_dictionary_id::<TestTable, _>(&first), Some(id));
+        assert_eq!(compression_dictionary_id::<TestTable, _>(&second), None);
+
+        // the table of the other database is compressed without the dictionary.
+        put(&first, 5_000);
+        put(&second, 5_000);
+        assert_eq!(stored_tag(&first, 5_000), 0x0f);
+        assert_eq!(stored_tag(&second, 5_000), 0x07);
+
+        // loading the dictionaries of one database doesn't change the other.
+        let txn = second.begin_ro_txn().unwrap();
+        load_compression_dictionaries(&txn).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(compression_dictionary_id::<TestTable, _>(&first), Some(id));
+
+        let txn = first.begin_ro_txn().unwrap();
+        let value = txn.open_table::<TestTable>().unwrap().get(&5_000).unwrap();
+        assert_eq!(value, Some(test_value(5_000)));
+    }
+
+    #[test]
+    fn test_train_dictionary_needs_samples() {
+        let path = tempdir().unwrap();
+        let db = Environment::<NoWriteMap>::builder()
+            .with_size_gib(1, 2)
+            .open(path.path())
+            .unwrap();
+
+        let txn = db.begin_rw_txn().unwrap();
+        txn.ensure_table::<EmptyTable>(None).unwrap();
+        txn.ensure_table::<CompressionDictionaryTable>(None)
+            .unwrap();
+        txn.commit().unwrap();
+
+        assert_eq!(train_dictionary::<EmptyTable, _>(&db, 4_096).unwrap(), None);
+        assert_eq!(compression_dictionary_id::<EmptyTable, _>(&db), None);
+    }
+
+    #[derive(Debug, Clone, Copy, Default)]
+    struct EmptyTable {}
+
+    impl Table for EmptyTable {
+        type Key = u64;
+        type Value = TestValue;
+
+        fn db_name() -> &'static str {
+            "TestEmptyTable"
+        }
+
+        fn compression() -> TableCompression {
+            TableCompression::Zstd(3)
+        }
+    }
+}
@@ -1,13 +1,13 @@
-use std::{marker::PhantomData, ops::Range, path::Path};
+use std::{borrow::Cow, marker::PhantomData, ops::Range, path::Path, sync::Arc};
 
-use apibara_core::stream::{MessageData, RawMessageData};
+use apibara_core::stream::RawMessageData;
 use libmdbx::{
     Cursor, Database, DatabaseFlags, Environment, EnvironmentBuilder, EnvironmentKind,
     Error as MdbxError, Geometry, TableObject, Transaction, TransactionKind, WriteFlags, RW,
 };
-use prost::Message;
 
 use super::{
+    compression::{self, Dictionaries},
     table::{Table, TableKey},
     DupSortTable,
 };
@@ -21,6 +21,7 @@ where
 {
     txn: &'txn Transaction<'txn, K, E>,
     db: Database<'txn>,
+    dictionaries: Arc<Dictionaries>,
     phantom: PhantomData<T>,
 }
 
@@ -31,6 +32,7 @@ where
     K: TransactionKind,
 {
     cursor: Cursor<'txn, K>,
+    dictionaries: Arc<Dictionaries>,
     phantom: PhantomData<T>,
 }
 
@@ -143,6 +145,7 @@ where
         Ok(MdbxTable {
             txn: self,
             db: database,
+            dictionaries: Dictionaries::of(self.env()),
             phantom: Default::default(),
         })
     }
@@ -161,38 +164,6 @@ impl<'env, E: EnvironmentKind> MdbxRWTransactionExt for Transaction<'env, RW, E>
     }
 }
 
-#[derive(Debug, Clone)]
-struct TableObjectWrapper<T>(T);
-
-impl<'txn, T> TableObject<'txn> for TableObjectWrapper<T>
-where
-    T: Message + Default + Clone,
-{
-    fn decode(data_val: &[u8]) -> MdbxResult<Self>
-    where
-        Self: Sized,
-    {
-        T::decode(data_val)
-            .map_err(|err| MdbxError::DecodeError(Box::new(err)))
-            .map(Self)
-    }
-}
-
-#[derive(Debug, Clone)]
-struct RawTableObjectWrapper<T: MessageData>(RawMessageData<T>);
-
-impl<'txn, T> TableObject<'txn> for RawTableObjectWrapper<T>
-where
-    T: MessageData,
-{
-    fn decode(data_val: &[u8]) -> MdbxResult<Self>
-    where
-        Self: Sized,
-    {
-        Ok(Self(RawMessageData::from_vec(data_val.to_vec())))
-    }
-}
-
 #[derive(Debug, Clone)]
 struct TableKeyWrapper<T>(T);
 
@@ -221,6 +192,7 @@ where
         let cursor = self.txn.cursor(&self.db)?;
         Ok(TableCursor {
             cursor,
+            dictionaries: self.dictionaries.clone(),
             phantom: Default::default(),
         })
     }
@@ -229,8 +201,9 @@ where
     pub fn get(&self, key: &T::Key) -> MdbxResult<Option<T::Value>> {
         let data = self
             .txn
-            .get::<TableObjectWrapper<_>>(&self.db, key.encode().as_ref())?;
-        Ok(data.map(|d| d.0))
+            .get::<Cow<'_, [u8]>>(&self.db, key.encode().as_ref())?;
+        data.map(|d| compression::decode_value::<T>(&self.dictionaries, &d))
+            .transpose()
     }
 }
 
@@ -241,32 +214,35 @@ where
 {
     /// Get key/data at current cursor position.
     pub fn get_current(&mut self) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.get_current())
+        map_kv_result::<T>(&self.dictionaries, self.cursor.get_current())
     }
     /// Position at the first item.
     pub fn first(&mut self) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.first())
+        map_kv_result::<T>(&self.dictionaries, self.cursor.first())
     }
 
     /// Position at the last item.
     pub fn last(&mut self) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.last())
+        map_kv_result::<T>(&self.dictionaries, self.cursor.last())
     }
 
     /// Position at the next item.
     #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.next())
+        map_kv_result::<T>(&self.dictionaries, self.cursor.next())
     }
 
     /// Position at the previous item.
     pub fn prev(&mut self) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.prev())
+        map_kv_result::<T>(&self.dictionaries, self.cursor.prev())
     }
 
     /// Position at the specified key.
     pub fn seek_exact(&mut self, key: &T::Key) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.set_key(key.encode().as_ref()))
+        map_kv_result::<T>(
+            &self.dictionaries,
+            self.cursor.set_key(key.encode().as_ref()),
+        )
     }
 
     /// Position at the specified key and return the raw value .
@@ -275,12 +251,18 @@ where
         &mut self,
         key: &T::Key,
     ) -> MdbxResult<Option<(T::Key, RawMessageData<T::Value>)>> {
-        raw_map_kv_result::<T>(self.cursor.set_key(key.encode().as_ref()))
+        raw_map_kv_result::<T>(
+            &self.dictionaries,
+            self.cursor.set_key(key.encode().as_ref()),
+        )
     }
 
     /// Position at the first key greater than or equal to the specified key.
     pub fn seek_range(&mut self, key: &T::Key) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.set_range(key.encode().as_ref()))
+        map_kv_result::<T>(
+            &self.dictionaries,
+            self.cursor.set_range(key.encode().as_ref()),
+        )
     }
 }
 
@@ -291,38 +273,38 @@ where
 {
     /// Position at the first item of the current key.
     pub fn first_dup(&mut self) -> MdbxResult<Option<T::Value>> {
-        Ok(self
-            .cursor
-            .first_dup::<TableObjectWrapper<_>>()?
-            .map(|d| d.0))
+        self.cursor
+            .first_dup::<Cow<'_, [u8]>>()?
+            .map(|d| compression::decode_value::<T>(&self.dictionaries, &d))
+            .transpose()
     }
 
     /// Position at the last item of the current key.
     pub fn last_dup(&mut self) -> MdbxResult<Option<T::Value>> {
-        Ok(self
-            .cursor
-            .last_dup::<TableObjectWrapper<_>>()?
-            .map(|d| d.0))
+        self.cursor
+            .last_dup::<Cow<'_, [u8]>>()?
+            .map(|d| compression::decode_value::<T>(&self.dictionaries, &d))
+            .transpose()
     }
 
     /// Position at the next item of the current key.
     pub fn next_dup(&mut self) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.next_dup())
+        map_kv_result::<T>(&self.dictionaries, self.cursor.next_dup())
     }
 
     /// Position at the first item of the next key.
     pub fn next_no_dup(&mut self) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.next_nodup())
+        map_kv_result::<T>(&self.dictionaries, self.cursor.next_nodup())
     }
 
     /// Position at the previous item of the current key.
     pub fn prev_dup(&mut self) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.prev_dup())
+        map_kv_result::<T>(&self.dictionaries, self.cursor.prev_dup())
     }
 
     /// Position at the first item of the previous key.
     pub fn prev_no_dup(&mut self) -> MdbxResult<Option<(T::Key, T::Value)>> {
-        map_kv_result::<T>(self.cursor.prev_nodup())
+        map_kv_result::<T>(&self.dictionaries, self.cursor.prev_nodup())
     }
 }
 
@@ -331,7 +313,7 @@ where
     T: Table,
 {
     pub fn put(&mut self, key: &T::Key, value: &T::Value) -> MdbxResult<()> {
-        let data = T::Value::encode_to_vec(value);
+        let data = compression::encode_value::<T>(&self.dictionaries, value);
         self.cursor
             .put(key.encode().as_ref(), &data, WriteFlags::default())?;
         Ok(())
@@ -348,7 +330,7 @@ where
     T: DupSortTable,
 {
     pub fn append_dup(&mut self, key: &T::Key, value: &T::Value) -> MdbxResult<()> {
-        let data = T::Value::encode_to_vec(value);
+        let data = compression::encode_value::<T>(&self.dictionaries, value);
         self.cursor
             .put(key.encode().as_ref(), &data, WriteFlags::APPEND_DUP)?;
         Ok(())
@@ -357,26 +339,30 @@ where
 
 #[allow(clippy::type_complexity)]
 fn map_kv_result<T>(
-    t: MdbxResult<Option<(TableKeyWrapper<T::Key>, TableObjectWrapper<T::Value>)>>,
+    dictionaries: &Dictionaries,
+    t: MdbxResult<Option<(TableKeyWrapper<T::Key>, Cow<'_, [u8]>)>>,
 ) -> MdbxResult<Option<(T::Key, T::Value)>>
 where
     T: Table,
 {
     if let Some((k, v)) = t? {
-        return Ok(Some((k.0, v.0)));
+        let v = compression::decode_value::<T>(dictionaries, &v)?;
+        return Ok(Some((k.0, v)));
     }
     Ok(None)
 }
 
 #[allow(clippy::type_complexity)]
 fn raw_map_kv_result<T>(
-    t: MdbxResult<Option<(TableKeyWrapper<T::Key>, RawTableObjectWrapper<T::Value>)>>,
+    dictionaries: &Dictionaries,
+    t: MdbxResult<Option<(TableKeyWrapper<T::Key>, Cow<'_, [u8]>)>>,
 ) -> MdbxResult<Option<(T::Key, RawMessageData<T::Value>)>>
 where
     T: Table,
 {
     if let Some((k, v)) = t? {
-        return Ok(Some((k.0, v.0)));
+        let v = compression::decompress(dictionaries, &v)?;
+        return Ok(Some((k.0, RawMessageData::from_vec(v.into_owned()))));
     }
     Ok(None)
 }
@@ -3,12 +3,17 @@
 //! This module provides all the abstractions over storage.
 mod chain_tracker;
 mod cli;
+mod compression;
 mod mdbx;
 mod message_storage;
 mod sequencer;
 mod table;
 
 pub use self::cli::default_data_dir;
+pub use self::compression::{
+    compression_dictionary_id, load_compression_dictionaries, recompress_table,
+    train_compression_dictionary, CompressionDictionary, CompressionError, TableCompression,
+};
 pub use self::mdbx::{
     MdbxEnvironmentExt, MdbxErrorExt, MdbxRWTransactionExt, MdbxTable, MdbxTransactionExt,
     TableCursor,
@@ -19,6 +24,7 @@ pub mod tables {
     pub use super::chain_tracker::{
         Block, BlockHash, BlockTable, CanonicalBlock, CanonicalBlockTable,
     };
+    pub use super::compression::CompressionDictionaryTable;
     pub use super::message_storage::MessageTable;
     pub use super::sequencer::{
         SequencerState, SequencerStateTable, StreamState, StreamStateTable,
@@ -7,6 +7,8 @@ use arrayvec::ArrayVec;
 use byteorder::{BigEndian, ReadBytesExt};
 use prost::Message;
 
+use super::compression::TableCompression;
+
 /// Error related to decoding keys.
 #[derive(Debug, thiserror::Error)]
 pub enum KeyDecodeError {
@@ -34,6 +36,11 @@ pub trait Table: Send + Sync {
     type Value: Message + Default + Clone;
 
     fn db_name() -> &'static str;
+
+    /// Compression used to store the table values.
+    fn compression() -> TableCompression {
+        TableCompression::None
+    }
 }
 
 pub trait DupSortTable: Table {}
@@ -134,6 +141,18 @@ impl TableKey for () {
     }
 }
 
+impl TableKey for String {
+    type Encoded = Vec<u8>;
+
+    fn encode(&self) -> Self::Encoded {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode(b: &[u8]) -> Result<Self, KeyDecodeError> {
+        String::from_utf8(b.to_vec()).map_err(|err| KeyDecodeError::Other(Box::new(err)))
+    }
+}
+
 impl TableKey for u64 {
     type Encoded = [u8; 8];
 
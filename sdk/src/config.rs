@@ -8,6 +8,8 @@ pub struct Configuration<F: Message + Default> {
     pub batch_size: u64,
     /// Starting cursor.
     pub starting_cursor: Option<Cursor>,
+    /// Ending cursor (inclusive).
+    pub ending_cursor: Option<Cursor>,
     /// Data finality.
     pub finality: Option<DataFinality>,
     /// The data filter.
@@ -28,6 +30,7 @@ where
         Self {
             batch_size,
             starting_cursor,
+            ending_cursor: None,
             finality,
             filter,
         }
@@ -54,6 +57,21 @@ where
         self
     }
 
+    /// Set the ending cursor. The stream ends after sending data for this cursor.
+    pub fn with_ending_cursor(mut self, cursor: Cursor) -> Self {
+        self.ending_cursor = Some(cursor);
+        self
+    }
+
+    /// Set the ending cursor to stop at the given block (inclusive).
+    pub fn with_ending_block(mut self, block_number: u64) -> Self {
+        self.ending_cursor = Some(Cursor {
+            order_key: block_number,
+            unique_key: vec![],
+        });
+        self
+    }
+
     /// Set the requested data finality.
     pub fn with_finality(mut self, finality: DataFinality) -> Self {
         self.finality = Some(finality);
@@ -78,6 +96,7 @@ where
         Self {
             batch_size: 1,
             starting_cursor: None,
+            ending_cursor: None,
             finality: None,
             filter: F::default(),
         }
@@ -111,6 +130,7 @@ mod tests {
         let config = Configuration::<Filter>::default()
             .with_batch_size(10)
             .with_starting_block(111)
+            .with_ending_block(222)
             .with_finality(DataFinality::DataStatusAccepted)
             .with_filter(|filter| {
                 filter
@@ -125,6 +145,7 @@ mod tests {
 
         assert_eq!(10, config.batch_size);
         assert_eq!(111, config.starting_cursor.unwrap().order_key);
+        assert_eq!(222, config.ending_cursor.unwrap().order_key);
         assert_eq!(DataFinality::DataStatusAccepted, config.finality.unwrap());
         assert_eq!(true, config.filter.header.unwrap().weak);
     }
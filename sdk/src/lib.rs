@@ -1,4 +1,5 @@
 pub mod config;
+pub mod normalized;
 
 use std::{
     marker::PhantomData,
@@ -7,8 +8,8 @@ use std::{
 };
 
 use apibara_core::node::v1alpha2::{
-    stream_client::StreamClient, stream_data_response, Cursor, DataFinality, StreamDataRequest,
-    StreamDataResponse,
+    stream_client::StreamClient, stream_data_response, Cursor, DataFinality, StatusRequest,
+    StreamDataRequest, StreamDataResponse,
 };
 use futures::Stream;
 use pin_project::pin_project;
@@ -16,14 +17,19 @@ use prost::Message;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{
-    metadata::{errors::InvalidMetadataValue, MetadataValue},
+    codec::CompressionEncoding,
+    codegen::InterceptedService,
+    metadata::{errors::InvalidMetadataValue, Ascii, MetadataValue},
+    service::Interceptor,
     transport::Channel,
     Streaming,
 };
 use tracing::debug;
 
-// Re-export tonic Uri
-pub use tonic::transport::Uri;
+// Re-export tonic types used to configure the client
+pub use tonic::transport::{Certificate, ClientTlsConfig, Identity, Uri};
+
+pub use apibara_core::node::v1alpha2::StatusResponse;
 
 pub use crate::config::Configuration;
 
@@ -62,6 +68,13 @@ pub enum DataMessage<D: Message + Default> {
         /// The cursor.
         cursor: Option<Cursor>,
     },
+    /// The stream reached the configured ending cursor.
+    ///
+    /// The server closes the stream after this message.
+    EndOfStream {
+        /// Cursor of the last data received.
+        cursor: Option<Cursor>,
+    },
 }
 
 /// Data stream builder.
@@ -75,6 +88,8 @@ where
 {
     token: Option<String>,
     configuration: Option<Configuration<F>>,
+    tls_config: Option<ClientTlsConfig>,
+    disable_compression: bool,
     _data: PhantomData<D>,
 }
 
@@ -97,6 +112,21 @@ where
 /// A client used to control a data stream.
 pub type DataStreamClient<F> = Sender<Configuration<F>>;
 
+/// Adds the bearer token, if any, to all requests.
+#[derive(Clone)]
+struct BearerTokenInterceptor {
+    token: Option<MetadataValue<Ascii>>,
+}
+
+impl Interceptor for BearerTokenInterceptor {
+    fn call(&mut self, mut req: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+        if let Some(token) = self.token.clone() {
+            req.metadata_mut().insert("authorization", token);
+        }
+        Ok(req)
+    }
+}
+
 impl<F, D> ClientBuilder<F, D>
 where
     F: Message + Default,
@@ -108,6 +138,25 @@ where
         self
     }
 
+    /// Connect to the server using TLS.
+    ///
+    /// Use [ClientTlsConfig::ca_certificate] to trust a self-signed server certificate and
+    /// [ClientTlsConfig::identity] to authenticate the client with mutual TLS.
+    pub fn with_tls_config(mut self, tls_config: ClientTlsConfig) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    /// Ask the server to send uncompressed responses.
+    ///
+    /// Responses are compressed with gzip by default, zstd is not supported.
+    /// Disabling compression reduces latency at the cost of bandwidth, for example when
+    /// streaming pending data.
+    pub fn without_compression(mut self) -> Self {
+        self.disable_compression = true;
+        self
+    }
+
     /// Send the given configuration upon connect.
     pub fn with_configuration(mut self, configuration: Configuration<F>) -> Self {
         self.configuration = Some(configuration);
@@ -122,16 +171,7 @@ where
         self,
         url: Uri,
     ) -> Result<(DataStream<F, D>, DataStreamClient<F>), ClientBuilderError> {
-        let channel = Channel::builder(url).connect().await?;
-
-        let mut default_client =
-            StreamClient::with_interceptor(channel, move |mut req: tonic::Request<()>| {
-                if let Some(token) = self.token.clone() {
-                    let token: MetadataValue<_> = format!("Bearer {token}").parse().unwrap();
-                    req.metadata_mut().insert("authorization", token);
-                }
-                Ok(req)
-            });
+        let mut default_client = self.stream_client(url).await?;
 
         let (configuration_tx, configuration_rx) = mpsc::channel(128);
         let (inner_tx, inner_rx) = mpsc::channel(128);
@@ -155,6 +195,41 @@ where
 
         Ok((stream, configuration_tx))
     }
+
+    /// Returns the ingestion status of the node at the given url.
+    ///
+    /// Use it to compute how far the node is behind the chain head, or to pick a starting cursor.
+    pub async fn status(&self, url: Uri) -> Result<StatusResponse, ClientBuilderError> {
+        let mut client = self.stream_client(url).await?;
+        let response = client.status(StatusRequest {}).await?;
+        Ok(response.into_inner())
+    }
+
+    async fn stream_client(
+        &self,
+        url: Uri,
+    ) -> Result<StreamClient<InterceptedService<Channel, BearerTokenInterceptor>>, ClientBuilderError>
+    {
+        let mut endpoint = Channel::builder(url);
+        if let Some(tls_config) = self.tls_config.clone() {
+            endpoint = endpoint.tls_config(tls_config)?;
+        }
+        let channel = endpoint.connect().await?;
+
+        let token = self
+            .token
+            .as_ref()
+            .map(|token| format!("Bearer {token}").parse())
+            .transpose()?;
+        let interceptor = BearerTokenInterceptor { token };
+
+        let client = StreamClient::with_interceptor(channel, interceptor);
+        if self.disable_compression {
+            Ok(client)
+        } else {
+            Ok(client.accept_compressed(CompressionEncoding::Gzip))
+        }
+    }
 }
 
 impl<F, D> Stream for DataStream<F, D>
@@ -173,8 +248,11 @@ where
                     stream_id: Some(self.stream_id),
                     batch_size: Some(configuration.batch_size),
                     starting_cursor: configuration.starting_cursor,
+                    ending_cursor: configuration.ending_cursor,
                     finality: configuration.finality.map(|f| f as i32),
                     filter: configuration.filter.encode_to_vec(),
+                    multiplex: false,
+                    cancel: false,
                 };
 
                 self.inner_tx.try_send(request)?;
@@ -220,6 +298,16 @@ where
                         };
                         Poll::Ready(Some(Ok(message)))
                     }
+                    Some(stream_data_response::Message::EndOfStream(end_of_stream)) => {
+                        let message = DataMessage::EndOfStream {
+                            cursor: end_of_stream.cursor,
+                        };
+                        Poll::Ready(Some(Ok(message)))
+                    }
+                    // only sent to multiplexed streams, which this client doesn't use.
+                    Some(stream_data_response::Message::Error(error)) => {
+                        Poll::Ready(Some(Err(error.message.into())))
+                    }
                     Some(stream_data_response::Message::Heartbeat(_)) => {
                         debug!("received heartbeat");
                         cx.waker().wake_by_ref();
@@ -0,0 +1,106 @@
+//! Helpers to work with normalized StarkNet blocks.
+//!
+//! When the filter is normalized, events and messages don't include their
+//! transaction and receipt. Instead, they reference the transactions in
+//! `Block.transactions` by transaction index.
+
+use std::collections::HashMap;
+
+use apibara_core::starknet::v1alpha2::{Block, Transaction, TransactionReceipt};
+
+/// Copies the transaction and receipt of each event and message from the block transactions.
+///
+/// After this call, the block has the same shape as blocks streamed with a filter that is
+/// not normalized. Notice that the block transactions still include the transactions
+/// referenced by events and messages.
+pub fn rehydrate_block(block: &mut Block) {
+    let transactions: HashMap<u64, (Option<Transaction>, Option<TransactionReceipt>)> = block
+        .transactions
+        .iter()
+        .map(|tx| {
+            (
+                tx.transaction_index,
+                (tx.transaction.clone(), tx.receipt.clone()),
+            )
+        })
+        .collect();
+
+    for event in &mut block.events {
+        if let Some((transaction, receipt)) = transactions.get(&event.transaction_index) {
+            event.transaction = event.transaction.take().or_else(|| transaction.clone());
+            event.receipt = event.receipt.take().or_else(|| receipt.clone());
+        }
+    }
+
+    for message in &mut block.l2_to_l1_messages {
+        if let Some((transaction, receipt)) = transactions.get(&message.transaction_index) {
+            message.transaction = message.transaction.take().or_else(|| transaction.clone());
+            message.receipt = message.receipt.take().or_else(|| receipt.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::starknet::v1alpha2::{
+        Block, Event, EventWithTransaction, FieldElement, Transaction, TransactionMeta,
+        TransactionReceipt, TransactionWithReceipt,
+    };
+
+    use super::rehydrate_block;
+
+    fn transaction(index: u64) -> TransactionWithReceipt {
+        let hash = FieldElement::from_u64(index);
+        TransactionWithReceipt {
+            transaction: Some(Transaction {
+                meta: Some(TransactionMeta {
+                    hash: Some(hash.clone()),
+                    ..TransactionMeta::default()
+                }),
+                transaction: None,
+            }),
+            receipt: Some(TransactionReceipt {
+                transaction_hash: Some(hash),
+                transaction_index: index,
+                ..TransactionReceipt::default()
+            }),
+            transaction_index: index,
+        }
+    }
+
+    fn event(index: u64) -> EventWithTransaction {
+        EventWithTransaction {
+            transaction: None,
+            receipt: None,
+            event: Some(Event::default()),
+            transaction_index: index,
+        }
+    }
+
+    #[test]
+    fn test_rehydrate_block() {
+        let mut block = Block {
+            transactions: vec![transaction(1), transaction(4)],
+            events: vec![event(4), event(1), event(4)],
+            ..Block::default()
+        };
+
+        rehydrate_block(&mut block);
+
+        let indices: Vec<_> = block
+            .events
+            .iter()
+            .map(|ev| ev.receipt.as_ref().unwrap().transaction_index)
+            .collect();
+        assert_eq!(indices, vec![4, 1, 4]);
+        for event in &block.events {
+            let hash = event
+                .transaction
+                .as_ref()
+                .and_then(|tx| tx.meta.as_ref())
+                .and_then(|meta| meta.hash.clone())
+                .unwrap();
+            assert_eq!(hash, FieldElement::from_u64(event.transaction_index));
+        }
+    }
+}
@@ -1,12 +1,19 @@
-use std::path::PathBuf;
+use std::{io, path::PathBuf};
 
 use anyhow::Result;
-use apibara_node::{db::default_data_dir, o11y::init_opentelemetry};
+use apibara_node::{
+    db::{default_data_dir, libmdbx::Environment, MdbxEnvironmentExt},
+    o11y::init_opentelemetry,
+};
 use apibara_starknet::{
-    server::{MetadataKeyRequestObserver, SimpleRequestObserver},
+    db::{migrate_database, rebuild_block_blooms, rebuild_contract_classes},
+    server::{
+        read_usage, write_usage_csv, write_usage_json, AllowAllAuthenticator,
+        KeysFileAuthenticator, MetadataKeyRequestObserver, SimpleRequestObserver, TlsConfig,
+    },
     HttpProvider, NoWriteMap, StarkNetNode,
 };
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use tokio_util::sync::CancellationToken;
 
 #[derive(Parser)]
@@ -20,13 +27,26 @@ struct Cli {
 enum CliCommand {
     /// Start the StarkNet source node.
     Start(StartCommand),
+    /// Inspect data usage per api key.
+    #[command(subcommand)]
+    Usage(UsageCommand),
+    /// Maintain the node database.
+    #[command(subcommand)]
+    Db(DbCommand),
+}
+
+#[derive(Subcommand)]
+enum DbCommand {
+    /// Compute the bloom filters of blocks ingested by older versions of the node.
+    RebuildBloom(DatabaseArgs),
+    /// Store the class hash of contracts deployed in blocks ingested by older versions of the node.
+    RebuildContractClass(DatabaseArgs),
+    /// Upgrade the database to the current format, in place.
+    Migrate(DatabaseArgs),
 }
 
 #[derive(Args)]
-struct StartCommand {
-    /// StarkNet RPC address.
-    #[arg(long, env)]
-    rpc: String,
+struct DatabaseArgs {
     /// Data directory. Defaults to `$XDG_DATA_HOME`.
     #[arg(long, env)]
     data: Option<PathBuf>,
@@ -35,23 +55,123 @@ struct StartCommand {
     name: Option<String>,
 }
 
+#[derive(Subcommand)]
+enum UsageCommand {
+    /// Export the usage ledger.
+    Export(UsageExportArgs),
+}
+
+#[derive(Args)]
+struct UsageExportArgs {
+    #[command(flatten)]
+    database: DatabaseArgs,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = UsageFormat::Csv)]
+    format: UsageFormat,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum UsageFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Args)]
+struct StartCommand {
+    /// StarkNet RPC address.
+    #[arg(long, env)]
+    rpc: String,
+    #[command(flatten)]
+    database: DatabaseArgs,
+    /// Address the stream server listens on.
+    #[arg(long, env, default_value = "0.0.0.0:7171")]
+    address: String,
+    /// PEM-encoded TLS certificate. Enables TLS when set.
+    #[arg(long, env, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM-encoded TLS private key.
+    #[arg(long, env, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// PEM-encoded CA certificate used to verify client certificates (mutual TLS).
+    #[arg(long, env, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+    /// JSON file with the api keys allowed to stream data.
+    ///
+    /// If not set, all requests are accepted.
+    #[arg(long, env)]
+    api_keys: Option<PathBuf>,
+    /// Address the http gateway listens on. The gateway streams data as server-sent events.
+    ///
+    /// The gateway is disabled if not set.
+    #[arg(long, env)]
+    http_address: Option<String>,
+    /// Address the admin server listens on. The admin server is disabled if not set.
+    #[arg(long, env, requires = "admin_token")]
+    admin_address: Option<String>,
+    /// Token used to authenticate admin requests.
+    #[arg(long, env, requires = "admin_address")]
+    admin_token: Option<String>,
+    /// PEM-encoded TLS certificate of the admin server. Enables TLS on the admin server when set.
+    #[arg(long, env, requires = "admin_tls_key")]
+    admin_tls_cert: Option<PathBuf>,
+    /// PEM-encoded TLS private key of the admin server.
+    #[arg(long, env, requires = "admin_tls_cert")]
+    admin_tls_key: Option<PathBuf>,
+    /// PEM-encoded CA certificate used to verify admin client certificates (mutual TLS).
+    #[arg(long, env, requires = "admin_tls_cert")]
+    admin_tls_client_ca: Option<PathBuf>,
+    /// Number of blocks whose data is cached in memory, shared by all streams.
+    #[arg(long, env)]
+    block_cache_size: Option<usize>,
+}
+
 async fn start(args: StartCommand) -> Result<()> {
     init_opentelemetry()?;
 
-    let mut node =
-        StarkNetNode::<HttpProvider, SimpleRequestObserver, NoWriteMap>::builder(&args.rpc)?
-            .with_request_observer(MetadataKeyRequestObserver::new("x-api-key".to_string()));
+    let authenticator = args.api_keys.map(KeysFileAuthenticator::new).transpose()?;
+
+    let mut node = StarkNetNode::<
+        HttpProvider,
+        SimpleRequestObserver,
+        AllowAllAuthenticator,
+        NoWriteMap,
+    >::builder(&args.rpc)?
+    .with_request_observer(MetadataKeyRequestObserver::new("x-api-key".to_string()))
+    .with_request_authenticator(authenticator);
 
     // give precedence to --data
-    if let Some(datadir) = args.data {
+    if let Some(datadir) = args.database.data {
         node.with_datadir(datadir);
-    } else if let Some(name) = args.name {
+    } else if let Some(name) = args.database.name {
         let datadir = default_data_dir()
             .map(|p| p.join(name))
             .expect("no datadir");
         node.with_datadir(datadir);
     }
 
+    node.with_server_address(args.address);
+
+    if let Some(tls_config) = tls_config(args.tls_cert, args.tls_key, args.tls_client_ca) {
+        node.with_tls_config(tls_config);
+    }
+
+    if let Some(http_address) = args.http_address {
+        node.with_http_address(http_address);
+    }
+
+    if let (Some(address), Some(token)) = (args.admin_address, args.admin_token) {
+        let tls_config = tls_config(
+            args.admin_tls_cert,
+            args.admin_tls_key,
+            args.admin_tls_client_ca,
+        );
+        node.with_admin(address, token, tls_config);
+    }
+
+    if let Some(block_cache_size) = args.block_cache_size {
+        node.with_block_cache_size(block_cache_size);
+    }
+
     // Setup cancellation for graceful shutdown
     let cts = CancellationToken::new();
     ctrlc::set_handler({
@@ -66,9 +186,83 @@ async fn start(args: StartCommand) -> Result<()> {
     Ok(())
 }
 
+impl DatabaseArgs {
+    fn datadir(self) -> PathBuf {
+        match (self.data, self.name) {
+            (Some(datadir), _) => datadir,
+            (None, name) => default_data_dir()
+                .map(|p| p.join(name.unwrap_or_else(|| "starknet".to_string())))
+                .expect("no datadir"),
+        }
+    }
+}
+
+/// Returns the TLS configuration if both the certificate and key are set.
+fn tls_config(
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    client_ca: Option<PathBuf>,
+) -> Option<TlsConfig> {
+    let (cert, key) = (cert?, key?);
+    let mut tls_config = TlsConfig::new(cert, key);
+    if let Some(client_ca) = client_ca {
+        tls_config = tls_config.with_client_ca(client_ca);
+    }
+    Some(tls_config)
+}
+
+fn usage_export(args: UsageExportArgs) -> Result<()> {
+    let datadir = args.database.datadir();
+
+    let db = Environment::<NoWriteMap>::builder().open(&datadir)?;
+    let records = read_usage(&db)?;
+
+    let stdout = io::stdout().lock();
+    match args.format {
+        UsageFormat::Csv => write_usage_csv(stdout, &records)?,
+        UsageFormat::Json => write_usage_json(stdout, &records)?,
+    }
+
+    Ok(())
+}
+
+fn db_rebuild_bloom(args: DatabaseArgs) -> Result<()> {
+    let datadir = args.datadir();
+
+    let db = Environment::<NoWriteMap>::builder().open(&datadir)?;
+    let processed = rebuild_block_blooms(&db)?;
+    println!("rebuilt bloom filters of {} blocks", processed);
+
+    Ok(())
+}
+
+fn db_rebuild_contract_class(args: DatabaseArgs) -> Result<()> {
+    let datadir = args.datadir();
+
+    let db = Environment::<NoWriteMap>::builder().open(&datadir)?;
+    let processed = rebuild_contract_classes(&db)?;
+    println!("rebuilt contract classes of {} blocks", processed);
+
+    Ok(())
+}
+
+fn db_migrate(args: DatabaseArgs) -> Result<()> {
+    let datadir = args.datadir();
+
+    let db = Environment::<NoWriteMap>::builder().open(&datadir)?;
+    let migrated = migrate_database(&db)?;
+    println!("migrated {} values", migrated);
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     match Cli::parse().command {
         CliCommand::Start(args) => start(args).await,
+        CliCommand::Usage(UsageCommand::Export(args)) => usage_export(args),
+        CliCommand::Db(DbCommand::RebuildBloom(args)) => db_rebuild_bloom(args),
+        CliCommand::Db(DbCommand::RebuildContractClass(args)) => db_rebuild_contract_class(args),
+        CliCommand::Db(DbCommand::Migrate(args)) => db_migrate(args),
     }
 }
@@ -22,6 +22,8 @@ pub enum IngestionMessage {
     /// Notice that the given root belongs to the new chain
     /// and is now the tip of it.
     Invalidate(GlobalBlockId),
+    /// Block data downloaded and stored again, replacing the data with the same id.
+    Reingested(GlobalBlockId),
 }
 
 #[derive(Debug, thiserror::Error)]
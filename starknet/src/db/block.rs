@@ -3,12 +3,20 @@
 use std::io::Cursor;
 
 use apibara_core::starknet::v1alpha2;
-use apibara_node::db::{KeyDecodeError, Table, TableKey};
+use apibara_node::db::{
+    libmdbx::{self, Environment, EnvironmentKind, RW},
+    KeyDecodeError, MdbxTransactionExt, Table, TableCursor, TableKey,
+};
 use byteorder::{BigEndian, ReadBytesExt};
 use prost::Message;
 
 use crate::core::{BlockHash, GlobalBlockId};
 
+use super::tables;
+
+/// Number of blocks processed in each transaction by [rebuild_from_blocks].
+const REBUILD_BATCH_SIZE: usize = 1_000;
+
 #[derive(Clone, PartialEq, Message)]
 pub struct BlockBody {
     #[prost(message, repeated, tag = "1")]
@@ -94,3 +102,75 @@ impl Table for BlockHeaderTable {
         "BlockHeader"
     }
 }
+
+/// Calls `update` with the data of all the blocks in the database, to rebuild table `T`.
+///
+/// Blocks are processed in batches, each batch in its own transaction.
+/// Returns the number of blocks processed.
+pub(crate) fn rebuild_from_blocks<E, T, F>(
+    db: &Environment<E>,
+    mut update: F,
+) -> Result<usize, libmdbx::Error>
+where
+    E: EnvironmentKind,
+    T: Table,
+    F: FnMut(
+        &mut TableCursor<'_, T, RW>,
+        &GlobalBlockId,
+        &[v1alpha2::Transaction],
+        &[v1alpha2::TransactionReceipt],
+        Option<&v1alpha2::StateUpdate>,
+    ) -> Result<(), libmdbx::Error>,
+{
+    let txn = db.begin_rw_txn()?;
+    tables::ensure(&txn)?;
+    txn.commit()?;
+
+    let mut processed = 0;
+    let mut next_block_id = None;
+    loop {
+        let txn = db.begin_rw_txn()?;
+        let mut body_cursor = txn.open_cursor::<tables::BlockBodyTable>()?;
+        let mut receipts_cursor = txn.open_cursor::<tables::BlockReceiptsTable>()?;
+        let mut state_update_cursor = txn.open_cursor::<tables::StateUpdateTable>()?;
+        let mut cursor = txn.open_cursor::<T>()?;
+
+        let mut item = match next_block_id {
+            None => body_cursor.first()?,
+            Some(block_id) => body_cursor.seek_range(&block_id)?,
+        };
+
+        let mut batch_size = 0;
+        while let Some((block_id, body)) = item {
+            if batch_size == REBUILD_BATCH_SIZE {
+                break;
+            }
+            let BlockBody { transactions } = body;
+            let receipts = receipts_cursor
+                .seek_exact(&block_id)?
+                .map(|t| t.1)
+                .unwrap_or_default();
+            let BlockReceipts { receipts } = receipts;
+            let state_update = state_update_cursor.seek_exact(&block_id)?.map(|t| t.1);
+
+            update(
+                &mut cursor,
+                &block_id,
+                &transactions,
+                &receipts,
+                state_update.as_ref(),
+            )?;
+
+            batch_size += 1;
+            item = body_cursor.next()?;
+        }
+
+        processed += batch_size;
+        next_block_id = item.map(|t| t.0);
+        txn.commit()?;
+
+        if next_block_id.is_none() {
+            return Ok(processed);
+        }
+    }
+}
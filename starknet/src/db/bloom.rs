@@ -0,0 +1,389 @@
+//! Per-block bloom filters.
+//!
+//! The bloom filter of a block contains the event emitters, event keys, transaction
+//! senders and contracts with storage changes of the block. Streams use it to skip
+//! blocks without data matching their filter.
+//!
+//! Filters are sized by the number of distinct values in the block. Filters stored
+//! by older versions of the node have a fixed size, they're still used but match
+//! more blocks; `db rebuild-bloom` recomputes them.
+
+use std::collections::HashSet;
+
+use apibara_core::starknet::v1alpha2;
+use apibara_node::db::{
+    libmdbx::{self, Environment, EnvironmentKind, RW},
+    Table, TableCursor,
+};
+use prost::Message;
+
+use crate::core::GlobalBlockId;
+
+use super::block::rebuild_from_blocks;
+
+/// Bits of the bloom filter for each value added to it.
+///
+/// With [BLOOM_HASHES] hashes, about 1% of the values not added match the filter.
+const BITS_PER_VALUE: usize = 10;
+
+/// Number of bits set by each value.
+const BLOOM_HASHES: u32 = 7;
+
+/// Smallest bloom filter size, in bits.
+const MIN_BLOOM_BITS: usize = 2048;
+
+/// Largest bloom filter size, in bits. Larger blocks have more false positives.
+const MAX_BLOOM_BITS: usize = 1 << 20;
+
+/// Size of the bloom filters stored before they were sized by the number of values.
+///
+/// These filters set three bits per value, see [legacy_bloom_bits].
+const LEGACY_BLOOM_BITS: usize = 2048;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Store block bloom filters.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockBloomTable {}
+
+/// Type of the values added to the bloom filter.
+///
+/// The same value is hashed differently for each type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BloomItem {
+    EventEmitter = 1,
+    EventKey = 2,
+    TransactionSender = 3,
+    StorageDiffContract = 4,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct BlockBloom {
+    #[prost(bytes = "vec", tag = "1")]
+    pub bits: Vec<u8>,
+    /// Number of bits set by each value. Zero for legacy filters.
+    #[prost(uint32, tag = "2")]
+    pub hashes: u32,
+}
+
+impl BlockBloom {
+    /// Returns an empty bloom filter sized for the given number of values.
+    pub fn with_capacity(values: usize) -> Self {
+        let size = values
+            .saturating_mul(BITS_PER_VALUE)
+            .clamp(MIN_BLOOM_BITS, MAX_BLOOM_BITS)
+            .next_power_of_two();
+        BlockBloom {
+            bits: vec![0; size / 8],
+            hashes: BLOOM_HASHES,
+        }
+    }
+
+    /// Returns the bloom filter of the block with the given data.
+    pub fn from_block(
+        transactions: &[v1alpha2::Transaction],
+        receipts: &[v1alpha2::TransactionReceipt],
+        state_update: Option<&v1alpha2::StateUpdate>,
+    ) -> Self {
+        use v1alpha2::transaction::Transaction;
+
+        // values repeat a lot (for example, the keys of transfer events), so count
+        // distinct values to size the filter.
+        let mut hashes = HashSet::new();
+
+        for transaction in transactions {
+            let sender = match transaction.transaction.as_ref() {
+                Some(Transaction::InvokeV0(tx)) => tx.contract_address.as_ref(),
+                Some(Transaction::InvokeV1(tx)) => tx.sender_address.as_ref(),
+                Some(Transaction::Declare(tx)) => tx.sender_address.as_ref(),
+                _ => None,
+            };
+            if let Some(sender) = sender {
+                hashes.insert(value_hash(BloomItem::TransactionSender, sender));
+            }
+        }
+
+        for receipt in receipts {
+            for event in &receipt.events {
+                if let Some(from_address) = event.from_address.as_ref() {
+                    hashes.insert(value_hash(BloomItem::EventEmitter, from_address));
+                }
+                for key in &event.keys {
+                    hashes.insert(value_hash(BloomItem::EventKey, key));
+                }
+            }
+        }
+
+        let state_diff = state_update.and_then(|update| update.state_diff.as_ref());
+        if let Some(state_diff) = state_diff {
+            for storage_diff in &state_diff.storage_diffs {
+                if let Some(contract_address) = storage_diff.contract_address.as_ref() {
+                    hashes.insert(value_hash(BloomItem::StorageDiffContract, contract_address));
+                }
+            }
+        }
+
+        let mut bloom = BlockBloom::with_capacity(hashes.len());
+        for hash in hashes {
+            bloom.insert_hash(hash);
+        }
+        bloom
+    }
+
+    /// Adds the value to the bloom filter.
+    pub fn insert(&mut self, item: BloomItem, value: &v1alpha2::FieldElement) {
+        self.insert_hash(value_hash(item, value));
+    }
+
+    /// Returns false if the value is certainly not in the bloom filter.
+    pub fn may_contain(&self, item: BloomItem, value: &v1alpha2::FieldElement) -> bool {
+        let hash = value_hash(item, value);
+        if self.hashes == 0 {
+            if self.bits.len() != LEGACY_BLOOM_BITS / 8 {
+                // malformed bloom filter, assume it contains everything.
+                return true;
+            }
+            return legacy_bloom_bits(hash)
+                .into_iter()
+                .all(|bit| self.is_set(bit));
+        }
+
+        if !self.is_well_formed() {
+            return true;
+        }
+        bloom_bits(hash, self.bits.len() * 8, self.hashes).all(|bit| self.is_set(bit))
+    }
+
+    fn insert_hash(&mut self, hash: u64) {
+        if !self.is_well_formed() {
+            return;
+        }
+        for bit in bloom_bits(hash, self.bits.len() * 8, self.hashes) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    fn is_well_formed(&self) -> bool {
+        let size = self.bits.len() * 8;
+        size.is_power_of_two() && size <= MAX_BLOOM_BITS && (1..=32).contains(&self.hashes)
+    }
+
+    fn is_set(&self, bit: usize) -> bool {
+        self.bits[bit / 8] & (1 << (bit % 8)) != 0
+    }
+}
+
+/// Returns the FNV-1a hash of the value.
+fn value_hash(item: BloomItem, value: &v1alpha2::FieldElement) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    let tag = [item as u8];
+    for byte in tag.iter().chain(value.to_bytes().iter()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Returns the bits set by the value with the given hash.
+///
+/// Bits are derived with double hashing from the two halves of the mixed hash.
+/// `size` must be a power of two.
+fn bloom_bits(hash: u64, size: usize, hashes: u32) -> impl Iterator<Item = usize> {
+    // spread the FNV hash to all bits, see the murmur3 finalizer.
+    let mut hash = hash;
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^= hash >> 33;
+
+    let h1 = hash & 0xffff_ffff;
+    // odd, so that all probes are different.
+    let h2 = (hash >> 32) | 1;
+    let mask = (size - 1) as u64;
+    (0..hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) & mask) as usize)
+}
+
+/// Returns the three bits set by the value in legacy bloom filters.
+fn legacy_bloom_bits(hash: u64) -> [usize; 3] {
+    let mask = (LEGACY_BLOOM_BITS - 1) as u64;
+    [
+        (hash & mask) as usize,
+        ((hash >> 16) & mask) as usize,
+        ((hash >> 32) & mask) as usize,
+    ]
+}
+
+impl Table for BlockBloomTable {
+    type Key = GlobalBlockId;
+    type Value = BlockBloom;
+
+    fn db_name() -> &'static str {
+        "BlockBloom"
+    }
+}
+
+/// Computes the bloom filter of all blocks stored in the database.
+///
+/// Used to add bloom filters to databases created before they were introduced.
+/// Returns the number of blocks processed.
+pub fn rebuild_block_blooms<E: EnvironmentKind>(
+    db: &Environment<E>,
+) -> Result<usize, libmdbx::Error> {
+    rebuild_from_blocks(
+        db,
+        |cursor: &mut TableCursor<'_, BlockBloomTable, RW>,
+         block_id,
+         transactions,
+         receipts,
+         state_update| {
+            let bloom = BlockBloom::from_block(transactions, receipts, state_update);
+            cursor.seek_exact(block_id)?;
+            cursor.put(block_id, &bloom)?;
+            Ok(())
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::starknet::v1alpha2;
+
+    use super::{legacy_bloom_bits, value_hash, BlockBloom, BloomItem, MIN_BLOOM_BITS};
+
+    fn felt(value: u64) -> v1alpha2::FieldElement {
+        v1alpha2::FieldElement::from_u64(value)
+    }
+
+    /// Returns receipts with one event for each key.
+    fn receipts_with_keys(keys: impl Iterator<Item = u64>) -> Vec<v1alpha2::TransactionReceipt> {
+        let events = keys
+            .map(|key| v1alpha2::Event {
+                from_address: Some(felt(1)),
+                keys: vec![felt(key)],
+                data: Vec::default(),
+            })
+            .collect();
+        vec![v1alpha2::TransactionReceipt {
+            events,
+            ..Default::default()
+        }]
+    }
+
+    #[test]
+    fn test_insert_and_may_contain() {
+        let mut bloom = BlockBloom::with_capacity(10);
+        assert_eq!(bloom.bits.len() * 8, MIN_BLOOM_BITS);
+        for value in 0..10 {
+            assert!(!bloom.may_contain(BloomItem::EventKey, &felt(value)));
+        }
+
+        bloom.insert(BloomItem::EventKey, &felt(1));
+        bloom.insert(BloomItem::EventEmitter, &felt(2));
+        assert!(bloom.may_contain(BloomItem::EventKey, &felt(1)));
+        assert!(bloom.may_contain(BloomItem::EventEmitter, &felt(2)));
+        // the same value is hashed differently for each item type.
+        assert!(!bloom.may_contain(BloomItem::EventEmitter, &felt(1)));
+        assert!(!bloom.may_contain(BloomItem::TransactionSender, &felt(2)));
+        assert!(!bloom.may_contain(BloomItem::EventKey, &felt(3)));
+    }
+
+    #[test]
+    fn test_from_block() {
+        let transactions = vec![v1alpha2::Transaction {
+            transaction: Some(v1alpha2::transaction::Transaction::InvokeV1(
+                v1alpha2::InvokeTransactionV1 {
+                    sender_address: Some(felt(10)),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        }];
+        let receipts = receipts_with_keys([20, 21].into_iter());
+        let state_update = v1alpha2::StateUpdate {
+            state_diff: Some(v1alpha2::StateDiff {
+                storage_diffs: vec![v1alpha2::StorageDiff {
+                    contract_address: Some(felt(30)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let bloom = BlockBloom::from_block(&transactions, &receipts, Some(&state_update));
+        assert!(bloom.may_contain(BloomItem::TransactionSender, &felt(10)));
+        assert!(bloom.may_contain(BloomItem::EventEmitter, &felt(1)));
+        assert!(bloom.may_contain(BloomItem::EventKey, &felt(20)));
+        assert!(bloom.may_contain(BloomItem::EventKey, &felt(21)));
+        assert!(bloom.may_contain(BloomItem::StorageDiffContract, &felt(30)));
+        assert!(!bloom.may_contain(BloomItem::StorageDiffContract, &felt(10)));
+    }
+
+    #[test]
+    fn test_sized_by_distinct_values() {
+        // repeated values are counted once.
+        let receipts = receipts_with_keys((0..5_000).map(|i| i % 10));
+        let bloom = BlockBloom::from_block(&[], &receipts, None);
+        assert_eq!(bloom.bits.len() * 8, MIN_BLOOM_BITS);
+
+        let receipts = receipts_with_keys(0..5_000);
+        let bloom = BlockBloom::from_block(&[], &receipts, None);
+        assert!(bloom.bits.len() * 8 >= 5_000 * 10);
+        assert!(bloom.bits.len().is_power_of_two());
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        // a busy block, that saturated the fixed size filters.
+        let receipts = receipts_with_keys(0..5_000);
+        let bloom = BlockBloom::from_block(&[], &receipts, None);
+        for key in 0..5_000 {
+            assert!(bloom.may_contain(BloomItem::EventKey, &felt(key)));
+        }
+
+        let false_positives = (1_000_000..1_010_000)
+            .filter(|key| bloom.may_contain(BloomItem::EventKey, &felt(*key)))
+            .count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_legacy_bloom() {
+        let mut bloom = BlockBloom {
+            bits: vec![0; 256],
+            hashes: 0,
+        };
+        for bit in legacy_bloom_bits(value_hash(BloomItem::EventKey, &felt(1))) {
+            bloom.bits[bit / 8] |= 1 << (bit % 8);
+        }
+        assert!(bloom.may_contain(BloomItem::EventKey, &felt(1)));
+        assert!(!bloom.may_contain(BloomItem::EventKey, &felt(2)));
+    }
+
+    #[test]
+    fn test_malformed_bloom_contains_everything() {
+        let malformed = [
+            BlockBloom {
+                bits: vec![0; 100],
+                hashes: 0,
+            },
+            BlockBloom {
+                bits: vec![0; 100],
+                hashes: 7,
+            },
+            BlockBloom {
+                bits: Vec::default(),
+                hashes: 7,
+            },
+            BlockBloom {
+                bits: vec![0; 256],
+                hashes: 1_000,
+            },
+        ];
+        for bloom in malformed {
+            assert!(bloom.may_contain(BloomItem::EventKey, &felt(1)));
+        }
+    }
+}
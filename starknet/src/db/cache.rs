@@ -0,0 +1,696 @@
+//! Cache decoded block data shared by all streams.
+//!
+//! Entries are keyed by block number and hash, so data of blocks removed from
+//! the canonical chain is never returned for the blocks that replaced them.
+//! Invalidating the cache on chain reorganizations only frees memory early.
+//!
+//! Blocks re-ingested by the admin service keep their id, so they must be
+//! removed from the cache with [BlockCache::remove].
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use apibara_core::starknet::v1alpha2;
+use apibara_node::{
+    db::TableKey,
+    o11y::{self, Counter, KeyValue},
+};
+
+use crate::core::GlobalBlockId;
+
+use super::{bloom::BlockBloom, BlockParts, StorageReader, StoredBlock};
+
+/// Default number of blocks kept in the cache.
+pub const DEFAULT_BLOCK_CACHE_SIZE: usize = 256;
+
+/// A [StorageReader] that caches block headers, bodies, receipts and state updates.
+pub struct CachedStorage<R: StorageReader> {
+    inner: R,
+    cache: BlockCache,
+}
+
+/// A bounded, least recently used, cache of decoded block data.
+///
+/// Clones share the same cache.
+#[derive(Clone)]
+pub struct BlockCache {
+    state: Arc<Mutex<BlockCacheState>>,
+    hits: Counter<u64>,
+    misses: Counter<u64>,
+}
+
+struct BlockCacheState {
+    capacity: usize,
+    tick: u64,
+    /// Incremented every time blocks are removed.
+    generation: u64,
+    blocks: HashMap<<GlobalBlockId as TableKey>::Encoded, CachedBlock>,
+    by_last_use: BTreeMap<u64, <GlobalBlockId as TableKey>::Encoded>,
+}
+
+/// Cached data is shared with readers, so that a cache hit never copies it.
+struct CachedBlock {
+    number: u64,
+    last_use: u64,
+    header: Option<Option<v1alpha2::BlockHeader>>,
+    body: Option<Arc<[v1alpha2::Transaction]>>,
+    receipts: Option<Arc<[v1alpha2::TransactionReceipt]>>,
+    state_update: Option<Option<Arc<v1alpha2::StateUpdate>>>,
+}
+
+/// The cached parts of a block, see [BlockCache::get_parts].
+///
+/// Parts that were not requested are empty.
+struct CachedParts {
+    header: Option<v1alpha2::BlockHeader>,
+    transactions: Arc<[v1alpha2::Transaction]>,
+    receipts: Arc<[v1alpha2::TransactionReceipt]>,
+    state_update: Option<Arc<v1alpha2::StateUpdate>>,
+}
+
+impl<R: StorageReader> CachedStorage<R> {
+    pub fn new(inner: R, capacity: usize) -> Self {
+        let cache = BlockCache::new(capacity);
+        CachedStorage { inner, cache }
+    }
+
+    /// Returns a handle to the cache, used to invalidate it.
+    pub fn cache(&self) -> BlockCache {
+        self.cache.clone()
+    }
+}
+
+/// Returns true if the block data can be cached.
+///
+/// Pending blocks are updated in place, so they are never cached. They are the
+/// only blocks without a hash.
+fn is_cacheable(id: &GlobalBlockId) -> bool {
+    !id.hash().is_zero()
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        let state = BlockCacheState {
+            capacity,
+            tick: 0,
+            generation: 0,
+            blocks: HashMap::default(),
+            by_last_use: BTreeMap::default(),
+        };
+        let meter = o11y::meter("block_cache");
+        BlockCache {
+            state: Arc::new(Mutex::new(state)),
+            hits: meter.u64_counter("hit").init(),
+            misses: meter.u64_counter("miss").init(),
+        }
+    }
+
+    /// Removes all blocks after the given new chain root.
+    pub fn invalidate(&self, new_chain_root: &GlobalBlockId) {
+        let mut state = self.state.lock().expect("block cache lock poisoned");
+        let number = new_chain_root.number();
+        state.remove_where(|block| block.number > number);
+        state.generation += 1;
+    }
+
+    /// Removes the given block.
+    pub fn remove(&self, id: &GlobalBlockId) {
+        let mut state = self.state.lock().expect("block cache lock poisoned");
+        if let Some(block) = state.blocks.remove(&id.encode()) {
+            state.by_last_use.remove(&block.last_use);
+        }
+        state.generation += 1;
+    }
+
+    /// Removes all blocks.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().expect("block cache lock poisoned");
+        state.blocks.clear();
+        state.by_last_use.clear();
+        state.generation += 1;
+    }
+
+    /// Returns the current generation, to be passed to [BlockCache::insert].
+    fn generation(&self) -> u64 {
+        self.state
+            .lock()
+            .expect("block cache lock poisoned")
+            .generation
+    }
+
+    /// Returns the cached value selected by `select`, tracking the cache hit or miss.
+    fn get<T: Clone>(
+        &self,
+        datum: &'static str,
+        id: &GlobalBlockId,
+        select: impl FnOnce(&CachedBlock) -> Option<&T>,
+    ) -> Option<T> {
+        let value = {
+            let mut state = self.state.lock().expect("block cache lock poisoned");
+            state.touch(id).and_then(select).cloned()
+        };
+        self.record(datum, value.is_some());
+        value
+    }
+
+    /// Returns the given parts of the block, if they're all cached.
+    fn get_parts(&self, id: &GlobalBlockId, parts: BlockParts) -> Option<CachedParts> {
+        let value = {
+            let mut state = self.state.lock().expect("block cache lock poisoned");
+            state.touch(id).and_then(|block| block.parts(parts))
+        };
+        self.record("block", value.is_some());
+        value
+    }
+
+    fn record(&self, datum: &'static str, hit: bool) {
+        let cx = o11y::Context::current();
+        let attributes = [KeyValue::new("datum", datum)];
+        if hit {
+            self.hits.add(&cx, 1, &attributes);
+        } else {
+            self.misses.add(&cx, 1, &attributes);
+        }
+    }
+
+    /// Updates the cached block with `update`, evicting the least recently used blocks if needed.
+    ///
+    /// Nothing is cached if blocks were removed since `generation`, since the
+    /// data read from storage may be the data that was just removed.
+    fn insert(&self, id: &GlobalBlockId, generation: u64, update: impl FnOnce(&mut CachedBlock)) {
+        let mut state = self.state.lock().expect("block cache lock poisoned");
+        if state.capacity == 0 || state.generation != generation {
+            return;
+        }
+
+        if state.touch(id).is_none() {
+            while state.blocks.len() >= state.capacity {
+                state.evict_least_recently_used();
+            }
+            state.tick += 1;
+            let block = CachedBlock {
+                number: id.number(),
+                last_use: state.tick,
+                header: None,
+                body: None,
+                receipts: None,
+                state_update: None,
+            };
+            let key = id.encode();
+            state.by_last_use.insert(block.last_use, key);
+            state.blocks.insert(key, block);
+        }
+
+        if let Some(block) = state.blocks.get_mut(&id.encode()) {
+            update(block);
+        }
+    }
+}
+
+impl CachedBlock {
+    fn parts(&self, parts: BlockParts) -> Option<CachedParts> {
+        let header = if parts.header {
+            self.header.clone()?
+        } else {
+            None
+        };
+        let (transactions, receipts) = if parts.transactions {
+            (self.body.clone()?, self.receipts.clone()?)
+        } else {
+            (Vec::default().into(), Vec::default().into())
+        };
+        let state_update = if parts.state_update {
+            self.state_update.clone()?
+        } else {
+            None
+        };
+        Some(CachedParts {
+            header,
+            transactions,
+            receipts,
+            state_update,
+        })
+    }
+
+    fn set_parts(&mut self, block: &StoredBlock, parts: BlockParts) {
+        if parts.header {
+            self.header = Some(block.header.clone());
+        }
+        if parts.transactions {
+            self.body = Some(block.transactions.clone());
+            self.receipts = Some(block.receipts.clone());
+        }
+        if parts.state_update {
+            self.state_update = Some(block.state_update.clone());
+        }
+    }
+}
+
+impl BlockCacheState {
+    /// Marks the block as the most recently used and returns it.
+    fn touch(&mut self, id: &GlobalBlockId) -> Option<&CachedBlock> {
+        self.tick += 1;
+        let tick = self.tick;
+        let key = id.encode();
+        let block = self.blocks.get_mut(&key)?;
+        self.by_last_use.remove(&block.last_use);
+        block.last_use = tick;
+        self.by_last_use.insert(tick, key);
+        Some(block)
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let least_recently_used = self.by_last_use.keys().next().copied();
+        if let Some(last_use) = least_recently_used {
+            if let Some(key) = self.by_last_use.remove(&last_use) {
+                self.blocks.remove(&key);
+            }
+        }
+    }
+
+    fn remove_where(&mut self, predicate: impl Fn(&CachedBlock) -> bool) {
+        let by_last_use = &mut self.by_last_use;
+        self.blocks.retain(|_, block| {
+            if predicate(block) {
+                by_last_use.remove(&block.last_use);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+impl<R: StorageReader> StorageReader for CachedStorage<R> {
+    type Error = R::Error;
+    type Snapshot<'a>
+        = CachedStorage<R::Snapshot<'a>>
+    where
+        Self: 'a;
+
+    fn snapshot(&self) -> Result<Self::Snapshot<'_>, Self::Error> {
+        Ok(CachedStorage {
+            inner: self.inner.snapshot()?,
+            cache: self.cache.clone(),
+        })
+    }
+
+    fn highest_accepted_block(&self) -> Result<Option<GlobalBlockId>, Self::Error> {
+        self.inner.highest_accepted_block()
+    }
+
+    fn highest_finalized_block(&self) -> Result<Option<GlobalBlockId>, Self::Error> {
+        self.inner.highest_finalized_block()
+    }
+
+    fn canonical_block_id(&self, number: u64) -> Result<Option<GlobalBlockId>, Self::Error> {
+        self.inner.canonical_block_id(number)
+    }
+
+    fn read_status(
+        &self,
+        id: &GlobalBlockId,
+    ) -> Result<Option<v1alpha2::BlockStatus>, Self::Error> {
+        self.inner.read_status(id)
+    }
+
+    fn read_header(
+        &self,
+        id: &GlobalBlockId,
+    ) -> Result<Option<v1alpha2::BlockHeader>, Self::Error> {
+        self.inner.read_header(id)
+    }
+
+    fn read_body(&self, id: &GlobalBlockId) -> Result<Arc<[v1alpha2::Transaction]>, Self::Error> {
+        if let Some(body) = self.cache.get("body", id, |block| block.body.as_ref()) {
+            return Ok(body);
+        }
+
+        let generation = self.cache.generation();
+        let body = self.inner.read_body(id)?;
+        if is_cacheable(id) {
+            self.cache
+                .insert(id, generation, |block| block.body = Some(body.clone()));
+        }
+        Ok(body)
+    }
+
+    fn read_receipts(
+        &self,
+        id: &GlobalBlockId,
+    ) -> Result<Arc<[v1alpha2::TransactionReceipt]>, Self::Error> {
+        if let Some(receipts) = self
+            .cache
+            .get("receipts", id, |block| block.receipts.as_ref())
+        {
+            return Ok(receipts);
+        }
+
+        let generation = self.cache.generation();
+        let receipts = self.inner.read_receipts(id)?;
+        if is_cacheable(id) {
+            self.cache.insert(id, generation, |block| {
+                block.receipts = Some(receipts.clone())
+            });
+        }
+        Ok(receipts)
+    }
+
+    fn read_state_update(
+        &self,
+        id: &GlobalBlockId,
+    ) -> Result<Option<Arc<v1alpha2::StateUpdate>>, Self::Error> {
+        if let Some(state_update) = self
+            .cache
+            .get("state_update", id, |block| block.state_update.as_ref())
+        {
+            return Ok(state_update);
+        }
+
+        let generation = self.cache.generation();
+        let state_update = self.inner.read_state_update(id)?;
+        if is_cacheable(id) {
+            self.cache.insert(id, generation, |block| {
+                block.state_update = Some(state_update.clone())
+            });
+        }
+        Ok(state_update)
+    }
+
+    fn read_contract_class_hash(
+        &self,
+        address: &v1alpha2::FieldElement,
+    ) -> Result<Option<v1alpha2::FieldElement>, Self::Error> {
+        self.inner.read_contract_class_hash(address)
+    }
+
+    fn read_block_bloom(&self, id: &GlobalBlockId) -> Result<Option<BlockBloom>, Self::Error> {
+        self.inner.read_block_bloom(id)
+    }
+
+    fn indexed_from_block(&self) -> Result<Option<u64>, Self::Error> {
+        self.inner.indexed_from_block()
+    }
+
+    fn next_event_block(
+        &self,
+        from_address: &v1alpha2::FieldElement,
+        key: &v1alpha2::FieldElement,
+        start: u64,
+    ) -> Result<Option<u64>, Self::Error> {
+        self.inner.next_event_block(from_address, key, start)
+    }
+
+    fn next_message_block(
+        &self,
+        to_address: &v1alpha2::FieldElement,
+        start: u64,
+    ) -> Result<Option<u64>, Self::Error> {
+        self.inner.next_message_block(to_address, start)
+    }
+
+    fn read_block_range<F>(
+        &self,
+        start: u64,
+        end: u64,
+        parts: BlockParts,
+        mut include: F,
+    ) -> Result<Vec<StoredBlock>, Self::Error>
+    where
+        F: FnMut(&GlobalBlockId, Option<&BlockBloom>) -> bool,
+    {
+        if parts == BlockParts::default() {
+            return self.inner.read_block_range(start, end, parts, include);
+        }
+
+        // only read the data of the blocks that are not cached. the inner reader
+        // still returns their id and status.
+        let generation = self.cache.generation();
+        let mut cached = HashMap::new();
+        let mut read = HashSet::new();
+        let mut blocks = self
+            .inner
+            .read_block_range(start, end, parts, |id, bloom| {
+                if !include(id, bloom) {
+                    return false;
+                }
+                if is_cacheable(id) {
+                    if let Some(data) = self.cache.get_parts(id, parts) {
+                        cached.insert(id.number(), data);
+                        return false;
+                    }
+                }
+                read.insert(id.number());
+                true
+            })?;
+
+        for block in &mut blocks {
+            if let Some(data) = cached.remove(&block.id.number()) {
+                block.header = data.header;
+                block.transactions = data.transactions;
+                block.receipts = data.receipts;
+                block.state_update = data.state_update;
+            } else if read.contains(&block.id.number()) && is_cacheable(&block.id) {
+                self.cache
+                    .insert(&block.id, generation, |entry| entry.set_parts(block, parts));
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use apibara_core::starknet::v1alpha2;
+
+    use crate::{
+        core::{BlockHash, GlobalBlockId},
+        db::{
+            test_utils::{block_id, open_test_db, open_test_storage, write_test_block},
+            BlockBody, BlockParts, DatabaseStorage, StorageReader, StorageWriter,
+        },
+    };
+
+    use super::{BlockCache, CachedStorage};
+
+    fn transaction(value: u64) -> v1alpha2::Transaction {
+        v1alpha2::Transaction {
+            meta: Some(v1alpha2::TransactionMeta {
+                hash: Some(v1alpha2::FieldElement::from_u64(value)),
+                ..Default::default()
+            }),
+            transaction: None,
+        }
+    }
+
+    fn insert_body(cache: &BlockCache, id: &GlobalBlockId, value: u64) {
+        let generation = cache.generation();
+        cache.insert(id, generation, |block| {
+            block.body = Some(vec![transaction(value)].into())
+        });
+    }
+
+    fn cached_body(cache: &BlockCache, id: &GlobalBlockId) -> Option<Vec<v1alpha2::Transaction>> {
+        cache
+            .get("body", id, |block| block.body.as_ref())
+            .map(|body| body.to_vec())
+    }
+
+    #[test]
+    fn test_get_and_insert() {
+        let cache = BlockCache::new(4);
+        let id = block_id(1);
+        assert!(cached_body(&cache, &id).is_none());
+
+        insert_body(&cache, &id, 1);
+        assert_eq!(cached_body(&cache, &id), Some(vec![transaction(1)]));
+        // other data of the same block is not cached yet.
+        assert!(cache
+            .get("receipts", &id, |block| block.receipts.as_ref())
+            .is_none());
+        // blocks with the same number but different hash are different blocks.
+        let other_id = GlobalBlockId::new(1, BlockHash::zero());
+        assert!(cached_body(&cache, &other_id).is_none());
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let cache = BlockCache::new(3);
+        for number in 0..3 {
+            insert_body(&cache, &block_id(number), number);
+        }
+
+        // block 0 becomes the most recently used, so block 1 is evicted.
+        assert!(cached_body(&cache, &block_id(0)).is_some());
+        insert_body(&cache, &block_id(3), 3);
+
+        assert!(cached_body(&cache, &block_id(0)).is_some());
+        assert!(cached_body(&cache, &block_id(1)).is_none());
+        assert!(cached_body(&cache, &block_id(2)).is_some());
+        assert!(cached_body(&cache, &block_id(3)).is_some());
+    }
+
+    #[test]
+    fn test_zero_capacity() {
+        let cache = BlockCache::new(0);
+        insert_body(&cache, &block_id(0), 0);
+        assert!(cached_body(&cache, &block_id(0)).is_none());
+    }
+
+    #[test]
+    fn test_invalidate_remove_and_clear() {
+        let cache = BlockCache::new(10);
+        for number in 0..5 {
+            insert_body(&cache, &block_id(number), number);
+        }
+
+        cache.invalidate(&block_id(2));
+        for number in 0..=2 {
+            assert!(cached_body(&cache, &block_id(number)).is_some());
+        }
+        for number in 3..5 {
+            assert!(cached_body(&cache, &block_id(number)).is_none());
+        }
+
+        cache.remove(&block_id(1));
+        assert!(cached_body(&cache, &block_id(1)).is_none());
+        assert!(cached_body(&cache, &block_id(0)).is_some());
+
+        cache.clear();
+        assert!(cached_body(&cache, &block_id(0)).is_none());
+        assert!(cached_body(&cache, &block_id(2)).is_none());
+    }
+
+    #[test]
+    fn test_insert_after_remove_is_ignored() {
+        let cache = BlockCache::new(10);
+        let id = block_id(1);
+        // data read before the block is removed may be stale.
+        let generation = cache.generation();
+        cache.remove(&id);
+        cache.insert(&id, generation, |block| {
+            block.body = Some(vec![transaction(1)].into())
+        });
+        assert!(cached_body(&cache, &id).is_none());
+    }
+
+    #[test]
+    fn test_cached_storage() {
+        let (_path, db) = open_test_db();
+        let db_storage = DatabaseStorage::new(db.clone());
+        let write_body = |id: &GlobalBlockId, value: u64| {
+            let mut txn = db_storage.begin_txn().unwrap();
+            let body = BlockBody {
+                transactions: vec![transaction(value)],
+            };
+            txn.write_body(id, body).unwrap();
+            txn.commit().unwrap();
+        };
+
+        let id = block_id(1);
+        let pending_id = GlobalBlockId::new(2, BlockHash::zero());
+        write_body(&id, 1);
+        write_body(&pending_id, 1);
+
+        let storage = CachedStorage::new(DatabaseStorage::new(db), 10);
+        let body = storage.read_body(&id).unwrap();
+        assert_eq!(body.to_vec(), vec![transaction(1)]);
+        // cache hits share the cached data.
+        assert!(Arc::ptr_eq(&body, &storage.read_body(&id).unwrap()));
+        assert_eq!(
+            storage.read_body(&pending_id).unwrap().to_vec(),
+            vec![transaction(1)]
+        );
+
+        // re-ingested in place.
+        write_body(&id, 2);
+        write_body(&pending_id, 2);
+
+        // pending blocks are never cached.
+        assert_eq!(
+            storage.read_body(&pending_id).unwrap().to_vec(),
+            vec![transaction(2)]
+        );
+        assert_eq!(
+            storage.read_body(&id).unwrap().to_vec(),
+            vec![transaction(1)]
+        );
+        storage.cache().remove(&id);
+        assert_eq!(
+            storage.read_body(&id).unwrap().to_vec(),
+            vec![transaction(2)]
+        );
+    }
+
+    #[test]
+    fn test_cached_block_range() {
+        let (_path, db_storage) = open_test_storage();
+        let write_block = |number: u64| {
+            let id = write_test_block(&db_storage, number, v1alpha2::BlockStatus::AcceptedOnL1);
+            let mut txn = db_storage.begin_txn().unwrap();
+            let body = BlockBody {
+                transactions: vec![transaction(number)],
+            };
+            txn.write_body(&id, body).unwrap();
+            txn.commit().unwrap();
+        };
+        for number in 0..3 {
+            write_block(number);
+        }
+
+        let storage = CachedStorage::new(db_storage.clone(), 10);
+        let parts = BlockParts {
+            header: true,
+            transactions: true,
+            state_update: false,
+        };
+        // block 1 data is not read, so it's not cached.
+        let first = storage
+            .read_block_range(0, 2, parts, |id, _| id.number() != 1)
+            .unwrap();
+        assert_eq!(first.len(), 3);
+        assert_eq!(first[0].transactions.to_vec(), vec![transaction(0)]);
+        assert!(first[1].transactions.is_empty());
+        assert!(first[1].header.is_none());
+
+        let second = storage.read_block_range(0, 2, parts, |_, _| true).unwrap();
+        for (number, block) in second.iter().enumerate() {
+            assert_eq!(block.id, block_id(number as u64));
+            assert_eq!(
+                block.transactions.to_vec(),
+                vec![transaction(number as u64)]
+            );
+            assert_eq!(
+                block.header.as_ref().map(|h| h.block_number),
+                Some(number as u64)
+            );
+        }
+        // cache hits share the cached data.
+        assert!(Arc::ptr_eq(&first[0].transactions, &second[0].transactions));
+        assert!(Arc::ptr_eq(&first[2].transactions, &second[2].transactions));
+
+        // a snapshot doesn't see blocks written after it was taken, but still
+        // uses the cache.
+        let snapshot = storage.snapshot().unwrap();
+        // mdbx doesn't allow read and write transactions on the same thread.
+        std::thread::scope(|scope| {
+            scope.spawn(|| write_block(3)).join().unwrap();
+        });
+        let blocks = snapshot.read_block_range(0, 3, parts, |_, _| true).unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert!(Arc::ptr_eq(
+            &second[1].transactions,
+            &blocks[1].transactions
+        ));
+        assert!(snapshot.canonical_block_id(3).unwrap().is_none());
+        drop(snapshot);
+
+        let blocks = storage.read_block_range(0, 3, parts, |_, _| true).unwrap();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[3].transactions.to_vec(), vec![transaction(3)]);
+    }
+}
@@ -0,0 +1,259 @@
+//! Contract data.
+//!
+//! Ingestion stores the class hash of the contracts deployed in each block. Databases
+//! ingested by older versions of the node don't have the contracts deployed before
+//! the upgrade, `db rebuild-contract-class` adds them.
+
+use apibara_core::starknet::v1alpha2;
+use apibara_node::db::{
+    libmdbx::{self, Environment, EnvironmentKind, RW},
+    KeyDecodeError, Table, TableCursor, TableKey,
+};
+use prost::Message;
+
+use super::block::rebuild_from_blocks;
+
+/// Address of a contract, used as table key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContractAddress([u8; 32]);
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ContractClass {
+    #[prost(message, optional, tag = "1")]
+    pub class_hash: Option<v1alpha2::FieldElement>,
+}
+
+/// Store the class hash of each deployed contract.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContractClassTable {}
+
+impl From<&v1alpha2::FieldElement> for ContractAddress {
+    fn from(address: &v1alpha2::FieldElement) -> Self {
+        ContractAddress(address.to_bytes())
+    }
+}
+
+impl TableKey for ContractAddress {
+    type Encoded = [u8; 32];
+
+    fn encode(&self) -> Self::Encoded {
+        self.0
+    }
+
+    fn decode(b: &[u8]) -> Result<Self, KeyDecodeError> {
+        let bytes = b.try_into().map_err(|_| KeyDecodeError::InvalidByteSize {
+            expected: 32,
+            actual: b.len(),
+        })?;
+        Ok(ContractAddress(bytes))
+    }
+}
+
+impl Table for ContractClassTable {
+    type Key = ContractAddress;
+    type Value = ContractClass;
+
+    fn db_name() -> &'static str {
+        "ContractClass"
+    }
+}
+
+/// Returns the address and class hash of the contracts deployed in the block.
+///
+/// Contracts are deployed by deploy and deploy account transactions, and are listed
+/// in the state diff.
+pub fn deployed_contracts(
+    transactions: &[v1alpha2::Transaction],
+    receipts: &[v1alpha2::TransactionReceipt],
+    state_update: Option<&v1alpha2::StateUpdate>,
+) -> Vec<(v1alpha2::FieldElement, v1alpha2::FieldElement)> {
+    use v1alpha2::transaction::Transaction;
+
+    let mut deployed = Vec::default();
+
+    // receipts were downloaded concurrently, so they're not sorted.
+    for receipt in receipts {
+        let address = match receipt.contract_address.as_ref() {
+            None => continue,
+            Some(address) => address,
+        };
+        let transaction = transactions
+            .get(receipt.transaction_index as usize)
+            .and_then(|tx| tx.transaction.as_ref());
+        let class_hash = match transaction {
+            Some(Transaction::Deploy(tx)) => tx.class_hash.as_ref(),
+            Some(Transaction::DeployAccount(tx)) => tx.class_hash.as_ref(),
+            _ => None,
+        };
+        if let Some(class_hash) = class_hash {
+            deployed.push((address.clone(), class_hash.clone()));
+        }
+    }
+
+    let state_diff = state_update.and_then(|update| update.state_diff.as_ref());
+    if let Some(state_diff) = state_diff {
+        for contract in &state_diff.deployed_contracts {
+            if let (Some(address), Some(class_hash)) = (
+                contract.contract_address.as_ref(),
+                contract.class_hash.as_ref(),
+            ) {
+                deployed.push((address.clone(), class_hash.clone()));
+            }
+        }
+    }
+
+    deployed
+}
+
+/// Stores the class hash of the contracts deployed in all the blocks in the database.
+///
+/// Blocks are processed in batches, each batch in its own transaction.
+/// Returns the number of blocks processed.
+pub fn rebuild_contract_classes<E: EnvironmentKind>(
+    db: &Environment<E>,
+) -> Result<usize, libmdbx::Error> {
+    rebuild_from_blocks(
+        db,
+        |cursor: &mut TableCursor<'_, ContractClassTable, RW>,
+         _block_id,
+         transactions,
+         receipts,
+         state_update| {
+            for (address, class_hash) in deployed_contracts(transactions, receipts, state_update) {
+                let key = ContractAddress::from(&address);
+                let value = ContractClass {
+                    class_hash: Some(class_hash),
+                };
+                cursor.seek_exact(&key)?;
+                cursor.put(&key, &value)?;
+            }
+            Ok(())
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::starknet::v1alpha2;
+
+    use crate::db::{
+        test_utils::{block_id, felt, open_test_db},
+        BlockBody, DatabaseStorage, StorageReader, StorageWriter,
+    };
+
+    use super::{deployed_contracts, rebuild_contract_classes};
+
+    /// Returns a deploy, an invoke and a deploy account transaction, with their receipts
+    /// in reverse order.
+    fn block_transactions() -> (
+        Vec<v1alpha2::Transaction>,
+        Vec<v1alpha2::TransactionReceipt>,
+    ) {
+        use v1alpha2::transaction::Transaction;
+
+        let transactions = vec![
+            Transaction::Deploy(v1alpha2::DeployTransaction {
+                class_hash: Some(felt(10)),
+                ..Default::default()
+            }),
+            Transaction::InvokeV1(v1alpha2::InvokeTransactionV1::default()),
+            Transaction::DeployAccount(v1alpha2::DeployAccountTransaction {
+                class_hash: Some(felt(12)),
+                ..Default::default()
+            }),
+        ]
+        .into_iter()
+        .map(|tx| v1alpha2::Transaction {
+            meta: None,
+            transaction: Some(tx),
+        })
+        .collect();
+        let receipts = (0..3)
+            .rev()
+            .map(|i| v1alpha2::TransactionReceipt {
+                transaction_index: i,
+                contract_address: Some(felt(i)),
+                ..Default::default()
+            })
+            .collect();
+        (transactions, receipts)
+    }
+
+    fn state_update() -> v1alpha2::StateUpdate {
+        v1alpha2::StateUpdate {
+            state_diff: Some(v1alpha2::StateDiff {
+                deployed_contracts: vec![
+                    v1alpha2::DeployedContract {
+                        contract_address: Some(felt(5)),
+                        class_hash: Some(felt(15)),
+                    },
+                    v1alpha2::DeployedContract {
+                        contract_address: Some(felt(6)),
+                        class_hash: None,
+                    },
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_deployed_contracts() {
+        let (transactions, receipts) = block_transactions();
+
+        // the invoke transaction doesn't deploy a contract.
+        let deployed = deployed_contracts(&transactions, &receipts, None);
+        assert_eq!(deployed, vec![(felt(2), felt(12)), (felt(0), felt(10))]);
+
+        let state_update = state_update();
+        let deployed = deployed_contracts(&transactions, &receipts, Some(&state_update));
+        assert_eq!(
+            deployed,
+            vec![
+                (felt(2), felt(12)),
+                (felt(0), felt(10)),
+                (felt(5), felt(15))
+            ]
+        );
+
+        // receipts without a matching transaction are skipped.
+        let deployed = deployed_contracts(&[], &receipts, Some(&state_update));
+        assert_eq!(deployed, vec![(felt(5), felt(15))]);
+    }
+
+    #[test]
+    fn test_rebuild_contract_classes() {
+        let (_path, db) = open_test_db();
+        let storage = DatabaseStorage::new(db.clone());
+
+        // blocks written without their contract classes, like older versions of the node.
+        let (transactions, receipts) = block_transactions();
+        let mut txn = storage.begin_txn().unwrap();
+        for number in 0..2 {
+            let id = block_id(number);
+            txn.write_body(
+                &id,
+                BlockBody {
+                    transactions: transactions.clone(),
+                },
+            )
+            .unwrap();
+            txn.write_receipts(&id, receipts.clone()).unwrap();
+            if number == 1 {
+                txn.write_state_update(&id, state_update()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        assert_eq!(storage.read_contract_class_hash(&felt(0)).unwrap(), None);
+
+        assert_eq!(rebuild_contract_classes(&db).unwrap(), 2);
+
+        let class_hash = |address| storage.read_contract_class_hash(&felt(address)).unwrap();
+        assert_eq!(class_hash(0), Some(felt(10)));
+        assert_eq!(class_hash(1), None);
+        assert_eq!(class_hash(2), Some(felt(12)));
+        assert_eq!(class_hash(5), Some(felt(15)));
+        assert_eq!(class_hash(6), None);
+    }
+}
@@ -0,0 +1,279 @@
+//! Indexes to find the blocks with data matching a filter.
+//!
+//! Indexes only store keys. They are a superset of the blocks with matching data:
+//! entries are never removed, so they may point to blocks that were later rejected.
+//! Blocks ingested before the indexes were introduced are not indexed, the
+//! [IndexedFromTable] stores the first block that was.
+
+use std::io::Cursor;
+
+use apibara_core::starknet::v1alpha2;
+use apibara_node::db::{KeyDecodeError, Table, TableKey};
+use byteorder::{BigEndian, ReadBytesExt};
+use prost::Message;
+
+/// Store the first block added to the indexes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IndexedFromTable {}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct IndexedFrom {
+    #[prost(uint64, tag = "1")]
+    pub block_number: u64,
+}
+
+/// Blocks with events, by contract emitting the event and first event key.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EventIndexTable {}
+
+/// Blocks with L2 to L1 messages, by message destination.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessageIndexTable {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventIndexKey {
+    pub from_address: [u8; 32],
+    pub key: [u8; 32],
+    pub block_number: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageIndexKey {
+    pub to_address: [u8; 32],
+    pub block_number: u64,
+}
+
+impl EventIndexKey {
+    /// Returns the key for an event in the given block.
+    ///
+    /// Events without keys are indexed with a zero first key.
+    pub fn from_event(block_number: u64, event: &v1alpha2::Event) -> Option<Self> {
+        let from_address = event.from_address.as_ref()?.to_bytes();
+        let key = event
+            .keys
+            .first()
+            .map(|key| key.to_bytes())
+            .unwrap_or_default();
+        Some(EventIndexKey {
+            from_address,
+            key,
+            block_number,
+        })
+    }
+}
+
+impl MessageIndexKey {
+    /// Returns the key for a message in the given block.
+    pub fn from_message(block_number: u64, message: &v1alpha2::L2ToL1Message) -> Option<Self> {
+        let to_address = message.to_address.as_ref()?.to_bytes();
+        Some(MessageIndexKey {
+            to_address,
+            block_number,
+        })
+    }
+}
+
+// An event index key is encoded as:
+// - 32 bytes contract address
+// - 32 bytes first event key
+// - 8 bytes big endian representation of the block number
+impl TableKey for EventIndexKey {
+    type Encoded = [u8; 72];
+
+    fn encode(&self) -> Self::Encoded {
+        let mut out = [0; 72];
+        out[..32].copy_from_slice(&self.from_address);
+        out[32..64].copy_from_slice(&self.key);
+        out[64..].copy_from_slice(&self.block_number.to_be_bytes());
+        out
+    }
+
+    fn decode(b: &[u8]) -> Result<Self, KeyDecodeError> {
+        if b.len() != 72 {
+            return Err(KeyDecodeError::InvalidByteSize {
+                expected: 72,
+                actual: b.len(),
+            });
+        }
+        let mut from_address = [0; 32];
+        from_address.copy_from_slice(&b[..32]);
+        let mut key = [0; 32];
+        key.copy_from_slice(&b[32..64]);
+        let block_number = Cursor::new(&b[64..])
+            .read_u64::<BigEndian>()
+            .map_err(KeyDecodeError::ReadError)?;
+        Ok(EventIndexKey {
+            from_address,
+            key,
+            block_number,
+        })
+    }
+}
+
+// A message index key is encoded as:
+// - 32 bytes destination address
+// - 8 bytes big endian representation of the block number
+impl TableKey for MessageIndexKey {
+    type Encoded = [u8; 40];
+
+    fn encode(&self) -> Self::Encoded {
+        let mut out = [0; 40];
+        out[..32].copy_from_slice(&self.to_address);
+        out[32..].copy_from_slice(&self.block_number.to_be_bytes());
+        out
+    }
+
+    fn decode(b: &[u8]) -> Result<Self, KeyDecodeError> {
+        if b.len() != 40 {
+            return Err(KeyDecodeError::InvalidByteSize {
+                expected: 40,
+                actual: b.len(),
+            });
+        }
+        let mut to_address = [0; 32];
+        to_address.copy_from_slice(&b[..32]);
+        let block_number = Cursor::new(&b[32..])
+            .read_u64::<BigEndian>()
+            .map_err(KeyDecodeError::ReadError)?;
+        Ok(MessageIndexKey {
+            to_address,
+            block_number,
+        })
+    }
+}
+
+impl Table for IndexedFromTable {
+    type Key = ();
+    type Value = IndexedFrom;
+
+    fn db_name() -> &'static str {
+        "IndexedFrom"
+    }
+}
+
+impl Table for EventIndexTable {
+    type Key = EventIndexKey;
+    type Value = ();
+
+    fn db_name() -> &'static str {
+        "EventIndex"
+    }
+}
+
+impl Table for MessageIndexTable {
+    type Key = MessageIndexKey;
+    type Value = ();
+
+    fn db_name() -> &'static str {
+        "MessageIndex"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::starknet::v1alpha2;
+    use apibara_node::db::{KeyDecodeError, TableKey};
+    use assert_matches::assert_matches;
+
+    use super::{EventIndexKey, MessageIndexKey};
+
+    fn bytes(value: u64) -> [u8; 32] {
+        v1alpha2::FieldElement::from_u64(value).to_bytes()
+    }
+
+    #[test]
+    fn test_event_index_key_encoding() {
+        let key = EventIndexKey {
+            from_address: bytes(1),
+            key: bytes(2),
+            block_number: 0x0102,
+        };
+        let encoded = key.encode();
+        assert_eq!(&encoded[..32], &bytes(1));
+        assert_eq!(&encoded[32..64], &bytes(2));
+        assert_eq!(&encoded[64..], &[0, 0, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(EventIndexKey::decode(&encoded).unwrap(), key);
+
+        assert_matches!(
+            EventIndexKey::decode(&encoded[..71]),
+            Err(KeyDecodeError::InvalidByteSize {
+                expected: 72,
+                actual: 71
+            })
+        );
+    }
+
+    #[test]
+    fn test_event_index_key_order() {
+        let key = |from_address, key, block_number| {
+            EventIndexKey {
+                from_address: bytes(from_address),
+                key: bytes(key),
+                block_number,
+            }
+            .encode()
+        };
+        // keys are sorted by contract, then first key, then block number.
+        assert!(key(1, 1, 2) < key(1, 1, 256));
+        assert!(key(1, 1, u64::MAX) < key(1, 2, 0));
+        assert!(key(1, u64::MAX, u64::MAX) < key(2, 0, 0));
+    }
+
+    #[test]
+    fn test_event_index_key_from_event() {
+        let event = v1alpha2::Event {
+            from_address: Some(v1alpha2::FieldElement::from_u64(1)),
+            keys: vec![
+                v1alpha2::FieldElement::from_u64(2),
+                v1alpha2::FieldElement::from_u64(3),
+            ],
+            ..Default::default()
+        };
+        let key = EventIndexKey::from_event(10, &event).unwrap();
+        assert_eq!(key.from_address, bytes(1));
+        assert_eq!(key.key, bytes(2));
+        assert_eq!(key.block_number, 10);
+
+        // events without keys use a zero key.
+        let no_keys = v1alpha2::Event {
+            keys: Vec::default(),
+            ..event.clone()
+        };
+        let key = EventIndexKey::from_event(10, &no_keys).unwrap();
+        assert_eq!(key.key, [0; 32]);
+
+        let no_address = v1alpha2::Event {
+            from_address: None,
+            ..event
+        };
+        assert!(EventIndexKey::from_event(10, &no_address).is_none());
+    }
+
+    #[test]
+    fn test_message_index_key_encoding() {
+        let key = MessageIndexKey {
+            to_address: bytes(3),
+            block_number: 7,
+        };
+        let encoded = key.encode();
+        assert_eq!(&encoded[..32], &bytes(3));
+        assert_eq!(&encoded[32..], &7u64.to_be_bytes());
+        assert_eq!(MessageIndexKey::decode(&encoded).unwrap(), key);
+
+        assert_matches!(
+            MessageIndexKey::decode(&encoded[..39]),
+            Err(KeyDecodeError::InvalidByteSize {
+                expected: 40,
+                actual: 39
+            })
+        );
+
+        let message = v1alpha2::L2ToL1Message {
+            to_address: Some(v1alpha2::FieldElement::from_u64(3)),
+            ..Default::default()
+        };
+        assert_eq!(MessageIndexKey::from_message(7, &message), Some(key));
+        let message = v1alpha2::L2ToL1Message::default();
+        assert!(MessageIndexKey::from_message(7, &message).is_none());
+    }
+}
@@ -1,22 +1,53 @@
 mod block;
+mod bloom;
+mod cache;
 mod chain;
+mod contract;
+mod index;
 mod state;
 mod storage;
+#[cfg(test)]
+pub(crate) mod test_utils;
 mod transaction;
+mod usage;
+mod version;
 
 pub use self::block::{BlockBody, BlockReceipts, BlockStatus};
-pub use self::storage::{DatabaseStorage, DatabaseStorageWriter, StorageReader, StorageWriter};
+pub use self::bloom::{rebuild_block_blooms, BlockBloom, BloomItem};
+pub use self::cache::{BlockCache, CachedStorage, DEFAULT_BLOCK_CACHE_SIZE};
+pub use self::contract::{deployed_contracts, rebuild_contract_classes};
+pub use self::storage::{
+    BlockParts, DatabaseSnapshot, DatabaseStorage, DatabaseStorageWriter, StorageReader,
+    StorageWriter, StoredBlock,
+};
+pub use self::usage::{Usage, UsageKey};
+pub use self::version::{
+    has_compression_dictionaries, migrate_database, read_database_version, write_database_version,
+    DATABASE_VERSION,
+};
+
+use apibara_node::db::TableCompression;
+
+/// Compression of block bodies, receipts and state updates.
+const BLOCK_DATA_COMPRESSION: TableCompression = TableCompression::Zstd(3);
 
 pub mod tables {
     use apibara_node::db::libmdbx::{EnvironmentKind, Error as MdbxError, Transaction, RW};
-    use apibara_node::db::MdbxRWTransactionExt;
+    use apibara_node::db::{
+        load_compression_dictionaries, tables::CompressionDictionaryTable, MdbxRWTransactionExt,
+    };
 
     pub use super::block::{BlockHeaderTable, BlockStatusTable};
+    pub use super::bloom::BlockBloomTable;
     pub use super::chain::CanonicalChainTable;
+    pub use super::contract::ContractClassTable;
+    pub use super::index::{EventIndexTable, IndexedFromTable, MessageIndexTable};
     pub use super::state::StateUpdateTable;
     pub use super::transaction::{BlockBodyTable, BlockReceiptsTable};
+    pub use super::usage::UsageTable;
+    pub use super::version::DatabaseVersionTable;
 
-    /// Ensures all tables exist.
+    /// Ensures all tables exist and loads the compression dictionaries.
     pub fn ensure<E: EnvironmentKind>(txn: &Transaction<RW, E>) -> Result<(), MdbxError> {
         txn.ensure_table::<self::BlockBodyTable>(None)?;
         txn.ensure_table::<self::BlockHeaderTable>(None)?;
@@ -24,6 +55,15 @@ pub mod tables {
         txn.ensure_table::<self::CanonicalChainTable>(None)?;
         txn.ensure_table::<self::BlockReceiptsTable>(None)?;
         txn.ensure_table::<self::StateUpdateTable>(None)?;
+        txn.ensure_table::<self::UsageTable>(None)?;
+        txn.ensure_table::<self::ContractClassTable>(None)?;
+        txn.ensure_table::<self::EventIndexTable>(None)?;
+        txn.ensure_table::<self::MessageIndexTable>(None)?;
+        txn.ensure_table::<self::IndexedFromTable>(None)?;
+        txn.ensure_table::<self::BlockBloomTable>(None)?;
+        txn.ensure_table::<self::DatabaseVersionTable>(None)?;
+        txn.ensure_table::<CompressionDictionaryTable>(None)?;
+        load_compression_dictionaries(txn)?;
         Ok(())
     }
 }
@@ -1,10 +1,12 @@
 //! State update data.
 
 use apibara_core::starknet::v1alpha2;
-use apibara_node::db::Table;
+use apibara_node::db::{Table, TableCompression};
 
 use crate::core::GlobalBlockId;
 
+use super::BLOCK_DATA_COMPRESSION;
+
 /// Store state updates.
 #[derive(Debug, Clone, Copy, Default)]
 pub struct StateUpdateTable {}
@@ -16,4 +18,8 @@ impl Table for StateUpdateTable {
     fn db_name() -> &'static str {
         "StateUpdate"
     }
+
+    fn compression() -> TableCompression {
+        BLOCK_DATA_COMPRESSION
+    }
 }
@@ -1,10 +1,10 @@
 //! Abstraction over raw db tables.
 
-use std::sync::Arc;
+use std::{rc::Rc, sync::Arc};
 
 use apibara_core::starknet::v1alpha2;
 use apibara_node::db::{
-    libmdbx::{self, Environment, EnvironmentKind, Transaction, RW},
+    libmdbx::{self, Environment, EnvironmentKind, Transaction, RO, RW},
     MdbxErrorExt, MdbxTransactionExt, TableCursor,
 };
 
@@ -12,12 +12,23 @@ use crate::core::GlobalBlockId;
 
 use super::{
     block::{BlockBody, BlockReceipts},
+    bloom::BlockBloom,
+    contract::{ContractAddress, ContractClass},
+    index::{EventIndexKey, IndexedFrom, MessageIndexKey},
     tables,
 };
 
 /// An object to read chain data from storage.
 pub trait StorageReader {
     type Error: std::error::Error + Send + Sync + 'static;
+    type Snapshot<'a>: StorageReader<Error = Self::Error>
+    where
+        Self: 'a;
+
+    /// Returns a reader that reads all data from the same snapshot.
+    ///
+    /// Use it when several reads must see the same chain.
+    fn snapshot(&self) -> Result<Self::Snapshot<'_>, Self::Error>;
 
     /// Returns the highest accepted block that was indexed.
     fn highest_accepted_block(&self) -> Result<Option<GlobalBlockId>, Self::Error>;
@@ -38,19 +49,89 @@ pub trait StorageReader {
         -> Result<Option<v1alpha2::BlockHeader>, Self::Error>;
 
     /// Returns all transactions in the given block.
-    fn read_body(&self, id: &GlobalBlockId) -> Result<Vec<v1alpha2::Transaction>, Self::Error>;
+    fn read_body(&self, id: &GlobalBlockId) -> Result<Arc<[v1alpha2::Transaction]>, Self::Error>;
 
     /// Returns all receipts in the given block.
     fn read_receipts(
         &self,
         id: &GlobalBlockId,
-    ) -> Result<Vec<v1alpha2::TransactionReceipt>, Self::Error>;
+    ) -> Result<Arc<[v1alpha2::TransactionReceipt]>, Self::Error>;
 
     /// Returns the state update for the given block.
     fn read_state_update(
         &self,
         id: &GlobalBlockId,
-    ) -> Result<Option<v1alpha2::StateUpdate>, Self::Error>;
+    ) -> Result<Option<Arc<v1alpha2::StateUpdate>>, Self::Error>;
+
+    /// Returns the class hash of the contract deployed at the given address.
+    fn read_contract_class_hash(
+        &self,
+        address: &v1alpha2::FieldElement,
+    ) -> Result<Option<v1alpha2::FieldElement>, Self::Error>;
+
+    /// Returns the bloom filter of the given block.
+    fn read_block_bloom(&self, id: &GlobalBlockId) -> Result<Option<BlockBloom>, Self::Error>;
+
+    /// Returns the first block added to the indexes. Blocks before it are not indexed.
+    fn indexed_from_block(&self) -> Result<Option<u64>, Self::Error>;
+
+    /// Returns the first block, starting from `start`, that may contain events emitted by
+    /// `from_address` with the given first key.
+    fn next_event_block(
+        &self,
+        from_address: &v1alpha2::FieldElement,
+        key: &v1alpha2::FieldElement,
+        start: u64,
+    ) -> Result<Option<u64>, Self::Error>;
+
+    /// Returns the first block, starting from `start`, that may contain messages to `to_address`.
+    fn next_message_block(
+        &self,
+        to_address: &v1alpha2::FieldElement,
+        start: u64,
+    ) -> Result<Option<u64>, Self::Error>;
+
+    /// Returns the canonical blocks between `start` and `end` (inclusive).
+    ///
+    /// All blocks in the range are read from the same database snapshot, so they
+    /// belong to the same chain and their status matches their data. Separate calls
+    /// read separate snapshots, unless they're made on the same [StorageReader::snapshot].
+    ///
+    /// Only the `parts` of the blocks accepted by `include`, given the block bloom filter,
+    /// are read. The other blocks only have their id and status.
+    /// Stops at the first block that is not in the canonical chain.
+    fn read_block_range<F>(
+        &self,
+        start: u64,
+        end: u64,
+        parts: BlockParts,
+        include: F,
+    ) -> Result<Vec<StoredBlock>, Self::Error>
+    where
+        F: FnMut(&GlobalBlockId, Option<&BlockBloom>) -> bool;
+}
+
+/// The parts of a block read by [StorageReader::read_block_range].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockParts {
+    pub header: bool,
+    /// Transactions and their receipts.
+    pub transactions: bool,
+    pub state_update: bool,
+}
+
+/// The data of a block, as stored in the database.
+///
+/// Parts that were not read are empty. Block data is shared with the block cache,
+/// so it's never copied.
+#[derive(Debug, Clone)]
+pub struct StoredBlock {
+    pub id: GlobalBlockId,
+    pub status: v1alpha2::BlockStatus,
+    pub header: Option<v1alpha2::BlockHeader>,
+    pub transactions: Arc<[v1alpha2::Transaction]>,
+    pub receipts: Arc<[v1alpha2::TransactionReceipt]>,
+    pub state_update: Option<Arc<v1alpha2::StateUpdate>>,
 }
 
 /// An object to write chain data to storage in a single transaction.
@@ -96,6 +177,27 @@ pub trait StorageWriter {
         id: &GlobalBlockId,
         state_update: v1alpha2::StateUpdate,
     ) -> Result<(), Self::Error>;
+
+    /// Writes the class hash of the contract deployed at the given address.
+    fn write_contract_class_hash(
+        &mut self,
+        address: &v1alpha2::FieldElement,
+        class_hash: v1alpha2::FieldElement,
+    ) -> Result<(), Self::Error>;
+
+    /// Writes the block bloom filter.
+    fn write_block_bloom(
+        &mut self,
+        id: &GlobalBlockId,
+        bloom: BlockBloom,
+    ) -> Result<(), Self::Error>;
+
+    /// Adds the events and messages in the block receipts to the indexes.
+    fn write_indexes(
+        &mut self,
+        id: &GlobalBlockId,
+        receipts: &[v1alpha2::TransactionReceipt],
+    ) -> Result<(), Self::Error>;
 }
 
 #[derive(Debug, Clone)]
@@ -103,6 +205,11 @@ pub struct DatabaseStorage<E: EnvironmentKind> {
     db: Arc<Environment<E>>,
 }
 
+/// A read-only transaction over the database, see [StorageReader::snapshot].
+pub struct DatabaseSnapshot<'env, E: EnvironmentKind> {
+    txn: Rc<Transaction<'env, RO, E>>,
+}
+
 pub struct DatabaseStorageWriter<'env, 'txn, E: EnvironmentKind> {
     txn: Transaction<'env, RW, E>,
     status_cursor: TableCursor<'txn, tables::BlockStatusTable, RW>,
@@ -110,6 +217,11 @@ pub struct DatabaseStorageWriter<'env, 'txn, E: EnvironmentKind> {
     body_cursor: TableCursor<'txn, tables::BlockBodyTable, RW>,
     receipts_cursor: TableCursor<'txn, tables::BlockReceiptsTable, RW>,
     state_update_cursor: TableCursor<'txn, tables::StateUpdateTable, RW>,
+    contract_class_cursor: TableCursor<'txn, tables::ContractClassTable, RW>,
+    event_index_cursor: TableCursor<'txn, tables::EventIndexTable, RW>,
+    message_index_cursor: TableCursor<'txn, tables::MessageIndexTable, RW>,
+    indexed_from_cursor: TableCursor<'txn, tables::IndexedFromTable, RW>,
+    bloom_cursor: TableCursor<'txn, tables::BlockBloomTable, RW>,
     canonical_chain_cursor: TableCursor<'txn, tables::CanonicalChainTable, RW>,
 }
 
@@ -125,6 +237,11 @@ impl<E: EnvironmentKind> DatabaseStorage<E> {
         let body_cursor = txn.open_cursor::<tables::BlockBodyTable>()?;
         let receipts_cursor = txn.open_cursor::<tables::BlockReceiptsTable>()?;
         let state_update_cursor = txn.open_cursor::<tables::StateUpdateTable>()?;
+        let contract_class_cursor = txn.open_cursor::<tables::ContractClassTable>()?;
+        let event_index_cursor = txn.open_cursor::<tables::EventIndexTable>()?;
+        let message_index_cursor = txn.open_cursor::<tables::MessageIndexTable>()?;
+        let indexed_from_cursor = txn.open_cursor::<tables::IndexedFromTable>()?;
+        let bloom_cursor = txn.open_cursor::<tables::BlockBloomTable>()?;
         let canonical_chain_cursor = txn.open_cursor::<tables::CanonicalChainTable>()?;
         let writer = DatabaseStorageWriter {
             txn,
@@ -133,19 +250,33 @@ impl<E: EnvironmentKind> DatabaseStorage<E> {
             body_cursor,
             receipts_cursor,
             state_update_cursor,
+            contract_class_cursor,
+            event_index_cursor,
+            message_index_cursor,
+            indexed_from_cursor,
+            bloom_cursor,
             canonical_chain_cursor,
         };
         Ok(writer)
     }
 }
 
-impl<E: EnvironmentKind> StorageReader for DatabaseStorage<E> {
+impl<'env, E: EnvironmentKind> StorageReader for DatabaseSnapshot<'env, E> {
     type Error = libmdbx::Error;
+    type Snapshot<'a>
+        = DatabaseSnapshot<'env, E>
+    where
+        Self: 'a;
+
+    fn snapshot(&self) -> Result<Self::Snapshot<'_>, Self::Error> {
+        Ok(DatabaseSnapshot {
+            txn: self.txn.clone(),
+        })
+    }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn highest_accepted_block(&self) -> Result<Option<GlobalBlockId>, Self::Error> {
-        let txn = self.db.begin_ro_txn()?;
-        let mut cursor = txn.open_cursor::<tables::CanonicalChainTable>()?;
+        let mut cursor = self.txn.open_cursor::<tables::CanonicalChainTable>()?;
         let block_id = match cursor.last()? {
             None => None,
             Some((number, hash)) => {
@@ -153,15 +284,13 @@ impl<E: EnvironmentKind> StorageReader for DatabaseStorage<E> {
                 Some(GlobalBlockId::new(number, hash))
             }
         };
-        txn.commit()?;
         Ok(block_id)
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn highest_finalized_block(&self) -> Result<Option<GlobalBlockId>, Self::Error> {
-        let txn = self.db.begin_ro_txn()?;
-        let mut canon_cursor = txn.open_cursor::<tables::CanonicalChainTable>()?;
-        let mut status_cursor = txn.open_cursor::<tables::BlockStatusTable>()?;
+        let mut canon_cursor = self.txn.open_cursor::<tables::CanonicalChainTable>()?;
+        let mut status_cursor = self.txn.open_cursor::<tables::BlockStatusTable>()?;
         let mut maybe_block_id = canon_cursor.last()?;
         while let Some((block_num, block_hash)) = maybe_block_id {
             let block_hash = (&block_hash)
@@ -173,31 +302,24 @@ impl<E: EnvironmentKind> StorageReader for DatabaseStorage<E> {
                 .expect("database is in inconsistent state.");
 
             if status.status().is_finalized() {
-                txn.commit()?;
                 return Ok(Some(block_id));
             }
 
             maybe_block_id = canon_cursor.prev()?;
         }
-        txn.commit()?;
         Ok(None)
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn canonical_block_id(&self, number: u64) -> Result<Option<GlobalBlockId>, Self::Error> {
-        let txn = self.db.begin_ro_txn()?;
-        let mut cursor = txn.open_cursor::<tables::CanonicalChainTable>()?;
+        let mut cursor = self.txn.open_cursor::<tables::CanonicalChainTable>()?;
         match cursor.seek_exact(&number)? {
-            None => {
-                txn.commit()?;
-                Ok(None)
-            }
+            None => Ok(None),
             Some((_, block_hash)) => {
                 let block_hash = (&block_hash)
                     .try_into()
                     .map_err(libmdbx::Error::decode_error)?;
                 let block_id = GlobalBlockId::new(number, block_hash);
-                txn.commit()?;
                 Ok(Some(block_id))
             }
         }
@@ -208,10 +330,8 @@ impl<E: EnvironmentKind> StorageReader for DatabaseStorage<E> {
         &self,
         id: &GlobalBlockId,
     ) -> Result<Option<v1alpha2::BlockStatus>, Self::Error> {
-        let txn = self.db.begin_ro_txn()?;
-        let mut cursor = txn.open_cursor::<tables::BlockStatusTable>()?;
+        let mut cursor = self.txn.open_cursor::<tables::BlockStatusTable>()?;
         let status = cursor.seek_exact(id)?.map(|t| t.1.status());
-        txn.commit()?;
         Ok(status)
     }
 
@@ -220,51 +340,314 @@ impl<E: EnvironmentKind> StorageReader for DatabaseStorage<E> {
         &self,
         id: &GlobalBlockId,
     ) -> Result<Option<v1alpha2::BlockHeader>, Self::Error> {
-        let txn = self.db.begin_ro_txn()?;
-        let mut cursor = txn.open_cursor::<tables::BlockHeaderTable>()?;
+        let mut cursor = self.txn.open_cursor::<tables::BlockHeaderTable>()?;
         let header = cursor.seek_exact(id)?.map(|t| t.1);
-        txn.commit()?;
         Ok(header)
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
-    fn read_body(&self, id: &GlobalBlockId) -> Result<Vec<v1alpha2::Transaction>, Self::Error> {
-        let txn = self.db.begin_ro_txn()?;
-        let mut cursor = txn.open_cursor::<tables::BlockBodyTable>()?;
+    fn read_body(&self, id: &GlobalBlockId) -> Result<Arc<[v1alpha2::Transaction]>, Self::Error> {
+        let mut cursor = self.txn.open_cursor::<tables::BlockBodyTable>()?;
         let transactions = cursor
             .seek_exact(id)?
             .map(|t| t.1.transactions)
             .unwrap_or_default();
-        txn.commit()?;
-        Ok(transactions)
+        Ok(transactions.into())
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn read_receipts(
         &self,
         id: &GlobalBlockId,
-    ) -> Result<Vec<v1alpha2::TransactionReceipt>, Self::Error> {
-        let txn = self.db.begin_ro_txn()?;
-        let mut cursor = txn.open_cursor::<tables::BlockReceiptsTable>()?;
+    ) -> Result<Arc<[v1alpha2::TransactionReceipt]>, Self::Error> {
+        let mut cursor = self.txn.open_cursor::<tables::BlockReceiptsTable>()?;
         let receipts = cursor
             .seek_exact(id)?
             .map(|t| t.1.receipts)
             .unwrap_or_default();
-        txn.commit()?;
-        Ok(receipts)
+        Ok(receipts.into())
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn read_state_update(
         &self,
         id: &GlobalBlockId,
-    ) -> Result<Option<v1alpha2::StateUpdate>, Self::Error> {
-        let txn = self.db.begin_ro_txn()?;
-        let mut cursor = txn.open_cursor::<tables::StateUpdateTable>()?;
-        let state_update = cursor.seek_exact(id)?.map(|t| t.1);
-        txn.commit()?;
+    ) -> Result<Option<Arc<v1alpha2::StateUpdate>>, Self::Error> {
+        let mut cursor = self.txn.open_cursor::<tables::StateUpdateTable>()?;
+        let state_update = cursor.seek_exact(id)?.map(|t| Arc::new(t.1));
         Ok(state_update)
     }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_contract_class_hash(
+        &self,
+        address: &v1alpha2::FieldElement,
+    ) -> Result<Option<v1alpha2::FieldElement>, Self::Error> {
+        let mut cursor = self.txn.open_cursor::<tables::ContractClassTable>()?;
+        let class_hash = cursor
+            .seek_exact(&ContractAddress::from(address))?
+            .and_then(|t| t.1.class_hash);
+        Ok(class_hash)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_block_bloom(&self, id: &GlobalBlockId) -> Result<Option<BlockBloom>, Self::Error> {
+        let mut cursor = self.txn.open_cursor::<tables::BlockBloomTable>()?;
+        let bloom = cursor.seek_exact(id)?.map(|t| t.1);
+        Ok(bloom)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn indexed_from_block(&self) -> Result<Option<u64>, Self::Error> {
+        let mut cursor = self.txn.open_cursor::<tables::IndexedFromTable>()?;
+        let block_number = cursor.seek_exact(&())?.map(|t| t.1.block_number);
+        Ok(block_number)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn next_event_block(
+        &self,
+        from_address: &v1alpha2::FieldElement,
+        key: &v1alpha2::FieldElement,
+        start: u64,
+    ) -> Result<Option<u64>, Self::Error> {
+        let mut cursor = self.txn.open_cursor::<tables::EventIndexTable>()?;
+        let from_address = from_address.to_bytes();
+
+        let key = key.to_bytes();
+        let seek = EventIndexKey {
+            from_address,
+            key,
+            block_number: start,
+        };
+        let block_number = cursor
+            .seek_range(&seek)?
+            .map(|t| t.0)
+            .filter(|k| k.from_address == from_address && k.key == key)
+            .map(|k| k.block_number);
+        Ok(block_number)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn next_message_block(
+        &self,
+        to_address: &v1alpha2::FieldElement,
+        start: u64,
+    ) -> Result<Option<u64>, Self::Error> {
+        let mut cursor = self.txn.open_cursor::<tables::MessageIndexTable>()?;
+        let to_address = to_address.to_bytes();
+        let seek = MessageIndexKey {
+            to_address,
+            block_number: start,
+        };
+        let block_number = cursor
+            .seek_range(&seek)?
+            .map(|t| t.0)
+            .filter(|k| k.to_address == to_address)
+            .map(|k| k.block_number);
+        Ok(block_number)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, include))]
+    fn read_block_range<F>(
+        &self,
+        start: u64,
+        end: u64,
+        parts: BlockParts,
+        mut include: F,
+    ) -> Result<Vec<StoredBlock>, Self::Error>
+    where
+        F: FnMut(&GlobalBlockId, Option<&BlockBloom>) -> bool,
+    {
+        let mut canon_cursor = self.txn.open_cursor::<tables::CanonicalChainTable>()?;
+        let mut status_cursor = self.txn.open_cursor::<tables::BlockStatusTable>()?;
+        let mut bloom_cursor = self.txn.open_cursor::<tables::BlockBloomTable>()?;
+        let mut header_cursor = self.txn.open_cursor::<tables::BlockHeaderTable>()?;
+        let mut body_cursor = self.txn.open_cursor::<tables::BlockBodyTable>()?;
+        let mut receipts_cursor = self.txn.open_cursor::<tables::BlockReceiptsTable>()?;
+        let mut state_update_cursor = self.txn.open_cursor::<tables::StateUpdateTable>()?;
+
+        let mut blocks = Vec::default();
+        for number in start..=end {
+            let block_hash = match canon_cursor.seek_exact(&number)? {
+                None => break,
+                Some((_, block_hash)) => block_hash,
+            };
+            let block_hash = (&block_hash)
+                .try_into()
+                .map_err(libmdbx::Error::decode_error)?;
+            let id = GlobalBlockId::new(number, block_hash);
+
+            let status = status_cursor
+                .seek_exact(&id)?
+                .map(|t| t.1.status())
+                .unwrap_or(v1alpha2::BlockStatus::Unspecified);
+
+            let bloom = bloom_cursor.seek_exact(&id)?.map(|t| t.1);
+            if !include(&id, bloom.as_ref()) {
+                blocks.push(StoredBlock {
+                    id,
+                    status,
+                    header: None,
+                    transactions: Vec::default().into(),
+                    receipts: Vec::default().into(),
+                    state_update: None,
+                });
+                continue;
+            }
+
+            let header = if parts.header {
+                header_cursor.seek_exact(&id)?.map(|t| t.1)
+            } else {
+                None
+            };
+            let (transactions, receipts): (Arc<[_]>, Arc<[_]>) = if parts.transactions {
+                let transactions = body_cursor
+                    .seek_exact(&id)?
+                    .map(|t| t.1.transactions)
+                    .unwrap_or_default();
+                let receipts = receipts_cursor
+                    .seek_exact(&id)?
+                    .map(|t| t.1.receipts)
+                    .unwrap_or_default();
+                (transactions.into(), receipts.into())
+            } else {
+                (Vec::default().into(), Vec::default().into())
+            };
+            let state_update = if parts.state_update {
+                state_update_cursor.seek_exact(&id)?.map(|t| Arc::new(t.1))
+            } else {
+                None
+            };
+
+            blocks.push(StoredBlock {
+                id,
+                status,
+                header,
+                transactions,
+                receipts,
+                state_update,
+            });
+        }
+        Ok(blocks)
+    }
+}
+
+impl<E: EnvironmentKind> StorageReader for DatabaseStorage<E> {
+    type Error = libmdbx::Error;
+    type Snapshot<'a>
+        = DatabaseSnapshot<'a, E>
+    where
+        Self: 'a;
+
+    fn snapshot(&self) -> Result<Self::Snapshot<'_>, Self::Error> {
+        let txn = self.db.begin_ro_txn()?;
+        Ok(DatabaseSnapshot { txn: Rc::new(txn) })
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn highest_accepted_block(&self) -> Result<Option<GlobalBlockId>, Self::Error> {
+        self.snapshot()?.highest_accepted_block()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn highest_finalized_block(&self) -> Result<Option<GlobalBlockId>, Self::Error> {
+        self.snapshot()?.highest_finalized_block()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn canonical_block_id(&self, number: u64) -> Result<Option<GlobalBlockId>, Self::Error> {
+        self.snapshot()?.canonical_block_id(number)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_status(
+        &self,
+        id: &GlobalBlockId,
+    ) -> Result<Option<v1alpha2::BlockStatus>, Self::Error> {
+        self.snapshot()?.read_status(id)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_header(
+        &self,
+        id: &GlobalBlockId,
+    ) -> Result<Option<v1alpha2::BlockHeader>, Self::Error> {
+        self.snapshot()?.read_header(id)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_body(&self, id: &GlobalBlockId) -> Result<Arc<[v1alpha2::Transaction]>, Self::Error> {
+        self.snapshot()?.read_body(id)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_receipts(
+        &self,
+        id: &GlobalBlockId,
+    ) -> Result<Arc<[v1alpha2::TransactionReceipt]>, Self::Error> {
+        self.snapshot()?.read_receipts(id)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_state_update(
+        &self,
+        id: &GlobalBlockId,
+    ) -> Result<Option<Arc<v1alpha2::StateUpdate>>, Self::Error> {
+        self.snapshot()?.read_state_update(id)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_contract_class_hash(
+        &self,
+        address: &v1alpha2::FieldElement,
+    ) -> Result<Option<v1alpha2::FieldElement>, Self::Error> {
+        self.snapshot()?.read_contract_class_hash(address)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn read_block_bloom(&self, id: &GlobalBlockId) -> Result<Option<BlockBloom>, Self::Error> {
+        self.snapshot()?.read_block_bloom(id)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn indexed_from_block(&self) -> Result<Option<u64>, Self::Error> {
+        self.snapshot()?.indexed_from_block()
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn next_event_block(
+        &self,
+        from_address: &v1alpha2::FieldElement,
+        key: &v1alpha2::FieldElement,
+        start: u64,
+    ) -> Result<Option<u64>, Self::Error> {
+        self.snapshot()?.next_event_block(from_address, key, start)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn next_message_block(
+        &self,
+        to_address: &v1alpha2::FieldElement,
+        start: u64,
+    ) -> Result<Option<u64>, Self::Error> {
+        self.snapshot()?.next_message_block(to_address, start)
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, include))]
+    fn read_block_range<F>(
+        &self,
+        start: u64,
+        end: u64,
+        parts: BlockParts,
+        include: F,
+    ) -> Result<Vec<StoredBlock>, Self::Error>
+    where
+        F: FnMut(&GlobalBlockId, Option<&BlockBloom>) -> bool,
+    {
+        self.snapshot()?
+            .read_block_range(start, end, parts, include)
+    }
 }
 
 impl<'env, 'txn, E: EnvironmentKind> StorageWriter for DatabaseStorageWriter<'env, 'txn, E> {
@@ -352,4 +735,278 @@ impl<'env, 'txn, E: EnvironmentKind> StorageWriter for DatabaseStorageWriter<'en
         self.state_update_cursor.put(id, &state_update)?;
         Ok(())
     }
+
+    #[tracing::instrument(level = "trace", skip(self))]
+    fn write_contract_class_hash(
+        &mut self,
+        address: &v1alpha2::FieldElement,
+        class_hash: v1alpha2::FieldElement,
+    ) -> Result<(), Self::Error> {
+        let key = ContractAddress::from(address);
+        let value = ContractClass {
+            class_hash: Some(class_hash),
+        };
+        self.contract_class_cursor.seek_exact(&key)?;
+        self.contract_class_cursor.put(&key, &value)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, bloom))]
+    fn write_block_bloom(
+        &mut self,
+        id: &GlobalBlockId,
+        bloom: BlockBloom,
+    ) -> Result<(), Self::Error> {
+        self.bloom_cursor.seek_exact(id)?;
+        self.bloom_cursor.put(id, &bloom)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "trace", skip(self, receipts))]
+    fn write_indexes(
+        &mut self,
+        id: &GlobalBlockId,
+        receipts: &[v1alpha2::TransactionReceipt],
+    ) -> Result<(), Self::Error> {
+        let number = id.number();
+        if self.indexed_from_cursor.seek_exact(&())?.is_none() {
+            let indexed_from = IndexedFrom {
+                block_number: number,
+            };
+            self.indexed_from_cursor.put(&(), &indexed_from)?;
+        }
+
+        for receipt in receipts {
+            for event in &receipt.events {
+                if let Some(key) = EventIndexKey::from_event(number, event) {
+                    self.event_index_cursor.put(&key, &())?;
+                }
+            }
+            for message in &receipt.l2_to_l1_messages {
+                if let Some(key) = MessageIndexKey::from_message(number, message) {
+                    self.message_index_cursor.put(&key, &())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::starknet::v1alpha2;
+    use apibara_node::db::libmdbx::NoWriteMap;
+
+    use crate::db::{
+        test_utils::{block_id, felt, open_test_storage, write_test_block},
+        BlockBody,
+    };
+
+    use super::{BlockParts, DatabaseStorage, StorageReader, StorageWriter};
+
+    /// Writes a canonical block with one transaction.
+    fn write_block(storage: &DatabaseStorage<NoWriteMap>, number: u64) {
+        let id = write_test_block(storage, number, v1alpha2::BlockStatus::AcceptedOnL1);
+        let mut txn = storage.begin_txn().unwrap();
+        let transaction = v1alpha2::Transaction {
+            meta: Some(v1alpha2::TransactionMeta {
+                hash: Some(felt(number)),
+                ..Default::default()
+            }),
+            transaction: None,
+        };
+        let body = BlockBody {
+            transactions: vec![transaction],
+        };
+        txn.write_body(&id, body).unwrap();
+        let receipt = v1alpha2::TransactionReceipt {
+            transaction_hash: Some(felt(number)),
+            ..Default::default()
+        };
+        txn.write_receipts(&id, vec![receipt]).unwrap();
+        let state_update = v1alpha2::StateUpdate {
+            new_root: Some(felt(number)),
+            ..Default::default()
+        };
+        txn.write_state_update(&id, state_update).unwrap();
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_read_block_range() {
+        let (_path, storage) = open_test_storage();
+        for number in 0..5 {
+            write_block(&storage, number);
+        }
+
+        let all_parts = BlockParts {
+            header: true,
+            transactions: true,
+            state_update: true,
+        };
+        // the chain ends at block 4.
+        let blocks = storage
+            .read_block_range(1, 10, all_parts, |_, _| true)
+            .unwrap();
+        assert_eq!(blocks.len(), 4);
+        for (block, number) in blocks.iter().zip(1..) {
+            assert_eq!(block.id, block_id(number));
+            assert_eq!(block.status, v1alpha2::BlockStatus::AcceptedOnL1);
+            assert_eq!(block.header.as_ref().unwrap().block_number, number);
+            assert_eq!(block.transactions.len(), 1);
+            assert_eq!(block.receipts.len(), 1);
+            assert_eq!(
+                block.state_update.as_ref().unwrap().new_root,
+                Some(felt(number))
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_block_range_parts() {
+        let (_path, storage) = open_test_storage();
+        for number in 0..4 {
+            write_block(&storage, number);
+        }
+
+        let parts = BlockParts {
+            transactions: true,
+            ..Default::default()
+        };
+        // blocks not included only have their id and status.
+        let blocks = storage
+            .read_block_range(0, 3, parts, |id, bloom| {
+                assert!(bloom.is_none());
+                id.number() % 2 == 0
+            })
+            .unwrap();
+        assert_eq!(blocks.len(), 4);
+        for block in &blocks {
+            assert_eq!(block.status, v1alpha2::BlockStatus::AcceptedOnL1);
+            assert!(block.header.is_none());
+            assert!(block.state_update.is_none());
+            let expected_len = if block.id.number() % 2 == 0 { 1 } else { 0 };
+            assert_eq!(block.transactions.len(), expected_len);
+            assert_eq!(block.receipts.len(), expected_len);
+        }
+
+        let parts = BlockParts {
+            header: true,
+            state_update: true,
+            ..Default::default()
+        };
+        let blocks = storage.read_block_range(2, 2, parts, |_, _| true).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert!(blocks[0].header.is_some());
+        assert!(blocks[0].state_update.is_some());
+        assert!(blocks[0].transactions.is_empty());
+        assert!(blocks[0].receipts.is_empty());
+    }
+
+    #[test]
+    fn test_read_block_range_stops_at_non_canonical_block() {
+        let (_path, storage) = open_test_storage();
+        for number in 0..4 {
+            write_block(&storage, number);
+        }
+        let mut txn = storage.begin_txn().unwrap();
+        txn.reject_block_from_canonical_chain(&block_id(3)).unwrap();
+        txn.reject_block_from_canonical_chain(&block_id(2)).unwrap();
+        txn.commit().unwrap();
+
+        let blocks = storage
+            .read_block_range(0, 3, BlockParts::default(), |_, _| true)
+            .unwrap();
+        let numbers: Vec<_> = blocks.iter().map(|b| b.id.number()).collect();
+        assert_eq!(numbers, vec![0, 1]);
+    }
+
+    /// Adds the events and messages of a block to the indexes.
+    fn write_indexes(
+        storage: &DatabaseStorage<NoWriteMap>,
+        number: u64,
+        events: Vec<(u64, Vec<u64>)>,
+        messages: Vec<u64>,
+    ) {
+        let events = events
+            .into_iter()
+            .map(|(from_address, keys)| v1alpha2::Event {
+                from_address: Some(felt(from_address)),
+                keys: keys.into_iter().map(felt).collect(),
+                ..Default::default()
+            })
+            .collect();
+        let l2_to_l1_messages = messages
+            .into_iter()
+            .map(|to_address| v1alpha2::L2ToL1Message {
+                to_address: Some(felt(to_address)),
+                ..Default::default()
+            })
+            .collect();
+        let receipt = v1alpha2::TransactionReceipt {
+            events,
+            l2_to_l1_messages,
+            ..Default::default()
+        };
+        let mut txn = storage.begin_txn().unwrap();
+        txn.write_indexes(&block_id(number), &[receipt]).unwrap();
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_next_event_block() {
+        let (_path, storage) = open_test_storage();
+        assert_eq!(storage.indexed_from_block().unwrap(), None);
+
+        write_indexes(&storage, 0, vec![(2, vec![1])], vec![]);
+        write_indexes(&storage, 1, vec![(1, vec![3])], vec![]);
+        write_indexes(&storage, 2, vec![(1, vec![1, 2])], vec![]);
+        write_indexes(&storage, 5, vec![(1, vec![2])], vec![]);
+        write_indexes(&storage, 8, vec![(1, vec![1])], vec![]);
+        write_indexes(&storage, 9, vec![(1, vec![])], vec![]);
+        assert_eq!(storage.indexed_from_block().unwrap(), Some(0));
+
+        let next = |key: u64, start| {
+            storage
+                .next_event_block(&felt(1), &felt(key), start)
+                .unwrap()
+        };
+
+        // only the first key is indexed.
+        assert_eq!(next(1, 0), Some(2));
+        assert_eq!(next(1, 3), Some(8));
+        assert_eq!(next(1, 9), None);
+        assert_eq!(next(2, 0), Some(5));
+        assert_eq!(next(4, 0), None);
+        // events without keys are indexed with the zero key.
+        assert_eq!(next(0, 0), Some(9));
+
+        // blocks of other contracts are not returned.
+        assert_eq!(
+            storage.next_event_block(&felt(2), &felt(1), 0).unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            storage.next_event_block(&felt(2), &felt(1), 1).unwrap(),
+            None
+        );
+        assert_eq!(
+            storage.next_event_block(&felt(3), &felt(1), 0).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_next_message_block() {
+        let (_path, storage) = open_test_storage();
+        write_indexes(&storage, 3, vec![], vec![1]);
+        write_indexes(&storage, 6, vec![], vec![1, 2]);
+        assert_eq!(storage.indexed_from_block().unwrap(), Some(3));
+
+        assert_eq!(storage.next_message_block(&felt(1), 0).unwrap(), Some(3));
+        assert_eq!(storage.next_message_block(&felt(1), 4).unwrap(), Some(6));
+        assert_eq!(storage.next_message_block(&felt(1), 7).unwrap(), None);
+        assert_eq!(storage.next_message_block(&felt(2), 0).unwrap(), Some(6));
+        assert_eq!(storage.next_message_block(&felt(3), 0).unwrap(), None);
+    }
 }
@@ -0,0 +1,65 @@
+//! Temporary databases used in tests.
+
+use std::sync::Arc;
+
+use apibara_core::starknet::v1alpha2;
+use apibara_node::db::{
+    libmdbx::{Environment, NoWriteMap},
+    MdbxEnvironmentExt,
+};
+use tempfile::{tempdir, TempDir};
+
+use crate::core::{BlockHash, GlobalBlockId};
+
+use super::{tables, DatabaseStorage, StorageWriter};
+
+pub fn felt(value: u64) -> v1alpha2::FieldElement {
+    v1alpha2::FieldElement::from_u64(value)
+}
+
+/// Returns the id of the test block `number`, as written by [write_test_block].
+pub fn block_id(number: u64) -> GlobalBlockId {
+    let hash: BlockHash = felt(number + 1).into();
+    GlobalBlockId::new(number, hash)
+}
+
+/// Opens an empty database with all tables created.
+///
+/// The database is removed when the returned directory is dropped.
+pub fn open_test_db() -> (TempDir, Arc<Environment<NoWriteMap>>) {
+    let path = tempdir().unwrap();
+    let db = Environment::<NoWriteMap>::builder()
+        .with_size_gib(1, 2)
+        .open(path.path())
+        .unwrap();
+    let txn = db.begin_rw_txn().unwrap();
+    tables::ensure(&txn).unwrap();
+    txn.commit().unwrap();
+    (path, Arc::new(db))
+}
+
+/// Opens a storage over an empty database, see [open_test_db].
+pub fn open_test_storage() -> (TempDir, DatabaseStorage<NoWriteMap>) {
+    let (path, db) = open_test_db();
+    (path, DatabaseStorage::new(db))
+}
+
+/// Extends the canonical chain with block `number`, writing its status and header.
+pub fn write_test_block(
+    storage: &DatabaseStorage<NoWriteMap>,
+    number: u64,
+    status: v1alpha2::BlockStatus,
+) -> GlobalBlockId {
+    let id = block_id(number);
+    let mut txn = storage.begin_txn().unwrap();
+    txn.extend_canonical_chain(&id).unwrap();
+    txn.write_status(&id, status).unwrap();
+    let header = v1alpha2::BlockHeader {
+        block_hash: Some(felt(number + 1)),
+        block_number: number,
+        ..Default::default()
+    };
+    txn.write_header(&id, header).unwrap();
+    txn.commit().unwrap();
+    id
+}
@@ -1,8 +1,11 @@
 //! Transaction data.
 
-use apibara_node::db::Table;
+use apibara_node::db::{Table, TableCompression};
 
-use super::block::{BlockBody, BlockReceipts};
+use super::{
+    block::{BlockBody, BlockReceipts},
+    BLOCK_DATA_COMPRESSION,
+};
 use crate::core::GlobalBlockId;
 
 /// Store block body.
@@ -20,6 +23,10 @@ impl Table for BlockBodyTable {
     fn db_name() -> &'static str {
         "BlockBody"
     }
+
+    fn compression() -> TableCompression {
+        BLOCK_DATA_COMPRESSION
+    }
 }
 
 impl Table for BlockReceiptsTable {
@@ -29,4 +36,8 @@ impl Table for BlockReceiptsTable {
     fn db_name() -> &'static str {
         "BlockReceipts"
     }
+
+    fn compression() -> TableCompression {
+        BLOCK_DATA_COMPRESSION
+    }
 }
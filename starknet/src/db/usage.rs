@@ -0,0 +1,174 @@
+//! Data usage per api key.
+
+use apibara_node::db::{KeyDecodeError, Table, TableKey};
+use prost::Message;
+
+/// Store data usage per day and api key.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UsageTable {}
+
+/// A pair (day, api key).
+///
+/// The day is the number of days since the unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UsageKey {
+    pub day: u32,
+    pub key: String,
+}
+
+/// Amount of data sent, by data type.
+#[derive(Clone, PartialEq, Message)]
+pub struct Usage {
+    #[prost(uint64, tag = "1")]
+    pub header: u64,
+    #[prost(uint64, tag = "2")]
+    pub transaction: u64,
+    #[prost(uint64, tag = "3")]
+    pub event: u64,
+    #[prost(uint64, tag = "4")]
+    pub message: u64,
+    #[prost(uint64, tag = "5")]
+    pub storage_diff: u64,
+    #[prost(uint64, tag = "6")]
+    pub declared_contract: u64,
+    #[prost(uint64, tag = "7")]
+    pub deployed_contract: u64,
+    #[prost(uint64, tag = "8")]
+    pub nonce_update: u64,
+}
+
+impl Usage {
+    /// Increments the counter with the given name.
+    ///
+    /// Unknown counters are ignored.
+    pub fn increment(&mut self, name: &str, amount: u64) {
+        let counter = match name {
+            "header" => &mut self.header,
+            "transaction" => &mut self.transaction,
+            "event" => &mut self.event,
+            "message" => &mut self.message,
+            "storage_diff" => &mut self.storage_diff,
+            "declared_contract" => &mut self.declared_contract,
+            "deployed_contract" => &mut self.deployed_contract,
+            "nonce_update" => &mut self.nonce_update,
+            _ => return,
+        };
+        *counter += amount;
+    }
+
+    /// Adds the counters of `other` to this usage.
+    pub fn add(&mut self, other: &Usage) {
+        self.header += other.header;
+        self.transaction += other.transaction;
+        self.event += other.event;
+        self.message += other.message;
+        self.storage_diff += other.storage_diff;
+        self.declared_contract += other.declared_contract;
+        self.deployed_contract += other.deployed_contract;
+        self.nonce_update += other.nonce_update;
+    }
+}
+
+// A pair (day, api key) is encoded as:
+// - 4 bytes big endian representation of the day
+// - the utf-8 bytes of the api key
+impl TableKey for UsageKey {
+    type Encoded = Vec<u8>;
+
+    fn encode(&self) -> Self::Encoded {
+        let mut out = Vec::with_capacity(4 + self.key.len());
+        out.extend_from_slice(&self.day.to_be_bytes());
+        out.extend_from_slice(self.key.as_bytes());
+        out
+    }
+
+    fn decode(b: &[u8]) -> Result<Self, KeyDecodeError> {
+        if b.len() < 4 {
+            return Err(KeyDecodeError::InvalidByteSize {
+                expected: 4,
+                actual: b.len(),
+            });
+        }
+        let mut day = [0; 4];
+        day.copy_from_slice(&b[..4]);
+        let key = String::from_utf8(b[4..].to_vec())
+            .map_err(|err| KeyDecodeError::Other(Box::new(err)))?;
+        Ok(UsageKey {
+            day: u32::from_be_bytes(day),
+            key,
+        })
+    }
+}
+
+impl Table for UsageTable {
+    type Key = UsageKey;
+    type Value = Usage;
+
+    fn db_name() -> &'static str {
+        "Usage"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_node::db::TableKey;
+
+    use super::{Usage, UsageKey};
+
+    #[test]
+    fn test_usage_key_encode_decode() {
+        let key = UsageKey {
+            day: 19_500,
+            key: "sha256:0123456789abcdef".to_string(),
+        };
+        let encoded = key.encode();
+        assert_eq!(&encoded[..4], &19_500u32.to_be_bytes());
+        assert_eq!(UsageKey::decode(&encoded).unwrap(), key);
+    }
+
+    #[test]
+    fn test_usage_key_empty_key() {
+        let key = UsageKey {
+            day: 1,
+            key: String::default(),
+        };
+        assert_eq!(UsageKey::decode(&key.encode()).unwrap(), key);
+    }
+
+    #[test]
+    fn test_usage_key_invalid() {
+        assert!(UsageKey::decode(&[0, 1]).is_err());
+        assert!(UsageKey::decode(&[0, 0, 0, 1, 0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_usage_key_sorted_by_day() {
+        let first = UsageKey {
+            day: 1,
+            key: "zzz".to_string(),
+        };
+        let second = UsageKey {
+            day: 2,
+            key: "aaa".to_string(),
+        };
+        assert!(first.encode() < second.encode());
+    }
+
+    #[test]
+    fn test_usage_increment_and_add() {
+        let mut usage = Usage::default();
+        usage.increment("event", 3);
+        usage.increment("event", 2);
+        usage.increment("unknown", 10);
+        assert_eq!(usage.event, 5);
+
+        let mut total = Usage {
+            header: 1,
+            ..Usage::default()
+        };
+        total.add(&usage);
+        assert_eq!(total.header, 1);
+        assert_eq!(total.event, 5);
+        assert_eq!(total.transaction, 0);
+    }
+}
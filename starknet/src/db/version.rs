@@ -0,0 +1,290 @@
+//! Database format version and migrations.
+
+use apibara_node::db::{
+    compression_dictionary_id,
+    libmdbx::{self, Environment, EnvironmentKind, Transaction, TransactionKind, RW},
+    recompress_table, train_compression_dictionary, MdbxTransactionExt, Table,
+};
+use prost::Message;
+use tracing::{info, warn};
+
+use super::tables;
+
+/// Format version of databases created by this version of the node.
+///
+/// - 0: block data stored uncompressed.
+/// - 1: block bodies, receipts and state updates compressed with zstd.
+/// - 2: block bodies, receipts and state updates compressed with zstd dictionaries
+///   trained on the database.
+pub const DATABASE_VERSION: u32 = 2;
+
+/// Store the database format version.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DatabaseVersionTable {}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct DatabaseVersion {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+}
+
+impl Table for DatabaseVersionTable {
+    type Key = ();
+    type Value = DatabaseVersion;
+
+    fn db_name() -> &'static str {
+        "DatabaseVersion"
+    }
+}
+
+/// Returns the database format version.
+///
+/// Databases created before the version was recorded are at version 0, empty
+/// databases are at the current version.
+pub fn read_database_version<K: TransactionKind, E: EnvironmentKind>(
+    txn: &Transaction<K, E>,
+) -> Result<u32, libmdbx::Error> {
+    let mut version_cursor = txn.open_cursor::<DatabaseVersionTable>()?;
+    if let Some((_, version)) = version_cursor.seek_exact(&())? {
+        return Ok(version.version);
+    }
+
+    let mut chain_cursor = txn.open_cursor::<tables::CanonicalChainTable>()?;
+    if chain_cursor.first()?.is_none() {
+        Ok(DATABASE_VERSION)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Records the database format version.
+pub fn write_database_version<E: EnvironmentKind>(
+    txn: &Transaction<RW, E>,
+    version: u32,
+) -> Result<(), libmdbx::Error> {
+    let mut cursor = txn.open_cursor::<DatabaseVersionTable>()?;
+    cursor.seek_exact(&())?;
+    cursor.put(&(), &DatabaseVersion { version })?;
+    Ok(())
+}
+
+/// Returns true if block bodies, receipts and state updates have a compression dictionary.
+///
+/// Dictionaries are loaded by [tables::ensure].
+pub fn has_compression_dictionaries<E: EnvironmentKind>(db: &Environment<E>) -> bool {
+    compression_dictionary_id::<tables::BlockBodyTable, _>(db).is_some()
+        && compression_dictionary_id::<tables::BlockReceiptsTable, _>(db).is_some()
+        && compression_dictionary_id::<tables::StateUpdateTable, _>(db).is_some()
+}
+
+/// Migrates the database to the current format version, in place.
+///
+/// Trains the compression dictionaries of tables that don't have one yet, then
+/// re-encodes the values not compressed with them. The migration can be interrupted
+/// and resumed. Returns the number of values re-encoded.
+pub fn migrate_database<E: EnvironmentKind>(db: &Environment<E>) -> Result<usize, libmdbx::Error> {
+    let txn = db.begin_rw_txn()?;
+    tables::ensure(&txn)?;
+    let version = read_database_version(&txn)?;
+    txn.commit()?;
+
+    if version > DATABASE_VERSION {
+        warn!(version = %version, "database created by a newer version of the node");
+        return Ok(0);
+    }
+
+    info!(from = %version, to = %DATABASE_VERSION, "migrating database");
+
+    // train the dictionaries first, so that values are re-encoded only once.
+    train_compression_dictionary::<tables::BlockBodyTable, _>(db)?;
+    train_compression_dictionary::<tables::BlockReceiptsTable, _>(db)?;
+    train_compression_dictionary::<tables::StateUpdateTable, _>(db)?;
+
+    let mut migrated = 0;
+    migrated += recompress_table::<tables::BlockBodyTable, _>(db)?;
+    migrated += recompress_table::<tables::BlockReceiptsTable, _>(db)?;
+    migrated += recompress_table::<tables::StateUpdateTable, _>(db)?;
+
+    let txn = db.begin_rw_txn()?;
+    write_database_version(&txn, DATABASE_VERSION)?;
+    txn.commit()?;
+
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::starknet::v1alpha2;
+    use apibara_node::db::{
+        libmdbx::{Environment, NoWriteMap, WriteFlags},
+        recompress_table, train_compression_dictionary, MdbxTransactionExt, Table, TableKey,
+    };
+    use prost::Message;
+
+    use crate::{
+        core::{BlockHash, GlobalBlockId},
+        db::{
+            tables,
+            test_utils::{felt, open_test_db},
+            BlockBody, BlockReceipts,
+        },
+    };
+
+    use super::{
+        has_compression_dictionaries, migrate_database, read_database_version,
+        write_database_version, DATABASE_VERSION,
+    };
+
+    const BLOCK_COUNT: u64 = 150;
+
+    fn block_id(number: u64) -> GlobalBlockId {
+        let hash: BlockHash = felt(number * 7919 + 1).into();
+        GlobalBlockId::new(number, hash)
+    }
+
+    fn body(number: u64) -> BlockBody {
+        let transactions = (0..3)
+            .map(|i| v1alpha2::Transaction {
+                meta: Some(v1alpha2::TransactionMeta {
+                    hash: Some(felt(number * 1_000 + i)),
+                    max_fee: Some(felt(1_000_000)),
+                    signature: vec![felt(number), felt(i)],
+                    nonce: Some(felt(number)),
+                    version: 1,
+                }),
+                transaction: None,
+            })
+            .collect();
+        BlockBody { transactions }
+    }
+
+    fn receipts(number: u64) -> BlockReceipts {
+        let receipts = (0..3)
+            .map(|i| v1alpha2::TransactionReceipt {
+                transaction_hash: Some(felt(number * 1_000 + i)),
+                transaction_index: i,
+                actual_fee: Some(felt(12_345)),
+                ..Default::default()
+            })
+            .collect();
+        BlockReceipts { receipts }
+    }
+
+    fn state_update(number: u64) -> v1alpha2::StateUpdate {
+        v1alpha2::StateUpdate {
+            new_root: Some(felt(number + 1)),
+            old_root: Some(felt(number)),
+            state_diff: None,
+        }
+    }
+
+    /// Writes values the way a version 0 database stores them.
+    fn put_uncompressed<T: Table<Key = GlobalBlockId>>(
+        db: &Environment<NoWriteMap>,
+        number: u64,
+        value: &T::Value,
+    ) {
+        let txn = db.begin_rw_txn().unwrap();
+        let table = txn.open_db(Some(T::db_name())).unwrap();
+        txn.put(
+            &table,
+            block_id(number).encode(),
+            value.encode_to_vec(),
+            WriteFlags::default(),
+        )
+        .unwrap();
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_read_version_of_empty_database() {
+        let (_path, db) = open_test_db();
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(read_database_version(&txn).unwrap(), DATABASE_VERSION);
+    }
+
+    #[test]
+    fn test_read_version_of_database_without_version() {
+        let (_path, db) = open_test_db();
+        let txn = db.begin_rw_txn().unwrap();
+        let mut cursor = txn.open_cursor::<tables::CanonicalChainTable>().unwrap();
+        cursor.put(&0, &felt(1)).unwrap();
+        assert_eq!(read_database_version(&txn).unwrap(), 0);
+
+        write_database_version(&txn, 1).unwrap();
+        assert_eq!(read_database_version(&txn).unwrap(), 1);
+        write_database_version(&txn, DATABASE_VERSION).unwrap();
+        assert_eq!(read_database_version(&txn).unwrap(), DATABASE_VERSION);
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_migrate_database() {
+        let (_path, db) = open_test_db();
+
+        // a version 0 database, where some values were written by newer versions.
+        let txn = db.begin_rw_txn().unwrap();
+        let mut cursor = txn.open_cursor::<tables::CanonicalChainTable>().unwrap();
+        cursor.put(&0, &felt(1)).unwrap();
+        txn.commit().unwrap();
+        for number in 0..BLOCK_COUNT {
+            if number % 3 == 0 {
+                let txn = db.begin_rw_txn().unwrap();
+                let id = block_id(number);
+                let mut cursor = txn.open_cursor::<tables::BlockBodyTable>().unwrap();
+                cursor.put(&id, &body(number)).unwrap();
+                let mut cursor = txn.open_cursor::<tables::BlockReceiptsTable>().unwrap();
+                cursor.put(&id, &receipts(number)).unwrap();
+                let mut cursor = txn.open_cursor::<tables::StateUpdateTable>().unwrap();
+                cursor.put(&id, &state_update(number)).unwrap();
+                txn.commit().unwrap();
+            } else {
+                put_uncompressed::<tables::BlockBodyTable>(&db, number, &body(number));
+                put_uncompressed::<tables::BlockReceiptsTable>(&db, number, &receipts(number));
+                put_uncompressed::<tables::StateUpdateTable>(&db, number, &state_update(number));
+            }
+        }
+
+        // the migration was interrupted after the block bodies.
+        train_compression_dictionary::<tables::BlockBodyTable, _>(&db)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            recompress_table::<tables::BlockBodyTable, _>(&db).unwrap(),
+            BLOCK_COUNT as usize
+        );
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(read_database_version(&txn).unwrap(), 0);
+        txn.commit().unwrap();
+
+        // resume the migration.
+        assert_eq!(migrate_database(&db).unwrap(), 2 * BLOCK_COUNT as usize);
+        assert!(has_compression_dictionaries(&db));
+        assert_eq!(migrate_database(&db).unwrap(), 0);
+
+        let txn = db.begin_ro_txn().unwrap();
+        assert_eq!(read_database_version(&txn).unwrap(), DATABASE_VERSION);
+        for number in 0..BLOCK_COUNT {
+            let id = block_id(number);
+            let stored = txn
+                .open_table::<tables::BlockBodyTable>()
+                .unwrap()
+                .get(&id)
+                .unwrap();
+            assert_eq!(stored, Some(body(number)));
+            let stored = txn
+                .open_table::<tables::BlockReceiptsTable>()
+                .unwrap()
+                .get(&id)
+                .unwrap();
+            assert_eq!(stored, Some(receipts(number)));
+            let stored = txn
+                .open_table::<tables::StateUpdateTable>()
+                .unwrap()
+                .get(&id)
+                .unwrap();
+            assert_eq!(stored, Some(state_update(number)));
+        }
+        txn.commit().unwrap();
+    }
+}
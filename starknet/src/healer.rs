@@ -87,6 +87,13 @@ where
 }
 
 impl HealerClient {
+    /// Returns a client not connected to any healer, used in tests.
+    #[cfg(test)]
+    pub fn disconnected() -> Self {
+        let (tx, _) = mpsc::channel(1);
+        HealerClient { tx }
+    }
+
     pub fn status_finalized_expected(&self, cursor: GlobalBlockId) {
         self.send_message(HealerMessage::StatusFinalizedExpected(cursor))
     }
@@ -12,7 +12,10 @@ use crate::{
 };
 
 use super::{
-    config::BlockIngestionConfig, downloader::Downloader, error::BlockIngestionError,
+    admin::{self, AdminCommand, IngestionAdmin},
+    config::BlockIngestionConfig,
+    downloader::Downloader,
+    error::BlockIngestionError,
     subscription::IngestionStreamPublisher,
 };
 
@@ -22,6 +25,7 @@ pub struct AcceptedBlockIngestion<G: Provider + Send, E: EnvironmentKind> {
     downloader: Downloader<G>,
     storage: DatabaseStorage<E>,
     publisher: IngestionStreamPublisher,
+    admin: IngestionAdmin,
 }
 
 struct AcceptedBlockIngestionImpl<G: Provider + Send, E: EnvironmentKind> {
@@ -34,6 +38,7 @@ struct AcceptedBlockIngestionImpl<G: Provider + Send, E: EnvironmentKind> {
     downloader: Downloader<G>,
     storage: DatabaseStorage<E>,
     publisher: IngestionStreamPublisher,
+    admin: IngestionAdmin,
 }
 
 enum TickResult {
@@ -56,6 +61,7 @@ where
         storage: DatabaseStorage<E>,
         config: BlockIngestionConfig,
         publisher: IngestionStreamPublisher,
+        admin: IngestionAdmin,
     ) -> Self {
         let downloader = Downloader::new(provider.clone(), config.rpc_concurrency);
         AcceptedBlockIngestion {
@@ -64,6 +70,7 @@ where
             storage,
             downloader,
             publisher,
+            admin,
         }
     }
 
@@ -95,6 +102,7 @@ where
             storage: self.storage,
             downloader: self.downloader,
             publisher: self.publisher,
+            admin: self.admin,
         };
         ingestion.start(ct).await
     }
@@ -107,6 +115,10 @@ where
 {
     pub async fn start(mut self, ct: CancellationToken) -> Result<(), BlockIngestionError> {
         loop {
+            while let Some(command) = self.admin.next_command(&ct).await {
+                self.handle_admin_command(command).await?;
+            }
+
             if ct.is_cancelled() {
                 return Ok(());
             }
@@ -114,16 +126,58 @@ where
             match self.tick().await? {
                 TickResult::MoreToSync => {}
                 TickResult::FullySynced => {
-                    // no need to do anything for now
-                    tokio::select! {
-                        _ = tokio::time::sleep(self.config.head_refresh_interval) => {},
-                        _ = ct.cancelled() => {},
+                    // wait for the next head refresh, handling admin commands meanwhile.
+                    let refresh = tokio::time::sleep(self.config.head_refresh_interval);
+                    tokio::pin!(refresh);
+                    loop {
+                        tokio::select! {
+                            _ = &mut refresh => break,
+                            _ = ct.cancelled() => break,
+                            Some(command) = self.admin.recv_command(&ct) => {
+                                self.handle_admin_command(command).await?;
+                            }
+                        }
                     }
                 }
             }
         }
     }
 
+    /// Handles an admin command, sending the result back to the caller.
+    async fn handle_admin_command(
+        &mut self,
+        command: AdminCommand,
+    ) -> Result<(), BlockIngestionError> {
+        match command {
+            AdminCommand::ReingestBlocks { start, end, reply } => {
+                let result = admin::reingest_blocks(
+                    self.provider.as_ref(),
+                    &self.downloader,
+                    &self.storage,
+                    &self.publisher,
+                    start,
+                    end,
+                )
+                .await;
+                let _ = reply.send(result);
+            }
+            AdminCommand::InvalidateBlocks { number, reply } => {
+                let result = admin::invalidate_blocks(&self.storage, &self.publisher, number);
+                if let Ok(new_head) = result {
+                    // continue ingesting from the new head. the chain head is
+                    // refreshed at the next tick.
+                    self.previous = new_head;
+                    self.current_head = new_head;
+                    self.pending_ingested = false;
+                    self.finalized = self.storage.highest_finalized_block()?;
+                }
+                let _ = reply.send(result);
+            }
+            AdminCommand::Pause { .. } | AdminCommand::Resume { .. } => {}
+        }
+        Ok(())
+    }
+
     /// Perform one tick in the loop that keeps the indexer up-to-date with the chain.
     ///
     /// If the indexer has not caught up with the head, then it will ingest one more
@@ -0,0 +1,396 @@
+//! Operational control of block ingestion.
+
+use apibara_node::db::libmdbx::EnvironmentKind;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{
+    core::GlobalBlockId,
+    db::{DatabaseStorage, StorageReader, StorageWriter},
+    provider::{BlockId, Provider},
+};
+
+use super::{
+    downloader::Downloader, error::BlockIngestionError, subscription::IngestionStreamPublisher,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum IngestionAdminError {
+    #[error("ingestion is not running")]
+    ChannelClosed,
+    #[error(transparent)]
+    Ingestion(#[from] BlockIngestionError),
+}
+
+/// A command sent to the block ingestion service.
+#[derive(Debug)]
+pub enum AdminCommand {
+    /// Download and store again the given blocks (inclusive range).
+    ReingestBlocks {
+        start: u64,
+        end: u64,
+        reply: oneshot::Sender<Result<u64, BlockIngestionError>>,
+    },
+    /// Remove all blocks after the given block from the canonical chain.
+    InvalidateBlocks {
+        number: u64,
+        reply: oneshot::Sender<Result<GlobalBlockId, BlockIngestionError>>,
+    },
+    /// Pause ingestion.
+    Pause { reply: oneshot::Sender<()> },
+    /// Resume ingestion.
+    Resume { reply: oneshot::Sender<()> },
+}
+
+/// Receives admin commands inside the ingestion loop.
+pub struct IngestionAdmin {
+    rx: mpsc::Receiver<AdminCommand>,
+    paused: bool,
+}
+
+/// Send admin commands to the block ingestion service.
+#[derive(Clone)]
+pub struct IngestionAdminClient {
+    tx: mpsc::Sender<AdminCommand>,
+}
+
+impl IngestionAdmin {
+    pub fn new() -> (IngestionAdminClient, IngestionAdmin) {
+        let (tx, rx) = mpsc::channel(16);
+        let admin = IngestionAdmin { rx, paused: false };
+        let client = IngestionAdminClient { tx };
+        (client, admin)
+    }
+
+    /// Returns the next command that needs to be handled by the ingestion loop.
+    ///
+    /// Pause and resume commands are handled here. While paused, this function
+    /// waits for new commands and only returns `None` after ingestion is resumed
+    /// or cancelled.
+    pub async fn next_command(&mut self, ct: &CancellationToken) -> Option<AdminCommand> {
+        loop {
+            let command = if self.paused {
+                tokio::select! {
+                    _ = ct.cancelled() => return None,
+                    command = self.rx.recv() => command?,
+                }
+            } else {
+                self.rx.try_recv().ok()?
+            };
+
+            if let Some(command) = self.handle_pause_command(command) {
+                return Some(command);
+            }
+        }
+    }
+
+    /// Waits for the next command that needs to be handled by the ingestion loop.
+    ///
+    /// Unlike [IngestionAdmin::next_command], this function waits even if ingestion
+    /// is not paused, so that the ingestion loop can listen for commands while idle.
+    /// Returns `None` if the channel is closed or if cancelled while paused.
+    pub async fn recv_command(&mut self, ct: &CancellationToken) -> Option<AdminCommand> {
+        loop {
+            let command = self.rx.recv().await?;
+            if let Some(command) = self.handle_pause_command(command) {
+                return Some(command);
+            }
+
+            if self.paused {
+                if let Some(command) = self.next_command(ct).await {
+                    return Some(command);
+                }
+                if ct.is_cancelled() {
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Handles pause and resume commands, returning any other command.
+    fn handle_pause_command(&mut self, command: AdminCommand) -> Option<AdminCommand> {
+        match command {
+            AdminCommand::Pause { reply } => {
+                info!("ingestion paused");
+                self.paused = true;
+                let _ = reply.send(());
+                None
+            }
+            AdminCommand::Resume { reply } => {
+                info!("ingestion resumed");
+                self.paused = false;
+                let _ = reply.send(());
+                None
+            }
+            command => Some(command),
+        }
+    }
+}
+
+impl IngestionAdminClient {
+    /// Re-ingest the canonical blocks between `start` and `end` (inclusive).
+    ///
+    /// Returns the number of blocks re-ingested.
+    pub async fn reingest_blocks(&self, start: u64, end: u64) -> Result<u64, IngestionAdminError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(AdminCommand::ReingestBlocks { start, end, reply })
+            .await?;
+        let count = rx.await.map_err(|_| IngestionAdminError::ChannelClosed)??;
+        Ok(count)
+    }
+
+    /// Remove all blocks after `number` from the canonical chain.
+    ///
+    /// Returns the new head of the canonical chain.
+    pub async fn invalidate_blocks(
+        &self,
+        number: u64,
+    ) -> Result<GlobalBlockId, IngestionAdminError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(AdminCommand::InvalidateBlocks { number, reply })
+            .await?;
+        let new_head = rx.await.map_err(|_| IngestionAdminError::ChannelClosed)??;
+        Ok(new_head)
+    }
+
+    /// Pause block ingestion.
+    pub async fn pause(&self) -> Result<(), IngestionAdminError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(AdminCommand::Pause { reply }).await?;
+        rx.await.map_err(|_| IngestionAdminError::ChannelClosed)
+    }
+
+    /// Resume block ingestion.
+    pub async fn resume(&self) -> Result<(), IngestionAdminError> {
+        let (reply, rx) = oneshot::channel();
+        self.send(AdminCommand::Resume { reply }).await?;
+        rx.await.map_err(|_| IngestionAdminError::ChannelClosed)
+    }
+
+    async fn send(&self, command: AdminCommand) -> Result<(), IngestionAdminError> {
+        self.tx
+            .send(command)
+            .await
+            .map_err(|_| IngestionAdminError::ChannelClosed)
+    }
+}
+
+/// Download and store again the canonical blocks between `start` and `end` (inclusive).
+///
+/// Subscribers are notified of each re-ingested block, so that they drop any copy
+/// of its old data.
+pub async fn reingest_blocks<G, E>(
+    provider: &G,
+    downloader: &Downloader<G>,
+    storage: &DatabaseStorage<E>,
+    publisher: &IngestionStreamPublisher,
+    start: u64,
+    end: u64,
+) -> Result<u64, BlockIngestionError>
+where
+    G: Provider + Send,
+    E: EnvironmentKind,
+{
+    info!(start = %start, end = %end, "re-ingesting blocks");
+    let mut count = 0;
+    for number in start..=end {
+        let global_id = storage
+            .canonical_block_id(number)?
+            .ok_or(BlockIngestionError::BlockNotCanonical)?;
+        let block_id = BlockId::Hash(*global_id.hash());
+        let (status, header, body) = provider
+            .get_block(&block_id)
+            .await
+            .map_err(BlockIngestionError::provider)?;
+
+        let mut txn = storage.begin_txn()?;
+        downloader
+            .finish_ingesting_block(&global_id, status, header, body, &mut txn)
+            .await?;
+        txn.commit()?;
+        publisher.publish_reingested(global_id)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Remove all blocks after `number` from the canonical chain and notify subscribers.
+///
+/// Finalized blocks cannot be invalidated, so `number` must be at least the highest
+/// finalized block.
+pub fn invalidate_blocks<E>(
+    storage: &DatabaseStorage<E>,
+    publisher: &IngestionStreamPublisher,
+    number: u64,
+) -> Result<GlobalBlockId, BlockIngestionError>
+where
+    E: EnvironmentKind,
+{
+    if let Some(finalized) = storage.highest_finalized_block()? {
+        if number < finalized.number() {
+            return Err(BlockIngestionError::BlockFinalized);
+        }
+    }
+
+    let new_head = storage
+        .canonical_block_id(number)?
+        .ok_or(BlockIngestionError::BlockNotCanonical)?;
+    let current_head = storage
+        .highest_accepted_block()?
+        .ok_or(BlockIngestionError::InconsistentDatabase)?;
+
+    info!(new_head = %new_head, current_head = %current_head, "invalidating blocks");
+
+    let mut txn = storage.begin_txn()?;
+    for block_number in (number + 1..=current_head.number()).rev() {
+        if let Some(block_id) = storage.canonical_block_id(block_number)? {
+            txn.reject_block_from_canonical_chain(&block_id)?;
+        }
+    }
+    txn.commit()?;
+
+    publisher.publish_invalidate(new_head)?;
+    Ok(new_head)
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::starknet::v1alpha2;
+    use assert_matches::assert_matches;
+    use futures::StreamExt;
+    use tokio::sync::oneshot;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        core::IngestionMessage,
+        db::{
+            test_utils::{block_id, open_test_storage, write_test_block},
+            StorageReader,
+        },
+        ingestion::{error::BlockIngestionError, subscription::IngestionStreamPublisher},
+    };
+
+    use super::{invalidate_blocks, AdminCommand, IngestionAdmin};
+
+    #[tokio::test]
+    async fn test_next_command_while_paused() {
+        let (client, mut admin) = IngestionAdmin::new();
+        let ct = CancellationToken::new();
+
+        // no command, ingestion continues.
+        assert!(admin.next_command(&ct).await.is_none());
+
+        let (pause_reply, pause_rx) = oneshot::channel();
+        client
+            .send(AdminCommand::Pause { reply: pause_reply })
+            .await
+            .unwrap();
+        let (reply, _rx) = oneshot::channel();
+        client
+            .send(AdminCommand::InvalidateBlocks { number: 1, reply })
+            .await
+            .unwrap();
+
+        // other commands are handled while paused.
+        let command = admin.next_command(&ct).await;
+        assert_matches!(
+            command,
+            Some(AdminCommand::InvalidateBlocks { number: 1, .. })
+        );
+        pause_rx.await.unwrap();
+
+        // waits for the resume command.
+        let resume = tokio::spawn(async move { client.resume().await });
+        assert!(admin.next_command(&ct).await.is_none());
+        resume.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_next_command_cancelled_while_paused() {
+        let (client, mut admin) = IngestionAdmin::new();
+        let ct = CancellationToken::new();
+
+        let pause = tokio::spawn(async move { client.pause().await });
+        // handles the pause command, then waits until cancelled.
+        ct.cancel();
+        while !pause.is_finished() {
+            assert!(admin.next_command(&ct).await.is_none());
+            tokio::task::yield_now().await;
+        }
+        pause.await.unwrap().unwrap();
+        assert!(admin.paused);
+    }
+
+    #[tokio::test]
+    async fn test_recv_command_waits_for_commands() {
+        let (client, mut admin) = IngestionAdmin::new();
+        let ct = CancellationToken::new();
+
+        let invalidate = tokio::spawn({
+            let client = client.clone();
+            async move { client.invalidate_blocks(1).await }
+        });
+        let command = admin.recv_command(&ct).await;
+        assert_matches!(command, Some(AdminCommand::InvalidateBlocks { number: 1, reply }) => {
+            reply.send(Ok(block_id(1))).unwrap();
+        });
+        assert_eq!(invalidate.await.unwrap().unwrap(), block_id(1));
+
+        // pause and resume are handled while waiting.
+        let pause = tokio::spawn(async move {
+            client.pause().await.unwrap();
+            client.resume().await.unwrap();
+            client.invalidate_blocks(2).await
+        });
+        let command = admin.recv_command(&ct).await;
+        assert_matches!(command, Some(AdminCommand::InvalidateBlocks { number: 2, reply }) => {
+            reply.send(Ok(block_id(2))).unwrap();
+        });
+        assert_eq!(pause.await.unwrap().unwrap(), block_id(2));
+        assert!(!admin.paused);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_blocks() {
+        let (_path, storage) = open_test_storage();
+        for number in 0..5 {
+            let status = if number < 2 {
+                v1alpha2::BlockStatus::AcceptedOnL1
+            } else {
+                v1alpha2::BlockStatus::AcceptedOnL2
+            };
+            write_test_block(&storage, number, status);
+        }
+
+        let (client, publisher) = IngestionStreamPublisher::new();
+        let mut stream = client.subscribe().await;
+
+        let new_head = invalidate_blocks(&storage, &publisher, 2).unwrap();
+        assert_eq!(new_head, block_id(2));
+        assert_eq!(storage.highest_accepted_block().unwrap(), Some(block_id(2)));
+        assert_eq!(storage.canonical_block_id(3).unwrap(), None);
+
+        // subscribers are notified of the new head.
+        let message = stream.next().await.unwrap().unwrap();
+        assert_matches!(message, IngestionMessage::Invalidate(id) => {
+            assert_eq!(id, block_id(2));
+        });
+
+        assert_matches!(
+            invalidate_blocks(&storage, &publisher, 3),
+            Err(BlockIngestionError::BlockNotCanonical)
+        );
+
+        // the highest finalized block can become the head, but not removed.
+        assert_eq!(
+            invalidate_blocks(&storage, &publisher, 1).unwrap(),
+            block_id(1)
+        );
+        assert_matches!(
+            invalidate_blocks(&storage, &publisher, 0),
+            Err(BlockIngestionError::BlockFinalized)
+        );
+        assert_eq!(storage.canonical_block_id(1).unwrap(), Some(block_id(1)));
+    }
+}
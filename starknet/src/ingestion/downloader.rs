@@ -7,7 +7,7 @@ use futures::{stream, StreamExt};
 
 use crate::{
     core::GlobalBlockId,
-    db::{BlockBody, StorageWriter},
+    db::{deployed_contracts, BlockBloom, BlockBody, StorageWriter},
     provider::{BlockId, Provider},
 };
 
@@ -94,16 +94,26 @@ where
             None
         };
 
+        let deployed_contracts =
+            deployed_contracts(&body.transactions, &receipts, state_update.as_ref());
+        let bloom = BlockBloom::from_block(&body.transactions, &receipts, state_update.as_ref());
+
         // write block status, header, body, receipts and state update to storage
         writer.write_status(global_id, status)?;
         writer.write_header(global_id, header)?;
         writer.write_body(global_id, body)?;
+        writer.write_block_bloom(global_id, bloom)?;
+        writer.write_indexes(global_id, &receipts)?;
         writer.write_receipts(global_id, receipts)?;
 
         if let Some(state_update) = state_update {
             writer.write_state_update(global_id, state_update)?;
         }
 
+        for (address, class_hash) in deployed_contracts {
+            writer.write_contract_class_hash(&address, class_hash)?;
+        }
+
         Ok(())
     }
 }
@@ -20,6 +20,8 @@ pub enum BlockIngestionError {
     InconsistentDatabase,
     #[error("tried to access a block as canonical, but it's not")]
     BlockNotCanonical,
+    #[error("tried to invalidate a finalized block")]
+    BlockFinalized,
     #[error(transparent)]
     InvalidBlockHash(#[from] InvalidBlockHashSize),
     #[error(transparent)]
@@ -14,7 +14,10 @@ use crate::{
 };
 
 use super::{
-    config::BlockIngestionConfig, downloader::Downloader, error::BlockIngestionError,
+    admin::{self, AdminCommand, IngestionAdmin},
+    config::BlockIngestionConfig,
+    downloader::Downloader,
+    error::BlockIngestionError,
     subscription::IngestionStreamPublisher,
 };
 
@@ -24,6 +27,7 @@ pub struct FinalizedBlockIngestion<G: Provider + Send, E: EnvironmentKind> {
     downloader: Downloader<G>,
     storage: DatabaseStorage<E>,
     publisher: IngestionStreamPublisher,
+    admin: IngestionAdmin,
 }
 
 #[derive(Debug)]
@@ -43,6 +47,7 @@ where
         storage: DatabaseStorage<E>,
         config: BlockIngestionConfig,
         publisher: IngestionStreamPublisher,
+        admin: IngestionAdmin,
     ) -> Self {
         let downloader = Downloader::new(provider.clone(), config.rpc_concurrency);
         FinalizedBlockIngestion {
@@ -51,11 +56,12 @@ where
             storage,
             downloader,
             publisher,
+            admin,
         }
     }
 
     pub async fn start(
-        self,
+        mut self,
         latest_indexed: GlobalBlockId,
         ct: CancellationToken,
     ) -> Result<(), BlockIngestionError> {
@@ -74,6 +80,12 @@ where
         let mut current_block = latest_indexed;
 
         let latest_indexed = loop {
+            while let Some(command) = self.admin.next_command(&ct).await {
+                if let Some(new_head) = self.handle_admin_command(command).await {
+                    current_block = new_head;
+                }
+            }
+
             if ct.is_cancelled() {
                 return Ok(());
             }
@@ -85,7 +97,20 @@ where
                     current_block = global_id;
                 }
                 IngestResult::RetryWithDelay(delay) => {
-                    tokio::time::sleep(delay).await;
+                    // handle admin commands while waiting.
+                    let retry = tokio::time::sleep(delay);
+                    tokio::pin!(retry);
+                    loop {
+                        tokio::select! {
+                            _ = &mut retry => break,
+                            _ = ct.cancelled() => break,
+                            Some(command) = self.admin.recv_command(&ct) => {
+                                if let Some(new_head) = self.handle_admin_command(command).await {
+                                    current_block = new_head;
+                                }
+                            }
+                        }
+                    }
                 }
                 IngestResult::TransitionToAccepted(global_id) => {
                     info!(
@@ -97,9 +122,41 @@ where
             }
         };
 
-        AcceptedBlockIngestion::new(self.provider, self.storage, self.config, self.publisher)
-            .start(latest_indexed, ct)
-            .await
+        AcceptedBlockIngestion::new(
+            self.provider,
+            self.storage,
+            self.config,
+            self.publisher,
+            self.admin,
+        )
+        .start(latest_indexed, ct)
+        .await
+    }
+
+    /// Handles an admin command, returning the new chain head if it changed.
+    async fn handle_admin_command(&self, command: AdminCommand) -> Option<GlobalBlockId> {
+        match command {
+            AdminCommand::ReingestBlocks { start, end, reply } => {
+                let result = admin::reingest_blocks(
+                    self.provider.as_ref(),
+                    &self.downloader,
+                    &self.storage,
+                    &self.publisher,
+                    start,
+                    end,
+                )
+                .await;
+                let _ = reply.send(result);
+                None
+            }
+            AdminCommand::InvalidateBlocks { number, reply } => {
+                let result = admin::invalidate_blocks(&self.storage, &self.publisher, number);
+                let new_head = result.as_ref().ok().cloned();
+                let _ = reply.send(result);
+                new_head
+            }
+            AdminCommand::Pause { .. } | AdminCommand::Resume { .. } => None,
+        }
     }
 
     #[tracing::instrument(skip(self))]
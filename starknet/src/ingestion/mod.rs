@@ -1,4 +1,5 @@
 mod accepted;
+mod admin;
 mod config;
 mod downloader;
 mod error;
@@ -13,9 +14,15 @@ use tokio_util::sync::CancellationToken;
 
 use crate::{db::DatabaseStorage, provider::Provider};
 
-use self::{started::StartedBlockIngestion, subscription::IngestionStreamPublisher};
+use self::started::StartedBlockIngestion;
+
+pub(crate) use self::{admin::IngestionAdmin, subscription::IngestionStreamPublisher};
+
+#[cfg(test)]
+pub(crate) use self::admin::AdminCommand;
 
 pub use self::{
+    admin::{IngestionAdminClient, IngestionAdminError},
     config::BlockIngestionConfig,
     error::BlockIngestionError,
     subscription::{IngestionStream, IngestionStreamClient},
@@ -27,6 +34,8 @@ pub struct BlockIngestion<G: Provider + Send, E: EnvironmentKind> {
     provider: Arc<G>,
     storage: DatabaseStorage<E>,
     publisher: IngestionStreamPublisher,
+    admin: IngestionAdmin,
+    admin_client: IngestionAdminClient,
 }
 
 impl<G, E> BlockIngestion<G, E>
@@ -41,20 +50,34 @@ where
     ) -> (IngestionStreamClient, Self) {
         let storage = DatabaseStorage::new(db);
         let (sub_client, publisher) = IngestionStreamPublisher::new();
+        let (admin_client, admin) = IngestionAdmin::new();
 
         let ingestion = BlockIngestion {
             provider,
             storage,
             config,
             publisher,
+            admin,
+            admin_client,
         };
         (sub_client, ingestion)
     }
 
+    /// Returns a client used to control ingestion.
+    pub fn admin_client(&self) -> IngestionAdminClient {
+        self.admin_client.clone()
+    }
+
     /// Start ingesting blocks.
     pub async fn start(self, ct: CancellationToken) -> Result<(), BlockIngestionError> {
-        StartedBlockIngestion::new(self.provider, self.storage, self.config, self.publisher)
-            .start(ct)
-            .await
+        StartedBlockIngestion::new(
+            self.provider,
+            self.storage,
+            self.config,
+            self.publisher,
+            self.admin,
+        )
+        .start(ct)
+        .await
     }
 }
@@ -14,8 +14,8 @@ use crate::{
 };
 
 use super::{
-    accepted::AcceptedBlockIngestion, config::BlockIngestionConfig, downloader::Downloader,
-    error::BlockIngestionError, subscription::IngestionStreamPublisher,
+    accepted::AcceptedBlockIngestion, admin::IngestionAdmin, config::BlockIngestionConfig,
+    downloader::Downloader, error::BlockIngestionError, subscription::IngestionStreamPublisher,
 };
 
 pub struct StartedBlockIngestion<G: Provider + Send, E: EnvironmentKind> {
@@ -24,6 +24,7 @@ pub struct StartedBlockIngestion<G: Provider + Send, E: EnvironmentKind> {
     downloader: Downloader<G>,
     storage: DatabaseStorage<E>,
     publisher: IngestionStreamPublisher,
+    admin: IngestionAdmin,
 }
 
 impl<G, E> StartedBlockIngestion<G, E>
@@ -36,6 +37,7 @@ where
         storage: DatabaseStorage<E>,
         config: BlockIngestionConfig,
         publisher: IngestionStreamPublisher,
+        admin: IngestionAdmin,
     ) -> Self {
         let downloader = Downloader::new(provider.clone(), config.rpc_concurrency);
         StartedBlockIngestion {
@@ -44,6 +46,7 @@ where
             storage,
             downloader,
             publisher,
+            admin,
         }
     }
 
@@ -87,11 +90,23 @@ where
     }
 
     fn into_accepted_block_ingestion(self) -> AcceptedBlockIngestion<G, E> {
-        AcceptedBlockIngestion::new(self.provider, self.storage, self.config, self.publisher)
+        AcceptedBlockIngestion::new(
+            self.provider,
+            self.storage,
+            self.config,
+            self.publisher,
+            self.admin,
+        )
     }
 
     fn into_finalized_block_ingestion(self) -> FinalizedBlockIngestion<G, E> {
-        FinalizedBlockIngestion::new(self.provider, self.storage, self.config, self.publisher)
+        FinalizedBlockIngestion::new(
+            self.provider,
+            self.storage,
+            self.config,
+            self.publisher,
+            self.admin,
+        )
     }
 
     async fn block_status(
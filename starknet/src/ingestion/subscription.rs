@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use tokio::sync::broadcast;
 use tokio_stream::wrappers::BroadcastStream;
@@ -13,22 +13,26 @@ pub type IngestionStream = BroadcastStream<IngestionMessage>;
 pub struct IngestionStreamPublisher {
     tx: Arc<broadcast::Sender<IngestionMessage>>,
     _rx: broadcast::Receiver<IngestionMessage>,
+    pending: Arc<Mutex<Option<GlobalBlockId>>>,
 }
 
 pub struct IngestionStreamClient {
     tx: Arc<broadcast::Sender<IngestionMessage>>,
+    pending: Arc<Mutex<Option<GlobalBlockId>>>,
 }
 
 impl IngestionStreamPublisher {
     pub fn new() -> (IngestionStreamClient, IngestionStreamPublisher) {
         let (tx, rx) = broadcast::channel(128);
         let tx = Arc::new(tx);
+        let pending = Arc::new(Mutex::new(None));
 
         let manager = IngestionStreamPublisher {
             tx: tx.clone(),
             _rx: rx,
+            pending: pending.clone(),
         };
-        let client = IngestionStreamClient { tx };
+        let client = IngestionStreamClient { tx, pending };
         (client, manager)
     }
 
@@ -37,17 +41,28 @@ impl IngestionStreamPublisher {
     }
 
     pub fn publish_accepted(&self, id: GlobalBlockId) -> Result<(), BlockIngestionError> {
+        self.set_pending(None);
         self.publish(IngestionMessage::Accepted(id))
     }
 
     pub fn publish_pending(&self, id: GlobalBlockId) -> Result<(), BlockIngestionError> {
+        self.set_pending(Some(id));
         self.publish(IngestionMessage::Pending(id))
     }
 
     pub fn publish_invalidate(&self, id: GlobalBlockId) -> Result<(), BlockIngestionError> {
+        self.set_pending(None);
         self.publish(IngestionMessage::Invalidate(id))
     }
 
+    pub fn publish_reingested(&self, id: GlobalBlockId) -> Result<(), BlockIngestionError> {
+        self.publish(IngestionMessage::Reingested(id))
+    }
+
+    fn set_pending(&self, id: Option<GlobalBlockId>) {
+        *self.pending.lock().expect("pending lock poisoned") = id;
+    }
+
     fn publish(&self, message: IngestionMessage) -> Result<(), BlockIngestionError> {
         self.tx
             .send(message)
@@ -61,4 +76,9 @@ impl IngestionStreamClient {
         debug!("subscribing to ingestion stream");
         BroadcastStream::new(self.tx.subscribe())
     }
+
+    /// Returns the most recent pending block, if any.
+    pub fn pending_block(&self) -> Option<GlobalBlockId> {
+        *self.pending.lock().expect("pending lock poisoned")
+    }
 }
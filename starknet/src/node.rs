@@ -16,23 +16,50 @@ use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
 use crate::{
-    db::tables,
+    db::{
+        has_compression_dictionaries, read_database_version, tables, write_database_version,
+        DATABASE_VERSION, DEFAULT_BLOCK_CACHE_SIZE,
+    },
     healer::{Healer, HealerError},
     ingestion::{BlockIngestion, BlockIngestionConfig, BlockIngestionError},
     provider::{HttpProviderError, Provider},
-    server::{RequestObserver, Server, ServerError, SimpleRequestObserver},
+    server::{
+        AdminServer, AllowAllAuthenticator, RequestAuthenticator, RequestObserver, Server,
+        ServerError, SimpleRequestObserver, TlsConfig,
+    },
     HttpProvider,
 };
 
-pub struct StarkNetNode<G, O, E>
+/// Default address used by the stream server.
+pub const DEFAULT_SERVER_ADDRESS: &str = "0.0.0.0:7171";
+
+pub struct StarkNetNode<G, O, A, E>
 where
     G: Provider + Send + Sync + 'static,
     O: RequestObserver,
+    A: RequestAuthenticator,
     E: EnvironmentKind,
 {
     db: Arc<Environment<E>>,
     sequencer_provider: Arc<G>,
     request_span: O,
+    authenticator: A,
+    server_address: String,
+    tls_config: Option<TlsConfig>,
+    http_address: Option<String>,
+    admin: Option<AdminConfig>,
+    block_cache_size: usize,
+}
+
+/// Address, token and TLS configuration used by the admin server.
+///
+/// The admin server doesn't share the TLS configuration of the stream server, so
+/// that it can require different client certificates.
+#[derive(Debug, Clone)]
+pub struct AdminConfig {
+    pub address: String,
+    pub token: String,
+    pub tls_config: Option<TlsConfig>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -47,28 +74,51 @@ pub enum StarkNetNodeError {
     Healer(#[from] HealerError),
     #[error("error parsing server address")]
     AddressParseError(#[from] AddrParseError),
+    #[error("database format version {0} is not supported by this version of the node")]
+    UnsupportedDatabaseVersion(u32),
 }
 
-impl<G, O, E> StarkNetNode<G, O, E>
+impl<G, O, A, E> StarkNetNode<G, O, A, E>
 where
     G: Provider + Send + Sync + 'static,
     O: RequestObserver,
+    A: RequestAuthenticator,
     E: EnvironmentKind,
 {
     /// Creates a new builder, used to configure the node.
     pub fn builder(
         url: &str,
-    ) -> Result<StarkNetNodeBuilder<SimpleRequestObserver, E>, StarkNetNodeBuilderError> {
-        StarkNetNodeBuilder::<SimpleRequestObserver, E>::new(url)
+    ) -> Result<
+        StarkNetNodeBuilder<SimpleRequestObserver, AllowAllAuthenticator, E>,
+        StarkNetNodeBuilderError,
+    > {
+        StarkNetNodeBuilder::<SimpleRequestObserver, AllowAllAuthenticator, E>::new(url)
     }
 
-    pub(crate) fn new(db: Environment<E>, sequencer_provider: G, request_span: O) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        db: Environment<E>,
+        sequencer_provider: G,
+        request_span: O,
+        authenticator: A,
+        server_address: String,
+        tls_config: Option<TlsConfig>,
+        http_address: Option<String>,
+        admin: Option<AdminConfig>,
+        block_cache_size: usize,
+    ) -> Self {
         let db = Arc::new(db);
         let sequencer_provider = Arc::new(sequencer_provider);
         StarkNetNode {
             db,
             sequencer_provider,
             request_span,
+            authenticator,
+            server_address,
+            tls_config,
+            http_address,
+            admin,
+            block_cache_size,
         }
     }
 
@@ -83,6 +133,7 @@ where
             self.db.clone(),
             BlockIngestionConfig::default(),
         );
+        let admin_client = block_ingestion.admin_client();
 
         let mut block_ingestion_handle = tokio::spawn({
             let ct = ct.clone();
@@ -101,10 +152,46 @@ where
             async move { healer.start(ct).await.map_err(StarkNetNodeError::Healer) }
         });
 
-        // TODO: configure from command line
-        let server_addr: SocketAddr = "0.0.0.0:7171".parse()?;
-        let server = Server::<E, O>::new(self.db.clone(), block_ingestion_client, healer_client)
-            .with_request_observer(self.request_span);
+        let mut admin_handle = tokio::spawn({
+            let ct = ct.clone();
+            let admin = self.admin;
+            async move {
+                let admin = match admin {
+                    None => {
+                        // keep the task alive so that it doesn't stop the node
+                        ct.cancelled().await;
+                        return Ok(());
+                    }
+                    Some(admin) => admin,
+                };
+                let admin_addr: SocketAddr = admin.address.parse()?;
+                let mut admin_server = AdminServer::new(admin_client, admin.token);
+                if let Some(tls_config) = admin.tls_config {
+                    admin_server = admin_server.with_tls_config(tls_config);
+                }
+                admin_server
+                    .start(admin_addr, ct)
+                    .await
+                    .map_err(StarkNetNodeError::Server)
+            }
+        });
+
+        let server_addr: SocketAddr = self.server_address.parse()?;
+        let mut server = Server::<G, E, O, A>::new(
+            self.db.clone(),
+            self.sequencer_provider.clone(),
+            block_ingestion_client,
+            healer_client,
+        )
+        .with_request_observer(self.request_span)
+        .with_request_authenticator(self.authenticator)
+        .with_block_cache_size(self.block_cache_size);
+        if let Some(tls_config) = self.tls_config {
+            server = server.with_tls_config(tls_config);
+        }
+        if let Some(http_address) = self.http_address {
+            server = server.with_http_address(http_address.parse()?);
+        }
         let mut server_handle = tokio::spawn({
             let ct = ct.clone();
             async move {
@@ -127,6 +214,9 @@ where
             ret = &mut healer_handle => {
                 warn!(result = ?ret, "healer terminated");
             }
+            ret = &mut admin_handle => {
+                warn!(result = ?ret, "admin server terminated");
+            }
         }
 
         info!("terminated. bye");
@@ -136,16 +226,44 @@ where
     fn ensure_tables(&self) -> Result<(), StarkNetNodeError> {
         let txn = self.db.begin_rw_txn()?;
         tables::ensure(&txn)?;
+
+        let version = read_database_version(&txn)?;
+        if version > DATABASE_VERSION {
+            return Err(StarkNetNodeError::UnsupportedDatabaseVersion(version));
+        }
+        if version < DATABASE_VERSION {
+            // data in the old format can still be read, it's just bigger.
+            warn!(
+                version = %version,
+                "database format is outdated. run `apibara-starknet db migrate` to upgrade it"
+            );
+        } else {
+            write_database_version(&txn, version)?;
+        }
+
         txn.commit()?;
+
+        if !has_compression_dictionaries(&self.db) {
+            info!(
+                "block data is compressed without dictionaries. run `apibara-starknet db migrate` after ingesting some blocks to train them"
+            );
+        }
+
         Ok(())
     }
 }
 
-pub struct StarkNetNodeBuilder<O: RequestObserver, E: EnvironmentKind> {
+pub struct StarkNetNodeBuilder<O: RequestObserver, A: RequestAuthenticator, E: EnvironmentKind> {
     datadir: PathBuf,
     provider: HttpProvider,
     poll_interval: Duration,
     request_observer: O,
+    authenticator: A,
+    server_address: String,
+    tls_config: Option<TlsConfig>,
+    http_address: Option<String>,
+    admin: Option<AdminConfig>,
+    block_cache_size: usize,
     _phantom: PhantomData<E>,
 }
 
@@ -161,14 +279,18 @@ pub enum StarkNetNodeBuilderError {
     Provider(#[from] HttpProviderError),
 }
 
-impl<O, E> StarkNetNodeBuilder<O, E>
+impl<O, A, E> StarkNetNodeBuilder<O, A, E>
 where
     O: RequestObserver,
+    A: RequestAuthenticator,
     E: EnvironmentKind,
 {
     pub(crate) fn new(
         url: &str,
-    ) -> Result<StarkNetNodeBuilder<SimpleRequestObserver, E>, StarkNetNodeBuilderError> {
+    ) -> Result<
+        StarkNetNodeBuilder<SimpleRequestObserver, AllowAllAuthenticator, E>,
+        StarkNetNodeBuilderError,
+    > {
         let datadir = default_data_dir()
             .map(|d| d.join("starknet"))
             .expect("no datadir");
@@ -176,11 +298,18 @@ where
         let sequencer = HttpProvider::new(url);
         let poll_interval = Duration::from_millis(5_000);
         let request_observer = SimpleRequestObserver::default();
+        let authenticator = AllowAllAuthenticator::default();
         let builder = StarkNetNodeBuilder {
             datadir,
             provider: sequencer,
             poll_interval,
             request_observer,
+            authenticator,
+            server_address: DEFAULT_SERVER_ADDRESS.to_string(),
+            tls_config: None,
+            http_address: None,
+            admin: None,
+            block_cache_size: DEFAULT_BLOCK_CACHE_SIZE,
             _phantom: Default::default(),
         };
         Ok(builder)
@@ -194,20 +323,76 @@ where
         self.poll_interval = poll_interval;
     }
 
+    /// Listen for stream requests on the given address.
+    pub fn with_server_address(&mut self, address: String) {
+        self.server_address = address;
+    }
+
+    /// Serve stream requests over TLS.
+    pub fn with_tls_config(&mut self, tls_config: TlsConfig) {
+        self.tls_config = Some(tls_config);
+    }
+
+    /// Also stream data as server-sent events on the given address.
+    pub fn with_http_address(&mut self, address: String) {
+        self.http_address = Some(address);
+    }
+
+    /// Serve the admin service on the given address, authenticating requests with `token`.
+    ///
+    /// The admin service is served over TLS only if `tls_config` is set.
+    pub fn with_admin(&mut self, address: String, token: String, tls_config: Option<TlsConfig>) {
+        self.admin = Some(AdminConfig {
+            address,
+            token,
+            tls_config,
+        });
+    }
+
+    /// Cache the data of up to `size` blocks, shared by all streams.
+    pub fn with_block_cache_size(&mut self, size: usize) {
+        self.block_cache_size = size;
+    }
+
     pub fn with_request_observer<N: RequestObserver>(
         self,
         request_observer: N,
-    ) -> StarkNetNodeBuilder<N, E> {
+    ) -> StarkNetNodeBuilder<N, A, E> {
         StarkNetNodeBuilder {
             datadir: self.datadir,
             provider: self.provider,
             poll_interval: self.poll_interval,
             request_observer,
+            authenticator: self.authenticator,
+            server_address: self.server_address,
+            tls_config: self.tls_config,
+            http_address: self.http_address,
+            admin: self.admin,
+            block_cache_size: self.block_cache_size,
             _phantom: self._phantom,
         }
     }
 
-    pub fn build(self) -> Result<StarkNetNode<HttpProvider, O, E>, StarkNetNodeBuilderError> {
+    pub fn with_request_authenticator<N: RequestAuthenticator>(
+        self,
+        authenticator: N,
+    ) -> StarkNetNodeBuilder<O, N, E> {
+        StarkNetNodeBuilder {
+            datadir: self.datadir,
+            provider: self.provider,
+            poll_interval: self.poll_interval,
+            request_observer: self.request_observer,
+            authenticator,
+            server_address: self.server_address,
+            tls_config: self.tls_config,
+            http_address: self.http_address,
+            admin: self.admin,
+            block_cache_size: self.block_cache_size,
+            _phantom: self._phantom,
+        }
+    }
+
+    pub fn build(self) -> Result<StarkNetNode<HttpProvider, O, A, E>, StarkNetNodeBuilderError> {
         fs::create_dir_all(&self.datadir).map_err(StarkNetNodeBuilderError::CreateDatadir)?;
 
         let db = Environment::<E>::builder()
@@ -216,6 +401,16 @@ where
             .open(&self.datadir)
             .map_err(StarkNetNodeBuilderError::DatabaseOpen)?;
 
-        Ok(StarkNetNode::new(db, self.provider, self.request_observer))
+        Ok(StarkNetNode::new(
+            db,
+            self.provider,
+            self.request_observer,
+            self.authenticator,
+            self.server_address,
+            self.tls_config,
+            self.http_address,
+            self.admin,
+            self.block_cache_size,
+        ))
     }
 }
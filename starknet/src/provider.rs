@@ -30,6 +30,9 @@ pub trait Provider {
     /// Get the most recent accepted block number and hash.
     async fn get_head(&self) -> Result<GlobalBlockId, Self::Error>;
 
+    /// Get the chain id, as a hex string.
+    async fn get_chain_id(&self) -> Result<String, Self::Error>;
+
     /// Get a specific block.
     async fn get_block(
         &self,
@@ -129,6 +132,16 @@ impl Provider for HttpProvider {
         ))
     }
 
+    #[tracing::instrument(skip(self))]
+    async fn get_chain_id(&self) -> Result<String, Self::Error> {
+        let chain_id = self
+            .provider
+            .chain_id()
+            .await
+            .map_err(HttpProviderError::from_provider_error)?;
+        Ok(format!("{:#x}", chain_id))
+    }
+
     #[tracing::instrument(skip(self))]
     async fn get_block(
         &self,
@@ -381,6 +394,7 @@ impl ToProto<v1alpha2::Transaction> for jsonrpc::models::InvokeTransactionV1 {
         let invoke_v1 = v1alpha2::InvokeTransactionV1 {
             sender_address: Some(sender_address),
             calldata,
+            calls: Vec::default(),
         };
 
         v1alpha2::Transaction {
@@ -552,90 +566,49 @@ impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::PendingTransacti
 
 impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::PendingInvokeTransactionReceipt {
     fn to_proto(&self) -> v1alpha2::TransactionReceipt {
-        let transaction_hash = self.transaction_hash.into();
-        let actual_fee = self.actual_fee.into();
-        let l2_to_l1_messages = self
-            .messages_sent
-            .iter()
-            .map(|msg| msg.to_proto())
-            .collect();
-        let events = self.events.iter().map(|ev| ev.to_proto()).collect();
-
-        v1alpha2::TransactionReceipt {
-            transaction_index: 0,
-            transaction_hash: Some(transaction_hash),
-            actual_fee: Some(actual_fee),
-            l2_to_l1_messages,
-            events,
-            contract_address: None,
-        }
+        receipt_to_proto(
+            &self.transaction_hash,
+            &self.actual_fee,
+            &self.messages_sent,
+            &self.events,
+            None,
+        )
     }
 }
 
 impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::PendingL1HandlerTransactionReceipt {
     fn to_proto(&self) -> v1alpha2::TransactionReceipt {
-        let transaction_hash = self.transaction_hash.into();
-        let actual_fee = self.actual_fee.into();
-        let l2_to_l1_messages = self
-            .messages_sent
-            .iter()
-            .map(|msg| msg.to_proto())
-            .collect();
-        let events = self.events.iter().map(|ev| ev.to_proto()).collect();
-
-        v1alpha2::TransactionReceipt {
-            transaction_index: 0,
-            transaction_hash: Some(transaction_hash),
-            actual_fee: Some(actual_fee),
-            l2_to_l1_messages,
-            events,
-            contract_address: None,
-        }
+        receipt_to_proto(
+            &self.transaction_hash,
+            &self.actual_fee,
+            &self.messages_sent,
+            &self.events,
+            None,
+        )
     }
 }
 
 impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::PendingDeclareTransactionReceipt {
     fn to_proto(&self) -> v1alpha2::TransactionReceipt {
-        let transaction_hash = self.transaction_hash.into();
-        let actual_fee = self.actual_fee.into();
-        let l2_to_l1_messages = self
-            .messages_sent
-            .iter()
-            .map(|msg| msg.to_proto())
-            .collect();
-        let events = self.events.iter().map(|ev| ev.to_proto()).collect();
-
-        v1alpha2::TransactionReceipt {
-            transaction_index: 0,
-            transaction_hash: Some(transaction_hash),
-            actual_fee: Some(actual_fee),
-            l2_to_l1_messages,
-            events,
-            contract_address: None,
-        }
+        receipt_to_proto(
+            &self.transaction_hash,
+            &self.actual_fee,
+            &self.messages_sent,
+            &self.events,
+            None,
+        )
     }
 }
 
 impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::PendingDeployTransactionReceipt {
     fn to_proto(&self) -> v1alpha2::TransactionReceipt {
-        let transaction_hash = self.transaction_hash.into();
-        let actual_fee = self.actual_fee.into();
-        let l2_to_l1_messages = self
-            .messages_sent
-            .iter()
-            .map(|msg| msg.to_proto())
-            .collect();
-        let events = self.events.iter().map(|ev| ev.to_proto()).collect();
-        let contract_address = self.contract_address.into();
-
-        v1alpha2::TransactionReceipt {
-            transaction_index: 0,
-            transaction_hash: Some(transaction_hash),
-            actual_fee: Some(actual_fee),
-            l2_to_l1_messages,
-            events,
-            contract_address: Some(contract_address),
-        }
+        receipt_to_proto(
+            &self.transaction_hash,
+            &self.actual_fee,
+            &self.messages_sent,
+            &self.events,
+            Some(&self.contract_address),
+        )
     }
 }
 
@@ -643,23 +616,13 @@ impl ToProto<v1alpha2::TransactionReceipt>
     for jsonrpc::models::PendingDeployAccountTransactionReceipt
 {
     fn to_proto(&self) -> v1alpha2::TransactionReceipt {
-        let transaction_hash = self.transaction_hash.into();
-        let actual_fee = self.actual_fee.into();
-        let l2_to_l1_messages = self
-            .messages_sent
-            .iter()
-            .map(|msg| msg.to_proto())
-            .collect();
-        let events = self.events.iter().map(|ev| ev.to_proto()).collect();
-
-        v1alpha2::TransactionReceipt {
-            transaction_index: 0,
-            transaction_hash: Some(transaction_hash),
-            actual_fee: Some(actual_fee),
-            l2_to_l1_messages,
-            events,
-            contract_address: None,
-        }
+        receipt_to_proto(
+            &self.transaction_hash,
+            &self.actual_fee,
+            &self.messages_sent,
+            &self.events,
+            None,
+        )
     }
 }
 
@@ -679,114 +642,98 @@ impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::TransactionRecei
 
 impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::InvokeTransactionReceipt {
     fn to_proto(&self) -> v1alpha2::TransactionReceipt {
-        let transaction_hash = self.transaction_hash.into();
-        let actual_fee = self.actual_fee.into();
-        let l2_to_l1_messages = self
-            .messages_sent
-            .iter()
-            .map(|msg| msg.to_proto())
-            .collect();
-        let events = self.events.iter().map(|ev| ev.to_proto()).collect();
-
-        v1alpha2::TransactionReceipt {
-            transaction_index: 0,
-            transaction_hash: Some(transaction_hash),
-            actual_fee: Some(actual_fee),
-            l2_to_l1_messages,
-            events,
-            contract_address: None,
-        }
+        receipt_to_proto(
+            &self.transaction_hash,
+            &self.actual_fee,
+            &self.messages_sent,
+            &self.events,
+            None,
+        )
     }
 }
 
 impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::L1HandlerTransactionReceipt {
     fn to_proto(&self) -> v1alpha2::TransactionReceipt {
-        let transaction_hash = self.transaction_hash.into();
-        let actual_fee = self.actual_fee.into();
-        let l2_to_l1_messages = self
-            .messages_sent
-            .iter()
-            .map(|msg| msg.to_proto())
-            .collect();
-        let events = self.events.iter().map(|ev| ev.to_proto()).collect();
-
-        v1alpha2::TransactionReceipt {
-            transaction_index: 0,
-            transaction_hash: Some(transaction_hash),
-            actual_fee: Some(actual_fee),
-            l2_to_l1_messages,
-            events,
-            contract_address: None,
-        }
+        receipt_to_proto(
+            &self.transaction_hash,
+            &self.actual_fee,
+            &self.messages_sent,
+            &self.events,
+            None,
+        )
     }
 }
 
 impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::DeclareTransactionReceipt {
     fn to_proto(&self) -> v1alpha2::TransactionReceipt {
-        let transaction_hash = self.transaction_hash.into();
-        let actual_fee = self.actual_fee.into();
-        let l2_to_l1_messages = self
-            .messages_sent
-            .iter()
-            .map(|msg| msg.to_proto())
-            .collect();
-        let events = self.events.iter().map(|ev| ev.to_proto()).collect();
-
-        v1alpha2::TransactionReceipt {
-            transaction_index: 0,
-            transaction_hash: Some(transaction_hash),
-            actual_fee: Some(actual_fee),
-            l2_to_l1_messages,
-            events,
-            contract_address: None,
-        }
+        receipt_to_proto(
+            &self.transaction_hash,
+            &self.actual_fee,
+            &self.messages_sent,
+            &self.events,
+            None,
+        )
     }
 }
 
 impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::DeployTransactionReceipt {
     fn to_proto(&self) -> v1alpha2::TransactionReceipt {
-        let transaction_hash = self.transaction_hash.into();
-        let actual_fee = self.actual_fee.into();
-        let l2_to_l1_messages = self
-            .messages_sent
-            .iter()
-            .map(|msg| msg.to_proto())
-            .collect();
-        let events = self.events.iter().map(|ev| ev.to_proto()).collect();
-        let contract_address = self.contract_address.into();
-
-        v1alpha2::TransactionReceipt {
-            transaction_index: 0,
-            transaction_hash: Some(transaction_hash),
-            actual_fee: Some(actual_fee),
-            l2_to_l1_messages,
-            events,
-            contract_address: Some(contract_address),
-        }
+        receipt_to_proto(
+            &self.transaction_hash,
+            &self.actual_fee,
+            &self.messages_sent,
+            &self.events,
+            Some(&self.contract_address),
+        )
     }
 }
 
 impl ToProto<v1alpha2::TransactionReceipt> for jsonrpc::models::DeployAccountTransactionReceipt {
     fn to_proto(&self) -> v1alpha2::TransactionReceipt {
-        let transaction_hash = self.transaction_hash.into();
-        let actual_fee = self.actual_fee.into();
-        let l2_to_l1_messages = self
-            .messages_sent
-            .iter()
-            .map(|msg| msg.to_proto())
-            .collect();
-        let events = self.events.iter().map(|ev| ev.to_proto()).collect();
-        let contract_address = self.contract_address.into();
-
-        v1alpha2::TransactionReceipt {
-            transaction_index: 0,
-            transaction_hash: Some(transaction_hash),
-            actual_fee: Some(actual_fee),
-            l2_to_l1_messages,
-            events,
-            contract_address: Some(contract_address),
-        }
-    }
+        receipt_to_proto(
+            &self.transaction_hash,
+            &self.actual_fee,
+            &self.messages_sent,
+            &self.events,
+            Some(&self.contract_address),
+        )
+    }
+}
+
+/// Converts the fields shared by all receipt types.
+///
+/// The finality status changes after the block is stored, so receipts are
+/// stored with an unspecified finality status that is set from the block
+/// status when the receipt is sent to clients.
+fn receipt_to_proto(
+    transaction_hash: &FieldElement,
+    actual_fee: &FieldElement,
+    messages_sent: &[jsonrpc::models::MsgToL1],
+    events: &[jsonrpc::models::Event],
+    contract_address: Option<&FieldElement>,
+) -> v1alpha2::TransactionReceipt {
+    let l2_to_l1_messages = messages_sent.iter().map(|msg| msg.to_proto()).collect();
+    let events = events.iter().map(|ev| ev.to_proto()).collect();
+
+    v1alpha2::TransactionReceipt {
+        transaction_index: 0,
+        transaction_hash: Some(transaction_hash.into()),
+        actual_fee: Some(actual_fee.into()),
+        l2_to_l1_messages,
+        events,
+        contract_address: contract_address.map(Into::into),
+        execution_status: execution_status_to_proto() as i32,
+        finality_status: v1alpha2::TransactionFinalityStatus::Unspecified as i32,
+    }
+}
+
+/// Returns the execution status of a receipt.
+///
+/// The rpc version used by the provider doesn't report the execution status of
+/// transactions, so receipts are stored with an unspecified execution status
+/// until the provider is upgraded to an rpc version that does.
+fn execution_status_to_proto() -> v1alpha2::ExecutionStatus {
+    v1alpha2::ExecutionStatus::Unspecified
 }
 
 impl ToProto<v1alpha2::L2ToL1Message> for jsonrpc::models::MsgToL1 {
@@ -0,0 +1,320 @@
+//! Implements the node admin service.
+//!
+//! The admin service is served on its own address so that it's never exposed
+//! together with the public stream service.
+
+use std::net::SocketAddr;
+
+use apibara_core::node::v1alpha2::{
+    admin_server, InvalidateBlocksRequest, InvalidateBlocksResponse, PauseIngestionRequest,
+    PauseIngestionResponse, ReingestBlocksRequest, ReingestBlocksResponse, ResumeIngestionRequest,
+    ResumeIngestionResponse,
+};
+use subtle::ConstantTimeEq;
+use tokio_util::sync::CancellationToken;
+use tonic::{service::Interceptor, transport::Server as TonicServer, Request, Response};
+use tracing::{info, info_span, warn};
+
+use crate::ingestion::{BlockIngestionError, IngestionAdminClient, IngestionAdminError};
+
+use super::{ServerError, TlsConfig};
+
+/// Serves the admin service.
+pub struct AdminServer {
+    client: IngestionAdminClient,
+    token: String,
+    tls_config: Option<TlsConfig>,
+}
+
+struct AdminService {
+    client: IngestionAdminClient,
+}
+
+/// Only accepts requests with the `authorization: Bearer <token>` header.
+#[derive(Clone)]
+struct AdminTokenInterceptor {
+    expected: String,
+}
+
+impl AdminServer {
+    /// Creates a new admin server. Requests must be authenticated with `token`.
+    pub fn new(client: IngestionAdminClient, token: String) -> Self {
+        AdminServer {
+            client,
+            token,
+            tls_config: None,
+        }
+    }
+
+    /// Serve requests over TLS using the given configuration.
+    pub fn with_tls_config(mut self, tls_config: TlsConfig) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    pub async fn start(self, addr: SocketAddr, ct: CancellationToken) -> Result<(), ServerError> {
+        let interceptor = AdminTokenInterceptor {
+            expected: format!("Bearer {}", self.token),
+        };
+        let admin_service = admin_server::AdminServer::with_interceptor(
+            AdminService {
+                client: self.client,
+            },
+            interceptor,
+        );
+
+        let mut builder = TonicServer::builder();
+        if let Some(tls_config) = &self.tls_config {
+            builder = builder.tls_config(tls_config.to_server_tls_config()?)?;
+        }
+
+        info!(addr = %addr, tls = self.tls_config.is_some(), "starting admin server");
+
+        builder
+            .trace_fn(|_| info_span!("admin_server"))
+            .add_service(admin_service)
+            .serve_with_shutdown(addr, async move { ct.cancelled().await })
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Interceptor for AdminTokenInterceptor {
+    fn call(&mut self, req: Request<()>) -> Result<Request<()>, tonic::Status> {
+        // compare in constant time to not leak the token through response times.
+        let authorized = req
+            .metadata()
+            .get("authorization")
+            .map(|value| bool::from(value.as_bytes().ct_eq(self.expected.as_bytes())))
+            .unwrap_or(false);
+        if !authorized {
+            return Err(tonic::Status::unauthenticated("invalid admin token"));
+        }
+        Ok(req)
+    }
+}
+
+#[tonic::async_trait]
+impl admin_server::Admin for AdminService {
+    async fn reingest_blocks(
+        &self,
+        request: Request<ReingestBlocksRequest>,
+    ) -> Result<Response<ReingestBlocksResponse>, tonic::Status> {
+        let request = request.into_inner();
+        if request.start_block > request.end_block {
+            return Err(tonic::Status::invalid_argument(
+                "start block must be before end block",
+            ));
+        }
+
+        let blocks_count = self
+            .client
+            .reingest_blocks(request.start_block, request.end_block)
+            .await
+            .map_err(admin_error_to_status)?;
+
+        Ok(Response::new(ReingestBlocksResponse { blocks_count }))
+    }
+
+    async fn invalidate_blocks(
+        &self,
+        request: Request<InvalidateBlocksRequest>,
+    ) -> Result<Response<InvalidateBlocksResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let new_head = self
+            .client
+            .invalidate_blocks(request.block_number)
+            .await
+            .map_err(admin_error_to_status)?;
+
+        Ok(Response::new(InvalidateBlocksResponse {
+            new_head: Some(new_head.to_cursor()),
+        }))
+    }
+
+    async fn pause_ingestion(
+        &self,
+        _request: Request<PauseIngestionRequest>,
+    ) -> Result<Response<PauseIngestionResponse>, tonic::Status> {
+        self.client.pause().await.map_err(admin_error_to_status)?;
+        Ok(Response::new(PauseIngestionResponse {}))
+    }
+
+    async fn resume_ingestion(
+        &self,
+        _request: Request<ResumeIngestionRequest>,
+    ) -> Result<Response<ResumeIngestionResponse>, tonic::Status> {
+        self.client.resume().await.map_err(admin_error_to_status)?;
+        Ok(Response::new(ResumeIngestionResponse {}))
+    }
+}
+
+fn admin_error_to_status(err: IngestionAdminError) -> tonic::Status {
+    match err {
+        IngestionAdminError::ChannelClosed => {
+            tonic::Status::unavailable("ingestion is not running")
+        }
+        IngestionAdminError::Ingestion(BlockIngestionError::BlockNotCanonical) => {
+            tonic::Status::failed_precondition("block is not part of the canonical chain")
+        }
+        IngestionAdminError::Ingestion(BlockIngestionError::BlockFinalized) => {
+            tonic::Status::failed_precondition("finalized blocks cannot be invalidated")
+        }
+        IngestionAdminError::Ingestion(err) => {
+            warn!(err = ?err, "admin command failed");
+            tonic::Status::internal("internal server error")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use apibara_core::node::v1alpha2::{
+        admin_server::Admin, InvalidateBlocksRequest, PauseIngestionRequest, ReingestBlocksRequest,
+        ResumeIngestionRequest,
+    };
+    use tokio_util::sync::CancellationToken;
+    use tonic::{service::Interceptor, Code, Request};
+
+    use crate::{
+        core::{BlockHash, GlobalBlockId},
+        ingestion::{AdminCommand, BlockIngestionError, IngestionAdmin},
+    };
+
+    use super::{AdminService, AdminTokenInterceptor};
+
+    fn interceptor() -> AdminTokenInterceptor {
+        AdminTokenInterceptor {
+            expected: "Bearer secret".to_string(),
+        }
+    }
+
+    fn request_with_authorization(value: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", value.parse().unwrap());
+        request
+    }
+
+    /// Returns an admin service with a fake ingestion loop.
+    ///
+    /// The loop re-ingests and invalidates only the blocks up to 10.
+    fn admin_service() -> AdminService {
+        let (client, mut admin) = IngestionAdmin::new();
+        tokio::spawn(async move {
+            let ct = CancellationToken::new();
+            loop {
+                match admin.next_command(&ct).await {
+                    None => tokio::time::sleep(Duration::from_millis(1)).await,
+                    Some(AdminCommand::ReingestBlocks { start, end, reply }) => {
+                        let result = if end > 10 {
+                            Err(BlockIngestionError::BlockNotCanonical)
+                        } else {
+                            Ok(end - start + 1)
+                        };
+                        let _ = reply.send(result);
+                    }
+                    Some(AdminCommand::InvalidateBlocks { number, reply }) => {
+                        let result = if number > 10 {
+                            Err(BlockIngestionError::BlockNotCanonical)
+                        } else {
+                            let hash: BlockHash =
+                                apibara_core::starknet::v1alpha2::FieldElement::from_u64(number)
+                                    .into();
+                            Ok(GlobalBlockId::new(number, hash))
+                        };
+                        let _ = reply.send(result);
+                    }
+                    Some(_) => unreachable!("pause and resume are handled by the admin"),
+                }
+            }
+        });
+        AdminService { client }
+    }
+
+    #[test]
+    fn test_token_interceptor() {
+        let mut interceptor = interceptor();
+
+        assert!(interceptor
+            .call(request_with_authorization("Bearer secret"))
+            .is_ok());
+
+        let status = interceptor.call(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        for value in ["Bearer other", "Bearer secre", "Bearer secrets", "secret"] {
+            let status = interceptor
+                .call(request_with_authorization(value))
+                .unwrap_err();
+            assert_eq!(status.code(), Code::Unauthenticated);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reingest_blocks() {
+        let service = admin_service();
+
+        let request = |start_block, end_block| {
+            Request::new(ReingestBlocksRequest {
+                start_block,
+                end_block,
+            })
+        };
+        let response = service.reingest_blocks(request(2, 5)).await.unwrap();
+        assert_eq!(response.into_inner().blocks_count, 4);
+
+        let status = service.reingest_blocks(request(5, 2)).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let status = service.reingest_blocks(request(5, 20)).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_blocks() {
+        let service = admin_service();
+
+        let response = service
+            .invalidate_blocks(Request::new(InvalidateBlocksRequest { block_number: 7 }))
+            .await
+            .unwrap();
+        let new_head = response.into_inner().new_head.unwrap();
+        assert_eq!(new_head.order_key, 7);
+
+        let status = service
+            .invalidate_blocks(Request::new(InvalidateBlocksRequest { block_number: 11 }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume_ingestion() {
+        let service = admin_service();
+        service
+            .pause_ingestion(Request::new(PauseIngestionRequest {}))
+            .await
+            .unwrap();
+        service
+            .resume_ingestion(Request::new(ResumeIngestionRequest {}))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ingestion_not_running() {
+        let (client, admin) = IngestionAdmin::new();
+        drop(admin);
+        let service = AdminService { client };
+
+        let status = service
+            .pause_ingestion(Request::new(PauseIngestionRequest {}))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+}
@@ -0,0 +1,67 @@
+//! Per-request control of response compression.
+//!
+//! The stream service compresses responses with gzip when clients accept it.
+//! Latency-sensitive clients (for example, streaming pending data) can opt out by
+//! sending the `x-apibara-compression: none` header.
+
+use hyper::{Body, Request};
+
+/// Header used by clients to disable response compression.
+///
+/// Responses are only ever compressed with gzip: tonic 0.8 doesn't support zstd, so
+/// it's not offered to clients.
+pub const COMPRESSION_HEADER: &str = "x-apibara-compression";
+
+/// Value of [COMPRESSION_HEADER] that disables compression.
+const COMPRESSION_NONE: &str = "none";
+
+/// Header used by grpc clients to advertise the encodings they accept.
+const GRPC_ACCEPT_ENCODING_HEADER: &str = "grpc-accept-encoding";
+
+/// Removes the accepted encodings from the request if the client disabled compression.
+///
+/// Without them, tonic sends the response uncompressed.
+pub fn disable_compression_if_requested(mut request: Request<Body>) -> Request<Body> {
+    let disabled = request
+        .headers()
+        .get(COMPRESSION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case(COMPRESSION_NONE))
+        .unwrap_or(false);
+    if disabled {
+        request.headers_mut().remove(GRPC_ACCEPT_ENCODING_HEADER);
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Body, Request};
+
+    use super::{disable_compression_if_requested, GRPC_ACCEPT_ENCODING_HEADER};
+
+    fn new_request(compression: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().header(GRPC_ACCEPT_ENCODING_HEADER, "gzip");
+        if let Some(compression) = compression {
+            builder = builder.header("x-apibara-compression", compression);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_disable_compression_if_requested() {
+        let request = disable_compression_if_requested(new_request(Some("none")));
+        assert!(request.headers().get(GRPC_ACCEPT_ENCODING_HEADER).is_none());
+
+        let request = disable_compression_if_requested(new_request(Some("NONE")));
+        assert!(request.headers().get(GRPC_ACCEPT_ENCODING_HEADER).is_none());
+
+        for compression in [None, Some("gzip")] {
+            let request = disable_compression_if_requested(new_request(compression));
+            assert_eq!(
+                request.headers().get(GRPC_ACCEPT_ENCODING_HEADER).unwrap(),
+                "gzip"
+            );
+        }
+    }
+}
@@ -0,0 +1,638 @@
+//! HTTP gateway that streams data as server-sent events.
+//!
+//! Clients send the stream configuration as JSON and receive the same messages
+//! as the grpc `StreamData` call, encoded as JSON.
+
+use std::{convert::Infallible, io, net::SocketAddr, sync::Arc, time::Duration};
+
+use apibara_core::{
+    node::v1alpha2::{
+        stream_data_response, Cursor, DataFinality, EndOfStream, Heartbeat, Invalidate,
+        StreamDataRequest, StreamDataResponse, StreamError,
+    },
+    starknet::v1alpha2::{Block, Filter},
+};
+use apibara_node::db::libmdbx::EnvironmentKind;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::get,
+    Json, Router,
+};
+use futures::{stream, StreamExt};
+use hyper::server::{accept, conn::AddrIncoming};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{rustls::ServerConfig, server::TlsStream, TlsAcceptor};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tonic::{
+    metadata::{AsciiMetadataValue, MetadataMap},
+    Code,
+};
+use tracing::{debug, info, warn};
+
+use crate::{db::StorageReader, provider::Provider};
+
+use super::{
+    metadata::{request_api_key, RequestAuthenticator, RequestObserver},
+    stream::StreamService,
+    ws::stream_data_ws,
+    ServerError,
+};
+
+/// Stream configuration sent by http clients.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HttpStreamDataRequest {
+    pub stream_id: Option<u64>,
+    pub batch_size: Option<u64>,
+    pub finality: Option<DataFinality>,
+    pub starting_cursor: Option<Cursor>,
+    pub ending_cursor: Option<Cursor>,
+    pub filter: Filter,
+    pub multiplex: bool,
+    pub cancel: bool,
+}
+
+/// Clients that have not completed the TLS handshake after this long are disconnected.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Query string of `GET` requests, for clients that can only use `EventSource`.
+#[derive(Debug, Deserialize)]
+struct HttpStreamDataQuery {
+    request: String,
+    key: Option<String>,
+}
+
+/// Api key sent in the query string, for clients that can't set headers.
+#[derive(Debug, Deserialize)]
+pub(super) struct ApiKeyQuery {
+    key: Option<String>,
+}
+
+/// A [StreamDataResponse], with blocks decoded so that they can be sent as JSON.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct JsonStreamDataResponse {
+    stream_id: u64,
+    #[serde(flatten)]
+    message: JsonMessage,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonMessage {
+    Data(JsonData),
+    Invalidate(Invalidate),
+    Heartbeat(Heartbeat),
+    EndOfStream(EndOfStream),
+    StreamError(StreamError),
+}
+
+/// A batch of data, with blocks decoded as JSON.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonData {
+    cursor: Option<Cursor>,
+    end_cursor: Option<Cursor>,
+    finality: DataFinality,
+    data: Vec<Block>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorEvent {
+    code: String,
+    message: String,
+}
+
+impl JsonStreamDataResponse {
+    /// Decodes the data in the response. Returns `None` if the response has no message.
+    pub fn from_response(response: StreamDataResponse) -> Result<Option<Self>, prost::DecodeError> {
+        use stream_data_response::Message;
+
+        let message = match response.message {
+            None => return Ok(None),
+            Some(Message::Data(data)) => {
+                let blocks = data
+                    .data
+                    .iter()
+                    .map(|block| Block::decode(block.as_slice()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                JsonMessage::Data(JsonData {
+                    cursor: data.cursor,
+                    end_cursor: data.end_cursor,
+                    finality: DataFinality::from_i32(data.finality).unwrap_or_default(),
+                    data: blocks,
+                })
+            }
+            Some(Message::Invalidate(invalidate)) => JsonMessage::Invalidate(invalidate),
+            Some(Message::Heartbeat(heartbeat)) => JsonMessage::Heartbeat(heartbeat),
+            Some(Message::EndOfStream(end_of_stream)) => JsonMessage::EndOfStream(end_of_stream),
+            Some(Message::Error(error)) => JsonMessage::StreamError(error),
+        };
+
+        Ok(Some(JsonStreamDataResponse {
+            stream_id: response.stream_id,
+            message,
+        }))
+    }
+
+    /// Converts the response to a server-sent event, named after the message type.
+    fn into_event(self) -> Result<Event, axum::Error> {
+        match self.message {
+            JsonMessage::Data(data) => Event::default().event("data").json_data(data),
+            JsonMessage::Invalidate(invalidate) => {
+                Event::default().event("invalidate").json_data(invalidate)
+            }
+            JsonMessage::Heartbeat(heartbeat) => {
+                Event::default().event("heartbeat").json_data(heartbeat)
+            }
+            JsonMessage::EndOfStream(end_of_stream) => Event::default()
+                .event("end_of_stream")
+                .json_data(end_of_stream),
+            JsonMessage::StreamError(error) => {
+                Event::default().event("stream_error").json_data(error)
+            }
+        }
+    }
+}
+
+impl HttpStreamDataRequest {
+    /// Converts the request to the one used by the grpc stream.
+    pub fn into_stream_data_request(self) -> StreamDataRequest {
+        StreamDataRequest {
+            stream_id: self.stream_id,
+            batch_size: self.batch_size,
+            starting_cursor: self.starting_cursor,
+            ending_cursor: self.ending_cursor,
+            finality: self.finality.map(|f| f as i32),
+            filter: self.filter.encode_to_vec(),
+            multiplex: self.multiplex,
+            cancel: self.cancel,
+        }
+    }
+}
+
+/// Serves the stream service over http, or https if `tls_config` is given.
+pub async fn start_http_server<G, R, O, A, E>(
+    service: Arc<StreamService<G, R, O, A, E>>,
+    addr: SocketAddr,
+    tls_config: Option<ServerConfig>,
+    ct: CancellationToken,
+) -> Result<(), ServerError>
+where
+    G: Provider + Send + Sync + 'static,
+    R: StorageReader + Send + Sync + 'static,
+    O: RequestObserver,
+    A: RequestAuthenticator,
+    E: EnvironmentKind,
+{
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(ServerError::HttpBind)?;
+    info!(addr = %addr, tls = tls_config.is_some(), "starting http server");
+    serve_http(listener, http_router(service), tls_config, ct).await
+}
+
+/// Serves the router on the listener until `ct` is cancelled.
+async fn serve_http(
+    listener: TcpListener,
+    router: Router,
+    tls_config: Option<ServerConfig>,
+    ct: CancellationToken,
+) -> Result<(), ServerError> {
+    let shutdown = {
+        let ct = ct.clone();
+        async move { ct.cancelled().await }
+    };
+
+    let tls_config = match tls_config {
+        None => {
+            axum::Server::builder(AddrIncoming::from_listener(listener)?)
+                .serve(router.into_make_service())
+                .with_graceful_shutdown(shutdown)
+                .await?;
+            return Ok(());
+        }
+        Some(tls_config) => tls_config,
+    };
+
+    let (connections_tx, connections_rx) = mpsc::channel(16);
+    let accept_handle = tokio::spawn(accept_tls_connections(
+        listener,
+        TlsAcceptor::from(Arc::new(tls_config)),
+        connections_tx,
+        ct,
+    ));
+
+    let connections = ReceiverStream::new(connections_rx).map(Ok::<_, io::Error>);
+    axum::Server::builder(accept::from_stream(connections))
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown)
+        .await?;
+
+    accept_handle.await?;
+    Ok(())
+}
+
+/// Returns the router with the http and WebSocket endpoints.
+pub(super) fn http_router<G, R, O, A, E>(service: Arc<StreamService<G, R, O, A, E>>) -> Router
+where
+    G: Provider + Send + Sync + 'static,
+    R: StorageReader + Send + Sync + 'static,
+    O: RequestObserver,
+    A: RequestAuthenticator,
+    E: EnvironmentKind,
+{
+    Router::new()
+        .route(
+            "/v1alpha2/stream",
+            get(stream_data_query::<G, R, O, A, E>).post(stream_data_json::<G, R, O, A, E>),
+        )
+        .route("/v1alpha2/ws", get(stream_data_ws::<G, R, O, A, E>))
+        .with_state(service)
+}
+
+/// Accepts tcp connections and sends them to the http server after the TLS handshake.
+///
+/// Handshakes run in their own task so that slow clients don't block new connections.
+async fn accept_tls_connections(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    connections: mpsc::Sender<TlsStream<TcpStream>>,
+    ct: CancellationToken,
+) {
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            _ = ct.cancelled() => return,
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // usually caused by too many open files, wait for some to close.
+                    warn!(err = ?err, "failed to accept http connection");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+        };
+
+        let acceptor = acceptor.clone();
+        let connections = connections.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = connections.send(stream).await;
+                }
+                Ok(Err(err)) => {
+                    debug!(remote_addr = %remote_addr, err = ?err, "tls handshake failed");
+                }
+                Err(_) => {
+                    debug!(remote_addr = %remote_addr, "tls handshake timed out");
+                }
+            }
+        });
+    }
+}
+
+async fn stream_data_json<G, R, O, A, E>(
+    State(service): State<Arc<StreamService<G, R, O, A, E>>>,
+    headers: HeaderMap,
+    Query(query): Query<ApiKeyQuery>,
+    Json(request): Json<HttpStreamDataRequest>,
+) -> Response
+where
+    G: Provider + Send + Sync + 'static,
+    R: StorageReader + Send + Sync + 'static,
+    O: RequestObserver,
+    A: RequestAuthenticator,
+    E: EnvironmentKind,
+{
+    let metadata = request_metadata(headers, query.key.as_deref());
+    stream_data_sse(service, metadata, request).await
+}
+
+async fn stream_data_query<G, R, O, A, E>(
+    State(service): State<Arc<StreamService<G, R, O, A, E>>>,
+    headers: HeaderMap,
+    Query(query): Query<HttpStreamDataQuery>,
+) -> Response
+where
+    G: Provider + Send + Sync + 'static,
+    R: StorageReader + Send + Sync + 'static,
+    O: RequestObserver,
+    A: RequestAuthenticator,
+    E: EnvironmentKind,
+{
+    let metadata = request_metadata(headers, query.key.as_deref());
+    match serde_json::from_str::<HttpStreamDataRequest>(&query.request) {
+        Ok(request) => stream_data_sse(service, metadata, request).await,
+        Err(err) => (StatusCode::BAD_REQUEST, format!("invalid request: {err}")).into_response(),
+    }
+}
+
+async fn stream_data_sse<G, R, O, A, E>(
+    service: Arc<StreamService<G, R, O, A, E>>,
+    metadata: MetadataMap,
+    request: HttpStreamDataRequest,
+) -> Response
+where
+    G: Provider + Send + Sync + 'static,
+    R: StorageReader + Send + Sync + 'static,
+    O: RequestObserver,
+    A: RequestAuthenticator,
+    E: EnvironmentKind,
+{
+    // the configuration stream must stay open, otherwise the data stream is closed.
+    let requests =
+        stream::once(async move { Ok::<_, Infallible>(request.into_stream_data_request()) })
+            .chain(stream::pending())
+            .boxed();
+
+    let response = match service.stream_data_with_requests(&metadata, requests).await {
+        Ok(response) => response,
+        Err(status) => return status_to_response(status),
+    };
+
+    let events = response
+        .scan(false, |errored, message| {
+            // stop after sending the first error to the client.
+            if *errored {
+                return futures::future::ready(None);
+            }
+            let event = match message {
+                Ok(message) => response_to_event(message),
+                Err(status) => {
+                    *errored = true;
+                    status_to_event(status)
+                }
+            };
+            futures::future::ready(Some(Ok::<_, Infallible>(event)))
+        })
+        .filter_map(|event| async move { event.transpose() });
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Returns the request metadata, with the api key sent outside of the headers.
+///
+/// A key in the headers takes precedence over `key`.
+pub(super) fn request_metadata(headers: HeaderMap, key: Option<&str>) -> MetadataMap {
+    let mut metadata = MetadataMap::from_headers(headers);
+    if request_api_key(&metadata).is_none() {
+        // an invalid value is ignored, the request then fails authentication.
+        if let Some(key) = key.and_then(|key| key.parse::<AsciiMetadataValue>().ok()) {
+            metadata.insert("x-api-key", key);
+        }
+    }
+    metadata
+}
+
+fn response_to_event(response: StreamDataResponse) -> Option<Event> {
+    let event = match JsonStreamDataResponse::from_response(response) {
+        Ok(response) => response?.into_event(),
+        Err(err) => {
+            warn!(err = ?err, "failed to decode block");
+            return Some(error_event(Code::Internal, "internal server error"));
+        }
+    };
+
+    match event {
+        Ok(event) => Some(event),
+        Err(err) => {
+            warn!(err = ?err, "failed to encode event");
+            Some(error_event(Code::Internal, "internal server error"))
+        }
+    }
+}
+
+fn status_to_event(status: tonic::Status) -> Option<Event> {
+    Some(error_event(status.code(), status.message()))
+}
+
+fn error_event(code: Code, message: &str) -> Event {
+    let error = ErrorEvent {
+        code: format!("{:?}", code),
+        message: message.to_string(),
+    };
+    Event::default()
+        .event("error")
+        .json_data(error)
+        .unwrap_or_else(|_| Event::default().event("error"))
+}
+
+pub(super) fn status_to_response(status: tonic::Status) -> Response {
+    let code = match status.code() {
+        Code::InvalidArgument => StatusCode::BAD_REQUEST,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (code, status.message().to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        response::Response,
+    };
+    use hyper::body::HttpBody;
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_rustls::{
+        rustls::{self, ClientConfig, RootCertStore, ServerName},
+        TlsConnector,
+    };
+    use tokio_util::sync::CancellationToken;
+    use tower::ServiceExt;
+
+    use crate::server::{
+        test_utils::{TestServer, TEST_API_KEY},
+        TlsConfig,
+    };
+
+    use super::{http_router, serve_http};
+
+    const REQUEST: &str =
+        r#"{"finality": "DATA_STATUS_ACCEPTED", "filter": {"header": {"weak": false}}}"#;
+
+    fn stream_query(key: Option<&str>) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query.append_pair("request", REQUEST);
+        if let Some(key) = key {
+            query.append_pair("key", key);
+        }
+        format!("/v1alpha2/stream?{}", query.finish())
+    }
+
+    async fn send(server: &TestServer, request: Request<Body>) -> Response {
+        http_router(server.service.clone())
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    /// Returns the first server-sent event in the response.
+    async fn first_event(response: Response) -> String {
+        let mut body = response.into_body();
+        let mut event = String::default();
+        while !event.contains("\n\n") {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), body.data())
+                .await
+                .expect("no event received")
+                .expect("body ended")
+                .unwrap();
+            event.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        event
+    }
+
+    #[tokio::test]
+    async fn test_stream_with_header_key() {
+        let server = TestServer::new(3);
+        let request = Request::post("/v1alpha2/stream")
+            .header("content-type", "application/json")
+            .header("x-api-key", TEST_API_KEY)
+            .body(Body::from(REQUEST))
+            .unwrap();
+        let response = send(&server, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let event = first_event(response).await;
+        assert!(event.starts_with("event: data\n"), "{}", event);
+        assert!(event.contains("DATA_STATUS_FINALIZED"), "{}", event);
+        assert!(event.contains("blockHash"), "{}", event);
+    }
+
+    #[tokio::test]
+    async fn test_stream_with_query_key() {
+        let server = TestServer::new(3);
+        let request = Request::get(stream_query(Some(TEST_API_KEY)))
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&server, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let event = first_event(response).await;
+        assert!(event.starts_with("event: data\n"), "{}", event);
+
+        // the key can also be sent in the query string of POST requests.
+        let uri = format!("/v1alpha2/stream?key={}", TEST_API_KEY);
+        let request = Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(REQUEST))
+            .unwrap();
+        let response = send(&server, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_stream_rejects_invalid_keys() {
+        let server = TestServer::new(3);
+        let request = Request::get(stream_query(None))
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&server, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let request = Request::get(stream_query(Some("not-a-key")))
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&server, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the header key takes precedence.
+        let request = Request::get(stream_query(Some(TEST_API_KEY)))
+            .header("x-api-key", "not-a-key")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(&server, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_stream_over_tls() {
+        let server = TestServer::new(3);
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = tempdir().unwrap();
+        let cert_path = dir.path().join("server.pem");
+        let key_path = dir.path().join("server.key");
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        let tls_config = TlsConfig::new(cert_path, key_path)
+            .to_rustls_server_config()
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ct = CancellationToken::new();
+        let server_handle = tokio::spawn(serve_http(
+            listener,
+            http_router(server.service.clone()),
+            Some(tls_config),
+            ct.clone(),
+        ));
+
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&rustls::Certificate(cert.serialize_der().unwrap()))
+            .unwrap();
+        let client_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client_config));
+
+        // a plain http client fails the handshake, without stopping the server.
+        let mut plain = TcpStream::connect(addr).await.unwrap();
+        plain
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = Vec::default();
+        let _ = plain.read_to_end(&mut buf).await;
+        assert!(!String::from_utf8_lossy(&buf).contains("HTTP/1.1"));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(server_name, stream).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nhost: localhost\r\n\r\n",
+            stream_query(Some(TEST_API_KEY))
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::default();
+        while !response.contains("event: data") {
+            let mut buf = [0; 4096];
+            let size = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+                .await
+                .expect("no data received")
+                .unwrap();
+            assert!(size > 0, "connection closed: {}", response);
+            response.push_str(&String::from_utf8_lossy(&buf[..size]));
+        }
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+        // the streaming response keeps the connection open, so don't wait for it.
+        ct.cancel();
+        server_handle.abort();
+    }
+}
@@ -0,0 +1,346 @@
+//! Authenticate requests using api keys stored in a file.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::RwLock,
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::Deserialize;
+use tonic::metadata::MetadataMap;
+use tracing::{info, warn};
+
+use super::metadata::{request_api_key, AuthenticationError, KeyPermissions, RequestAuthenticator};
+
+/// How often to check if the keys file changed.
+const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A [RequestAuthenticator] that checks api keys against a JSON file.
+///
+/// The file has the following format:
+///
+/// ```json
+/// {
+///   "keys": [
+///     { "key": "my-secret-key", "name": "acme", "allow_pending": false, "finalized_only": true }
+///   ]
+/// }
+/// ```
+///
+/// The file is reloaded when it changes on disk.
+pub struct KeysFileAuthenticator {
+    path: PathBuf,
+    reload_interval: Duration,
+    state: RwLock<KeysFileState>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeysFileError {
+    #[error("failed to read keys file")]
+    Io(#[from] std::io::Error),
+    #[error("failed to parse keys file")]
+    Parse(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub permissions: KeyPermissions,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeysFile {
+    keys: Vec<ApiKey>,
+}
+
+struct KeysFileState {
+    keys: HashMap<String, KeyPermissions>,
+    modified: Option<SystemTime>,
+    checked_at: Instant,
+}
+
+impl KeysFileAuthenticator {
+    /// Creates a new authenticator, loading keys from the given file.
+    pub fn new(path: PathBuf) -> Result<Self, KeysFileError> {
+        let state = KeysFileState::load(&path)?;
+        info!(path = ?path, keys = state.keys.len(), "loaded api keys");
+        Ok(KeysFileAuthenticator {
+            path,
+            reload_interval: DEFAULT_RELOAD_INTERVAL,
+            state: RwLock::new(state),
+        })
+    }
+
+    /// Changes how often the file is checked for changes.
+    pub fn with_reload_interval(mut self, reload_interval: Duration) -> Self {
+        self.reload_interval = reload_interval;
+        self
+    }
+
+    /// Reloads the keys from the file.
+    pub fn reload(&self) -> Result<(), KeysFileError> {
+        let state = KeysFileState::load(&self.path)?;
+        info!(path = ?self.path, keys = state.keys.len(), "reloaded api keys");
+        *self.state.write().expect("keys file lock poisoned") = state;
+        Ok(())
+    }
+
+    /// Reloads the keys if the file changed since it was last loaded.
+    fn reload_if_changed(&self) {
+        {
+            let state = self.state.read().expect("keys file lock poisoned");
+            if state.checked_at.elapsed() < self.reload_interval {
+                return;
+            }
+        }
+
+        let mut state = self.state.write().expect("keys file lock poisoned");
+        // another request may have reloaded the file already
+        if state.checked_at.elapsed() < self.reload_interval {
+            return;
+        }
+        state.checked_at = Instant::now();
+
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified == state.modified {
+            return;
+        }
+
+        match KeysFileState::load(&self.path) {
+            Ok(new_state) => {
+                info!(path = ?self.path, keys = new_state.keys.len(), "reloaded api keys");
+                *state = new_state;
+            }
+            Err(err) => {
+                // keep serving with the old keys
+                warn!(err = ?err, path = ?self.path, "failed to reload api keys");
+            }
+        }
+    }
+}
+
+impl KeysFileState {
+    fn load(path: &PathBuf) -> Result<Self, KeysFileError> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let content = fs::read(path)?;
+        let file: KeysFile = serde_json::from_slice(&content)?;
+        let keys = file
+            .keys
+            .into_iter()
+            .map(|api_key| (api_key.key, api_key.permissions))
+            .collect();
+        Ok(KeysFileState {
+            keys,
+            modified,
+            checked_at: Instant::now(),
+        })
+    }
+}
+
+impl RequestAuthenticator for KeysFileAuthenticator {
+    fn authenticate(&self, metadata: &MetadataMap) -> Result<KeyPermissions, AuthenticationError> {
+        self.reload_if_changed();
+
+        let key = request_api_key(metadata).ok_or(AuthenticationError::MissingKey)?;
+        let state = self.state.read().expect("keys file lock poisoned");
+        state
+            .keys
+            .get(key)
+            .cloned()
+            .ok_or(AuthenticationError::InvalidKey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    };
+
+    use apibara_core::node::v1alpha2::DataFinality;
+    use assert_matches::assert_matches;
+    use tempfile::{tempdir, TempDir};
+    use tonic::metadata::MetadataMap;
+
+    use crate::server::{
+        metadata::{AuthenticationError, KeyPermissions, RequestAuthenticator},
+        QuotaAction,
+    };
+
+    use super::{KeysFileAuthenticator, KeysFileError};
+
+    const KEYS: &str = r#"{
+        "keys": [
+            { "key": "default-key" },
+            {
+                "key": "finalized-key",
+                "name": "acme",
+                "allow_pending": false,
+                "finalized_only": true,
+                "quota": { "window_seconds": 30, "on_exceeded": "reject", "limits": { "event": 100 } }
+            }
+        ]
+    }"#;
+
+    fn keys_file(content: &str) -> (TempDir, PathBuf) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("keys.json");
+        fs::write(&path, content).unwrap();
+        (dir, path)
+    }
+
+    fn authenticate(
+        authenticator: &KeysFileAuthenticator,
+        key: Option<&str>,
+    ) -> Result<KeyPermissions, AuthenticationError> {
+        let mut metadata = MetadataMap::new();
+        if let Some(key) = key {
+            metadata.insert("x-api-key", key.parse().unwrap());
+        }
+        authenticator.authenticate(&metadata)
+    }
+
+    /// Marks the loaded keys as older than the file.
+    ///
+    /// File systems with coarse timestamps may not change the modification time
+    /// of a file rewritten in the same test.
+    fn mark_outdated(authenticator: &KeysFileAuthenticator) {
+        let mut state = authenticator.state.write().unwrap();
+        state.modified = Some(SystemTime::UNIX_EPOCH);
+    }
+
+    fn load_error(path: &Path) -> KeysFileError {
+        match KeysFileAuthenticator::new(path.to_path_buf()) {
+            Ok(_) => panic!("expected an error loading {:?}", path),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn test_parse_keys_file() {
+        let (_dir, path) = keys_file(KEYS);
+        let authenticator = KeysFileAuthenticator::new(path).unwrap();
+
+        let permissions = authenticate(&authenticator, Some("default-key")).unwrap();
+        assert!(permissions.allow_pending);
+        assert!(!permissions.finalized_only);
+        assert!(permissions.quota.is_none());
+        assert!(permissions.name.is_none());
+
+        let permissions = authenticate(&authenticator, Some("finalized-key")).unwrap();
+        assert!(!permissions.allow_pending);
+        assert!(permissions.finalized_only);
+        assert_eq!(permissions.name.as_deref(), Some("acme"));
+        let quota = permissions.quota.unwrap();
+        assert_eq!(quota.window_seconds, 30);
+        assert_eq!(quota.on_exceeded, QuotaAction::Reject);
+        assert_eq!(quota.limits.get("event"), Some(&100));
+    }
+
+    #[test]
+    fn test_invalid_keys_file() {
+        let (_dir, path) = keys_file(r#"{ "keys": [{ "name": "no key" }] }"#);
+        assert_matches!(load_error(&path), KeysFileError::Parse(_));
+
+        let (_dir, path) = keys_file("not json");
+        assert_matches!(load_error(&path), KeysFileError::Parse(_));
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("missing.json");
+        assert_matches!(load_error(&path), KeysFileError::Io(_));
+    }
+
+    #[test]
+    fn test_reject_missing_and_unknown_keys() {
+        let (_dir, path) = keys_file(KEYS);
+        let authenticator = KeysFileAuthenticator::new(path).unwrap();
+
+        assert_matches!(
+            authenticate(&authenticator, None),
+            Err(AuthenticationError::MissingKey)
+        );
+        assert_matches!(
+            authenticate(&authenticator, Some("unknown-key")),
+            Err(AuthenticationError::InvalidKey)
+        );
+        // keys are case sensitive.
+        assert_matches!(
+            authenticate(&authenticator, Some("DEFAULT-KEY")),
+            Err(AuthenticationError::InvalidKey)
+        );
+    }
+
+    #[test]
+    fn test_reload() {
+        let (_dir, path) = keys_file(KEYS);
+        let authenticator = KeysFileAuthenticator::new(path.clone()).unwrap();
+
+        fs::write(&path, r#"{ "keys": [{ "key": "new-key" }] }"#).unwrap();
+        authenticator.reload().unwrap();
+        assert!(authenticate(&authenticator, Some("new-key")).is_ok());
+        assert_matches!(
+            authenticate(&authenticator, Some("default-key")),
+            Err(AuthenticationError::InvalidKey)
+        );
+
+        // explicit reloads return the error and keep the old keys.
+        fs::write(&path, "not json").unwrap();
+        assert_matches!(authenticator.reload(), Err(KeysFileError::Parse(_)));
+        assert!(authenticate(&authenticator, Some("new-key")).is_ok());
+    }
+
+    #[test]
+    fn test_reload_when_file_changes() {
+        let (_dir, path) = keys_file(KEYS);
+        let authenticator = KeysFileAuthenticator::new(path.clone())
+            .unwrap()
+            .with_reload_interval(Duration::from_secs(3600));
+
+        // the file is not checked again before the reload interval.
+        fs::write(&path, r#"{ "keys": [{ "key": "new-key" }] }"#).unwrap();
+        mark_outdated(&authenticator);
+        assert!(authenticate(&authenticator, Some("default-key")).is_ok());
+        assert!(authenticate(&authenticator, Some("new-key")).is_err());
+
+        let authenticator = authenticator.with_reload_interval(Duration::ZERO);
+        assert!(authenticate(&authenticator, Some("new-key")).is_ok());
+        assert!(authenticate(&authenticator, Some("default-key")).is_err());
+
+        // invalid files are ignored and the old keys are used.
+        fs::write(&path, "not json").unwrap();
+        mark_outdated(&authenticator);
+        assert!(authenticate(&authenticator, Some("new-key")).is_ok());
+    }
+
+    #[test]
+    fn test_allows_finality() {
+        let default = KeyPermissions::default();
+        assert!(default.allows_finality(DataFinality::DataStatusPending));
+        assert!(default.allows_finality(DataFinality::DataStatusAccepted));
+        assert!(default.allows_finality(DataFinality::DataStatusFinalized));
+        assert!(default.allows_finality(DataFinality::DataStatusUnknown));
+
+        let no_pending = KeyPermissions {
+            allow_pending: false,
+            ..Default::default()
+        };
+        assert!(!no_pending.allows_finality(DataFinality::DataStatusPending));
+        assert!(no_pending.allows_finality(DataFinality::DataStatusAccepted));
+        assert!(no_pending.allows_finality(DataFinality::DataStatusFinalized));
+
+        // finalized only wins over allow pending.
+        let finalized_only = KeyPermissions {
+            finalized_only: true,
+            ..Default::default()
+        };
+        assert!(!finalized_only.allows_finality(DataFinality::DataStatusPending));
+        assert!(!finalized_only.allows_finality(DataFinality::DataStatusAccepted));
+        assert!(!finalized_only.allows_finality(DataFinality::DataStatusUnknown));
+        assert!(finalized_only.allows_finality(DataFinality::DataStatusFinalized));
+    }
+}
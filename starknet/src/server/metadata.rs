@@ -1,7 +1,12 @@
+use apibara_core::node::v1alpha2::DataFinality;
 use apibara_node::o11y::{self, Counter, KeyValue};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use tonic::metadata::MetadataMap;
 use tracing::{info_span, Span};
 
+use super::quota::{Quota, QuotaStatus};
+
 pub trait RequestObserver: Send + Sync + 'static {
     type Meter: RequestMeter;
 
@@ -15,12 +20,50 @@ pub trait RequestObserver: Send + Sync + 'static {
 pub trait RequestMeter: Send + Sync + 'static {
     /// Increments the counter for the given name by the given amount.
     fn increment_counter(&self, name: &'static str, amount: u64);
+
+    /// Returns whether the request can send more data.
+    fn quota_status(&self) -> QuotaStatus {
+        QuotaStatus::Available
+    }
+}
+
+pub trait RequestAuthenticator: Send + Sync + 'static {
+    /// Authenticates a `stream_data` request, returning the permissions of the caller.
+    fn authenticate(&self, metadata: &MetadataMap) -> Result<KeyPermissions, AuthenticationError>;
+}
+
+/// Permissions granted to an api key.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct KeyPermissions {
+    /// Allow streaming pending data.
+    pub allow_pending: bool,
+    /// Only allow streaming finalized data.
+    pub finalized_only: bool,
+    /// Limit the amount of data streamed by the key.
+    pub quota: Option<Quota>,
+    /// Name that identifies the key in usage reports.
+    ///
+    /// If not set, the key is identified by [api_key_id].
+    pub name: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthenticationError {
+    #[error("missing api key")]
+    MissingKey,
+    #[error("invalid api key")]
+    InvalidKey,
 }
 
 /// A [RequestObserver] that adds no context.
 #[derive(Debug, Default)]
 pub struct SimpleRequestObserver {}
 
+/// A [RequestAuthenticator] that accepts all requests.
+#[derive(Debug, Default)]
+pub struct AllowAllAuthenticator {}
+
 /// A [RequestMeter] that adds no context.
 pub struct SimpleMeter {
     counter: Counter<u64>,
@@ -120,7 +163,86 @@ impl RequestMeter for MetadataKeyMeter {
     }
 }
 
+impl Default for KeyPermissions {
+    fn default() -> Self {
+        KeyPermissions {
+            allow_pending: true,
+            finalized_only: false,
+            quota: None,
+            name: None,
+        }
+    }
+}
+
+impl KeyPermissions {
+    /// Returns true if the key is allowed to stream data with the given finality.
+    pub fn allows_finality(&self, finality: DataFinality) -> bool {
+        match finality {
+            DataFinality::DataStatusFinalized => true,
+            DataFinality::DataStatusPending => self.allow_pending && !self.finalized_only,
+            _ => !self.finalized_only,
+        }
+    }
+}
+
+impl RequestAuthenticator for AllowAllAuthenticator {
+    fn authenticate(&self, _metadata: &MetadataMap) -> Result<KeyPermissions, AuthenticationError> {
+        Ok(KeyPermissions::default())
+    }
+}
+
+/// An optional authenticator accepts all requests when not set.
+impl<A: RequestAuthenticator> RequestAuthenticator for Option<A> {
+    fn authenticate(&self, metadata: &MetadataMap) -> Result<KeyPermissions, AuthenticationError> {
+        match self {
+            None => Ok(KeyPermissions::default()),
+            Some(authenticator) => authenticator.authenticate(metadata),
+        }
+    }
+}
+
+/// Returns the api key sent by the client.
+///
+/// The key is read from the `authorization: Bearer <key>` header, falling back
+/// to the `x-api-key` header.
+pub fn request_api_key(metadata: &MetadataMap) -> Option<&str> {
+    if let Some(key) = metadata
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return Some(key);
+    }
+
+    metadata
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Returns an identifier of the api key that can be stored and exported.
+///
+/// The identifier is derived from the sha256 hash of the key, so it doesn't
+/// reveal the key itself.
+pub fn api_key_id(key: &str) -> String {
+    let hash = Sha256::digest(key.as_bytes());
+    format!("sha256:{}", hex::encode(&hash[..16]))
+}
+
 fn new_data_out_counter() -> Counter<u64> {
     let meter = o11y::meter("stream_data");
     meter.u64_counter("data_out").init()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::api_key_id;
+
+    #[test]
+    fn test_api_key_id_does_not_contain_key() {
+        let id = api_key_id("my-secret-key");
+        assert!(!id.contains("my-secret-key"));
+        assert_eq!(id, api_key_id("my-secret-key"));
+        assert_ne!(id, api_key_id("my-other-key"));
+        assert_eq!(id.len(), "sha256:".len() + 32);
+    }
+}
@@ -1,32 +1,74 @@
+mod admin;
+mod compression;
 mod health;
+mod http;
+mod keys_file;
 mod metadata;
+mod quota;
 mod stream;
+#[cfg(test)]
+mod test_utils;
+mod tls;
+mod usage;
+mod ws;
 
 use std::{net::SocketAddr, sync::Arc};
 
 use apibara_core::node as node_pb;
 use apibara_node::db::libmdbx::{Environment, EnvironmentKind};
+use futures::StreamExt;
 use tokio::task::JoinError;
 use tokio_util::sync::CancellationToken;
 use tonic::transport::Server as TonicServer;
-use tracing::{error, info, info_span};
+use tower::util::MapRequestLayer;
+use tracing::{error, info, info_span, warn};
 
 use crate::{
-    db::DatabaseStorage, healer::HealerClient, ingestion::IngestionStreamClient,
+    core::IngestionMessage,
+    db::{BlockCache, CachedStorage, DatabaseStorage, DEFAULT_BLOCK_CACHE_SIZE},
+    healer::HealerClient,
+    ingestion::IngestionStreamClient,
+    provider::Provider,
     server::stream::StreamService,
 };
 
-use self::health::HealthReporter;
+use self::{
+    compression::disable_compression_if_requested, health::HealthReporter, http::start_http_server,
+    usage::UsageLedger,
+};
 
+pub use self::admin::AdminServer;
+pub use self::compression::COMPRESSION_HEADER;
+pub use self::http::HttpStreamDataRequest;
+pub use self::keys_file::{ApiKey, KeysFileAuthenticator, KeysFileError};
 pub use self::metadata::{
-    MetadataKeyRequestObserver, RequestMeter, RequestObserver, SimpleRequestObserver,
+    api_key_id, AllowAllAuthenticator, AuthenticationError, KeyPermissions,
+    MetadataKeyRequestObserver, RequestAuthenticator, RequestMeter, RequestObserver,
+    SimpleRequestObserver,
+};
+pub use self::quota::{Quota, QuotaAction, QuotaMeter, QuotaStatus, QuotaTracker};
+pub use self::tls::{TlsConfig, TlsConfigError};
+pub use self::usage::{
+    read_usage, write_usage_csv, write_usage_json, UsageExportError, UsageLedger, UsageMeter,
+    UsageRecord,
 };
 
-pub struct Server<E: EnvironmentKind, O: RequestObserver> {
+pub struct Server<G, E, O, A>
+where
+    G: Provider + Send + Sync + 'static,
+    E: EnvironmentKind,
+    O: RequestObserver,
+    A: RequestAuthenticator,
+{
     db: Arc<Environment<E>>,
+    provider: Arc<G>,
     ingestion: Arc<IngestionStreamClient>,
     healer: Arc<HealerClient>,
     request_observer: O,
+    authenticator: A,
+    tls_config: Option<TlsConfig>,
+    http_address: Option<SocketAddr>,
+    block_cache_size: usize,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -37,39 +79,98 @@ pub enum ServerError {
     Task(#[from] JoinError),
     #[error("error starting reflection server")]
     ReflectionServer(#[from] tonic_reflection::server::Error),
+    #[error("error configuring tls")]
+    TlsConfig(#[from] TlsConfigError),
+    #[error("http transport error")]
+    Http(#[from] hyper::Error),
+    #[error("error binding http address")]
+    HttpBind(#[source] std::io::Error),
 }
 
-impl<E, O> Server<E, O>
+impl<G, E, O, A> Server<G, E, O, A>
 where
+    G: Provider + Send + Sync + 'static,
     E: EnvironmentKind,
     O: RequestObserver,
+    A: RequestAuthenticator,
 {
     pub fn new(
         db: Arc<Environment<E>>,
+        provider: Arc<G>,
         ingestion: IngestionStreamClient,
         healer: HealerClient,
-    ) -> Server<E, SimpleRequestObserver> {
+    ) -> Server<G, E, SimpleRequestObserver, AllowAllAuthenticator> {
         let ingestion = Arc::new(ingestion);
         let healer = Arc::new(healer);
         let request_observer = SimpleRequestObserver::default();
+        let authenticator = AllowAllAuthenticator::default();
         Server {
             db,
+            provider,
             ingestion,
             healer,
             request_observer,
+            authenticator,
+            tls_config: None,
+            http_address: None,
+            block_cache_size: DEFAULT_BLOCK_CACHE_SIZE,
         }
     }
 
     /// Creates a new Server with the given request observer.
-    pub fn with_request_observer<S: RequestObserver>(self, request_observer: S) -> Server<E, S> {
+    pub fn with_request_observer<S: RequestObserver>(
+        self,
+        request_observer: S,
+    ) -> Server<G, E, S, A> {
         Server {
             db: self.db,
+            provider: self.provider,
             ingestion: self.ingestion,
             healer: self.healer,
             request_observer,
+            authenticator: self.authenticator,
+            tls_config: self.tls_config,
+            http_address: self.http_address,
+            block_cache_size: self.block_cache_size,
         }
     }
 
+    /// Creates a new Server with the given request authenticator.
+    pub fn with_request_authenticator<T: RequestAuthenticator>(
+        self,
+        authenticator: T,
+    ) -> Server<G, E, O, T> {
+        Server {
+            db: self.db,
+            provider: self.provider,
+            ingestion: self.ingestion,
+            healer: self.healer,
+            request_observer: self.request_observer,
+            authenticator,
+            tls_config: self.tls_config,
+            http_address: self.http_address,
+            block_cache_size: self.block_cache_size,
+        }
+    }
+
+    /// Serve requests over TLS using the given configuration.
+    pub fn with_tls_config(mut self, tls_config: TlsConfig) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    /// Also serve the stream as server-sent events and over websocket on the given address.
+    pub fn with_http_address(mut self, http_address: SocketAddr) -> Self {
+        self.http_address = Some(http_address);
+        self
+    }
+
+    /// Cache the data of up to `block_cache_size` blocks, shared by all streams.
+    pub fn with_block_cache_size(mut self, block_cache_size: usize) -> Self {
+        self.block_cache_size = block_cache_size;
+        self
+    }
+
     pub async fn start(self, addr: SocketAddr, ct: CancellationToken) -> Result<(), ServerError> {
         let (mut health_reporter, health_service) = HealthReporter::new(self.db.clone());
 
@@ -78,19 +179,64 @@ where
             async move { health_reporter.start(ct).await }
         });
 
+        let usage_ledger = Arc::new(UsageLedger::new(self.db.clone()));
+        let usage_handle = tokio::spawn({
+            let ct = ct.clone();
+            let usage_ledger = usage_ledger.clone();
+            async move { usage_ledger.start(ct).await }
+        });
+
         let reflection_service = tonic_reflection::server::Builder::configure()
             .register_encoded_file_descriptor_set(node_pb::v1alpha2::node_file_descriptor_set())
             .build()?;
 
-        let storage = DatabaseStorage::new(self.db);
-        let stream_service =
-            StreamService::new(self.ingestion, self.healer, storage, self.request_observer)
-                .into_service();
+        let storage = CachedStorage::new(DatabaseStorage::new(self.db), self.block_cache_size);
+        let cache_handle = tokio::spawn({
+            let ct = ct.clone();
+            let cache = storage.cache();
+            let ingestion = self.ingestion.clone();
+            async move { invalidate_block_cache(cache, ingestion, ct).await }
+        });
+
+        let stream_service = Arc::new(StreamService::new(
+            self.provider,
+            self.ingestion,
+            self.healer,
+            storage,
+            self.request_observer,
+            self.authenticator,
+            usage_ledger,
+        ));
+
+        // the http server uses the same certificate as the grpc server.
+        let http_handle = match self.http_address {
+            None => None,
+            Some(http_address) => {
+                let http_tls_config = self
+                    .tls_config
+                    .as_ref()
+                    .map(TlsConfig::to_rustls_server_config)
+                    .transpose()?;
+                let ct = ct.clone();
+                let stream_service = stream_service.clone();
+                Some(tokio::spawn(async move {
+                    start_http_server(stream_service, http_address, http_tls_config, ct).await
+                }))
+            }
+        };
+
+        let stream_service = stream_service.into_service();
 
-        info!(addr = %addr, "starting server");
+        let mut builder = TonicServer::builder();
+        if let Some(tls_config) = &self.tls_config {
+            builder = builder.tls_config(tls_config.to_server_tls_config()?)?;
+        }
+
+        info!(addr = %addr, tls = self.tls_config.is_some(), "starting server");
 
-        TonicServer::builder()
+        builder
             .trace_fn(|_| info_span!("node_server"))
+            .layer(MapRequestLayer::new(disable_compression_if_requested))
             .add_service(health_service)
             .add_service(stream_service)
             .add_service(reflection_service)
@@ -103,7 +249,44 @@ where
         // signal health reporter to stop and wait for it
         ct.cancel();
         reporter_handle.await?;
+        usage_handle.await?;
+        cache_handle.await?;
+        if let Some(http_handle) = http_handle {
+            http_handle.await??;
+        }
 
         Ok(())
     }
 }
+
+/// Removes blocks from the cache when they're invalidated or updated by ingestion.
+async fn invalidate_block_cache(
+    cache: BlockCache,
+    ingestion: Arc<IngestionStreamClient>,
+    ct: CancellationToken,
+) {
+    let mut ingestion_stream = ingestion.subscribe().await;
+    loop {
+        let message = tokio::select! {
+            _ = ct.cancelled() => break,
+            message = ingestion_stream.next() => message,
+        };
+
+        match message {
+            None => break,
+            Some(Ok(IngestionMessage::Invalidate(new_chain_root))) => {
+                cache.invalidate(&new_chain_root);
+            }
+            Some(Ok(IngestionMessage::Pending(block_id)))
+            | Some(Ok(IngestionMessage::Reingested(block_id))) => {
+                cache.remove(&block_id);
+            }
+            Some(Ok(_)) => {}
+            Some(Err(err)) => {
+                // missed some messages, can't know which blocks changed.
+                warn!(err = ?err, "block cache lagging behind ingestion");
+                cache.clear();
+            }
+        }
+    }
+}
@@ -0,0 +1,408 @@
+//! Track data quotas per api key.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use super::metadata::RequestMeter;
+
+/// Number of buckets used to approximate the rolling window.
+const WINDOW_BUCKETS: u32 = 10;
+
+/// Minimum time a throttled stream waits before checking its quota again.
+const MIN_THROTTLE_DELAY: Duration = Duration::from_millis(100);
+
+/// The data budget of an api key.
+///
+/// Limits are keyed by counter name (`header`, `transaction`, `event`, ...) and
+/// apply to the data sent over the last `window_seconds` seconds, across all
+/// streams opened with the same key.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Quota {
+    #[serde(default = "default_window_seconds")]
+    pub window_seconds: u64,
+    #[serde(default)]
+    pub on_exceeded: QuotaAction,
+    pub limits: HashMap<String, u64>,
+}
+
+/// What to do when a key exceeds its quota.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    /// Pause the stream until the key is back within its budget.
+    #[default]
+    Delay,
+    /// Terminate the stream with an error.
+    Reject,
+}
+
+/// Result of checking a quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaStatus {
+    /// The request can send more data.
+    Available,
+    /// The request must wait for the given duration before sending more data.
+    Throttled(Duration),
+    /// The request exceeded its quota and must be terminated.
+    Exhausted,
+}
+
+/// Tracks data usage of all api keys over rolling windows.
+#[derive(Default)]
+pub struct QuotaTracker {
+    windows: Mutex<HashMap<String, RollingWindow>>,
+}
+
+/// A [RequestMeter] that records usage in a [QuotaTracker].
+pub struct QuotaMeter<M: RequestMeter> {
+    inner: M,
+    key: String,
+    quota: Option<Quota>,
+    tracker: Arc<QuotaTracker>,
+}
+
+#[derive(Default)]
+struct RollingWindow {
+    buckets: VecDeque<Bucket>,
+}
+
+struct Bucket {
+    started_at: Instant,
+    counters: HashMap<&'static str, u64>,
+}
+
+fn default_window_seconds() -> u64 {
+    60
+}
+
+impl Quota {
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.window_seconds.max(1))
+    }
+}
+
+impl QuotaTracker {
+    /// Records `amount` of data for the counter `name`.
+    pub fn record(&self, key: &str, quota: &Quota, name: &'static str, amount: u64) {
+        self.record_at(Instant::now(), key, quota, name, amount)
+    }
+
+    /// Checks if the key is within its quota.
+    pub fn status(&self, key: &str, quota: &Quota) -> QuotaStatus {
+        self.status_at(Instant::now(), key, quota)
+    }
+
+    fn record_at(&self, now: Instant, key: &str, quota: &Quota, name: &'static str, amount: u64) {
+        if amount == 0 {
+            return;
+        }
+        let mut windows = self.windows.lock().expect("quota lock poisoned");
+        let window = windows.entry(key.to_string()).or_default();
+        window.expire(now, quota.window());
+        window.record(now, quota.window() / WINDOW_BUCKETS, name, amount);
+    }
+
+    fn status_at(&self, now: Instant, key: &str, quota: &Quota) -> QuotaStatus {
+        let mut windows = self.windows.lock().expect("quota lock poisoned");
+        let window = match windows.get_mut(key) {
+            None => return QuotaStatus::Available,
+            Some(window) => window,
+        };
+        window.expire(now, quota.window());
+
+        let exceeded = quota
+            .limits
+            .iter()
+            .any(|(name, limit)| window.total(name) >= *limit);
+
+        if !exceeded {
+            return QuotaStatus::Available;
+        }
+
+        match quota.on_exceeded {
+            QuotaAction::Reject => QuotaStatus::Exhausted,
+            QuotaAction::Delay => {
+                // wait for the oldest bucket to leave the window
+                let delay = window
+                    .buckets
+                    .front()
+                    .map(|bucket| {
+                        (bucket.started_at + quota.window()).saturating_duration_since(now)
+                    })
+                    .unwrap_or_default();
+                QuotaStatus::Throttled(delay.max(MIN_THROTTLE_DELAY))
+            }
+        }
+    }
+}
+
+impl RollingWindow {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some(bucket) = self.buckets.front() {
+            if bucket.started_at + window > now {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    fn record(&mut self, now: Instant, bucket_size: Duration, name: &'static str, amount: u64) {
+        let needs_new_bucket = self
+            .buckets
+            .back()
+            .map(|bucket| bucket.started_at + bucket_size <= now)
+            .unwrap_or(true);
+
+        if needs_new_bucket {
+            self.buckets.push_back(Bucket {
+                started_at: now,
+                counters: HashMap::default(),
+            });
+        }
+
+        if let Some(bucket) = self.buckets.back_mut() {
+            *bucket.counters.entry(name).or_default() += amount;
+        }
+    }
+
+    fn total(&self, name: &str) -> u64 {
+        self.buckets
+            .iter()
+            .filter_map(|bucket| bucket.counters.get(name))
+            .sum()
+    }
+}
+
+impl<M: RequestMeter> QuotaMeter<M> {
+    /// Wraps `inner`, tracking usage of `key` against its `quota`.
+    ///
+    /// If `quota` is `None`, the meter doesn't enforce any limit.
+    pub fn new(inner: M, key: String, quota: Option<Quota>, tracker: Arc<QuotaTracker>) -> Self {
+        QuotaMeter {
+            inner,
+            key,
+            quota,
+            tracker,
+        }
+    }
+}
+
+impl<M: RequestMeter> RequestMeter for QuotaMeter<M> {
+    fn increment_counter(&self, name: &'static str, amount: u64) {
+        self.inner.increment_counter(name, amount);
+        if let Some(quota) = &self.quota {
+            self.tracker.record(&self.key, quota, name, amount);
+        }
+    }
+
+    fn quota_status(&self) -> QuotaStatus {
+        match &self.quota {
+            None => QuotaStatus::Available,
+            Some(quota) => self.tracker.status(&self.key, quota),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use crate::server::RequestMeter;
+
+    use super::{
+        Quota, QuotaAction, QuotaMeter, QuotaStatus, QuotaTracker, RollingWindow,
+        MIN_THROTTLE_DELAY,
+    };
+
+    fn quota(on_exceeded: QuotaAction, limits: &[(&str, u64)]) -> Quota {
+        Quota {
+            window_seconds: 10,
+            on_exceeded,
+            limits: limits
+                .iter()
+                .map(|(name, limit)| (name.to_string(), *limit))
+                .collect(),
+        }
+    }
+
+    fn secs(value: f64) -> Duration {
+        Duration::from_secs_f64(value)
+    }
+
+    /// Records the counters it's incremented with.
+    #[derive(Default, Clone)]
+    struct RecordingMeter {
+        counters: Arc<Mutex<Vec<(&'static str, u64)>>>,
+    }
+
+    impl RequestMeter for RecordingMeter {
+        fn increment_counter(&self, name: &'static str, amount: u64) {
+            self.counters.lock().unwrap().push((name, amount));
+        }
+    }
+
+    #[test]
+    fn test_rolling_window_buckets() {
+        let start = Instant::now();
+        let mut window = RollingWindow::default();
+
+        window.record(start, secs(1.0), "event", 1);
+        window.record(start + secs(0.5), secs(1.0), "event", 2);
+        window.record(start + secs(0.5), secs(1.0), "header", 5);
+        assert_eq!(window.buckets.len(), 1);
+        window.record(start + secs(1.0), secs(1.0), "event", 4);
+        assert_eq!(window.buckets.len(), 2);
+
+        assert_eq!(window.total("event"), 7);
+        assert_eq!(window.total("header"), 5);
+        assert_eq!(window.total("transaction"), 0);
+    }
+
+    #[test]
+    fn test_rolling_window_expire() {
+        let start = Instant::now();
+        let mut window = RollingWindow::default();
+        window.record(start, secs(1.0), "event", 1);
+        window.record(start + secs(3.0), secs(1.0), "event", 2);
+
+        window.expire(start + secs(9.9), secs(10.0));
+        assert_eq!(window.total("event"), 3);
+
+        // buckets leave the window when their start is a full window ago.
+        window.expire(start + secs(10.0), secs(10.0));
+        assert_eq!(window.total("event"), 2);
+
+        window.expire(start + secs(13.0), secs(10.0));
+        assert_eq!(window.total("event"), 0);
+        assert!(window.buckets.is_empty());
+    }
+
+    #[test]
+    fn test_tracker_delay() {
+        let start = Instant::now();
+        let tracker = QuotaTracker::default();
+        let quota = quota(QuotaAction::Delay, &[("event", 10), ("header", 100)]);
+
+        assert_eq!(
+            tracker.status_at(start, "a", &quota),
+            QuotaStatus::Available
+        );
+
+        tracker.record_at(start, "a", &quota, "event", 6);
+        tracker.record_at(start + secs(2.0), "a", &quota, "event", 3);
+        assert_eq!(
+            tracker.status_at(start + secs(2.0), "a", &quota),
+            QuotaStatus::Available
+        );
+
+        // the limit is reached with exactly 10 events.
+        tracker.record_at(start + secs(4.0), "a", &quota, "event", 1);
+        assert_eq!(
+            tracker.status_at(start + secs(4.0), "a", &quota),
+            QuotaStatus::Throttled(secs(6.0))
+        );
+        // other keys have their own budget.
+        assert_eq!(
+            tracker.status_at(start + secs(4.0), "b", &quota),
+            QuotaStatus::Available
+        );
+
+        // the delay is never shorter than the minimum.
+        assert_eq!(
+            tracker.status_at(start + secs(9.99), "a", &quota),
+            QuotaStatus::Throttled(MIN_THROTTLE_DELAY)
+        );
+
+        // the first 6 events left the window.
+        assert_eq!(
+            tracker.status_at(start + secs(10.0), "a", &quota),
+            QuotaStatus::Available
+        );
+    }
+
+    #[test]
+    fn test_tracker_reject() {
+        let start = Instant::now();
+        let tracker = QuotaTracker::default();
+        let quota = quota(QuotaAction::Reject, &[("transaction", 5)]);
+
+        // counters without a limit don't count.
+        tracker.record_at(start, "a", &quota, "event", 1_000);
+        assert_eq!(
+            tracker.status_at(start, "a", &quota),
+            QuotaStatus::Available
+        );
+
+        tracker.record_at(start, "a", &quota, "transaction", 5);
+        assert_eq!(
+            tracker.status_at(start, "a", &quota),
+            QuotaStatus::Exhausted
+        );
+        assert_eq!(
+            tracker.status_at(start + secs(10.0), "a", &quota),
+            QuotaStatus::Available
+        );
+    }
+
+    #[test]
+    fn test_tracker_ignores_empty_records() {
+        let start = Instant::now();
+        let tracker = QuotaTracker::default();
+        let quota = quota(QuotaAction::Reject, &[("event", 0)]);
+
+        tracker.record_at(start, "a", &quota, "event", 0);
+        assert!(tracker.windows.lock().unwrap().is_empty());
+        assert_eq!(
+            tracker.status_at(start, "a", &quota),
+            QuotaStatus::Available
+        );
+    }
+
+    #[test]
+    fn test_quota_window() {
+        let quota: Quota = serde_json::from_str(r#"{"limits": {"event": 10}}"#).unwrap();
+        assert_eq!(quota.window_seconds, 60);
+        assert_eq!(quota.on_exceeded, QuotaAction::Delay);
+        assert_eq!(quota.limits, HashMap::from([("event".to_string(), 10)]));
+        assert_eq!(quota.window(), secs(60.0));
+
+        let quota = Quota {
+            window_seconds: 0,
+            ..quota
+        };
+        assert_eq!(quota.window(), secs(1.0));
+    }
+
+    #[test]
+    fn test_quota_meter() {
+        let inner = RecordingMeter::default();
+        let tracker = Arc::new(QuotaTracker::default());
+        let quota = quota(QuotaAction::Reject, &[("event", 3)]);
+
+        let meter = QuotaMeter::new(inner.clone(), "a".to_string(), Some(quota), tracker.clone());
+        meter.increment_counter("event", 2);
+        assert_eq!(meter.quota_status(), QuotaStatus::Available);
+        meter.increment_counter("event", 1);
+        assert_eq!(meter.quota_status(), QuotaStatus::Exhausted);
+
+        // without a quota, usage is not tracked.
+        let meter = QuotaMeter::new(inner.clone(), "b".to_string(), None, tracker.clone());
+        meter.increment_counter("event", 10);
+        assert_eq!(meter.quota_status(), QuotaStatus::Available);
+        assert!(!tracker.windows.lock().unwrap().contains_key("b"));
+
+        assert_eq!(
+            *inner.counters.lock().unwrap(),
+            vec![("event", 2), ("event", 1), ("event", 10)]
+        );
+    }
+}
@@ -7,11 +7,13 @@ use std::{
     time::Duration,
 };
 
-use apibara_core::node::v1alpha2::{stream_server, StreamDataRequest, StreamDataResponse};
-use apibara_node::heartbeat::Heartbeat;
+use apibara_core::node::v1alpha2::{
+    stream_server, StatusRequest, StatusResponse, StreamDataRequest, StreamDataResponse,
+};
+use apibara_node::{db::libmdbx::EnvironmentKind, heartbeat::Heartbeat};
 use futures::Stream;
 use pin_project::pin_project;
-use tonic::{Request, Response, Streaming};
+use tonic::{codec::CompressionEncoding, metadata::MetadataMap, Request, Response, Streaming};
 use tracing::warn;
 use tracing_futures::Instrument;
 
@@ -21,60 +23,113 @@ use crate::{
     healer::HealerClient,
     // stream::{BatchDataStream, BatchMessage, StreamError},
     ingestion::IngestionStreamClient,
+    provider::Provider,
     stream::{DataStream, StreamConfigurationStream, StreamError},
 };
 
-use super::metadata::RequestObserver;
+use super::{
+    metadata::{api_key_id, request_api_key, RequestAuthenticator, RequestObserver},
+    quota::{QuotaMeter, QuotaTracker},
+    usage::{UsageLedger, UsageMeter},
+};
+
+/// Stream of responses sent to the client.
+pub type ResponseDataStream =
+    Pin<Box<dyn Stream<Item = Result<StreamDataResponse, tonic::Status>> + Send + 'static>>;
 
-pub struct StreamService<R: StorageReader, O: RequestObserver> {
+pub struct StreamService<G, R, O, A, E>
+where
+    G: Provider + Send + Sync + 'static,
+    R: StorageReader,
+    O: RequestObserver,
+    A: RequestAuthenticator,
+    E: EnvironmentKind,
+{
+    provider: Arc<G>,
     ingestion: Arc<IngestionStreamClient>,
     healer: Arc<HealerClient>,
     storage: Arc<R>,
     request_observer: O,
+    authenticator: A,
+    quota_tracker: Arc<QuotaTracker>,
+    usage_ledger: Arc<UsageLedger<E>>,
 }
 
-impl<R, O> StreamService<R, O>
+impl<G, R, O, A, E> StreamService<G, R, O, A, E>
 where
+    G: Provider + Send + Sync + 'static,
     R: StorageReader + Send + Sync + 'static,
     O: RequestObserver,
+    A: RequestAuthenticator,
+    E: EnvironmentKind,
 {
     pub fn new(
+        provider: Arc<G>,
         ingestion: Arc<IngestionStreamClient>,
         healer: Arc<HealerClient>,
         storage: R,
         request_observer: O,
+        authenticator: A,
+        usage_ledger: Arc<UsageLedger<E>>,
     ) -> Self {
         let storage = Arc::new(storage);
         StreamService {
+            provider,
             ingestion,
             healer,
             storage,
             request_observer,
+            authenticator,
+            quota_tracker: Arc::new(QuotaTracker::default()),
+            usage_ledger,
         }
     }
 
-    pub fn into_service(self) -> stream_server::StreamServer<Self> {
-        stream_server::StreamServer::new(self)
+    /// Returns the grpc service.
+    ///
+    /// Responses are compressed with gzip if the client accepts it.
+    pub fn into_service(self: Arc<Self>) -> stream_server::StreamServer<Self> {
+        stream_server::StreamServer::from_arc(self)
+            .send_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Gzip)
     }
-}
 
-#[tonic::async_trait]
-impl<R, O> stream_server::Stream for StreamService<R, O>
-where
-    R: StorageReader + Send + Sync + 'static,
-    O: RequestObserver,
-{
-    type StreamDataStream =
-        Pin<Box<dyn Stream<Item = Result<StreamDataResponse, tonic::Status>> + Send + 'static>>;
-
-    async fn stream_data(
+    /// Authenticates the request and starts streaming data configured by `requests`.
+    ///
+    /// This is shared by all transports so that they behave exactly like the grpc
+    /// `StreamData` call.
+    pub async fn stream_data_with_requests<S, SE>(
         &self,
-        request: Request<Streaming<StreamDataRequest>>,
-    ) -> Result<Response<Self::StreamDataStream>, tonic::Status> {
-        let stream_span = self.request_observer.stream_data_span(request.metadata());
-        let stream_meter = self.request_observer.stream_data_meter(request.metadata());
+        metadata: &MetadataMap,
+        requests: S,
+    ) -> Result<ResponseDataStream, tonic::Status>
+    where
+        S: Stream<Item = Result<StreamDataRequest, SE>> + Unpin + Send + 'static,
+        SE: std::error::Error + Send + Sync + 'static,
+    {
+        let permissions = self
+            .authenticator
+            .authenticate(metadata)
+            .map_err(|err| tonic::Status::unauthenticated(err.to_string()))?;
 
-        let configuration_stream = StreamConfigurationStream::new(request.into_inner());
+        let stream_span = self.request_observer.stream_data_span(metadata);
+        let stream_meter = self.request_observer.stream_data_meter(metadata);
+        let api_key = request_api_key(metadata).unwrap_or("anon").to_string();
+        // never store the key itself, only a name or hash that identifies it.
+        let usage_key = match (&permissions.name, request_api_key(metadata)) {
+            (Some(name), _) => name.clone(),
+            (None, Some(key)) => api_key_id(key),
+            (None, None) => "anon".to_string(),
+        };
+        let stream_meter = UsageMeter::new(stream_meter, usage_key, self.usage_ledger.clone());
+        let stream_meter = QuotaMeter::new(
+            stream_meter,
+            api_key,
+            permissions.quota.clone(),
+            self.quota_tracker.clone(),
+        );
+
+        let configuration_stream = StreamConfigurationStream::new(requests, permissions);
 
         let ingestion_stream = self.ingestion.subscribe().await;
         let ingestion_stream = IngestionStream::new(ingestion_stream);
@@ -88,7 +143,76 @@ where
         );
 
         let response = ResponseStream::new(data_stream).instrument(stream_span);
-        Ok(Response::new(Box::pin(response)))
+        Ok(Box::pin(response))
+    }
+}
+
+#[tonic::async_trait]
+impl<G, R, O, A, E> stream_server::Stream for StreamService<G, R, O, A, E>
+where
+    G: Provider + Send + Sync + 'static,
+    R: StorageReader + Send + Sync + 'static,
+    O: RequestObserver,
+    A: RequestAuthenticator,
+    E: EnvironmentKind,
+{
+    type StreamDataStream = ResponseDataStream;
+
+    async fn stream_data(
+        &self,
+        request: Request<Streaming<StreamDataRequest>>,
+    ) -> Result<Response<Self::StreamDataStream>, tonic::Status> {
+        let metadata = request.metadata().clone();
+        let response = self
+            .stream_data_with_requests(&metadata, request.into_inner())
+            .await?;
+        Ok(Response::new(response))
+    }
+
+    async fn status(
+        &self,
+        request: Request<StatusRequest>,
+    ) -> Result<Response<StatusResponse>, tonic::Status> {
+        self.authenticator
+            .authenticate(request.metadata())
+            .map_err(|err| tonic::Status::unauthenticated(err.to_string()))?;
+
+        let accepted = self.storage.highest_accepted_block().map_err(|err| {
+            warn!(err = ?err, "failed to read accepted block");
+            tonic::Status::internal("internal server error")
+        })?;
+        let finalized = self.storage.highest_finalized_block().map_err(|err| {
+            warn!(err = ?err, "failed to read finalized block");
+            tonic::Status::internal("internal server error")
+        })?;
+        let pending = self.ingestion.pending_block();
+
+        // the node can still report its own status if the rpc is unavailable.
+        let rpc_head = match self.provider.get_head().await {
+            Ok(head) => Some(head),
+            Err(err) => {
+                warn!(err = ?err, "failed to fetch rpc head");
+                None
+            }
+        };
+        let chain_id = match self.provider.get_chain_id().await {
+            Ok(chain_id) => chain_id,
+            Err(err) => {
+                warn!(err = ?err, "failed to fetch chain id");
+                String::default()
+            }
+        };
+
+        let response = StatusResponse {
+            accepted: accepted.map(|c| c.to_cursor()),
+            finalized: finalized.map(|c| c.to_cursor()),
+            pending: pending.map(|c| c.to_cursor()),
+            rpc_head: rpc_head.map(|c| c.to_cursor()),
+            chain_id,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        };
+
+        Ok(Response::new(response))
     }
 }
 
@@ -187,6 +311,12 @@ where
                             StreamError::Client { message } => {
                                 tonic::Status::invalid_argument(message)
                             }
+                            StreamError::PermissionDenied { message } => {
+                                tonic::Status::permission_denied(message)
+                            }
+                            StreamError::ResourceExhausted { message } => {
+                                tonic::Status::resource_exhausted(message)
+                            }
                             StreamError::Internal(err) => {
                                 warn!(err = ?err, "stream service error");
                                 tonic::Status::internal("internal server error")
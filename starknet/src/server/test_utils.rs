@@ -0,0 +1,82 @@
+//! Stream service used to test the server transports.
+
+use std::sync::Arc;
+
+use apibara_core::starknet::v1alpha2;
+use apibara_node::db::libmdbx::NoWriteMap;
+use tempfile::TempDir;
+use tonic::metadata::MetadataMap;
+
+use crate::{
+    db::{
+        test_utils::{open_test_db, write_test_block},
+        DatabaseStorage,
+    },
+    healer::HealerClient,
+    ingestion::IngestionStreamPublisher,
+    provider::HttpProvider,
+};
+
+use super::{
+    metadata::request_api_key, stream::StreamService, usage::UsageLedger, AuthenticationError,
+    KeyPermissions, RequestAuthenticator, SimpleRequestObserver,
+};
+
+/// The only api key accepted by [TestAuthenticator].
+pub const TEST_API_KEY: &str = "test-key";
+
+/// Accepts requests with the [TEST_API_KEY] key.
+pub struct TestAuthenticator;
+
+pub type TestStreamService = StreamService<
+    HttpProvider,
+    DatabaseStorage<NoWriteMap>,
+    SimpleRequestObserver,
+    TestAuthenticator,
+    NoWriteMap,
+>;
+
+/// A stream service over a temporary database.
+pub struct TestServer {
+    pub service: Arc<TestStreamService>,
+    _path: TempDir,
+}
+
+impl RequestAuthenticator for TestAuthenticator {
+    fn authenticate(&self, metadata: &MetadataMap) -> Result<KeyPermissions, AuthenticationError> {
+        match request_api_key(metadata) {
+            None => Err(AuthenticationError::MissingKey),
+            Some(TEST_API_KEY) => Ok(KeyPermissions::default()),
+            Some(_) => Err(AuthenticationError::InvalidKey),
+        }
+    }
+}
+
+impl TestServer {
+    /// Creates a stream service over `block_count` finalized blocks.
+    pub fn new(block_count: u64) -> TestServer {
+        let (path, db) = open_test_db();
+        let storage = DatabaseStorage::new(db.clone());
+        for number in 0..block_count {
+            write_test_block(&storage, number, v1alpha2::BlockStatus::AcceptedOnL1);
+        }
+
+        let (ingestion, _) = IngestionStreamPublisher::new();
+        // the provider is only used by the status call.
+        let provider = HttpProvider::new("http://localhost:1".parse().unwrap());
+        let service = StreamService::new(
+            Arc::new(provider),
+            Arc::new(ingestion),
+            Arc::new(HealerClient::disconnected()),
+            storage,
+            SimpleRequestObserver::default(),
+            TestAuthenticator,
+            Arc::new(UsageLedger::new(db)),
+        );
+
+        TestServer {
+            service: Arc::new(service),
+            _path: path,
+        }
+    }
+}
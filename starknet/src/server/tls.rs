@@ -0,0 +1,330 @@
+//! Configure TLS for the server.
+
+use std::{fs, io::BufReader, path::PathBuf};
+
+use tokio_rustls::rustls::{
+    self, server::AllowAnyAuthenticatedClient, PrivateKey, RootCertStore, ServerConfig,
+};
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+/// Paths to the PEM-encoded files used to serve TLS.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    cert: PathBuf,
+    key: PathBuf,
+    client_ca: Option<PathBuf>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsConfigError {
+    #[error("failed to read certificate file")]
+    Certificate(#[source] std::io::Error),
+    #[error("failed to read private key file")]
+    PrivateKey(#[source] std::io::Error),
+    #[error("failed to read client CA certificate file")]
+    ClientCa(#[source] std::io::Error),
+    #[error("no private key found in the private key file")]
+    MissingPrivateKey,
+    #[error("invalid client CA certificate")]
+    InvalidClientCa,
+    #[error("invalid certificate or private key")]
+    Rustls(#[from] rustls::Error),
+}
+
+impl TlsConfig {
+    /// Creates a new TLS configuration with the given certificate and private key.
+    pub fn new(cert: PathBuf, key: PathBuf) -> Self {
+        TlsConfig {
+            cert,
+            key,
+            client_ca: None,
+        }
+    }
+
+    /// Require clients to present a certificate signed by the given CA (mTLS).
+    pub fn with_client_ca(mut self, client_ca: PathBuf) -> Self {
+        self.client_ca = Some(client_ca);
+        self
+    }
+
+    /// Reads the PEM files and returns the tonic server configuration.
+    pub fn to_server_tls_config(&self) -> Result<ServerTlsConfig, TlsConfigError> {
+        let cert = fs::read(&self.cert).map_err(TlsConfigError::Certificate)?;
+        let key = fs::read(&self.key).map_err(TlsConfigError::PrivateKey)?;
+        let mut config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+        if let Some(client_ca) = &self.client_ca {
+            let client_ca = fs::read(client_ca).map_err(TlsConfigError::ClientCa)?;
+            config = config.client_ca_root(Certificate::from_pem(client_ca));
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the PEM files and returns the configuration used by the http server.
+    ///
+    /// Only http/1.1 is negotiated, since WebSocket connections need it.
+    pub fn to_rustls_server_config(&self) -> Result<ServerConfig, TlsConfigError> {
+        let cert = fs::read(&self.cert).map_err(TlsConfigError::Certificate)?;
+        let cert = rustls_pemfile::certs(&mut BufReader::new(cert.as_slice()))
+            .map_err(TlsConfigError::Certificate)?
+            .into_iter()
+            .map(rustls::Certificate)
+            .collect();
+
+        let key = fs::read(&self.key).map_err(TlsConfigError::PrivateKey)?;
+        let key = rustls_pemfile::read_all(&mut BufReader::new(key.as_slice()))
+            .map_err(TlsConfigError::PrivateKey)?
+            .into_iter()
+            .find_map(|item| match item {
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+                _ => None,
+            })
+            .ok_or(TlsConfigError::MissingPrivateKey)?;
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let mut config = if let Some(client_ca) = &self.client_ca {
+            let client_ca = fs::read(client_ca).map_err(TlsConfigError::ClientCa)?;
+            let client_ca = rustls_pemfile::certs(&mut BufReader::new(client_ca.as_slice()))
+                .map_err(TlsConfigError::ClientCa)?;
+            let mut roots = RootCertStore::empty();
+            for cert in client_ca {
+                roots
+                    .add(&rustls::Certificate(cert))
+                    .map_err(|_| TlsConfigError::InvalidClientCa)?;
+            }
+            if roots.is_empty() {
+                return Err(TlsConfigError::InvalidClientCa);
+            }
+            builder
+                .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+                .with_single_cert(cert, key)?
+        } else {
+            builder.with_no_client_auth().with_single_cert(cert, key)?
+        };
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, net::SocketAddr, path::PathBuf, time::Duration};
+
+    use apibara_core::{node::v1alpha2::DataFinality, starknet::v1alpha2};
+    use apibara_sdk::{
+        Certificate, ClientBuilder, ClientTlsConfig, Configuration, DataMessage, Identity, Uri,
+    };
+    use assert_matches::assert_matches;
+    use futures::StreamExt;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa};
+    use tempfile::{tempdir, TempDir};
+    use tokio::net::TcpListener;
+    use tokio_util::sync::CancellationToken;
+    use tonic::transport::Server as TonicServer;
+
+    use crate::server::test_utils::{TestServer, TEST_API_KEY};
+
+    use super::{TlsConfig, TlsConfigError};
+
+    /// Certificates written to a temporary directory.
+    struct TestCertificates {
+        dir: TempDir,
+        server_pem: String,
+        client_ca: rcgen::Certificate,
+    }
+
+    impl TestCertificates {
+        fn new() -> Self {
+            let dir = tempdir().unwrap();
+            let server = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            let client_ca = certificate_authority();
+            let certificates = TestCertificates {
+                dir,
+                server_pem: server.serialize_pem().unwrap(),
+                client_ca,
+            };
+            certificates.write("server.pem", &certificates.server_pem);
+            certificates.write("server.key", &server.serialize_private_key_pem());
+            certificates.write("ca.pem", &certificates.client_ca.serialize_pem().unwrap());
+            certificates
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.dir.path().join(name)
+        }
+
+        fn write(&self, name: &str, content: &str) -> PathBuf {
+            let path = self.path(name);
+            fs::write(&path, content).unwrap();
+            path
+        }
+
+        fn server_tls_config(&self) -> TlsConfig {
+            TlsConfig::new(self.path("server.pem"), self.path("server.key"))
+        }
+
+        /// Returns the client configuration trusting the server certificate.
+        fn client_tls_config(&self) -> ClientTlsConfig {
+            ClientTlsConfig::new()
+                .ca_certificate(Certificate::from_pem(&self.server_pem))
+                .domain_name("localhost")
+        }
+    }
+
+    /// Returns a client identity signed by `ca`.
+    fn client_identity(ca: &rcgen::Certificate) -> Identity {
+        let client = rcgen::generate_simple_self_signed(vec!["client".to_string()]).unwrap();
+        Identity::from_pem(
+            client.serialize_pem_with_signer(ca).unwrap(),
+            client.serialize_private_key_pem(),
+        )
+    }
+
+    fn certificate_authority() -> rcgen::Certificate {
+        let mut params = CertificateParams::new(Vec::default());
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        rcgen::Certificate::from_params(params).unwrap()
+    }
+
+    /// Serves the grpc stream of `server` over TLS on a random port.
+    async fn start_server(
+        server: &TestServer,
+        tls_config: &TlsConfig,
+        ct: CancellationToken,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = futures::stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+
+        let server = TonicServer::builder()
+            .tls_config(tls_config.to_server_tls_config().unwrap())
+            .unwrap()
+            .add_service(server.service.clone().into_service())
+            .serve_with_incoming_shutdown(incoming, async move { ct.cancelled().await });
+        tokio::spawn(server);
+        addr
+    }
+
+    /// Streams the block headers with the sdk and returns the first batch.
+    async fn first_batch(
+        addr: SocketAddr,
+        tls_config: ClientTlsConfig,
+    ) -> Result<Vec<v1alpha2::Block>, Box<dyn std::error::Error>> {
+        let configuration = Configuration::<v1alpha2::Filter>::default()
+            .with_finality(DataFinality::DataStatusAccepted)
+            .with_filter(|filter| filter.with_header(v1alpha2::HeaderFilter { weak: false }));
+        let url: Uri = format!("https://{}", addr).parse()?;
+        let connect = ClientBuilder::<v1alpha2::Filter, v1alpha2::Block>::default()
+            .with_bearer_token(TEST_API_KEY.to_string())
+            .with_tls_config(tls_config)
+            .with_configuration(configuration)
+            .connect(url);
+        let (mut stream, _client) = tokio::time::timeout(Duration::from_secs(5), connect).await??;
+
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), stream.next())
+                .await?
+                .ok_or("stream closed")??;
+            if let DataMessage::Data { batch, .. } = message {
+                return Ok(batch);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_over_tls() {
+        let server = TestServer::new(3);
+        let certificates = TestCertificates::new();
+        let ct = CancellationToken::new();
+        let addr = start_server(&server, &certificates.server_tls_config(), ct.clone()).await;
+
+        let batch = first_batch(addr, certificates.client_tls_config())
+            .await
+            .unwrap();
+        assert_eq!(batch[0].header.as_ref().unwrap().block_number, 0);
+
+        // the server certificate is not signed by a trusted CA.
+        let untrusted = ClientTlsConfig::new().domain_name("localhost");
+        assert!(first_batch(addr, untrusted).await.is_err());
+
+        // the server certificate is not valid for another name.
+        let wrong_name = certificates.client_tls_config().domain_name("example.com");
+        assert!(first_batch(addr, wrong_name).await.is_err());
+
+        ct.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_stream_over_mutual_tls() {
+        let server = TestServer::new(3);
+        let certificates = TestCertificates::new();
+        let tls_config = certificates
+            .server_tls_config()
+            .with_client_ca(certificates.path("ca.pem"));
+        let ct = CancellationToken::new();
+        let addr = start_server(&server, &tls_config, ct.clone()).await;
+
+        let identity = client_identity(&certificates.client_ca);
+        let client_tls_config = certificates.client_tls_config().identity(identity);
+        let batch = first_batch(addr, client_tls_config).await.unwrap();
+        assert_eq!(batch[0].header.as_ref().unwrap().block_number, 0);
+
+        // clients without a certificate are rejected.
+        assert!(first_batch(addr, certificates.client_tls_config())
+            .await
+            .is_err());
+
+        // clients with a certificate signed by another CA are rejected.
+        let other_ca = certificate_authority();
+        let identity = client_identity(&other_ca);
+        let client_tls_config = certificates.client_tls_config().identity(identity);
+        assert!(first_batch(addr, client_tls_config).await.is_err());
+
+        ct.cancel();
+    }
+
+    #[test]
+    fn test_rustls_server_config() {
+        let certificates = TestCertificates::new();
+
+        let config = certificates
+            .server_tls_config()
+            .with_client_ca(certificates.path("ca.pem"))
+            .to_rustls_server_config()
+            .unwrap();
+        assert_eq!(config.alpn_protocols, vec![b"http/1.1".to_vec()]);
+
+        let missing = TlsConfig::new(
+            certificates.path("missing.pem"),
+            certificates.path("server.key"),
+        );
+        assert_matches!(
+            missing.to_rustls_server_config().err(),
+            Some(TlsConfigError::Certificate(_))
+        );
+
+        // the certificate file doesn't contain a private key.
+        let no_key = TlsConfig::new(
+            certificates.path("server.pem"),
+            certificates.path("server.pem"),
+        );
+        assert_matches!(
+            no_key.to_rustls_server_config().err(),
+            Some(TlsConfigError::MissingPrivateKey)
+        );
+
+        let empty_ca = certificates.write("empty.pem", "");
+        let invalid_ca = certificates.server_tls_config().with_client_ca(empty_ca);
+        assert_matches!(
+            invalid_ca.to_rustls_server_config().err(),
+            Some(TlsConfigError::InvalidClientCa)
+        );
+    }
+}
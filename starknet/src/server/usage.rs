@@ -0,0 +1,319 @@
+//! Persist data usage per api key.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use apibara_node::db::{
+    libmdbx::{Environment, EnvironmentKind, Error as MdbxError},
+    MdbxTransactionExt,
+};
+use chrono::NaiveDate;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::db::{tables, Usage, UsageKey};
+
+use super::metadata::RequestMeter;
+
+/// How often the usage is written to the database.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Aggregates usage in memory and periodically writes it to the database.
+pub struct UsageLedger<E: EnvironmentKind> {
+    db: Arc<Environment<E>>,
+    pending: Mutex<HashMap<UsageKey, Usage>>,
+}
+
+/// A [RequestMeter] that records usage in a [UsageLedger].
+pub struct UsageMeter<M: RequestMeter, E: EnvironmentKind> {
+    inner: M,
+    key: String,
+    ledger: Arc<UsageLedger<E>>,
+}
+
+/// Usage of one api key in one day.
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageRecord {
+    pub date: String,
+    pub key: String,
+    pub header: u64,
+    pub transaction: u64,
+    pub event: u64,
+    pub message: u64,
+    pub storage_diff: u64,
+    pub declared_contract: u64,
+    pub deployed_contract: u64,
+    pub nonce_update: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UsageExportError {
+    #[error("database error")]
+    Database(#[from] MdbxError),
+    #[error("failed to write usage")]
+    Io(#[from] std::io::Error),
+    #[error("failed to serialize usage")]
+    Json(#[from] serde_json::Error),
+}
+
+impl<E: EnvironmentKind> UsageLedger<E> {
+    pub fn new(db: Arc<Environment<E>>) -> Self {
+        UsageLedger {
+            db,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Records `amount` of data for the counter `name`, for today.
+    ///
+    /// `key` identifies the api key, it must not be the key itself.
+    pub fn record(&self, key: &str, name: &'static str, amount: u64) {
+        self.record_on_day(current_day(), key, name, amount)
+    }
+
+    fn record_on_day(&self, day: u32, key: &str, name: &'static str, amount: u64) {
+        if amount == 0 {
+            return;
+        }
+        let usage_key = UsageKey {
+            day,
+            key: key.to_string(),
+        };
+        let mut pending = self.pending.lock().expect("usage lock poisoned");
+        pending
+            .entry(usage_key)
+            .or_default()
+            .increment(name, amount);
+    }
+
+    /// Writes the usage recorded so far to the database.
+    pub fn flush(&self) -> Result<(), MdbxError> {
+        let pending = std::mem::take(&mut *self.pending.lock().expect("usage lock poisoned"));
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let txn = self.db.begin_rw_txn()?;
+        let mut cursor = txn.open_cursor::<tables::UsageTable>()?;
+        for (key, usage) in pending {
+            let mut total = cursor
+                .seek_exact(&key)?
+                .map(|(_, usage)| usage)
+                .unwrap_or_default();
+            total.add(&usage);
+            cursor.put(&key, &total)?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Periodically flushes usage to the database, until cancelled.
+    pub async fn start(&self, ct: CancellationToken) {
+        loop {
+            tokio::select! {
+                _ = ct.cancelled() => break,
+                _ = tokio::time::sleep(FLUSH_INTERVAL) => {}
+            }
+
+            if let Err(err) = self.flush() {
+                warn!(err = ?err, "failed to flush usage");
+            }
+        }
+
+        // write usage recorded before shutdown
+        if let Err(err) = self.flush() {
+            warn!(err = ?err, "failed to flush usage");
+        }
+    }
+}
+
+impl<M: RequestMeter, E: EnvironmentKind> UsageMeter<M, E> {
+    pub fn new(inner: M, key: String, ledger: Arc<UsageLedger<E>>) -> Self {
+        UsageMeter { inner, key, ledger }
+    }
+}
+
+impl<M: RequestMeter, E: EnvironmentKind> RequestMeter for UsageMeter<M, E> {
+    fn increment_counter(&self, name: &'static str, amount: u64) {
+        self.inner.increment_counter(name, amount);
+        self.ledger.record(&self.key, name, amount);
+    }
+}
+
+impl UsageRecord {
+    fn new(key: UsageKey, usage: Usage) -> Self {
+        let date = NaiveDate::from_ymd_opt(1970, 1, 1).expect("valid date")
+            + chrono::Duration::days(key.day as i64);
+        UsageRecord {
+            date: date.format("%Y-%m-%d").to_string(),
+            key: key.key,
+            header: usage.header,
+            transaction: usage.transaction,
+            event: usage.event,
+            message: usage.message,
+            storage_diff: usage.storage_diff,
+            declared_contract: usage.declared_contract,
+            deployed_contract: usage.deployed_contract,
+            nonce_update: usage.nonce_update,
+        }
+    }
+}
+
+/// Reads all usage records, sorted by day and key.
+pub fn read_usage<E: EnvironmentKind>(
+    db: &Environment<E>,
+) -> Result<Vec<UsageRecord>, UsageExportError> {
+    let txn = db.begin_rw_txn()?;
+    tables::ensure(&txn)?;
+    txn.commit()?;
+
+    let txn = db.begin_ro_txn()?;
+    let mut cursor = txn.open_cursor::<tables::UsageTable>()?;
+    let mut records = Vec::default();
+    let mut item = cursor.first()?;
+    while let Some((key, usage)) = item {
+        records.push(UsageRecord::new(key, usage));
+        item = cursor.next()?;
+    }
+    txn.commit()?;
+    Ok(records)
+}
+
+/// Writes the usage records as CSV.
+pub fn write_usage_csv<W: Write>(
+    mut writer: W,
+    records: &[UsageRecord],
+) -> Result<(), UsageExportError> {
+    writeln!(
+        writer,
+        "date,key,header,transaction,event,message,storage_diff,declared_contract,deployed_contract,nonce_update"
+    )?;
+    for record in records {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{}",
+            record.date,
+            escape_csv(&record.key),
+            record.header,
+            record.transaction,
+            record.event,
+            record.message,
+            record.storage_diff,
+            record.declared_contract,
+            record.deployed_contract,
+            record.nonce_update,
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the usage records as a JSON array.
+pub fn write_usage_json<W: Write>(
+    writer: W,
+    records: &[UsageRecord],
+) -> Result<(), UsageExportError> {
+    serde_json::to_writer_pretty(writer, records)?;
+    Ok(())
+}
+
+fn escape_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn current_day() -> u32 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (since_epoch.as_secs() / 86_400) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use apibara_node::db::{
+        libmdbx::{Environment, NoWriteMap},
+        MdbxEnvironmentExt,
+    };
+    use tempfile::tempdir;
+
+    use crate::db::{Usage, UsageKey};
+
+    use super::{escape_csv, read_usage, write_usage_csv, UsageLedger, UsageRecord};
+
+    #[test]
+    fn test_usage_aggregated_per_day_and_key() {
+        let path = tempdir().unwrap();
+        let db = Environment::<NoWriteMap>::builder()
+            .with_size_gib(1, 2)
+            .open(path.path())
+            .unwrap();
+        let db = Arc::new(db);
+        let ledger = UsageLedger::new(db.clone());
+
+        ledger.record_on_day(10, "acme", "event", 3);
+        ledger.record_on_day(10, "acme", "event", 4);
+        ledger.record_on_day(10, "acme", "header", 0);
+        ledger.record_on_day(10, "other", "transaction", 1);
+        ledger.record_on_day(11, "acme", "event", 5);
+        // the table is created on first read.
+        assert!(read_usage(&db).unwrap().is_empty());
+        ledger.flush().unwrap();
+
+        // flushing again adds to the stored usage.
+        ledger.record_on_day(10, "acme", "event", 10);
+        ledger.flush().unwrap();
+        ledger.flush().unwrap();
+
+        let records = read_usage(&db).unwrap();
+        let summary: Vec<_> = records
+            .iter()
+            .map(|r| (r.date.as_str(), r.key.as_str(), r.event, r.transaction))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("1970-01-11", "acme", 17, 0),
+                ("1970-01-11", "other", 0, 1),
+                ("1970-01-12", "acme", 5, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escape_csv() {
+        assert_eq!(escape_csv("acme"), "acme");
+        assert_eq!(escape_csv("a,b"), "\"a,b\"");
+        assert_eq!(escape_csv("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape_csv("a\nb"), "\"a\nb\"");
+    }
+
+    #[test]
+    fn test_write_usage_csv() {
+        let key = UsageKey {
+            day: 0,
+            key: "acme, inc".to_string(),
+        };
+        let usage = Usage {
+            event: 2,
+            ..Usage::default()
+        };
+        let records = vec![UsageRecord::new(key, usage)];
+        let mut out = Vec::default();
+        write_usage_csv(&mut out, &records).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("date,key,header,"));
+        assert_eq!(lines[1], "1970-01-01,\"acme, inc\",0,0,2,0,0,0,0,0");
+    }
+}
@@ -0,0 +1,349 @@
+//! WebSocket transport for the stream service.
+//!
+//! Clients send [StreamDataRequest] messages, either as binary protobuf frames or
+//! as JSON text frames, and can reconfigure the stream at any time. Responses are
+//! encoded the same way as the last request received.
+//!
+//! Browsers can't set headers on WebSocket connections, so the api key can also be
+//! sent in the `key` query parameter or as the `apikey.<key>` subprotocol, next to
+//! the `apibara` subprotocol.
+
+use std::{borrow::Cow, convert::Infallible, sync::Arc};
+
+use apibara_core::node::v1alpha2::{StreamDataRequest, StreamDataResponse};
+use apibara_node::db::libmdbx::EnvironmentKind;
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderMap},
+    response::Response,
+};
+use futures::StreamExt;
+use prost::Message as ProstMessage;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Code;
+use tracing::{debug, warn};
+
+use crate::{db::StorageReader, provider::Provider};
+
+use super::{
+    http::{
+        request_metadata, status_to_response, ApiKeyQuery, HttpStreamDataRequest,
+        JsonStreamDataResponse,
+    },
+    metadata::{RequestAuthenticator, RequestObserver},
+    stream::{ResponseDataStream, StreamService},
+};
+
+/// Subprotocol selected by the server when the client requests it.
+pub(super) const WS_PROTOCOL: &str = "apibara";
+
+/// Prefix of the subprotocol used to send the api key.
+pub(super) const WS_API_KEY_PROTOCOL_PREFIX: &str = "apikey.";
+
+/// Close frame reasons must fit in a control frame.
+const MAX_CLOSE_REASON_LEN: usize = 120;
+
+/// How messages are encoded on the socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameEncoding {
+    Protobuf,
+    Json,
+}
+
+pub(super) async fn stream_data_ws<G, R, O, A, E>(
+    State(service): State<Arc<StreamService<G, R, O, A, E>>>,
+    headers: HeaderMap,
+    Query(query): Query<ApiKeyQuery>,
+    ws: WebSocketUpgrade,
+) -> Response
+where
+    G: Provider + Send + Sync + 'static,
+    R: StorageReader + Send + Sync + 'static,
+    O: RequestObserver,
+    A: RequestAuthenticator,
+    E: EnvironmentKind,
+{
+    let key = query.key.or_else(|| protocol_api_key(&headers));
+    let metadata = request_metadata(headers, key.as_deref());
+    let (requests_tx, requests_rx) = mpsc::channel(16);
+    let requests = ReceiverStream::new(requests_rx).map(Ok::<_, Infallible>);
+
+    // authenticate before upgrading the connection.
+    let response = match service.stream_data_with_requests(&metadata, requests).await {
+        Ok(response) => response,
+        Err(status) => return status_to_response(status),
+    };
+
+    // browsers refuse the connection if none of the requested subprotocols is selected.
+    ws.protocols([WS_PROTOCOL])
+        .on_upgrade(move |socket| handle_socket(socket, requests_tx, response))
+}
+
+/// Returns the api key sent in the `Sec-WebSocket-Protocol` header.
+fn protocol_api_key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|protocol| protocol.trim().strip_prefix(WS_API_KEY_PROTOCOL_PREFIX))
+        .map(|key| key.to_string())
+}
+
+async fn handle_socket(
+    mut socket: WebSocket,
+    requests_tx: mpsc::Sender<StreamDataRequest>,
+    mut response: ResponseDataStream,
+) {
+    let mut encoding = FrameEncoding::Protobuf;
+
+    let close = loop {
+        tokio::select! {
+            message = socket.recv() => {
+                let message = match message {
+                    None | Some(Ok(Message::Close(_))) => break None,
+                    Some(Err(err)) => {
+                        debug!(err = ?err, "websocket error");
+                        break None;
+                    }
+                    Some(Ok(message)) => message,
+                };
+
+                let request = match message {
+                    Message::Binary(bytes) => {
+                        encoding = FrameEncoding::Protobuf;
+                        StreamDataRequest::decode(bytes.as_slice())
+                            .map_err(|_| "invalid protobuf request".to_string())
+                    }
+                    Message::Text(text) => {
+                        encoding = FrameEncoding::Json;
+                        serde_json::from_str::<HttpStreamDataRequest>(&text)
+                            .map(HttpStreamDataRequest::into_stream_data_request)
+                            .map_err(|err| format!("invalid json request: {err}"))
+                    }
+                    // axum responds to pings automatically.
+                    Message::Ping(_) | Message::Pong(_) | Message::Close(_) => continue,
+                };
+
+                match request {
+                    Ok(request) => {
+                        if requests_tx.send(request).await.is_err() {
+                            break None;
+                        }
+                    }
+                    Err(message) => break Some(close_frame(Code::InvalidArgument, message)),
+                }
+            }
+            message = response.next() => {
+                let message = match message {
+                    None => break Some(close_frame(Code::Ok, "stream finished".to_string())),
+                    Some(Err(status)) => {
+                        break Some(close_frame(status.code(), status.message().to_string()))
+                    }
+                    Some(Ok(message)) => message,
+                };
+
+                let frame = match encode_response(message, encoding) {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        warn!(err = ?err, "failed to encode response");
+                        break Some(close_frame(Code::Internal, "internal server error".to_string()));
+                    }
+                };
+
+                if socket.send(frame).await.is_err() {
+                    break None;
+                }
+            }
+        }
+    };
+
+    if let Some(close) = close {
+        let _ = socket.send(Message::Close(Some(close))).await;
+    }
+}
+
+fn encode_response(
+    response: StreamDataResponse,
+    encoding: FrameEncoding,
+) -> Result<Option<Message>, Box<dyn std::error::Error + Send + Sync>> {
+    match encoding {
+        FrameEncoding::Protobuf => Ok(Some(Message::Binary(response.encode_to_vec()))),
+        FrameEncoding::Json => match JsonStreamDataResponse::from_response(response)? {
+            None => Ok(None),
+            Some(response) => Ok(Some(Message::Text(serde_json::to_string(&response)?))),
+        },
+    }
+}
+
+fn close_frame(code: Code, mut reason: String) -> CloseFrame<'static> {
+    let code = match code {
+        Code::Ok => close_code::NORMAL,
+        Code::Internal | Code::Unknown | Code::Unavailable => close_code::ERROR,
+        _ => close_code::POLICY,
+    };
+    if reason.len() > MAX_CLOSE_REASON_LEN {
+        let mut end = MAX_CLOSE_REASON_LEN;
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        reason.truncate(end);
+    }
+    CloseFrame {
+        code,
+        reason: Cow::Owned(reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use apibara_core::{
+        node::v1alpha2::{
+            stream_data_response, DataFinality, StreamDataRequest, StreamDataResponse,
+        },
+        starknet::v1alpha2,
+    };
+    use futures::{SinkExt, StreamExt};
+    use prost::Message as ProstMessage;
+    use tokio::task::JoinHandle;
+    use tokio_tungstenite::tungstenite::{
+        client::IntoClientRequest, http::StatusCode, Error as WsError, Message,
+    };
+
+    use crate::server::{
+        http::http_router,
+        test_utils::{TestServer, TEST_API_KEY},
+    };
+
+    use super::WS_PROTOCOL;
+
+    fn start_server(server: &TestServer) -> (SocketAddr, JoinHandle<()>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = http_router(server.service.clone());
+        let handle = tokio::spawn(async move {
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service())
+                .await
+                .unwrap();
+        });
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn test_json_stream_with_query_key() {
+        let server = TestServer::new(3);
+        let (addr, handle) = start_server(&server);
+
+        let url = format!("ws://{}/v1alpha2/ws?key={}", addr, TEST_API_KEY);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let request =
+            r#"{"finality": "DATA_STATUS_ACCEPTED", "filter": {"header": {"weak": false}}}"#;
+        socket
+            .send(Message::Text(request.to_string()))
+            .await
+            .unwrap();
+
+        let message = socket.next().await.unwrap().unwrap();
+        let text = message.into_text().unwrap();
+        let response: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(response["streamId"], 0);
+        assert_eq!(response["data"]["finality"], "DATA_STATUS_FINALIZED");
+        assert!(
+            response["data"]["data"][0]["header"].is_object(),
+            "{}",
+            text
+        );
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_protobuf_stream_with_protocol_key() {
+        let server = TestServer::new(3);
+        let (addr, handle) = start_server(&server);
+
+        let mut request = format!("ws://{}/v1alpha2/ws", addr)
+            .into_client_request()
+            .unwrap();
+        let protocols = format!("{}, apikey.{}", WS_PROTOCOL, TEST_API_KEY);
+        request
+            .headers_mut()
+            .insert("sec-websocket-protocol", protocols.parse().unwrap());
+        let (mut socket, response) = tokio_tungstenite::connect_async(request).await.unwrap();
+        // the key is never echoed back.
+        assert_eq!(
+            response.headers().get("sec-websocket-protocol").unwrap(),
+            WS_PROTOCOL
+        );
+
+        let filter = v1alpha2::Filter {
+            header: Some(v1alpha2::HeaderFilter { weak: false }),
+            ..Default::default()
+        };
+        let request = StreamDataRequest {
+            finality: Some(DataFinality::DataStatusAccepted as i32),
+            filter: filter.encode_to_vec(),
+            ..Default::default()
+        };
+        socket
+            .send(Message::Binary(request.encode_to_vec()))
+            .await
+            .unwrap();
+
+        let message = socket.next().await.unwrap().unwrap();
+        let response = StreamDataResponse::decode(message.into_data().as_slice()).unwrap();
+        assert_eq!(response.stream_id, 0);
+        match response.message {
+            Some(stream_data_response::Message::Data(data)) => {
+                assert_eq!(data.data.len(), 3);
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_keys() {
+        let server = TestServer::new(3);
+        let (addr, handle) = start_server(&server);
+
+        let urls = [
+            format!("ws://{}/v1alpha2/ws", addr),
+            format!("ws://{}/v1alpha2/ws?key=not-a-key", addr),
+        ];
+        for url in urls {
+            match tokio_tungstenite::connect_async(url).await {
+                Err(WsError::Http(response)) => {
+                    assert_eq!(response.status(), StatusCode::UNAUTHORIZED)
+                }
+                _ => panic!("connection should fail"),
+            }
+        }
+
+        let mut request = format!("ws://{}/v1alpha2/ws", addr)
+            .into_client_request()
+            .unwrap();
+        request.headers_mut().insert(
+            "sec-websocket-protocol",
+            "apibara, apikey.not-a-key".parse().unwrap(),
+        );
+        match tokio_tungstenite::connect_async(request).await {
+            Err(WsError::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED)
+            }
+            _ => panic!("connection should fail"),
+        }
+
+        handle.abort();
+    }
+}
@@ -1,10 +1,20 @@
 //! Filter data for one block.
 
-use std::sync::Arc;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
 use apibara_core::starknet::v1alpha2;
 
-use crate::{core::GlobalBlockId, db::StorageReader, server::RequestMeter};
+use crate::{
+    core::GlobalBlockId,
+    db::{BlockBloom, BlockParts, BloomItem, StorageReader, StoredBlock},
+    server::RequestMeter,
+};
+
+use super::{factory::FactoryEventFilters, mask::FieldMask};
 
 pub trait BlockDataFilter {
     type Error: std::error::Error + Send + Sync + 'static;
@@ -13,17 +23,66 @@ pub trait BlockDataFilter {
     ///
     /// If there is no data for the given block, it returns `None`.
     fn data_for_block<M: RequestMeter>(
-        &self,
+        &mut self,
         block_id: &GlobalBlockId,
         meter: &Arc<M>,
     ) -> Result<Option<v1alpha2::Block>, Self::Error>;
+
+    /// Returns a `Block` with data for the given block, already read from storage.
+    ///
+    /// If there is no data for the given block, it returns `None`.
+    fn data_for_stored_block<M: RequestMeter>(
+        &mut self,
+        block: &StoredBlock,
+        meter: &Arc<M>,
+    ) -> Result<Option<v1alpha2::Block>, Self::Error>;
+
+    /// Returns the parts of a block needed to filter its data.
+    fn required_parts(&self) -> BlockParts;
+
+    /// Returns false if the block certainly has no data matching the filter.
+    fn may_have_data(&self, block_id: &GlobalBlockId) -> Result<bool, Self::Error>;
+
+    /// Returns false if the block bloom filter shows the block has no data matching the filter.
+    fn may_match_bloom(&self, block_id: &GlobalBlockId, bloom: Option<&BlockBloom>) -> bool;
+
+    /// Returns the first block number, starting from `start`, that may have data.
+    ///
+    /// The indexes are read from `storage`, usually a snapshot shared with the reads
+    /// of the block data.
+    /// If no block after `start` has data, it returns `None`.
+    fn next_candidate_block<S>(&self, storage: &S, start: u64) -> Result<Option<u64>, Self::Error>
+    where
+        S: StorageReader<Error = Self::Error>;
 }
 
 pub struct DatabaseBlockDataFilter<R: StorageReader> {
     storage: Arc<R>,
     filter: v1alpha2::Filter,
+    factory_filters: FactoryEventFilters,
+}
+
+/// Source of the data of the block being filtered.
+///
+/// Data already in memory is borrowed, data read from storage is owned.
+trait BlockData<E> {
+    fn status(&self) -> Result<v1alpha2::BlockStatus, E>;
+    fn header(&self) -> Result<Option<Cow<'_, v1alpha2::BlockHeader>>, E>;
+    fn transactions(&self) -> Result<Arc<[v1alpha2::Transaction]>, E>;
+    fn receipts(&self) -> Result<Arc<[v1alpha2::TransactionReceipt]>, E>;
+    fn state_update(&self) -> Result<Option<Arc<v1alpha2::StateUpdate>>, E>;
+}
+
+/// Reads the block data from storage, only when needed.
+struct StorageBlockData<'a, R: StorageReader> {
+    storage: &'a R,
+    block_id: &'a GlobalBlockId,
 }
 
+/// Transactions referenced by a normalized block, by transaction index, with the
+/// fields to include.
+type ReferencedTransactions = BTreeMap<u64, FieldMask>;
+
 #[derive(Debug, Default)]
 struct DataCounter {
     pub header: usize,
@@ -54,15 +113,91 @@ where
     R: StorageReader,
 {
     pub fn new(storage: Arc<R>, filter: v1alpha2::Filter) -> Self {
-        DatabaseBlockDataFilter { storage, filter }
+        DatabaseBlockDataFilter {
+            storage,
+            filter,
+            factory_filters: FactoryEventFilters::default(),
+        }
     }
 
-    fn status(&self, block_id: &GlobalBlockId) -> Result<v1alpha2::BlockStatus, R::Error> {
-        let status = self
-            .storage
-            .read_status(block_id)?
-            .unwrap_or(v1alpha2::BlockStatus::Unspecified);
-        Ok(status)
+    /// Returns the factories of the filter.
+    pub fn factories(&self) -> &[v1alpha2::FactoryFilter] {
+        &self.filter.factories
+    }
+
+    /// Uses the event filters added by factories in a previous configuration of the stream.
+    pub fn with_factory_filters(mut self, factory_filters: FactoryEventFilters) -> Self {
+        self.factory_filters = factory_filters;
+        self
+    }
+
+    /// Takes the event filters added by factories, leaving none.
+    pub fn take_factory_filters(&mut self) -> FactoryEventFilters {
+        std::mem::take(&mut self.factory_filters)
+    }
+
+    /// Removes the event filters added by factories after the given block.
+    pub fn rollback_factory_filters(&mut self, block_number: u64) {
+        self.factory_filters.rollback(block_number);
+    }
+
+    /// Adds event filters for the contracts deployed by factories in the given block.
+    fn update_factory_filters<B: BlockData<R::Error>>(
+        &mut self,
+        block_id: &GlobalBlockId,
+        block: &B,
+    ) -> Result<(), R::Error> {
+        if self.filter.factories.is_empty() {
+            return Ok(());
+        }
+
+        let mut filters = Vec::default();
+
+        let has_event_factory = self.filter.factories.iter().any(|f| f.event.is_some());
+        if has_event_factory {
+            let receipts = block.receipts()?;
+            for receipt in receipts.iter() {
+                for event in &receipt.events {
+                    for factory in &self.filter.factories {
+                        let is_valid_receipt = factory
+                            .event
+                            .as_ref()
+                            .map(|f| f.matches_receipt(receipt))
+                            .unwrap_or(false);
+                        if !is_valid_receipt {
+                            continue;
+                        }
+                        if let Some(address) = factory.deployed_address(event) {
+                            filters.push(factory.event_filter_for(address));
+                        }
+                    }
+                }
+            }
+        }
+
+        let has_class_hash_factory = self.filter.factories.iter().any(|f| f.class_hash.is_some());
+        if has_class_hash_factory {
+            let state_update = block.state_update()?;
+            let deployed_contracts = state_update
+                .as_ref()
+                .and_then(|update| update.state_diff.as_ref())
+                .map(|diff| diff.deployed_contracts.as_slice())
+                .unwrap_or_default();
+            for deployed_contract in deployed_contracts {
+                for factory in &self.filter.factories {
+                    if factory.matches_deployed_contract(deployed_contract) {
+                        if let Some(address) = deployed_contract.contract_address.clone() {
+                            filters.push(factory.event_filter_for(address));
+                        }
+                    }
+                }
+            }
+        }
+
+        self.factory_filters
+            .set_block_filters(block_id.number(), filters);
+
+        Ok(())
     }
 
     fn has_weak_header(&self) -> bool {
@@ -70,46 +205,57 @@ where
         self.filter.header.as_ref().map(|h| h.weak).unwrap_or(true)
     }
 
-    fn header(
+    fn header<B: BlockData<R::Error>>(
         &self,
-        block_id: &GlobalBlockId,
+        block: &B,
         meter: &mut DataCounter,
     ) -> Result<Option<v1alpha2::BlockHeader>, R::Error> {
         if self.filter.header.is_some() {
             meter.header = 1;
-            self.storage.read_header(block_id)
+            Ok(block.header()?.map(Cow::into_owned))
         } else {
             Ok(None)
         }
     }
 
-    fn transactions(
+    fn transactions<B: BlockData<R::Error>>(
         &self,
         block_id: &GlobalBlockId,
+        block: &B,
         meter: &mut DataCounter,
+        referenced: &mut ReferencedTransactions,
     ) -> Result<Vec<v1alpha2::TransactionWithReceipt>, R::Error> {
-        if self.filter.transactions.is_empty() {
+        // only evaluate the filters valid at this block.
+        let filters: Vec<_> = self
+            .filter
+            .transactions
+            .iter()
+            .filter(|f| f.is_active_at(block_id.number()))
+            .collect();
+        if filters.is_empty() {
             return Ok(Vec::default());
         }
 
-        let transactions = self.storage.read_body(block_id)?;
-        let mut receipts = self.storage.read_receipts(block_id)?;
+        let transactions = block.transactions()?;
+        let receipts = block.receipts()?;
+        let receipts = sorted_receipts(&receipts);
 
         assert!(transactions.len() == receipts.len());
-        receipts.sort_by(|a, b| a.transaction_index.cmp(&b.transaction_index));
 
         let transactions_with_receipts: Vec<_> = transactions
-            .into_iter()
+            .iter()
             .zip(receipts.into_iter())
             .flat_map(|(tx, rx)| {
-                if self.filter_transaction(&tx) {
-                    Some(v1alpha2::TransactionWithReceipt {
-                        transaction: Some(tx),
-                        receipt: Some(rx),
-                    })
-                } else {
-                    None
+                let mask = transaction_mask(&filters, tx, rx)?;
+                if self.filter.normalized {
+                    add_reference(referenced, rx.transaction_index, mask);
+                    return None;
                 }
+                Some(v1alpha2::TransactionWithReceipt {
+                    transaction: mask.transaction(tx),
+                    receipt: mask.receipt(rx),
+                    transaction_index: rx.transaction_index,
+                })
             })
             .collect();
 
@@ -118,34 +264,51 @@ where
         Ok(transactions_with_receipts)
     }
 
-    fn events(
+    fn events<B: BlockData<R::Error>>(
         &self,
         block_id: &GlobalBlockId,
+        block: &B,
         meter: &mut DataCounter,
+        referenced: &mut ReferencedTransactions,
     ) -> Result<Vec<v1alpha2::EventWithTransaction>, R::Error> {
-        if self.filter.events.is_empty() {
+        let filters: Vec<_> = self.event_filters(block_id).collect();
+        if filters.is_empty() {
             return Ok(Vec::default());
         }
 
-        let transactions = self.storage.read_body(block_id)?;
-        let mut receipts = self.storage.read_receipts(block_id)?;
+        let transactions = block.transactions()?;
+        let receipts = block.receipts()?;
+        let receipts = sorted_receipts(&receipts);
 
         assert!(transactions.len() == receipts.len());
-        receipts.sort_by(|a, b| a.transaction_index.cmp(&b.transaction_index));
+
+        let has_class_hash_filter = filters.iter().any(|f| f.from_class_hash.is_some());
+        // class hash of the contracts emitting events, by contract address.
+        let mut class_hashes = HashMap::new();
 
         let mut events = Vec::default();
-        for receipt in &receipts {
+        for receipt in receipts {
             let transaction = &transactions[receipt.transaction_index as usize];
             for event in &receipt.events {
-                if self.filter_event(event) {
-                    let transaction = transaction.clone();
-                    let receipt = receipt.clone();
-                    let event = event.clone();
-
+                let class_hash = match (has_class_hash_filter, event.from_address.as_ref()) {
+                    (true, Some(address)) => {
+                        let key = address.to_bytes();
+                        if !class_hashes.contains_key(&key) {
+                            let class_hash = self.storage.read_contract_class_hash(address)?;
+                            class_hashes.insert(key, class_hash);
+                        }
+                        class_hashes.get(&key).and_then(|c| c.as_ref())
+                    }
+                    _ => None,
+                };
+                if let Some(mask) = event_mask(&filters, event, receipt, class_hash) {
+                    let (event_transaction, event_receipt) =
+                        self.transaction_and_receipt(transaction, receipt, mask, referenced);
                     events.push(v1alpha2::EventWithTransaction {
-                        transaction: Some(transaction),
-                        receipt: Some(receipt),
-                        event: Some(event),
+                        transaction: event_transaction,
+                        receipt: event_receipt,
+                        event: Some(event.clone()),
+                        transaction_index: receipt.transaction_index,
                     });
                 }
             }
@@ -156,34 +319,34 @@ where
         Ok(events)
     }
 
-    fn l2_to_l1_messages(
+    fn l2_to_l1_messages<B: BlockData<R::Error>>(
         &self,
-        block_id: &GlobalBlockId,
+        block: &B,
         meter: &mut DataCounter,
+        referenced: &mut ReferencedTransactions,
     ) -> Result<Vec<v1alpha2::L2ToL1MessageWithTransaction>, R::Error> {
         if self.filter.messages.is_empty() {
             return Ok(Vec::default());
         }
 
-        let transactions = self.storage.read_body(block_id)?;
-        let mut receipts = self.storage.read_receipts(block_id)?;
+        let transactions = block.transactions()?;
+        let receipts = block.receipts()?;
+        let receipts = sorted_receipts(&receipts);
 
         assert!(transactions.len() == receipts.len());
-        receipts.sort_by(|a, b| a.transaction_index.cmp(&b.transaction_index));
 
         let mut messages = Vec::default();
-        for receipt in &receipts {
+        for receipt in receipts {
             let transaction = &transactions[receipt.transaction_index as usize];
             for message in &receipt.l2_to_l1_messages {
-                if self.filter_l2_to_l1_message(message) {
-                    let transaction = transaction.clone();
-                    let receipt = receipt.clone();
-                    let message = message.clone();
-
+                if let Some(mask) = self.l2_to_l1_message_mask(message) {
+                    let (message_transaction, message_receipt) =
+                        self.transaction_and_receipt(transaction, receipt, mask, referenced);
                     messages.push(v1alpha2::L2ToL1MessageWithTransaction {
-                        transaction: Some(transaction),
-                        receipt: Some(receipt),
-                        message: Some(message),
+                        transaction: message_transaction,
+                        receipt: message_receipt,
+                        message: Some(message.clone()),
+                        transaction_index: receipt.transaction_index,
                     });
                 }
             }
@@ -194,9 +357,65 @@ where
         Ok(messages)
     }
 
-    fn state_update(
+    /// Returns the transaction and receipt to send together with an event or message.
+    ///
+    /// Normalized blocks send them once in the block transactions instead.
+    fn transaction_and_receipt(
         &self,
-        block_id: &GlobalBlockId,
+        transaction: &v1alpha2::Transaction,
+        receipt: &v1alpha2::TransactionReceipt,
+        mask: FieldMask,
+        referenced: &mut ReferencedTransactions,
+    ) -> (
+        Option<v1alpha2::Transaction>,
+        Option<v1alpha2::TransactionReceipt>,
+    ) {
+        if self.filter.normalized {
+            add_reference(referenced, receipt.transaction_index, mask);
+            (None, None)
+        } else {
+            (mask.transaction(transaction), mask.receipt(receipt))
+        }
+    }
+
+    /// Returns the transactions referenced by a normalized block.
+    fn referenced_transactions<B: BlockData<R::Error>>(
+        &self,
+        block: &B,
+        meter: &mut DataCounter,
+        referenced: ReferencedTransactions,
+    ) -> Result<Vec<v1alpha2::TransactionWithReceipt>, R::Error> {
+        if referenced.is_empty() {
+            return Ok(Vec::default());
+        }
+
+        let transactions = block.transactions()?;
+        let receipts = block.receipts()?;
+        let receipts = sorted_receipts(&receipts);
+
+        assert!(transactions.len() == receipts.len());
+
+        let transactions_with_receipts: Vec<_> = referenced
+            .into_iter()
+            .flat_map(|(index, mask)| {
+                let transaction = transactions.get(index as usize)?;
+                let receipt = receipts.get(index as usize)?;
+                Some(v1alpha2::TransactionWithReceipt {
+                    transaction: mask.transaction(transaction),
+                    receipt: mask.receipt(receipt),
+                    transaction_index: index,
+                })
+            })
+            .collect();
+
+        meter.transaction = transactions_with_receipts.len();
+
+        Ok(transactions_with_receipts)
+    }
+
+    fn state_update<B: BlockData<R::Error>>(
+        &self,
+        block: &B,
         meter: &mut DataCounter,
     ) -> Result<Option<v1alpha2::StateUpdate>, R::Error> {
         let filter = if let Some(filter) = self.filter.state_update.as_ref() {
@@ -205,14 +424,13 @@ where
             return Ok(None);
         };
 
-        let original_state_update =
-            if let Some(update) = self.storage.read_state_update(block_id)? {
-                update
-            } else {
-                return Ok(None);
-            };
+        let original_state_update = if let Some(update) = block.state_update()? {
+            update
+        } else {
+            return Ok(None);
+        };
 
-        let state_diff = if let Some(diff) = original_state_update.state_diff {
+        let state_diff = if let Some(diff) = original_state_update.state_diff.as_ref() {
             diff
         } else {
             return Ok(None);
@@ -222,32 +440,38 @@ where
 
         let storage_diffs: Vec<_> = state_diff
             .storage_diffs
-            .into_iter()
-            .filter(|diff| self.filter_storage_diff(diff, filter))
+            .iter()
+            .flat_map(|diff| self.filter_storage_diff(diff, filter))
             .collect();
         has_value |= !storage_diffs.is_empty();
-        meter.storage_diff = storage_diffs.len();
+        meter.storage_diff = storage_diffs
+            .iter()
+            .map(|diff| diff.storage_entries.len())
+            .sum();
 
         let declared_contracts: Vec<_> = state_diff
             .declared_contracts
-            .into_iter()
+            .iter()
             .filter(|d| self.filter_declared_contracts(d, filter))
+            .cloned()
             .collect();
         has_value |= !declared_contracts.is_empty();
         meter.declared_contract = declared_contracts.len();
 
         let deployed_contracts: Vec<_> = state_diff
             .deployed_contracts
-            .into_iter()
+            .iter()
             .filter(|d| self.filter_deployed_contracts(d, filter))
+            .cloned()
             .collect();
         has_value |= !deployed_contracts.is_empty();
         meter.deployed_contract = deployed_contracts.len();
 
         let nonces: Vec<_> = state_diff
             .nonces
-            .into_iter()
+            .iter()
             .filter(|n| self.filter_nonces(n, filter))
+            .cloned()
             .collect();
         has_value |= !nonces.is_empty();
         meter.nonce_update = nonces.len();
@@ -260,8 +484,8 @@ where
                 nonces,
             };
             let state_update = v1alpha2::StateUpdate {
-                new_root: original_state_update.new_root,
-                old_root: original_state_update.old_root,
+                new_root: original_state_update.new_root.clone(),
+                old_root: original_state_update.old_root.clone(),
                 state_diff: Some(diff),
             };
             Ok(Some(state_update))
@@ -270,24 +494,182 @@ where
         }
     }
 
-    fn filter_transaction(&self, tx: &v1alpha2::Transaction) -> bool {
-        self.filter.transactions.iter().any(|f| f.matches(tx))
+    /// Returns true if the block bloom filter can be used to skip blocks.
+    fn uses_bloom(&self) -> bool {
+        // the bloom filter doesn't contain messages, and factories must see every block to
+        // find the contracts they deploy.
+        self.has_weak_header()
+            && self.filter.messages.is_empty()
+            && self.filter.factories.is_empty()
     }
 
-    fn filter_event(&self, event: &v1alpha2::Event) -> bool {
-        self.filter.events.iter().any(|f| f.matches(event))
+    /// Returns a `Block` with the data of `block` matching the filter.
+    fn filter_block<B: BlockData<R::Error>, M: RequestMeter>(
+        &mut self,
+        block_id: &GlobalBlockId,
+        block: &B,
+        meter: &Arc<M>,
+    ) -> Result<Option<v1alpha2::Block>, R::Error> {
+        // contracts deployed in this block are indexed starting from this block.
+        self.update_factory_filters(block_id, block)?;
+
+        let mut has_data = false;
+
+        let mut data_counter = DataCounter::default();
+        let status = block.status()?;
+
+        let header = self.header(block, &mut data_counter)?;
+        if !self.has_weak_header() {
+            has_data |= header.is_some();
+        }
+
+        let mut referenced = ReferencedTransactions::default();
+
+        let mut transactions =
+            self.transactions(block_id, block, &mut data_counter, &mut referenced)?;
+
+        let events = self.events(block_id, block, &mut data_counter, &mut referenced)?;
+        has_data |= !events.is_empty();
+
+        let l2_to_l1_messages =
+            self.l2_to_l1_messages(block, &mut data_counter, &mut referenced)?;
+        has_data |= !l2_to_l1_messages.is_empty();
+
+        if self.filter.normalized {
+            transactions = self.referenced_transactions(block, &mut data_counter, referenced)?;
+        }
+        has_data |= !transactions.is_empty();
+
+        let state_update = self.state_update(block, &mut data_counter)?;
+        has_data |= state_update.is_some();
+
+        let mut data = v1alpha2::Block {
+            status: status as i32,
+            header,
+            state_update,
+            transactions,
+            events,
+            l2_to_l1_messages,
+        };
+
+        if has_data {
+            set_receipts_finality(&mut data, status.transaction_finality());
+            // emit here so that weak headers are not counted
+            data_counter.update_meter(meter);
+
+            Ok(Some(data))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the event filters valid at the given block.
+    ///
+    /// These are the stream event filters and the filters added by factories up to the block.
+    fn event_filters(
+        &self,
+        block_id: &GlobalBlockId,
+    ) -> impl Iterator<Item = &v1alpha2::EventFilter> {
+        let block_number = block_id.number();
+        self.filter
+            .events
+            .iter()
+            .chain(self.factory_filters.event_filters_at(block_number))
+            .filter(move |f| f.is_active_at(block_number))
     }
 
-    fn filter_l2_to_l1_message(&self, message: &v1alpha2::L2ToL1Message) -> bool {
-        self.filter.messages.iter().any(|f| f.matches(message))
+    /// Returns the first block, starting from `start`, that may have events matching the filter.
+    fn next_event_candidate<S>(
+        &self,
+        storage: &S,
+        filter: &v1alpha2::EventFilter,
+        start: u64,
+    ) -> Result<Option<u64>, R::Error>
+    where
+        S: StorageReader<Error = R::Error>,
+    {
+        let start = start.max(filter.from_block.unwrap_or_default());
+        if filter.to_block.map(|to| to < start).unwrap_or(false) {
+            return Ok(None);
+        }
+
+        // the indexes don't store the contract class hash.
+        let from_address = match filter.from_address.as_ref() {
+            Some(address) if filter.from_class_hash.is_none() => address,
+            _ => return Ok(Some(start)),
+        };
+
+        let first_keys: Vec<_> = match filter.keys.first() {
+            Some(key) => vec![key],
+            None => filter
+                .keys_match
+                .as_ref()
+                .and_then(|keys| keys.positions.first())
+                .map(|position| position.values.iter().collect())
+                .unwrap_or_default(),
+        };
+
+        // the index is keyed by the first event key, finding the events of any key means
+        // visiting all keys of the contract. the bloom filter skips these blocks instead.
+        if first_keys.is_empty() {
+            return Ok(Some(start));
+        }
+
+        let mut candidate = None;
+        for key in first_keys {
+            let block = storage.next_event_block(from_address, key, start)?;
+            candidate = lowest_block(candidate, block);
+        }
+
+        if let (Some(block), Some(to)) = (candidate, filter.to_block) {
+            if block > to {
+                candidate = None;
+            }
+        }
+
+        Ok(candidate)
+    }
+
+    /// Returns the fields to include with the message, or `None` if no filter matches it.
+    fn l2_to_l1_message_mask(&self, message: &v1alpha2::L2ToL1Message) -> Option<FieldMask> {
+        self.filter
+            .messages
+            .iter()
+            .filter(|f| f.matches(message))
+            .map(FieldMask::from_l2_to_l1_message_filter)
+            .reduce(FieldMask::merge)
     }
 
+    /// Returns the storage diff with only the entries accepted by the filters, or `None` if
+    /// no entry is accepted.
     fn filter_storage_diff(
         &self,
         diff: &v1alpha2::StorageDiff,
         filter: &v1alpha2::StateUpdateFilter,
-    ) -> bool {
-        filter.storage_diffs.iter().any(|f| f.matches(diff))
+    ) -> Option<v1alpha2::StorageDiff> {
+        let filters: Vec<_> = filter
+            .storage_diffs
+            .iter()
+            .filter(|f| f.matches(diff))
+            .collect();
+        if filters.is_empty() {
+            return None;
+        }
+
+        let storage_entries: Vec<_> = diff
+            .storage_entries
+            .iter()
+            .filter(|entry| filters.iter().any(|f| f.matches_entry(entry)))
+            .cloned()
+            .collect();
+        if storage_entries.is_empty() {
+            return None;
+        }
+
+        Some(v1alpha2::StorageDiff {
+            contract_address: diff.contract_address.clone(),
+            storage_entries,
+        })
     }
 
     fn filter_declared_contracts(
@@ -321,6 +703,194 @@ where
     }
 }
 
+impl<'a, R: StorageReader> BlockData<R::Error> for StorageBlockData<'a, R> {
+    fn status(&self) -> Result<v1alpha2::BlockStatus, R::Error> {
+        let status = self
+            .storage
+            .read_status(self.block_id)?
+            .unwrap_or(v1alpha2::BlockStatus::Unspecified);
+        Ok(status)
+    }
+
+    fn header(&self) -> Result<Option<Cow<'_, v1alpha2::BlockHeader>>, R::Error> {
+        let header = self.storage.read_header(self.block_id)?;
+        Ok(header.map(Cow::Owned))
+    }
+
+    fn transactions(&self) -> Result<Arc<[v1alpha2::Transaction]>, R::Error> {
+        self.storage.read_body(self.block_id)
+    }
+
+    fn receipts(&self) -> Result<Arc<[v1alpha2::TransactionReceipt]>, R::Error> {
+        self.storage.read_receipts(self.block_id)
+    }
+
+    fn state_update(&self) -> Result<Option<Arc<v1alpha2::StateUpdate>>, R::Error> {
+        self.storage.read_state_update(self.block_id)
+    }
+}
+
+impl<E> BlockData<E> for StoredBlock {
+    fn status(&self) -> Result<v1alpha2::BlockStatus, E> {
+        Ok(self.status)
+    }
+
+    fn header(&self) -> Result<Option<Cow<'_, v1alpha2::BlockHeader>>, E> {
+        Ok(self.header.as_ref().map(Cow::Borrowed))
+    }
+
+    fn transactions(&self) -> Result<Arc<[v1alpha2::Transaction]>, E> {
+        Ok(self.transactions.clone())
+    }
+
+    fn receipts(&self) -> Result<Arc<[v1alpha2::TransactionReceipt]>, E> {
+        Ok(self.receipts.clone())
+    }
+
+    fn state_update(&self) -> Result<Option<Arc<v1alpha2::StateUpdate>>, E> {
+        Ok(self.state_update.clone())
+    }
+}
+
+/// Returns the receipts sorted by transaction index.
+fn sorted_receipts(
+    receipts: &[v1alpha2::TransactionReceipt],
+) -> Vec<&v1alpha2::TransactionReceipt> {
+    let mut receipts: Vec<_> = receipts.iter().collect();
+    receipts.sort_by_key(|receipt| receipt.transaction_index);
+    receipts
+}
+
+/// Returns the fields to include with the transaction, or `None` if no filter matches it.
+fn transaction_mask(
+    filters: &[&v1alpha2::TransactionFilter],
+    tx: &v1alpha2::Transaction,
+    receipt: &v1alpha2::TransactionReceipt,
+) -> Option<FieldMask> {
+    filters
+        .iter()
+        .filter(|f| f.matches(tx) && f.matches_receipt(receipt))
+        .map(|f| FieldMask::from_transaction_filter(f))
+        .reduce(FieldMask::merge)
+}
+
+/// Returns the fields to include with the event, or `None` if no filter matches it.
+fn event_mask(
+    filters: &[&v1alpha2::EventFilter],
+    event: &v1alpha2::Event,
+    receipt: &v1alpha2::TransactionReceipt,
+    class_hash: Option<&v1alpha2::FieldElement>,
+) -> Option<FieldMask> {
+    filters
+        .iter()
+        .filter(|f| {
+            f.matches(event) && f.matches_receipt(receipt) && f.matches_class_hash(class_hash)
+        })
+        .map(|f| FieldMask::from_event_filter(f))
+        .reduce(FieldMask::merge)
+}
+
+/// Returns false if the block bloom filter shows no transaction matches the filter.
+fn transaction_may_match(bloom: &BlockBloom, filter: &v1alpha2::TransactionFilter) -> bool {
+    use v1alpha2::transaction_filter::Filter;
+
+    let sender = match filter.filter.as_ref() {
+        Some(Filter::InvokeV0(filter)) => filter.contract_address.as_ref(),
+        Some(Filter::InvokeV1(filter)) => filter.sender_address.as_ref(),
+        Some(Filter::Declare(filter)) => filter.sender_address.as_ref(),
+        _ => None,
+    };
+    sender
+        .map(|sender| bloom.may_contain(BloomItem::TransactionSender, sender))
+        .unwrap_or(true)
+}
+
+/// Returns false if the block bloom filter shows no event matches the filter.
+fn event_may_match(bloom: &BlockBloom, filter: &v1alpha2::EventFilter) -> bool {
+    let may_have_emitter = filter
+        .from_address
+        .as_ref()
+        .map(|address| bloom.may_contain(BloomItem::EventEmitter, address))
+        .unwrap_or(true);
+    let may_have_keys = filter
+        .keys
+        .iter()
+        .all(|key| bloom.may_contain(BloomItem::EventKey, key));
+    let may_have_keys_match = filter
+        .keys_match
+        .as_ref()
+        .map(|keys| {
+            keys.positions.iter().all(|position| {
+                position.values.is_empty()
+                    || position
+                        .values
+                        .iter()
+                        .any(|key| bloom.may_contain(BloomItem::EventKey, key))
+            })
+        })
+        .unwrap_or(true);
+    may_have_emitter && may_have_keys && may_have_keys_match
+}
+
+/// Returns false if the block bloom filter shows no state change matches the filter.
+fn state_update_may_match(bloom: &BlockBloom, filter: &v1alpha2::StateUpdateFilter) -> bool {
+    // the bloom filter only contains the contracts with storage changes.
+    let has_filter_without_bloom = !filter.declared_contracts.is_empty()
+        || !filter.deployed_contracts.is_empty()
+        || !filter.nonces.is_empty();
+    has_filter_without_bloom
+        || filter.storage_diffs.iter().any(|f| {
+            f.contract_address
+                .as_ref()
+                .map(|address| bloom.may_contain(BloomItem::StorageDiffContract, address))
+                .unwrap_or(true)
+        })
+}
+
+/// Returns the lowest of two optional block numbers.
+fn lowest_block(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Sets the finality status of the receipts in the block.
+///
+/// Receipts are stored before their block is finalized, so their finality is
+/// derived from the block status when they're sent.
+fn set_receipts_finality(
+    block: &mut v1alpha2::Block,
+    finality: v1alpha2::TransactionFinalityStatus,
+) {
+    let receipts = block
+        .transactions
+        .iter_mut()
+        .filter_map(|tx| tx.receipt.as_mut())
+        .chain(block.events.iter_mut().filter_map(|ev| ev.receipt.as_mut()))
+        .chain(
+            block
+                .l2_to_l1_messages
+                .iter_mut()
+                .filter_map(|msg| msg.receipt.as_mut()),
+        );
+    for receipt in receipts {
+        receipt.set_finality_status(finality);
+    }
+}
+
+/// Records that the transaction at `index` is referenced, merging the fields to include.
+fn add_reference(referenced: &mut ReferencedTransactions, index: u64, mask: FieldMask) {
+    if !mask.transaction && !mask.receipt {
+        return;
+    }
+    referenced
+        .entry(index)
+        .and_modify(|current| *current = current.merge(mask))
+        .or_insert(mask);
+}
+
 impl<R> BlockDataFilter for DatabaseBlockDataFilter<R>
 where
     R: StorageReader,
@@ -329,48 +899,955 @@ where
 
     #[tracing::instrument(level = "trace", skip(self, meter))]
     fn data_for_block<M: RequestMeter>(
-        &self,
+        &mut self,
         block_id: &GlobalBlockId,
         meter: &Arc<M>,
     ) -> Result<Option<v1alpha2::Block>, Self::Error> {
-        let mut has_data = false;
+        let storage = self.storage.clone();
+        let block = StorageBlockData {
+            storage: storage.as_ref(),
+            block_id,
+        };
+        self.filter_block(block_id, &block, meter)
+    }
 
-        let mut data_counter = DataCounter::default();
-        let status = self.status(block_id)?;
+    #[tracing::instrument(level = "trace", skip(self, block, meter))]
+    fn data_for_stored_block<M: RequestMeter>(
+        &mut self,
+        block: &StoredBlock,
+        meter: &Arc<M>,
+    ) -> Result<Option<v1alpha2::Block>, Self::Error> {
+        self.filter_block(&block.id, block, meter)
+    }
 
-        let header = self.header(block_id, &mut data_counter)?;
-        if !self.has_weak_header() {
-            has_data |= header.is_some();
+    fn required_parts(&self) -> BlockParts {
+        // contracts deployed by factories add event filters.
+        let has_factories = !self.filter.factories.is_empty();
+        let has_class_hash_factory = self.filter.factories.iter().any(|f| f.class_hash.is_some());
+        BlockParts {
+            header: self.filter.header.is_some(),
+            transactions: !self.filter.transactions.is_empty()
+                || !self.filter.events.is_empty()
+                || !self.filter.messages.is_empty()
+                || has_factories,
+            state_update: self.filter.state_update.is_some() || has_class_hash_factory,
         }
+    }
 
-        let transactions = self.transactions(block_id, &mut data_counter)?;
-        has_data |= !transactions.is_empty();
+    fn may_have_data(&self, block_id: &GlobalBlockId) -> Result<bool, Self::Error> {
+        if !self.uses_bloom() {
+            return Ok(true);
+        }
 
-        let events = self.events(block_id, &mut data_counter)?;
-        has_data |= !events.is_empty();
+        let bloom = self.storage.read_block_bloom(block_id)?;
+        Ok(self.may_match_bloom(block_id, bloom.as_ref()))
+    }
 
-        let l2_to_l1_messages = self.l2_to_l1_messages(block_id, &mut data_counter)?;
-        has_data |= !l2_to_l1_messages.is_empty();
+    fn may_match_bloom(&self, block_id: &GlobalBlockId, bloom: Option<&BlockBloom>) -> bool {
+        let bloom = match bloom {
+            Some(bloom) if self.uses_bloom() => bloom,
+            _ => return true,
+        };
 
-        let state_update = self.state_update(block_id, &mut data_counter)?;
-        has_data |= state_update.is_some();
+        let may_have_transactions = self
+            .filter
+            .transactions
+            .iter()
+            .filter(|f| f.is_active_at(block_id.number()))
+            .any(|f| transaction_may_match(bloom, f));
+        let may_have_events = self
+            .event_filters(block_id)
+            .any(|f| event_may_match(bloom, f));
+        let may_have_state_update = self
+            .filter
+            .state_update
+            .as_ref()
+            .map(|f| state_update_may_match(bloom, f))
+            .unwrap_or(false);
 
-        let data = v1alpha2::Block {
-            status: status as i32,
-            header,
-            state_update,
-            transactions,
-            events,
-            l2_to_l1_messages,
-        };
+        may_have_transactions || may_have_events || may_have_state_update
+    }
 
-        if has_data {
-            // emit here so that weak headers are not counted
-            data_counter.update_meter(meter);
+    fn next_candidate_block<S>(&self, storage: &S, start: u64) -> Result<Option<u64>, Self::Error>
+    where
+        S: StorageReader<Error = Self::Error>,
+    {
+        // the indexes only cover events and messages.
+        let has_data_without_index = !self.has_weak_header()
+            || !self.filter.transactions.is_empty()
+            || self.filter.state_update.is_some()
+            || self.filter.factories.iter().any(|f| f.class_hash.is_some())
+            || self.filter.messages.iter().any(|f| f.to_address.is_none());
+        if has_data_without_index {
+            return Ok(Some(start));
+        }
 
-            Ok(Some(data))
-        } else {
+        // blocks ingested before the indexes existed must be scanned.
+        match storage.indexed_from_block()? {
+            Some(indexed_from) if indexed_from <= start => {}
+            _ => return Ok(Some(start)),
+        }
+
+        // factory events must be visited to find the contracts they deploy.
+        let factory_events = self.filter.factories.iter().flat_map(|f| f.event.as_ref());
+        let event_filters = self
+            .filter
+            .events
+            .iter()
+            .chain(self.factory_filters.event_filters_at(u64::MAX))
+            .chain(factory_events);
+
+        let mut candidate = None;
+        for filter in event_filters {
+            let block = self.next_event_candidate(storage, filter, start)?;
+            candidate = lowest_block(candidate, block);
+            if candidate == Some(start) {
+                return Ok(candidate);
+            }
+        }
+
+        for filter in &self.filter.messages {
+            if let Some(to_address) = filter.to_address.as_ref() {
+                let block = storage.next_message_block(to_address, start)?;
+                candidate = lowest_block(candidate, block);
+                if candidate == Some(start) {
+                    return Ok(candidate);
+                }
+            }
+        }
+
+        Ok(candidate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, sync::Arc};
+
+    use apibara_core::starknet::v1alpha2;
+    use apibara_node::db::libmdbx::NoWriteMap;
+    use tempfile::TempDir;
+
+    use crate::{
+        core::GlobalBlockId,
+        db::{
+            test_utils::{block_id, felt, open_test_storage, write_test_block},
+            BlockBloom, BlockBody, BlockParts, BloomItem, DatabaseStorage, StorageReader,
+            StorageWriter,
+        },
+        server::RequestMeter,
+    };
+
+    use super::{
+        event_may_match, set_receipts_finality, state_update_may_match, transaction_may_match,
+        BlockData, BlockDataFilter, DatabaseBlockDataFilter,
+    };
+
+    struct TestMeter;
+
+    /// Block data used to test factories.
+    #[derive(Default)]
+    struct FactoryBlock {
+        receipts: Vec<v1alpha2::TransactionReceipt>,
+        deployed_contracts: Vec<v1alpha2::DeployedContract>,
+    }
+
+    impl<E> BlockData<E> for FactoryBlock {
+        fn status(&self) -> Result<v1alpha2::BlockStatus, E> {
+            Ok(v1alpha2::BlockStatus::AcceptedOnL1)
+        }
+
+        fn header(&self) -> Result<Option<Cow<'_, v1alpha2::BlockHeader>>, E> {
             Ok(None)
         }
+
+        fn transactions(&self) -> Result<Arc<[v1alpha2::Transaction]>, E> {
+            Ok(Vec::default().into())
+        }
+
+        fn receipts(&self) -> Result<Arc<[v1alpha2::TransactionReceipt]>, E> {
+            Ok(self.receipts.as_slice().into())
+        }
+
+        fn state_update(&self) -> Result<Option<Arc<v1alpha2::StateUpdate>>, E> {
+            let state_update = v1alpha2::StateUpdate {
+                state_diff: Some(v1alpha2::StateDiff {
+                    deployed_contracts: self.deployed_contracts.clone(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            Ok(Some(Arc::new(state_update)))
+        }
+    }
+
+    impl RequestMeter for TestMeter {
+        fn increment_counter(&self, _name: &'static str, _amount: u64) {}
+    }
+
+    fn open_storage() -> (TempDir, Arc<DatabaseStorage<NoWriteMap>>) {
+        let (path, storage) = open_test_storage();
+        (path, Arc::new(storage))
+    }
+
+    /// Writes a canonical block with two transactions, their receipts stored in reverse order.
+    fn write_block(storage: &DatabaseStorage<NoWriteMap>, id: &GlobalBlockId) {
+        write_test_block(storage, id.number(), v1alpha2::BlockStatus::AcceptedOnL1);
+        let mut txn = storage.begin_txn().unwrap();
+        let transactions = (0..2)
+            .map(|i| v1alpha2::Transaction {
+                meta: Some(v1alpha2::TransactionMeta {
+                    hash: Some(felt(100 + i)),
+                    ..Default::default()
+                }),
+                transaction: None,
+            })
+            .collect();
+        txn.write_body(id, BlockBody { transactions }).unwrap();
+        let receipts = (0..2)
+            .rev()
+            .map(|i| v1alpha2::TransactionReceipt {
+                transaction_hash: Some(felt(100 + i)),
+                transaction_index: i,
+                ..Default::default()
+            })
+            .collect();
+        txn.write_receipts(id, receipts).unwrap();
+        let entry = |key| v1alpha2::StorageEntry {
+            key: Some(felt(key)),
+            value: Some(felt(key * 10)),
+        };
+        let state_update = v1alpha2::StateUpdate {
+            new_root: Some(felt(2)),
+            old_root: Some(felt(1)),
+            state_diff: Some(v1alpha2::StateDiff {
+                storage_diffs: vec![v1alpha2::StorageDiff {
+                    contract_address: Some(felt(7)),
+                    storage_entries: vec![entry(1), entry(2)],
+                }],
+                ..Default::default()
+            }),
+        };
+        txn.write_state_update(id, state_update).unwrap();
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_required_parts() {
+        let parts = |filter: v1alpha2::Filter| {
+            let (_path, storage) = open_storage();
+            DatabaseBlockDataFilter::new(storage, filter).required_parts()
+        };
+
+        assert_eq!(parts(v1alpha2::Filter::default()), BlockParts::default());
+        assert_eq!(
+            parts(v1alpha2::Filter {
+                header: Some(v1alpha2::HeaderFilter { weak: true }),
+                ..Default::default()
+            }),
+            BlockParts {
+                header: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            parts(v1alpha2::Filter {
+                events: vec![v1alpha2::EventFilter::default()],
+                ..Default::default()
+            }),
+            BlockParts {
+                transactions: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            parts(v1alpha2::Filter {
+                factories: vec![v1alpha2::FactoryFilter {
+                    class_hash: Some(felt(1)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            BlockParts {
+                transactions: true,
+                state_update: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_stored_block_and_storage_have_the_same_data() {
+        let (_path, storage) = open_storage();
+        let id = block_id(1);
+        write_block(&storage, &id);
+
+        let filter = v1alpha2::Filter {
+            header: Some(v1alpha2::HeaderFilter { weak: false }),
+            transactions: vec![v1alpha2::TransactionFilter::default()],
+            state_update: Some(v1alpha2::StateUpdateFilter {
+                storage_diffs: vec![v1alpha2::StorageDiffFilter {
+                    keys: vec![felt(2)],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut filter = DatabaseBlockDataFilter::new(storage.clone(), filter);
+        let meter = Arc::new(TestMeter);
+
+        let from_storage = filter.data_for_block(&id, &meter).unwrap().unwrap();
+
+        let blocks = storage
+            .read_block_range(1, 1, filter.required_parts(), |_, _| true)
+            .unwrap();
+        assert_eq!(blocks.len(), 1);
+        let from_stored_block = filter
+            .data_for_stored_block(&blocks[0], &meter)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(from_storage, from_stored_block);
+
+        assert_eq!(
+            from_storage.header.as_ref().map(|h| h.block_number),
+            Some(1)
+        );
+        // receipts are matched with their transaction by index.
+        assert_eq!(from_storage.transactions.len(), 2);
+        for (i, tx) in from_storage.transactions.iter().enumerate() {
+            let tx_hash = tx
+                .transaction
+                .as_ref()
+                .and_then(|tx| tx.meta.as_ref())
+                .and_then(|meta| meta.hash.clone());
+            let receipt = tx.receipt.as_ref().unwrap();
+            assert_eq!(tx.transaction_index, i as u64);
+            assert_eq!(tx_hash, receipt.transaction_hash);
+        }
+        // only the storage entries matching the filter are included.
+        let storage_diffs = from_storage
+            .state_update
+            .and_then(|update| update.state_diff)
+            .map(|diff| diff.storage_diffs)
+            .unwrap_or_default();
+        assert_eq!(storage_diffs.len(), 1);
+        assert_eq!(storage_diffs[0].contract_address, Some(felt(7)));
+        assert_eq!(storage_diffs[0].storage_entries.len(), 1);
+        assert_eq!(storage_diffs[0].storage_entries[0].key, Some(felt(2)));
+    }
+
+    #[test]
+    fn test_events_by_class_hash() {
+        let (_path, storage) = open_storage();
+        let id = block_id(1);
+        write_block(&storage, &id);
+        // one event emitted by contracts 20 and 21 in each transaction.
+        let receipts = (0..2)
+            .map(|i| v1alpha2::TransactionReceipt {
+                transaction_hash: Some(felt(100 + i)),
+                transaction_index: i,
+                events: vec![v1alpha2::Event {
+                    from_address: Some(felt(20 + i)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+            .collect();
+        let mut txn = storage.begin_txn().unwrap();
+        txn.write_receipts(&id, receipts).unwrap();
+        txn.write_contract_class_hash(&felt(20), felt(30)).unwrap();
+        txn.commit().unwrap();
+
+        let meter = Arc::new(TestMeter);
+        let emitters = |from_class_hash: u64| {
+            let filter = v1alpha2::Filter {
+                events: vec![v1alpha2::EventFilter {
+                    from_class_hash: Some(felt(from_class_hash)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            DatabaseBlockDataFilter::new(storage.clone(), filter)
+                .data_for_block(&id, &meter)
+                .unwrap()
+                .map(|block| block.events)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|event| event.event.and_then(|e| e.from_address))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(emitters(30), vec![Some(felt(20))]);
+        // contract 21 has no class hash stored.
+        assert!(emitters(31).is_empty());
+    }
+
+    #[test]
+    fn test_reverted_receipts() {
+        let (_path, storage) = open_storage();
+        let id = block_id(1);
+        write_block(&storage, &id);
+        // the second transaction is reverted, both emit one event.
+        let receipts = (0..2)
+            .map(|i| {
+                let execution_status = if i == 1 {
+                    v1alpha2::ExecutionStatus::Reverted
+                } else {
+                    v1alpha2::ExecutionStatus::Succeeded
+                };
+                v1alpha2::TransactionReceipt {
+                    transaction_hash: Some(felt(100 + i)),
+                    transaction_index: i,
+                    events: vec![v1alpha2::Event {
+                        from_address: Some(felt(20)),
+                        ..Default::default()
+                    }],
+                    execution_status: execution_status as i32,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let mut txn = storage.begin_txn().unwrap();
+        txn.write_receipts(&id, receipts).unwrap();
+        txn.commit().unwrap();
+
+        let meter = Arc::new(TestMeter);
+        let included = |include_reverted: bool| {
+            let filter = v1alpha2::Filter {
+                transactions: vec![v1alpha2::TransactionFilter {
+                    include_reverted,
+                    ..Default::default()
+                }],
+                events: vec![v1alpha2::EventFilter {
+                    include_reverted,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            let block = DatabaseBlockDataFilter::new(storage.clone(), filter)
+                .data_for_block(&id, &meter)
+                .unwrap()
+                .unwrap_or_default();
+            let transactions = block
+                .transactions
+                .iter()
+                .map(|tx| tx.transaction_index)
+                .collect::<Vec<_>>();
+            let events = block
+                .events
+                .iter()
+                .map(|ev| ev.transaction_index)
+                .collect::<Vec<_>>();
+            (transactions, events)
+        };
+
+        assert_eq!(included(false), (vec![0], vec![0]));
+        assert_eq!(included(true), (vec![0, 1], vec![0, 1]));
+    }
+
+    #[test]
+    fn test_set_receipts_finality() {
+        let receipt = || Some(v1alpha2::TransactionReceipt::default());
+        let mut block = v1alpha2::Block {
+            transactions: vec![v1alpha2::TransactionWithReceipt {
+                receipt: receipt(),
+                ..Default::default()
+            }],
+            events: vec![v1alpha2::EventWithTransaction {
+                receipt: receipt(),
+                ..Default::default()
+            }],
+            l2_to_l1_messages: vec![
+                v1alpha2::L2ToL1MessageWithTransaction {
+                    receipt: receipt(),
+                    ..Default::default()
+                },
+                // receipts excluded by the field mask stay excluded.
+                v1alpha2::L2ToL1MessageWithTransaction::default(),
+            ],
+            ..Default::default()
+        };
+
+        set_receipts_finality(
+            &mut block,
+            v1alpha2::TransactionFinalityStatus::AcceptedOnL1,
+        );
+
+        let finality = |receipt: &Option<v1alpha2::TransactionReceipt>| {
+            receipt.as_ref().map(|r| r.finality_status())
+        };
+        let accepted = Some(v1alpha2::TransactionFinalityStatus::AcceptedOnL1);
+        assert_eq!(finality(&block.transactions[0].receipt), accepted);
+        assert_eq!(finality(&block.events[0].receipt), accepted);
+        assert_eq!(finality(&block.l2_to_l1_messages[0].receipt), accepted);
+        assert_eq!(finality(&block.l2_to_l1_messages[1].receipt), None);
+    }
+
+    /// Returns the addresses of the event filters added by factories at the given block.
+    fn factory_addresses(
+        filter: &DatabaseBlockDataFilter<DatabaseStorage<NoWriteMap>>,
+        block_number: u64,
+    ) -> Vec<v1alpha2::FieldElement> {
+        filter
+            .factory_filters
+            .event_filters_at(block_number)
+            .map(|f| f.from_address.clone().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_update_factory_filters_with_event_factory() {
+        let (_path, storage) = open_storage();
+        let factory = v1alpha2::FactoryFilter {
+            event: Some(v1alpha2::EventFilter {
+                from_address: Some(felt(10)),
+                keys: vec![felt(20)],
+                ..Default::default()
+            }),
+            address: Some(v1alpha2::factory_filter::Address::DataPosition(1)),
+            events: Some(v1alpha2::EventFilter {
+                keys: vec![felt(30)],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let filter = v1alpha2::Filter {
+            factories: vec![factory],
+            ..Default::default()
+        };
+        let mut filter = DatabaseBlockDataFilter::new(storage, filter);
+
+        let event = |from_address, key, data: Vec<u64>| v1alpha2::Event {
+            from_address: Some(felt(from_address)),
+            keys: vec![felt(key)],
+            data: data.into_iter().map(felt).collect(),
+        };
+        let block = FactoryBlock {
+            receipts: vec![
+                v1alpha2::TransactionReceipt {
+                    events: vec![
+                        event(10, 20, vec![0, 100]),
+                        // not the factory event.
+                        event(10, 21, vec![0, 101]),
+                        event(11, 20, vec![0, 102]),
+                        // no address at the position.
+                        event(10, 20, vec![0]),
+                    ],
+                    ..Default::default()
+                },
+                v1alpha2::TransactionReceipt {
+                    events: vec![event(10, 20, vec![0, 103])],
+                    ..Default::default()
+                },
+                // events of reverted transactions are ignored.
+                v1alpha2::TransactionReceipt {
+                    events: vec![event(10, 20, vec![0, 104])],
+                    execution_status: v1alpha2::ExecutionStatus::Reverted as i32,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        filter.update_factory_filters(&block_id(5), &block).unwrap();
+
+        assert!(factory_addresses(&filter, 4).is_empty());
+        assert_eq!(factory_addresses(&filter, 5), vec![felt(100), felt(103)]);
+        // the deployed contract filter uses the factory `events` filter.
+        let deployed_filter = filter.factory_filters.event_filters_at(5).next().unwrap();
+        assert_eq!(deployed_filter.keys, vec![felt(30)]);
+
+        // processing the block again replaces its filters.
+        filter
+            .update_factory_filters(&block_id(5), &FactoryBlock::default())
+            .unwrap();
+        assert!(filter.factory_filters.is_empty());
+    }
+
+    #[test]
+    fn test_update_factory_filters_with_class_hash_factory() {
+        let (_path, storage) = open_storage();
+        let factory = v1alpha2::FactoryFilter {
+            class_hash: Some(felt(40)),
+            ..Default::default()
+        };
+        let filter = v1alpha2::Filter {
+            factories: vec![factory],
+            ..Default::default()
+        };
+        let mut filter = DatabaseBlockDataFilter::new(storage, filter);
+
+        let deployed_contract = |address, class_hash| v1alpha2::DeployedContract {
+            contract_address: Some(felt(address)),
+            class_hash: Some(felt(class_hash)),
+        };
+        let block = FactoryBlock {
+            deployed_contracts: vec![
+                deployed_contract(100, 40),
+                deployed_contract(101, 41),
+                deployed_contract(102, 40),
+            ],
+            ..Default::default()
+        };
+
+        filter.update_factory_filters(&block_id(3), &block).unwrap();
+        filter
+            .update_factory_filters(&block_id(4), &FactoryBlock::default())
+            .unwrap();
+
+        assert!(factory_addresses(&filter, 2).is_empty());
+        assert_eq!(factory_addresses(&filter, 4), vec![felt(100), felt(102)]);
+    }
+
+    #[test]
+    fn test_update_factory_filters_without_factories() {
+        let (_path, storage) = open_storage();
+        let mut filter = DatabaseBlockDataFilter::new(storage, v1alpha2::Filter::default());
+        let block = FactoryBlock {
+            deployed_contracts: vec![v1alpha2::DeployedContract {
+                contract_address: Some(felt(100)),
+                class_hash: None,
+            }],
+            ..Default::default()
+        };
+        filter.update_factory_filters(&block_id(3), &block).unwrap();
+        assert!(filter.factory_filters.is_empty());
+    }
+
+    /// Returns a bloom filter with the given values.
+    fn bloom(values: &[(BloomItem, u64)]) -> BlockBloom {
+        let mut bloom = BlockBloom::with_capacity(values.len());
+        for (item, value) in values {
+            bloom.insert(*item, &felt(*value));
+        }
+        bloom
+    }
+
+    #[test]
+    fn test_event_may_match() {
+        let bloom = bloom(&[
+            (BloomItem::EventEmitter, 1),
+            (BloomItem::EventKey, 10),
+            (BloomItem::EventKey, 11),
+        ]);
+        let event_filter = |from_address: Option<u64>, keys: Vec<u64>| v1alpha2::EventFilter {
+            from_address: from_address.map(felt),
+            keys: keys.into_iter().map(felt).collect(),
+            ..Default::default()
+        };
+
+        assert!(event_may_match(&bloom, &v1alpha2::EventFilter::default()));
+        assert!(event_may_match(&bloom, &event_filter(Some(1), vec![])));
+        assert!(event_may_match(
+            &bloom,
+            &event_filter(Some(1), vec![10, 11])
+        ));
+        assert!(event_may_match(&bloom, &event_filter(None, vec![11])));
+        assert!(!event_may_match(&bloom, &event_filter(Some(2), vec![])));
+        assert!(!event_may_match(
+            &bloom,
+            &event_filter(Some(1), vec![10, 12])
+        ));
+        // emitters and keys are different items.
+        assert!(!event_may_match(&bloom, &event_filter(Some(10), vec![])));
+
+        let keys_match = |positions: Vec<Vec<u64>>| v1alpha2::EventFilter {
+            keys_match: Some(v1alpha2::FieldElementsFilter {
+                positions: positions
+                    .into_iter()
+                    .map(|values| v1alpha2::FieldElementSet {
+                        values: values.into_iter().map(felt).collect(),
+                    })
+                    .collect(),
+            }),
+            ..Default::default()
+        };
+        // any value of each position must be in the block.
+        assert!(event_may_match(&bloom, &keys_match(vec![vec![10, 20]])));
+        assert!(event_may_match(&bloom, &keys_match(vec![vec![], vec![11]])));
+        assert!(!event_may_match(&bloom, &keys_match(vec![vec![20, 21]])));
+        assert!(!event_may_match(
+            &bloom,
+            &keys_match(vec![vec![10], vec![20]])
+        ));
+    }
+
+    #[test]
+    fn test_transaction_may_match() {
+        use v1alpha2::transaction_filter::Filter;
+
+        let bloom = bloom(&[(BloomItem::TransactionSender, 1)]);
+        let invoke_v1 = |sender: u64| v1alpha2::TransactionFilter {
+            filter: Some(Filter::InvokeV1(v1alpha2::InvokeTransactionV1Filter {
+                sender_address: Some(felt(sender)),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let invoke_v0 = |contract: u64| v1alpha2::TransactionFilter {
+            filter: Some(Filter::InvokeV0(v1alpha2::InvokeTransactionV0Filter {
+                contract_address: Some(felt(contract)),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let declare = |sender: u64| v1alpha2::TransactionFilter {
+            filter: Some(Filter::Declare(v1alpha2::DeclareTransactionFilter {
+                sender_address: Some(felt(sender)),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+
+        assert!(transaction_may_match(&bloom, &invoke_v1(1)));
+        assert!(transaction_may_match(&bloom, &invoke_v0(1)));
+        assert!(transaction_may_match(&bloom, &declare(1)));
+        assert!(!transaction_may_match(&bloom, &invoke_v1(2)));
+        assert!(!transaction_may_match(&bloom, &invoke_v0(2)));
+        assert!(!transaction_may_match(&bloom, &declare(2)));
+
+        // filters without a sender can't use the bloom filter.
+        assert!(transaction_may_match(
+            &bloom,
+            &v1alpha2::TransactionFilter::default()
+        ));
+        let deploy = v1alpha2::TransactionFilter {
+            filter: Some(Filter::Deploy(v1alpha2::DeployTransactionFilter::default())),
+            ..Default::default()
+        };
+        assert!(transaction_may_match(&bloom, &deploy));
+        let invoke_any = v1alpha2::TransactionFilter {
+            filter: Some(Filter::InvokeV1(
+                v1alpha2::InvokeTransactionV1Filter::default(),
+            )),
+            ..Default::default()
+        };
+        assert!(transaction_may_match(&bloom, &invoke_any));
+    }
+
+    #[test]
+    fn test_state_update_may_match() {
+        let bloom = bloom(&[(BloomItem::StorageDiffContract, 1)]);
+        let storage_diffs = |addresses: Vec<Option<u64>>| v1alpha2::StateUpdateFilter {
+            storage_diffs: addresses
+                .into_iter()
+                .map(|address| v1alpha2::StorageDiffFilter {
+                    contract_address: address.map(felt),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        assert!(state_update_may_match(
+            &bloom,
+            &storage_diffs(vec![Some(1)])
+        ));
+        assert!(state_update_may_match(
+            &bloom,
+            &storage_diffs(vec![Some(2), Some(1)])
+        ));
+        assert!(state_update_may_match(&bloom, &storage_diffs(vec![None])));
+        assert!(!state_update_may_match(
+            &bloom,
+            &storage_diffs(vec![Some(2)])
+        ));
+
+        // the bloom filter doesn't contain the other state changes.
+        let nonces = v1alpha2::StateUpdateFilter {
+            nonces: vec![v1alpha2::NonceUpdateFilter::default()],
+            ..storage_diffs(vec![Some(2)])
+        };
+        assert!(state_update_may_match(&bloom, &nonces));
+        let deployed_contracts = v1alpha2::StateUpdateFilter {
+            deployed_contracts: vec![v1alpha2::DeployedContractFilter::default()],
+            ..Default::default()
+        };
+        assert!(state_update_may_match(&bloom, &deployed_contracts));
+        let declared_contracts = v1alpha2::StateUpdateFilter {
+            declared_contracts: vec![v1alpha2::DeclaredContractFilter::default()],
+            ..Default::default()
+        };
+        assert!(state_update_may_match(&bloom, &declared_contracts));
+    }
+
+    /// Adds the events and messages of a block to the indexes.
+    fn write_indexes(
+        storage: &DatabaseStorage<NoWriteMap>,
+        number: u64,
+        events: Vec<(u64, u64)>,
+        messages: Vec<u64>,
+    ) {
+        let receipt = v1alpha2::TransactionReceipt {
+            events: events
+                .into_iter()
+                .map(|(from_address, key)| v1alpha2::Event {
+                    from_address: Some(felt(from_address)),
+                    keys: vec![felt(key)],
+                    ..Default::default()
+                })
+                .collect(),
+            l2_to_l1_messages: messages
+                .into_iter()
+                .map(|to_address| v1alpha2::L2ToL1Message {
+                    to_address: Some(felt(to_address)),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let mut txn = storage.begin_txn().unwrap();
+        txn.write_indexes(&block_id(number), &[receipt]).unwrap();
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_next_candidate_block() {
+        let (_path, storage) = open_storage();
+        write_indexes(&storage, 2, vec![(1, 10)], vec![]);
+        write_indexes(&storage, 4, vec![(1, 11)], vec![5]);
+        write_indexes(&storage, 7, vec![(3, 10)], vec![]);
+
+        let next = |filter: v1alpha2::Filter, start| {
+            let snapshot = storage.snapshot().unwrap();
+            DatabaseBlockDataFilter::new(storage.clone(), filter)
+                .next_candidate_block(&snapshot, start)
+                .unwrap()
+        };
+        let events = |events: Vec<v1alpha2::EventFilter>| v1alpha2::Filter {
+            events,
+            ..Default::default()
+        };
+        let event = |from_address: u64, keys: Vec<u64>| v1alpha2::EventFilter {
+            from_address: Some(felt(from_address)),
+            keys: keys.into_iter().map(felt).collect(),
+            ..Default::default()
+        };
+
+        // blocks before the indexes must be scanned.
+        assert_eq!(next(events(vec![event(1, vec![10])]), 0), Some(0));
+        assert_eq!(next(events(vec![event(1, vec![10])]), 2), Some(2));
+        assert_eq!(next(events(vec![event(1, vec![10])]), 3), None);
+        assert_eq!(next(events(vec![event(1, vec![11])]), 2), Some(4));
+        assert_eq!(next(events(vec![event(1, vec![12])]), 2), None);
+        // events with any key are not looked up in the index.
+        assert_eq!(next(events(vec![event(1, vec![])]), 5), Some(5));
+
+        // any of the values of the first keys_match position.
+        let keys_match = |values: Vec<u64>| v1alpha2::EventFilter {
+            keys_match: Some(v1alpha2::FieldElementsFilter {
+                positions: vec![v1alpha2::FieldElementSet {
+                    values: values.into_iter().map(felt).collect(),
+                }],
+            }),
+            ..event(1, vec![])
+        };
+        assert_eq!(next(events(vec![keys_match(vec![12, 11])]), 3), Some(4));
+        assert_eq!(next(events(vec![keys_match(vec![12])]), 2), None);
+
+        // the lowest block of all filters.
+        assert_eq!(
+            next(events(vec![event(3, vec![10]), event(1, vec![11])]), 2),
+            Some(4)
+        );
+
+        // the block range of the event filter.
+        let bounded = v1alpha2::EventFilter {
+            from_block: Some(5),
+            to_block: Some(8),
+            ..event(3, vec![10])
+        };
+        assert_eq!(next(events(vec![bounded.clone()]), 2), Some(7));
+        let bounded = v1alpha2::EventFilter {
+            to_block: Some(6),
+            ..bounded
+        };
+        assert_eq!(next(events(vec![bounded]), 2), None);
+
+        // factory events are candidates too.
+        let factories = v1alpha2::Filter {
+            factories: vec![v1alpha2::FactoryFilter {
+                event: Some(event(3, vec![10])),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(next(factories, 2), Some(7));
+
+        let messages = |to_address: Option<u64>| v1alpha2::Filter {
+            messages: vec![v1alpha2::L2ToL1MessageFilter {
+                to_address: to_address.map(felt),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(next(messages(Some(5)), 3), Some(4));
+        assert_eq!(next(messages(Some(6)), 3), None);
+        assert_eq!(next(messages(None), 3), Some(3));
+    }
+
+    #[test]
+    fn test_next_candidate_block_without_index() {
+        let (_path, storage) = open_storage();
+        write_indexes(&storage, 0, vec![(1, 10)], vec![]);
+
+        let next = |filter: v1alpha2::Filter| {
+            DatabaseBlockDataFilter::new(storage.clone(), filter)
+                .next_candidate_block(storage.as_ref(), 3)
+                .unwrap()
+        };
+
+        // data that is not indexed must be scanned.
+        assert_eq!(
+            next(v1alpha2::Filter {
+                header: Some(v1alpha2::HeaderFilter { weak: false }),
+                ..Default::default()
+            }),
+            Some(3)
+        );
+        assert_eq!(
+            next(v1alpha2::Filter {
+                transactions: vec![v1alpha2::TransactionFilter::default()],
+                ..Default::default()
+            }),
+            Some(3)
+        );
+        assert_eq!(
+            next(v1alpha2::Filter {
+                state_update: Some(v1alpha2::StateUpdateFilter::default()),
+                ..Default::default()
+            }),
+            Some(3)
+        );
+        assert_eq!(
+            next(v1alpha2::Filter {
+                events: vec![v1alpha2::EventFilter::default()],
+                ..Default::default()
+            }),
+            Some(3)
+        );
+        assert_eq!(
+            next(v1alpha2::Filter {
+                events: vec![v1alpha2::EventFilter {
+                    from_address: Some(felt(1)),
+                    from_class_hash: Some(felt(2)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            Some(3)
+        );
+        assert_eq!(
+            next(v1alpha2::Filter {
+                factories: vec![v1alpha2::FactoryFilter {
+                    class_hash: Some(felt(2)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            Some(3)
+        );
     }
 }
@@ -14,7 +14,7 @@ use pin_project::pin_project;
 use prost::Message;
 use tracing::warn;
 
-use crate::core::GlobalBlockId;
+use crate::{core::GlobalBlockId, server::KeyPermissions};
 
 use super::StreamError;
 
@@ -28,12 +28,27 @@ pub struct StreamConfiguration {
     pub stream_id: u64,
     pub finality: DataFinality,
     pub starting_cursor: Option<GlobalBlockId>,
+    pub ending_cursor: Option<GlobalBlockId>,
     pub filter: Filter,
 }
 
-#[derive(Default)]
+/// A change to the streams running on a connection.
+#[derive(Debug)]
+pub enum StreamConfigurationChange {
+    /// Replace all streams with the given stream.
+    Replace(StreamConfiguration),
+    /// Add the given stream, or reconfigure it if it's already running.
+    Multiplex(StreamConfiguration),
+    /// Stop the stream with the given id.
+    Cancel { stream_id: u64 },
+    /// The multiplexed stream configuration is not valid.
+    ///
+    /// Only the stream with the given id is stopped.
+    Rejected { stream_id: u64, error: StreamError },
+}
+
 struct StreamConfigurationStreamState {
-    current: Option<StreamConfiguration>,
+    permissions: KeyPermissions,
 }
 
 #[pin_project]
@@ -52,11 +67,10 @@ where
     S: Stream<Item = Result<StreamDataRequest, E>>,
     E: std::error::Error + Send + Sync + 'static,
 {
-    pub fn new(inner: S) -> Self {
-        StreamConfigurationStream {
-            inner,
-            state: Default::default(),
-        }
+    /// Creates a new configuration stream, rejecting configurations not allowed by `permissions`.
+    pub fn new(inner: S, permissions: KeyPermissions) -> Self {
+        let state = StreamConfigurationStreamState { permissions };
+        StreamConfigurationStream { inner, state }
     }
 }
 
@@ -64,6 +78,28 @@ impl StreamConfigurationStreamState {
     fn handle_request(
         &mut self,
         request: StreamDataRequest,
+    ) -> Result<StreamConfigurationChange, StreamError> {
+        let stream_id = request.stream_id.unwrap_or_default();
+
+        if request.cancel {
+            return Ok(StreamConfigurationChange::Cancel { stream_id });
+        }
+
+        // errors in multiplexed streams don't affect the other streams.
+        let multiplex = request.multiplex;
+        match self.configuration_from_request(request) {
+            Ok(configuration) if multiplex => {
+                Ok(StreamConfigurationChange::Multiplex(configuration))
+            }
+            Ok(configuration) => Ok(StreamConfigurationChange::Replace(configuration)),
+            Err(error) if multiplex => Ok(StreamConfigurationChange::Rejected { stream_id, error }),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn configuration_from_request(
+        &self,
+        request: StreamDataRequest,
     ) -> Result<StreamConfiguration, StreamError> {
         let batch_size = request.batch_size.unwrap_or(DEFAULT_BATCH_SIZE as u64) as usize;
         let batch_size = batch_size.clamp(MIN_BATCH_SIZE, MAX_BATCH_SIZE);
@@ -73,6 +109,13 @@ impl StreamConfigurationStreamState {
             .and_then(DataFinality::from_i32)
             .unwrap_or(DataFinality::DataStatusAccepted);
 
+        if !self.permissions.allows_finality(finality) {
+            return Err(StreamError::permission_denied(format!(
+                "api key is not allowed to stream data with finality {:?}",
+                finality
+            )));
+        }
+
         let stream_id = request.stream_id.unwrap_or_default();
 
         let filter = Filter::decode(request.filter.as_ref())
@@ -84,16 +127,29 @@ impl StreamConfigurationStreamState {
             .transpose()
             .map_err(|_| StreamError::client("invalid stream cursor"))?;
 
+        let ending_cursor = request
+            .ending_cursor
+            .map(|c| GlobalBlockId::from_cursor(&c))
+            .transpose()
+            .map_err(|_| StreamError::client("invalid ending cursor"))?;
+
+        if let (Some(starting_cursor), Some(ending_cursor)) = (starting_cursor, ending_cursor) {
+            if ending_cursor.number() <= starting_cursor.number() {
+                return Err(StreamError::client(
+                    "ending cursor must be after starting cursor",
+                ));
+            }
+        }
+
         let configuration = StreamConfiguration {
             batch_size,
             finality,
             stream_id,
             filter,
             starting_cursor,
+            ending_cursor,
         };
 
-        self.current = Some(configuration.clone());
-
         Ok(configuration)
     }
 }
@@ -103,7 +159,7 @@ where
     S: Stream<Item = Result<StreamDataRequest, E>>,
     E: std::error::Error + Send + Sync + 'static,
 {
-    type Item = Result<StreamConfiguration, StreamError>;
+    type Item = Result<StreamConfigurationChange, StreamError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.project();
@@ -122,3 +178,128 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::{
+        node::v1alpha2::{Cursor, DataFinality, StreamDataRequest},
+        starknet::v1alpha2::Filter,
+    };
+    use assert_matches::assert_matches;
+    use prost::Message;
+
+    use crate::{server::KeyPermissions, stream::StreamError};
+
+    use super::{StreamConfigurationChange, StreamConfigurationStreamState, MAX_BATCH_SIZE};
+
+    fn new_state(permissions: KeyPermissions) -> StreamConfigurationStreamState {
+        StreamConfigurationStreamState { permissions }
+    }
+
+    fn request(stream_id: u64, multiplex: bool) -> StreamDataRequest {
+        StreamDataRequest {
+            stream_id: Some(stream_id),
+            filter: Filter::default().encode_to_vec(),
+            multiplex,
+            ..Default::default()
+        }
+    }
+
+    fn cursor(order_key: u64) -> Option<Cursor> {
+        Some(Cursor {
+            order_key,
+            unique_key: vec![0; 32],
+        })
+    }
+
+    #[test]
+    fn test_replace_and_multiplex() {
+        let mut state = new_state(KeyPermissions::default());
+
+        let change = state.handle_request(request(1, false)).unwrap();
+        assert_matches!(change, StreamConfigurationChange::Replace(c) => {
+            assert_eq!(c.stream_id, 1);
+            assert_eq!(c.finality, DataFinality::DataStatusAccepted);
+        });
+
+        let mut multiplexed = request(2, true);
+        multiplexed.batch_size = Some(1_000);
+        let change = state.handle_request(multiplexed).unwrap();
+        assert_matches!(change, StreamConfigurationChange::Multiplex(c) => {
+            assert_eq!(c.stream_id, 2);
+            assert_eq!(c.batch_size, MAX_BATCH_SIZE);
+        });
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut state = new_state(KeyPermissions::default());
+        let mut cancel = request(3, false);
+        cancel.cancel = true;
+        // the data stream knows which streams are running.
+        let change = state.handle_request(cancel).unwrap();
+        assert_matches!(change, StreamConfigurationChange::Cancel { stream_id: 3 });
+    }
+
+    #[test]
+    fn test_invalid_multiplexed_request_is_rejected() {
+        let mut state = new_state(KeyPermissions::default());
+
+        let mut invalid = request(4, true);
+        invalid.filter = vec![0xff, 0xff];
+        let change = state.handle_request(invalid).unwrap();
+        assert_matches!(
+            change,
+            StreamConfigurationChange::Rejected {
+                stream_id: 4,
+                error: StreamError::Client { .. }
+            }
+        );
+
+        let mut invalid = request(5, true);
+        invalid.starting_cursor = cursor(10);
+        invalid.ending_cursor = cursor(5);
+        let change = state.handle_request(invalid).unwrap();
+        assert_matches!(
+            change,
+            StreamConfigurationChange::Rejected { stream_id: 5, .. }
+        );
+    }
+
+    #[test]
+    fn test_invalid_request_is_an_error() {
+        let mut state = new_state(KeyPermissions::default());
+        let mut invalid = request(0, false);
+        invalid.filter = vec![0xff, 0xff];
+        assert_matches!(
+            state.handle_request(invalid),
+            Err(StreamError::Client { .. })
+        );
+    }
+
+    #[test]
+    fn test_finality_not_allowed() {
+        let permissions = KeyPermissions {
+            allow_pending: false,
+            ..Default::default()
+        };
+        let mut state = new_state(permissions);
+
+        let mut pending = request(0, false);
+        pending.finality = Some(DataFinality::DataStatusPending as i32);
+        assert_matches!(
+            state.handle_request(pending.clone()),
+            Err(StreamError::PermissionDenied { .. })
+        );
+
+        pending.multiplex = true;
+        let change = state.handle_request(pending).unwrap();
+        assert_matches!(
+            change,
+            StreamConfigurationChange::Rejected {
+                stream_id: 0,
+                error: StreamError::PermissionDenied { .. }
+            }
+        );
+    }
+}
@@ -1,12 +1,14 @@
 //! Root data stream.
 
 use std::{
+    collections::BTreeMap,
+    ops::Bound,
     pin::Pin,
     sync::Arc,
     task::{self, Poll},
 };
 
-use apibara_core::node::v1alpha2::StreamDataResponse;
+use apibara_core::node::v1alpha2::{self, stream_data_response, StreamDataResponse};
 use futures::Stream;
 use pin_project::pin_project;
 use tracing::info_span;
@@ -15,20 +17,26 @@ use crate::{
     core::IngestionMessage, db::StorageReader, healer::HealerClient, server::RequestMeter,
 };
 
-use super::{configuration::StreamConfiguration, filtered::FilteredDataStream, StreamError};
+use super::{
+    configuration::{StreamConfiguration, StreamConfigurationChange},
+    filtered::FilteredDataStream,
+    StreamError,
+};
+
+/// Maximum number of streams running on a connection.
+const MAX_MULTIPLEXED_STREAMS: usize = 64;
 
 #[derive(Debug, thiserror::Error)]
 pub enum DataStreamError {
     #[error("ingestion stream closed")]
     IngestionClosed,
-    #[error("data stream closed")]
-    DataClosed,
 }
 
+/// Streams data to the client, multiplexing all the streams configured on the connection.
 #[pin_project]
 pub struct DataStream<C, L, R, M>
 where
-    C: Stream<Item = Result<StreamConfiguration, StreamError>>,
+    C: Stream<Item = Result<StreamConfigurationChange, StreamError>>,
     L: Stream<Item = Result<IngestionMessage, StreamError>>,
     R: StorageReader,
     M: RequestMeter,
@@ -37,13 +45,23 @@ where
     configuration_stream: C,
     #[pin]
     ingestion_stream: L,
-    #[pin]
-    inner: FilteredDataStream<R, M>,
+    streams: FilteredDataStreams<R, M>,
+}
+
+/// The filtered streams running on a connection, by stream id.
+struct FilteredDataStreams<R: StorageReader, M: RequestMeter> {
+    storage: Arc<R>,
+    healer: Arc<HealerClient>,
+    meter: Arc<M>,
+    streams: BTreeMap<u64, FilteredDataStream<R, M>>,
+    last_polled: Option<u64>,
+    /// Errors are reported on the stream instead of closing the connection.
+    multiplexed: bool,
 }
 
 impl<C, L, R, M> DataStream<C, L, R, M>
 where
-    C: Stream<Item = Result<StreamConfiguration, StreamError>>,
+    C: Stream<Item = Result<StreamConfigurationChange, StreamError>>,
     L: Stream<Item = Result<IngestionMessage, StreamError>>,
     R: StorageReader,
     M: RequestMeter,
@@ -59,14 +77,21 @@ where
         DataStream {
             configuration_stream,
             ingestion_stream,
-            inner: FilteredDataStream::new(storage, healer, meter),
+            streams: FilteredDataStreams {
+                storage,
+                healer,
+                meter,
+                streams: BTreeMap::default(),
+                last_polled: None,
+                multiplexed: false,
+            },
         }
     }
 }
 
 impl<C, L, R, M> Stream for DataStream<C, L, R, M>
 where
-    C: Stream<Item = Result<StreamConfiguration, StreamError>>,
+    C: Stream<Item = Result<StreamConfigurationChange, StreamError>>,
     L: Stream<Item = Result<IngestionMessage, StreamError>>,
     R: StorageReader,
     M: RequestMeter,
@@ -93,14 +118,15 @@ where
                 // forward configuration error
                 return Poll::Ready(Some(Err(err)));
             }
-            Poll::Ready(Some(Ok(configuration))) => {
+            Poll::Ready(Some(Ok(change))) => {
                 // configuration changed.
                 // update and restart, or return error
-                match this.inner.reconfigure_data_stream(configuration) {
-                    Ok(_) => {
+                match this.streams.apply_change(change) {
+                    Ok(None) => {
                         cx.waker().wake_by_ref();
                         return Poll::Pending;
                     }
+                    Ok(Some(response)) => return Poll::Ready(Some(Ok(response))),
                     Err(err) => return Poll::Ready(Some(Err(err))),
                 }
             }
@@ -123,7 +149,7 @@ where
             }
             Poll::Ready(Some(Ok(message))) => {
                 // update state based on ingestion message.
-                match this.inner.handle_ingestion_message(message) {
+                match this.streams.handle_ingestion_message(message) {
                     Ok(_) => {
                         cx.waker().wake_by_ref();
                         return Poll::Pending;
@@ -134,12 +160,12 @@ where
         }
 
         // then yield new batches
-        match this.inner.poll_next(cx) {
+        match this.streams.poll_next(cx) {
             Poll::Pending => Poll::Pending,
             Poll::Ready(None) => {
-                // data stream should never close
-                let err = StreamError::internal(DataStreamError::DataClosed);
-                Poll::Ready(Some(Err(err)))
+                // all data streams reached their ending cursor.
+                // close this stream too.
+                Poll::Ready(None)
             }
             Poll::Ready(Some(Err(err))) => {
                 // forward data stream error
@@ -151,8 +177,380 @@ where
             }
         }
     }
+}
+
+impl<R, M> FilteredDataStreams<R, M>
+where
+    R: StorageReader,
+    M: RequestMeter,
+{
+    /// Applies the configuration change.
+    ///
+    /// Returns the response to send if the change failed for a multiplexed stream.
+    fn apply_change(
+        &mut self,
+        change: StreamConfigurationChange,
+    ) -> Result<Option<StreamDataResponse>, StreamError> {
+        match change {
+            StreamConfigurationChange::Replace(configuration) => {
+                self.multiplexed = false;
+                self.streams.clear();
+                self.configure_stream(configuration)?;
+                Ok(None)
+            }
+            StreamConfigurationChange::Multiplex(configuration) => {
+                self.multiplexed = true;
+                let stream_id = configuration.stream_id;
+                if !self.streams.contains_key(&stream_id)
+                    && self.streams.len() >= MAX_MULTIPLEXED_STREAMS
+                {
+                    let err = StreamError::client(format!(
+                        "too many streams, the maximum is {}",
+                        MAX_MULTIPLEXED_STREAMS
+                    ));
+                    return self.stream_failed(stream_id, err);
+                }
+                match self.configure_stream(configuration) {
+                    Ok(()) => Ok(None),
+                    Err(err) => self.stream_failed(stream_id, err),
+                }
+            }
+            StreamConfigurationChange::Cancel { stream_id } => {
+                self.multiplexed = true;
+                if self.streams.remove(&stream_id).is_none() {
+                    let err = StreamError::client(format!("stream {} is not running", stream_id));
+                    return self.stream_failed(stream_id, err);
+                }
+                Ok(None)
+            }
+            StreamConfigurationChange::Rejected { stream_id, error } => {
+                self.multiplexed = true;
+                self.stream_failed(stream_id, error)
+            }
+        }
+    }
+
+    /// Stops the stream after an error.
+    ///
+    /// Errors caused by the client of a multiplexed stream are sent on that stream,
+    /// other errors close the connection.
+    fn stream_failed(
+        &mut self,
+        stream_id: u64,
+        err: StreamError,
+    ) -> Result<Option<StreamDataResponse>, StreamError> {
+        use stream_data_response::Message;
+
+        self.streams.remove(&stream_id);
+
+        let is_client_error = matches!(
+            err,
+            StreamError::Client { .. } | StreamError::PermissionDenied { .. }
+        );
+        if !self.multiplexed || !is_client_error {
+            return Err(err);
+        }
+
+        let error = v1alpha2::StreamError {
+            message: err.to_string(),
+        };
+        let response = StreamDataResponse {
+            stream_id,
+            message: Some(Message::Error(error)),
+        };
+        Ok(Some(response))
+    }
+
+    fn configure_stream(&mut self, configuration: StreamConfiguration) -> Result<(), StreamError> {
+        let storage = self.storage.clone();
+        let healer = self.healer.clone();
+        let meter = self.meter.clone();
+        self.streams
+            .entry(configuration.stream_id)
+            .or_insert_with(|| FilteredDataStream::new(storage, healer, meter))
+            .reconfigure_data_stream(configuration)
+    }
+
+    fn handle_ingestion_message(&mut self, message: IngestionMessage) -> Result<(), StreamError> {
+        for stream in self.streams.values_mut() {
+            stream.handle_ingestion_message(message.clone())?;
+        }
+        Ok(())
+    }
+
+    fn poll_next(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<StreamDataResponse, StreamError>>> {
+        // start from the stream after the one that sent the last message,
+        // so that all streams make progress.
+        let stream_ids = match self.last_polled {
+            None => self.streams.keys().copied().collect::<Vec<_>>(),
+            Some(last_polled) => self
+                .streams
+                .range((Bound::Excluded(last_polled), Bound::Unbounded))
+                .chain(self.streams.range(..=last_polled))
+                .map(|(stream_id, _)| *stream_id)
+                .collect(),
+        };
+
+        let mut has_finished_stream = false;
+        for stream_id in stream_ids {
+            let stream = match self.streams.get_mut(&stream_id) {
+                None => continue,
+                Some(stream) => stream,
+            };
+            match Pin::new(stream).poll_next(cx) {
+                Poll::Pending => {}
+                Poll::Ready(None) => {
+                    // stream reached its ending cursor.
+                    self.streams.remove(&stream_id);
+                    has_finished_stream = true;
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    self.last_polled = Some(stream_id);
+                    return Poll::Ready(self.stream_failed(stream_id, err).transpose());
+                }
+                Poll::Ready(Some(Ok(response))) => {
+                    self.last_polled = Some(stream_id);
+                    return Poll::Ready(Some(Ok(response)));
+                }
+            }
+        }
+
+        if has_finished_stream && self.streams.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::Arc,
+        task::{Context, Poll},
+    };
+
+    use apibara_core::{
+        node::v1alpha2::{stream_data_response::Message, DataFinality, StreamDataResponse},
+        starknet::v1alpha2,
+    };
+    use apibara_node::db::libmdbx::NoWriteMap;
+    use assert_matches::assert_matches;
+    use futures::task::noop_waker_ref;
+    use tempfile::TempDir;
+
+    use crate::{
+        core::GlobalBlockId,
+        db::{
+            test_utils::{block_id, open_test_storage, write_test_block},
+            DatabaseStorage,
+        },
+        healer::HealerClient,
+        server::RequestMeter,
+        stream::{configuration::StreamConfiguration, StreamConfigurationChange, StreamError},
+    };
+
+    use super::{FilteredDataStreams, MAX_MULTIPLEXED_STREAMS};
+
+    struct TestMeter;
+
+    impl RequestMeter for TestMeter {
+        fn increment_counter(&self, _name: &'static str, _amount: u64) {}
+    }
+
+    type TestStreams = FilteredDataStreams<DatabaseStorage<NoWriteMap>, TestMeter>;
+
+    /// Returns streams over a chain of finalized blocks.
+    fn new_streams(block_count: u64) -> (TempDir, TestStreams) {
+        let (path, storage) = open_test_storage();
+        for number in 0..block_count {
+            write_test_block(&storage, number, v1alpha2::BlockStatus::AcceptedOnL1);
+        }
+
+        let streams = FilteredDataStreams {
+            storage: Arc::new(storage),
+            healer: Arc::new(HealerClient::disconnected()),
+            meter: Arc::new(TestMeter),
+            streams: BTreeMap::default(),
+            last_polled: None,
+            multiplexed: false,
+        };
+        (path, streams)
+    }
+
+    fn configuration(stream_id: u64) -> StreamConfiguration {
+        StreamConfiguration {
+            batch_size: 10,
+            stream_id,
+            finality: DataFinality::DataStatusAccepted,
+            starting_cursor: None,
+            ending_cursor: None,
+            filter: v1alpha2::Filter::default(),
+        }
+    }
+
+    fn poll(streams: &mut TestStreams) -> Poll<Option<Result<StreamDataResponse, StreamError>>> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        streams.poll_next(&mut cx)
+    }
+
+    fn assert_stream_error(response: Option<StreamDataResponse>, stream_id: u64) {
+        assert_matches!(response, Some(response) => {
+            assert_eq!(response.stream_id, stream_id);
+            assert_matches!(response.message, Some(Message::Error(_)));
+        });
+    }
+
+    #[test]
+    fn test_cancel() {
+        let (_path, mut streams) = new_streams(2);
+        let change = StreamConfigurationChange::Multiplex(configuration(1));
+        assert!(streams.apply_change(change).unwrap().is_none());
+
+        let change = StreamConfigurationChange::Cancel { stream_id: 1 };
+        assert!(streams.apply_change(change).unwrap().is_none());
+        assert!(streams.streams.is_empty());
+
+        let change = StreamConfigurationChange::Cancel { stream_id: 1 };
+        assert_stream_error(streams.apply_change(change).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_too_many_streams() {
+        let (_path, mut streams) = new_streams(2);
+        for stream_id in 0..MAX_MULTIPLEXED_STREAMS as u64 {
+            let change = StreamConfigurationChange::Multiplex(configuration(stream_id));
+            assert!(streams.apply_change(change).unwrap().is_none());
+        }
+
+        let stream_id = MAX_MULTIPLEXED_STREAMS as u64;
+        let change = StreamConfigurationChange::Multiplex(configuration(stream_id));
+        assert_stream_error(streams.apply_change(change).unwrap(), stream_id);
+
+        // running streams can be reconfigured.
+        let change = StreamConfigurationChange::Multiplex(configuration(0));
+        assert!(streams.apply_change(change).unwrap().is_none());
+        assert_eq!(streams.streams.len(), MAX_MULTIPLEXED_STREAMS);
+    }
+
+    #[test]
+    fn test_rejected_stream_is_stopped() {
+        let (_path, mut streams) = new_streams(2);
+        for stream_id in 0..2 {
+            let change = StreamConfigurationChange::Multiplex(configuration(stream_id));
+            streams.apply_change(change).unwrap();
+        }
+
+        let change = StreamConfigurationChange::Rejected {
+            stream_id: 1,
+            error: StreamError::client("invalid filter"),
+        };
+        assert_stream_error(streams.apply_change(change).unwrap(), 1);
+        assert!(streams.streams.contains_key(&0));
+        assert!(!streams.streams.contains_key(&1));
+
+        // internal errors close the connection.
+        let change = StreamConfigurationChange::Rejected {
+            stream_id: 0,
+            error: StreamError::internal(std::fmt::Error),
+        };
+        assert!(streams.apply_change(change).is_err());
+    }
+
+    #[test]
+    fn test_errors_close_single_stream_connections() {
+        let (_path, mut streams) = new_streams(2);
+        let change = StreamConfigurationChange::Replace(configuration(0));
+        streams.apply_change(change).unwrap();
+
+        let change = StreamConfigurationChange::Rejected {
+            stream_id: 0,
+            error: StreamError::client("invalid filter"),
+        };
+        assert_matches!(
+            streams.apply_change(change),
+            Err(StreamError::Client { .. })
+        );
+    }
+
+    #[test]
+    fn test_data_stream_error_only_stops_the_stream() {
+        let (_path, mut streams) = new_streams(2);
+        streams
+            .apply_change(StreamConfigurationChange::Multiplex(configuration(0)))
+            .unwrap();
+        // the starting cursor is not a known block.
+        let mut invalid = configuration(1);
+        invalid.starting_cursor = Some(GlobalBlockId::new(
+            1,
+            v1alpha2::FieldElement::from_u64(1234).into(),
+        ));
+        streams
+            .apply_change(StreamConfigurationChange::Multiplex(invalid))
+            .unwrap();
+
+        let mut responses = Vec::default();
+        for _ in 0..10 {
+            match poll(&mut streams) {
+                Poll::Ready(Some(Ok(response))) => responses.push(response),
+                Poll::Ready(Some(Err(err))) => panic!("connection failed: {:?}", err),
+                Poll::Ready(None) => panic!("connection closed"),
+                Poll::Pending => break,
+            }
+        }
+
+        let errors: Vec<_> = responses
+            .iter()
+            .filter(|r| matches!(r.message, Some(Message::Error(_))))
+            .map(|r| r.stream_id)
+            .collect();
+        assert_eq!(errors, vec![1]);
+        assert!(responses
+            .iter()
+            .any(|r| r.stream_id == 0 && matches!(r.message, Some(Message::Data(_)))));
+        assert!(streams.streams.contains_key(&0));
+        assert!(!streams.streams.contains_key(&1));
+    }
+
+    #[test]
+    fn test_finished_stream_is_removed() {
+        let (_path, mut streams) = new_streams(5);
+        let mut bounded = configuration(1);
+        bounded.ending_cursor = Some(block_id(2));
+        streams
+            .apply_change(StreamConfigurationChange::Multiplex(bounded))
+            .unwrap();
+        streams
+            .apply_change(StreamConfigurationChange::Multiplex(configuration(2)))
+            .unwrap();
+
+        let mut has_end_of_stream = false;
+        for _ in 0..10 {
+            match poll(&mut streams) {
+                Poll::Ready(Some(Ok(response))) => {
+                    if let Some(Message::EndOfStream(_)) = response.message {
+                        assert_eq!(response.stream_id, 1);
+                        has_end_of_stream = true;
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => panic!("connection failed: {:?}", err),
+                Poll::Ready(None) => panic!("connection closed"),
+                Poll::Pending => break,
+            }
+        }
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
+        assert!(has_end_of_stream);
+        assert!(!streams.streams.contains_key(&1));
+        // the id can be used again.
+        let change = StreamConfigurationChange::Cancel { stream_id: 1 };
+        assert_stream_error(streams.apply_change(change).unwrap(), 1);
+        streams
+            .apply_change(StreamConfigurationChange::Multiplex(configuration(1)))
+            .unwrap();
+        assert!(streams.streams.contains_key(&1));
     }
 }
@@ -7,6 +7,12 @@ pub enum StreamError {
     /// Error caused by the client.
     #[error("client error: {message}")]
     Client { message: String },
+    /// The client is not allowed to perform the request.
+    #[error("permission denied: {message}")]
+    PermissionDenied { message: String },
+    /// The client exceeded its quota.
+    #[error("resource exhausted: {message}")]
+    ResourceExhausted { message: String },
 }
 
 impl StreamError {
@@ -24,4 +30,18 @@ impl StreamError {
             message: message.into(),
         }
     }
+
+    /// Creates a new resource exhausted error.
+    pub fn resource_exhausted(message: impl Into<String>) -> Self {
+        StreamError::ResourceExhausted {
+            message: message.into(),
+        }
+    }
+
+    /// Creates a new permission denied error.
+    pub fn permission_denied(message: impl Into<String>) -> Self {
+        StreamError::PermissionDenied {
+            message: message.into(),
+        }
+    }
 }
@@ -0,0 +1,132 @@
+//! Event filters added by factory filters.
+
+use std::collections::BTreeMap;
+
+use apibara_core::starknet::v1alpha2;
+
+/// Event filters for the contracts deployed by factories, by the number of the
+/// block that deployed them.
+///
+/// Blocks are keyed by number so that processing a block again (for example, a
+/// pending block that is later accepted) replaces the filters found previously.
+#[derive(Debug, Default)]
+pub struct FactoryEventFilters {
+    by_block: BTreeMap<u64, Vec<v1alpha2::EventFilter>>,
+}
+
+impl FactoryEventFilters {
+    /// Returns true if no contract was deployed by a factory.
+    pub fn is_empty(&self) -> bool {
+        self.by_block.is_empty()
+    }
+
+    /// Replaces the filters for the contracts deployed at the given block.
+    pub fn set_block_filters(&mut self, block_number: u64, filters: Vec<v1alpha2::EventFilter>) {
+        if filters.is_empty() {
+            self.by_block.remove(&block_number);
+        } else {
+            self.by_block.insert(block_number, filters);
+        }
+    }
+
+    /// Removes the filters for contracts deployed after the given block.
+    pub fn rollback(&mut self, block_number: u64) {
+        if let Some(next_block_number) = block_number.checked_add(1) {
+            self.by_block.split_off(&next_block_number);
+        }
+    }
+
+    /// Removes all filters.
+    pub fn clear(&mut self) {
+        self.by_block.clear();
+    }
+
+    /// Returns the filters for the contracts deployed up to the given block (inclusive).
+    pub fn event_filters_at(
+        &self,
+        block_number: u64,
+    ) -> impl Iterator<Item = &v1alpha2::EventFilter> {
+        self.by_block
+            .range(..=block_number)
+            .flat_map(|(_, filters)| filters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apibara_core::starknet::v1alpha2;
+
+    use super::FactoryEventFilters;
+
+    fn filter(address: u64) -> v1alpha2::EventFilter {
+        v1alpha2::EventFilter::default()
+            .with_contract_address(v1alpha2::FieldElement::from_u64(address))
+    }
+
+    fn addresses_at(filters: &FactoryEventFilters, block_number: u64) -> Vec<u64> {
+        filters
+            .event_filters_at(block_number)
+            .map(|f| {
+                (1..10)
+                    .find(|n| f.from_address == Some(v1alpha2::FieldElement::from_u64(*n)))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn sample_filters() -> FactoryEventFilters {
+        let mut filters = FactoryEventFilters::default();
+        filters.set_block_filters(2, vec![filter(1), filter(2)]);
+        filters.set_block_filters(5, vec![filter(3)]);
+        filters.set_block_filters(8, vec![filter(4)]);
+        filters
+    }
+
+    #[test]
+    fn test_event_filters_at() {
+        let filters = sample_filters();
+        assert!(!filters.is_empty());
+        assert!(addresses_at(&filters, 1).is_empty());
+        assert_eq!(addresses_at(&filters, 2), vec![1, 2]);
+        assert_eq!(addresses_at(&filters, 7), vec![1, 2, 3]);
+        assert_eq!(addresses_at(&filters, 100), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_set_block_filters_replaces_block() {
+        let mut filters = sample_filters();
+        filters.set_block_filters(5, vec![filter(5), filter(6)]);
+        assert_eq!(addresses_at(&filters, 5), vec![1, 2, 5, 6]);
+
+        // a block without deployments removes the previous ones.
+        filters.set_block_filters(5, Vec::default());
+        assert_eq!(addresses_at(&filters, 7), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut filters = sample_filters();
+        // filters of the block itself are kept.
+        filters.rollback(5);
+        assert_eq!(addresses_at(&filters, 100), vec![1, 2, 3]);
+
+        filters.rollback(3);
+        assert_eq!(addresses_at(&filters, 100), vec![1, 2]);
+
+        filters.rollback(0);
+        assert!(filters.is_empty());
+
+        // rolling back to the highest block number keeps all filters.
+        let mut filters = sample_filters();
+        filters.rollback(u64::MAX);
+        assert_eq!(addresses_at(&filters, 100), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut filters = sample_filters();
+        filters.clear();
+        assert!(filters.is_empty());
+        assert!(addresses_at(&filters, 100).is_empty());
+    }
+}
@@ -1,28 +1,31 @@
 //! Filtered data stream.
 
 use std::{
+    future::Future,
     pin::Pin,
     sync::Arc,
     task::{self, Poll, Waker},
 };
 
 use apibara_core::node::v1alpha2::{
-    stream_data_response, Data, DataFinality, Invalidate, StreamDataResponse,
+    stream_data_response, Data, DataFinality, EndOfStream, Invalidate, StreamDataResponse,
 };
 use futures::Stream;
 use prost::Message;
+use tokio::time::Sleep;
 use tracing::debug;
 
 use crate::{
     core::{GlobalBlockId, IngestionMessage},
     db::StorageReader,
     healer::HealerClient,
-    server::RequestMeter,
+    server::{QuotaStatus, RequestMeter},
 };
 
 use super::{
     block::{BlockDataFilter, DatabaseBlockDataFilter},
     configuration::StreamConfiguration,
+    factory::FactoryEventFilters,
     StreamError,
 };
 
@@ -37,6 +40,7 @@ where
     meter: Arc<M>,
     healer: Arc<HealerClient>,
     waker: Option<Waker>,
+    throttle: Option<Pin<Box<Sleep>>>,
     inner: Option<InnerDataStream<R, M>>,
 }
 
@@ -55,6 +59,8 @@ struct InnerDataStream<R: StorageReader, M: RequestMeter> {
     batch_size: usize,
     data_finality: DataFinality,
     previous_iter_cursor: Option<GlobalBlockId>,
+    ending_cursor: Option<GlobalBlockId>,
+    is_finished: bool,
     finalized_cursor: Option<GlobalBlockId>,
     accepted_cursor: GlobalBlockId,
     pending_cursor: Option<GlobalBlockId>,
@@ -77,6 +83,7 @@ where
             meter,
             inner: None,
             waker: None,
+            throttle: None,
         }
     }
 
@@ -84,6 +91,20 @@ where
         &mut self,
         configuration: StreamConfiguration,
     ) -> Result<(), StreamError> {
+        // keep contracts deployed by factories, unless the factories changed since the
+        // filters depend on them. The ones deployed after the new starting cursor are
+        // found again while streaming.
+        let mut factory_filters = match self.inner.as_mut() {
+            Some(inner) if inner.filter.factories() == configuration.filter.factories => {
+                inner.filter.take_factory_filters()
+            }
+            _ => FactoryEventFilters::default(),
+        };
+        match configuration.starting_cursor {
+            None => factory_filters.clear(),
+            Some(starting_cursor) => factory_filters.rollback(starting_cursor.number()),
+        }
+
         // use finalized and accepted cursors from previous config, if any
         let (finalized_cursor, accepted_cursor) = if let Some(inner) = self.inner.take() {
             (inner.finalized_cursor, inner.accepted_cursor)
@@ -112,13 +133,16 @@ where
             }
         };
 
-        let filter = DatabaseBlockDataFilter::new(self.storage.clone(), configuration.filter);
+        let filter = DatabaseBlockDataFilter::new(self.storage.clone(), configuration.filter)
+            .with_factory_filters(factory_filters);
 
         let inner = InnerDataStream {
             stream_id: configuration.stream_id,
             batch_size: configuration.batch_size,
             data_finality: configuration.finality,
             previous_iter_cursor: configuration.starting_cursor,
+            ending_cursor: configuration.ending_cursor,
+            is_finished: false,
             finalized_cursor,
             accepted_cursor,
             pending_cursor: None,
@@ -157,6 +181,9 @@ where
                 IngestionMessage::Invalidate(new_chain_root) => {
                     inner.accepted_cursor = new_chain_root;
                     inner.pending_cursor = None;
+                    inner
+                        .filter
+                        .rollback_factory_filters(new_chain_root.number());
                     // only reset client cursor if the stream already sent a block
                     // _belonging to_ the now invalidated chain.
                     if let Some(previous_iter_cursor) = inner.previous_iter_cursor {
@@ -167,6 +194,8 @@ where
                     }
                     self.wake()
                 }
+                // data already sent is not sent again.
+                IngestionMessage::Reingested(_) => {}
             }
         }
 
@@ -221,6 +250,13 @@ where
             .map(|c| c.number() + 1)
             .unwrap_or(0);
 
+        // the stream sent all data up to the ending cursor.
+        if let Some(ending_cursor) = self.ending_cursor {
+            if next_block_number > ending_cursor.number() {
+                return self.send_end_of_stream();
+            }
+        }
+
         // check if the next block is what is the pending block now.
         if let Some(pending_cursor) = self.pending_cursor.take() {
             if pending_cursor.number() == next_block_number {
@@ -278,59 +314,83 @@ where
 
         let mut batch = Vec::with_capacity(self.batch_size);
         let mut batch_end_cursor = None;
-        let mut current_cursor = first_cursor;
+        let mut next_block_number = first_cursor.number();
 
-        let mut iter = 0;
-        while batch.len() < self.batch_size && iter < MAX_BATCH_ITER {
-            iter += 1;
+        // don't look for data past the finalized or ending cursor.
+        let last_block_number = self
+            .ending_cursor
+            .map(|c| c.number().min(finalized_cursor.number()))
+            .unwrap_or_else(|| finalized_cursor.number());
 
-            // check the next block is still finalized.
-            // if not, stop iterating.
-            let block_status = self
-                .storage
-                .read_status(&current_cursor)
-                .map_err(StreamError::internal)?
-                .ok_or_else(|| {
-                    StreamError::internal(FilteredDataStreamError::MissingBlockStatus(
-                        current_cursor,
-                    ))
-                })?;
+        // all reads of the batch use the same snapshot, so the indexes, the block ids
+        // and the block data belong to the same chain.
+        let storage = self.storage.snapshot().map_err(StreamError::internal)?;
 
-            if !block_status.is_finalized() {
-                if current_cursor.number() < finalized_cursor.number() {
-                    self.healer.status_finalized_expected(current_cursor);
-                    continue;
+        let mut iter = 0;
+        'batch: while batch.len() < self.batch_size && iter < MAX_BATCH_ITER {
+            // jump to the next block that may have data, using the indexes.
+            let candidate = self
+                .filter
+                .next_candidate_block(&storage, next_block_number)
+                .map_err(StreamError::internal)?;
+            match candidate {
+                Some(number) if number <= last_block_number => next_block_number = number,
+                _ => {
+                    // no data up to the last block. move the cursor there.
+                    if last_block_number >= next_block_number {
+                        batch_end_cursor = storage
+                            .canonical_block_id(last_block_number)
+                            .map_err(StreamError::internal)?;
+                    }
+                    break;
                 }
-                break;
             }
 
-            batch_end_cursor = Some(current_cursor);
+            // read the following blocks, skipping the data of the blocks that the bloom
+            // filter excludes.
+            let range_end = (next_block_number + self.batch_size as u64 - 1).min(last_block_number);
+            let filter = &self.filter;
+            let blocks = storage
+                .read_block_range(
+                    next_block_number,
+                    range_end,
+                    filter.required_parts(),
+                    |block_id, bloom| filter.may_match_bloom(block_id, bloom),
+                )
+                .map_err(StreamError::internal)?;
 
-            if let Some(data) = self
-                .filter
-                .data_for_block(&current_cursor, &self.meter)
-                .map_err(StreamError::internal)?
-            {
-                batch.push(data.encode_to_vec());
-            }
+            let range_len = blocks.len() as u64;
+            for block in blocks {
+                iter += 1;
 
-            match self
-                .storage
-                .canonical_block_id(current_cursor.number() + 1)
-                .map_err(StreamError::internal)?
-            {
-                None => {
-                    // reached the highest indexed block. return what we have
-                    break;
+                // check the next block is still finalized.
+                // if not, stop iterating.
+                if !block.status.is_finalized() {
+                    if block.id.number() < finalized_cursor.number() {
+                        self.healer.status_finalized_expected(block.id);
+                    }
+                    break 'batch;
                 }
-                Some(cursor) => {
-                    // don't mix accepted and finalized data
-                    if cursor.number() > finalized_cursor.number() {
-                        break;
+
+                batch_end_cursor = Some(block.id);
+
+                if let Some(data) = self
+                    .filter
+                    .data_for_stored_block(&block, &self.meter)
+                    .map_err(StreamError::internal)?
+                {
+                    batch.push(data.encode_to_vec());
+                    if batch.len() == self.batch_size {
+                        break 'batch;
                     }
-                    current_cursor = cursor;
                 }
             }
+
+            // reached the highest indexed block. return what we have
+            if range_len < range_end - next_block_number + 1 {
+                break;
+            }
+            next_block_number = range_end + 1;
         }
 
         if batch_end_cursor.is_some() {
@@ -365,6 +425,15 @@ where
         let batch_start_cursor = self.previous_iter_cursor.map(|c| c.to_cursor());
         self.previous_iter_cursor = Some(first_cursor);
 
+        // skip blocks without matching data
+        let may_have_data = self
+            .filter
+            .may_have_data(&first_cursor)
+            .map_err(StreamError::internal)?;
+        if !may_have_data {
+            return Ok(None);
+        }
+
         // read data at cursor
         let data = if let Some(data) = self
             .filter
@@ -424,6 +493,25 @@ where
         Ok(Some(response))
     }
 
+    /// Signal the client that the stream reached the ending cursor.
+    fn send_end_of_stream(&mut self) -> Result<Option<StreamDataResponse>, StreamError> {
+        use stream_data_response::Message;
+
+        debug!(previous_iter_cursor = ?self.previous_iter_cursor, "end of stream");
+
+        self.is_finished = true;
+
+        let end_of_stream = EndOfStream {
+            cursor: self.previous_iter_cursor.map(|c| c.to_cursor()),
+        };
+        let response = StreamDataResponse {
+            stream_id: self.stream_id,
+            message: Some(Message::EndOfStream(end_of_stream)),
+        };
+
+        Ok(Some(response))
+    }
+
     fn handle_invalidated_cursor(
         &mut self,
         cursor: GlobalBlockId,
@@ -456,6 +544,7 @@ where
         }
 
         self.previous_iter_cursor = Some(new_root);
+        self.filter.rollback_factory_filters(new_root.number());
 
         let invalidate = Invalidate {
             cursor: Some(new_root.to_cursor()),
@@ -481,6 +570,38 @@ where
         // state changes
         self.waker = Some(cx.waker().clone());
 
+        // wait for the throttle delay to expire before sending more data.
+        if let Some(throttle) = &mut self.throttle {
+            if throttle.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.throttle = None;
+        }
+
+        // check the stream can send more data before reading it.
+        let quota_status = self
+            .inner
+            .as_ref()
+            .map(|inner| inner.meter.quota_status())
+            .unwrap_or(QuotaStatus::Available);
+
+        match quota_status {
+            QuotaStatus::Available => {}
+            QuotaStatus::Exhausted => {
+                let err = StreamError::resource_exhausted("api key exceeded its data quota");
+                return Poll::Ready(Some(Err(err)));
+            }
+            QuotaStatus::Throttled(delay) => {
+                debug!(delay = ?delay, "stream throttled");
+                let mut throttle = Box::pin(tokio::time::sleep(delay));
+                // poll once to register the waker
+                if throttle.as_mut().poll(cx).is_pending() {
+                    self.throttle = Some(throttle);
+                    return Poll::Pending;
+                }
+            }
+        }
+
         // if `inner` is missing, then the block was never configured.
         // nothing to do.
         let inner = if let Some(inner) = &mut self.inner {
@@ -489,6 +610,11 @@ where
             return Poll::Pending;
         };
 
+        // the end of stream message was sent, close the stream.
+        if inner.is_finished {
+            return Poll::Ready(None);
+        }
+
         // if the stream received an invalidate message in the previous tick, then
         // forward it to the client.
         if let Some(new_root) = inner.invalidated.take() {
@@ -521,3 +647,140 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use apibara_core::{node::v1alpha2::DataFinality, starknet::v1alpha2};
+    use apibara_node::db::libmdbx::NoWriteMap;
+    use tempfile::TempDir;
+
+    use crate::{
+        db::{
+            test_utils::{block_id, open_test_storage, write_test_block},
+            DatabaseStorage,
+        },
+        healer::HealerClient,
+        server::RequestMeter,
+        stream::{block::DatabaseBlockDataFilter, configuration::StreamConfiguration},
+    };
+
+    use super::FilteredDataStream;
+
+    struct TestMeter;
+
+    impl RequestMeter for TestMeter {
+        fn increment_counter(&self, _name: &'static str, _amount: u64) {}
+    }
+
+    type TestStream = FilteredDataStream<DatabaseStorage<NoWriteMap>, TestMeter>;
+
+    fn new_stream() -> (TempDir, TestStream) {
+        let (path, storage) = open_test_storage();
+        for number in 0..10 {
+            write_test_block(&storage, number, v1alpha2::BlockStatus::AcceptedOnL1);
+        }
+
+        let stream = FilteredDataStream::new(
+            Arc::new(storage),
+            Arc::new(HealerClient::disconnected()),
+            Arc::new(TestMeter),
+        );
+        (path, stream)
+    }
+
+    fn factory_filter(class_hash: u64) -> v1alpha2::Filter {
+        v1alpha2::Filter {
+            factories: vec![v1alpha2::FactoryFilter {
+                class_hash: Some(v1alpha2::FieldElement::from_u64(class_hash)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn configuration(
+        filter: v1alpha2::Filter,
+        starting_cursor: Option<u64>,
+    ) -> StreamConfiguration {
+        StreamConfiguration {
+            batch_size: 10,
+            stream_id: 0,
+            finality: DataFinality::DataStatusAccepted,
+            starting_cursor: starting_cursor.map(block_id),
+            ending_cursor: None,
+            filter,
+        }
+    }
+
+    /// Configures the stream, as if it found contracts deployed at blocks 2 and 6.
+    fn configure_with_deployed_contracts(stream: &mut TestStream, filter: v1alpha2::Filter) {
+        stream
+            .reconfigure_data_stream(configuration(filter.clone(), None))
+            .unwrap();
+        let inner = stream.inner.as_mut().unwrap();
+        let mut factory_filters = inner.filter.take_factory_filters();
+        for number in [2, 6] {
+            let address = v1alpha2::FieldElement::from_u64(100 + number);
+            let event_filter = v1alpha2::EventFilter::default().with_contract_address(address);
+            factory_filters.set_block_filters(number, vec![event_filter]);
+        }
+        inner.filter = DatabaseBlockDataFilter::new(stream.storage.clone(), filter)
+            .with_factory_filters(factory_filters);
+    }
+
+    /// Takes the event filters added by factories and returns their number.
+    fn take_factory_filters_count(stream: &mut TestStream) -> usize {
+        let inner = stream.inner.as_mut().unwrap();
+        inner
+            .filter
+            .take_factory_filters()
+            .event_filters_at(u64::MAX)
+            .count()
+    }
+
+    /// Reconfigures a stream that found contracts deployed by factories and returns the
+    /// number of contracts it still knows about.
+    fn reconfigured_factory_filters_count(
+        filter: v1alpha2::Filter,
+        starting_cursor: Option<u64>,
+    ) -> usize {
+        let (_path, mut stream) = new_stream();
+        configure_with_deployed_contracts(&mut stream, factory_filter(1));
+        stream
+            .reconfigure_data_stream(configuration(filter, starting_cursor))
+            .unwrap();
+        take_factory_filters_count(&mut stream)
+    }
+
+    #[test]
+    fn test_reconfigure_keeps_factory_filters() {
+        assert_eq!(
+            reconfigured_factory_filters_count(factory_filter(1), Some(8)),
+            2
+        );
+        // contracts deployed after the starting cursor are found again.
+        assert_eq!(
+            reconfigured_factory_filters_count(factory_filter(1), Some(4)),
+            1
+        );
+        // restarting from genesis finds all contracts again.
+        assert_eq!(
+            reconfigured_factory_filters_count(factory_filter(1), None),
+            0
+        );
+    }
+
+    #[test]
+    fn test_reconfigure_clears_factory_filters_if_factories_change() {
+        assert_eq!(
+            reconfigured_factory_filters_count(factory_filter(2), Some(8)),
+            0
+        );
+        assert_eq!(
+            reconfigured_factory_filters_count(v1alpha2::Filter::default(), Some(8)),
+            0
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Trim transaction and receipt data sent to clients.
+
+use apibara_core::starknet::v1alpha2;
+
+/// Fields included together with the data matched by a filter.
+///
+/// When several filters match the same data, a field is included if any of them
+/// includes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldMask {
+    pub transaction: bool,
+    pub receipt: bool,
+    pub signature: bool,
+    pub calldata: bool,
+    pub calls: bool,
+}
+
+impl FieldMask {
+    pub fn from_event_filter(filter: &v1alpha2::EventFilter) -> Self {
+        FieldMask {
+            transaction: filter.include_transaction.unwrap_or(true),
+            receipt: filter.include_receipt.unwrap_or(true),
+            signature: filter.include_signature.unwrap_or(true),
+            calldata: filter.include_calldata.unwrap_or(true),
+            calls: false,
+        }
+    }
+
+    pub fn from_l2_to_l1_message_filter(filter: &v1alpha2::L2ToL1MessageFilter) -> Self {
+        FieldMask {
+            transaction: filter.include_transaction.unwrap_or(true),
+            receipt: filter.include_receipt.unwrap_or(true),
+            signature: filter.include_signature.unwrap_or(true),
+            calldata: filter.include_calldata.unwrap_or(true),
+            calls: false,
+        }
+    }
+
+    pub fn from_transaction_filter(filter: &v1alpha2::TransactionFilter) -> Self {
+        FieldMask {
+            transaction: true,
+            receipt: filter.include_receipt.unwrap_or(true),
+            signature: filter.include_signature.unwrap_or(true),
+            calldata: filter.include_calldata.unwrap_or(true),
+            calls: filter.include_calls,
+        }
+    }
+
+    /// Returns a mask that includes the fields included by either mask.
+    pub fn merge(self, other: FieldMask) -> Self {
+        FieldMask {
+            transaction: self.transaction || other.transaction,
+            receipt: self.receipt || other.receipt,
+            signature: self.signature || other.signature,
+            calldata: self.calldata || other.calldata,
+            calls: self.calls || other.calls,
+        }
+    }
+
+    /// Returns the transaction with the fields not included removed.
+    pub fn transaction(
+        &self,
+        transaction: &v1alpha2::Transaction,
+    ) -> Option<v1alpha2::Transaction> {
+        use v1alpha2::transaction::Transaction;
+
+        if !self.transaction {
+            return None;
+        }
+
+        let mut transaction = transaction.clone();
+        if self.calls {
+            if let Some(Transaction::InvokeV1(tx)) = transaction.transaction.as_mut() {
+                tx.calls = tx.decode_calls().unwrap_or_default();
+            }
+        }
+        if !self.signature {
+            if let Some(meta) = transaction.meta.as_mut() {
+                meta.signature.clear();
+            }
+        }
+        if !self.calldata {
+            match transaction.transaction.as_mut() {
+                Some(Transaction::InvokeV0(tx)) => tx.calldata.clear(),
+                Some(Transaction::InvokeV1(tx)) => tx.calldata.clear(),
+                Some(Transaction::Deploy(tx)) => tx.constructor_calldata.clear(),
+                Some(Transaction::L1Handler(tx)) => tx.calldata.clear(),
+                Some(Transaction::DeployAccount(tx)) => tx.constructor_calldata.clear(),
+                Some(Transaction::Declare(_)) | None => {}
+            }
+        }
+        Some(transaction)
+    }
+
+    /// Returns the receipt, if included.
+    pub fn receipt(
+        &self,
+        receipt: &v1alpha2::TransactionReceipt,
+    ) -> Option<v1alpha2::TransactionReceipt> {
+        if self.receipt {
+            Some(receipt.clone())
+        } else {
+            None
+        }
+    }
+}
@@ -3,6 +3,12 @@ mod block;
 mod configuration;
 mod data;
 mod error;
+mod factory;
 mod filtered;
+mod mask;
 
-pub use self::{configuration::StreamConfigurationStream, data::DataStream, error::StreamError};
+pub use self::{
+    configuration::{StreamConfigurationChange, StreamConfigurationStream},
+    data::DataStream,
+    error::StreamError,
+};